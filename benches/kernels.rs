@@ -0,0 +1,116 @@
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use earworm::complex::Complex;
+use earworm::simd::{self, scalar};
+use earworm::spectrum::dft;
+use earworm::{Spectrogram, StftConfig, Window};
+
+// Size of a typical analysis frame
+const SIZE: usize = 4096;
+
+fn noise(size: usize) -> Vec<f64> {
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+        })
+        .collect()
+}
+
+fn spectrum(size: usize) -> Vec<Complex> {
+    noise(2 * size)
+        .chunks(2)
+        .map(|pair| Complex::new(pair[0], pair[1]))
+        .collect()
+}
+
+// Every kernel against its scalar loop on the same data, the simd ones fall back to scalar without AVX2
+fn kernels(c: &mut Criterion) {
+    let frame = noise(SIZE);
+    let win = Window::Hann.periodic(SIZE);
+    let a = spectrum(SIZE);
+    let b = spectrum(SIZE + 1)[1..].to_vec();
+    let magnitudes: Vec<f64> = noise(SIZE).iter().map(|x| x.abs()).collect();
+    let mut out = vec![0.0; SIZE];
+
+    let mut group = c.benchmark_group("multiply");
+    group.bench_function("scalar", |bench| {
+        let mut values = frame.clone();
+        bench.iter(|| scalar::multiply(black_box(&mut values), &win))
+    });
+    group.bench_function("simd", |bench| {
+        let mut values = frame.clone();
+        bench.iter(|| simd::multiply(black_box(&mut values), &win))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("complex_mac");
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| scalar::complex_mac(black_box(&a), black_box(&b)))
+    });
+    group.bench_function("simd", |bench| {
+        bench.iter(|| simd::complex_mac(black_box(&a), black_box(&b)))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("magnitude");
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| scalar::magnitude(black_box(&a), &mut out))
+    });
+    group.bench_function("simd", |bench| {
+        bench.iter(|| simd::magnitude(black_box(&a), &mut out))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("power");
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| scalar::power(black_box(&a), &mut out))
+    });
+    group.bench_function("simd", |bench| {
+        bench.iter(|| simd::power(black_box(&a), &mut out))
+    });
+    group.finish();
+
+    // Converting in place would feed dB back in, so every iteration starts from a fresh copy
+    let mut group = c.benchmark_group("to_db");
+    group.bench_function("scalar", |bench| {
+        bench.iter(|| {
+            out.copy_from_slice(&magnitudes);
+            scalar::to_db(black_box(&mut out), 1.0)
+        })
+    });
+    group.bench_function("simd", |bench| {
+        bench.iter(|| {
+            out.copy_from_slice(&magnitudes);
+            simd::to_db(black_box(&mut out), 1.0)
+        })
+    });
+    group.finish();
+}
+
+// The kernels in their callers, the naive DFT and a 10s spectrogram at 44.1kHz
+fn pipeline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pipeline");
+    for size in [256, 1024] {
+        let frame = noise(size);
+        group.bench_with_input(BenchmarkId::new("dft", size), &frame, |bench, frame| {
+            bench.iter(|| dft(black_box(frame)))
+        });
+    }
+
+    let samples = noise(441_000);
+    let single: Vec<f32> = samples.iter().map(|&x| x as f32).collect();
+    group.sample_size(20);
+    group.bench_function("spectrogram f64", |bench| {
+        bench.iter(|| Spectrogram::new(black_box(&samples), 44100, StftConfig::default()))
+    });
+    group.bench_function("spectrogram f32", |bench| {
+        bench.iter(|| Spectrogram::new(black_box(&single), 44100, StftConfig::default()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, kernels, pipeline);
+criterion_main!(benches);
@@ -1,9 +1,12 @@
 use std::{
     fs::File,
     io::{BufReader, Read},
+    ops::ControlFlow,
+    path::Path,
 };
 
-use crate::error::{Result, WavError};
+use crate::error::{Error, Result};
+use crate::float::Float;
 
 enum ChunkKind {
     Fmt,
@@ -24,6 +27,13 @@ pub struct WavFmt {
     pub bits_per_sample: u16,
 }
 
+impl WavFmt {
+    // Amplitude of a full scale sample, samples are signed integers so 32768 for 16 bits
+    pub fn full_scale(&self) -> f64 {
+        2f64.powi(self.bits_per_sample as i32 - 1)
+    }
+}
+
 pub struct WavConfig {
     wav_fmt: WavFmt,
     size: u32,
@@ -39,6 +49,19 @@ impl WavConfig {
     pub fn fmt(&self) -> &WavFmt {
         &self.wav_fmt
     }
+
+    pub fn bytes_per_sample(&self) -> u32 {
+        self.bytes_per_sample
+    }
+
+    pub fn samples_per_channel(&self) -> u32 {
+        self.samples / self.wav_fmt.channels as u32
+    }
+
+    // Exact duration in seconds, duration() rounds down to whole seconds
+    pub fn duration_secs(&self) -> f64 {
+        self.samples_per_channel() as f64 / self.wav_fmt.sample_rate as f64
+    }
 }
 
 // Helpers for reading WAVE file
@@ -49,6 +72,8 @@ pub trait ReaderExt {
 
     fn read_4_bytes(&mut self) -> Result<[u8; 4]>;
 
+    fn read_8_bytes(&mut self) -> Result<[u8; 8]>;
+
     fn read_le_u16(&mut self) -> Result<u16>;
 
     fn read_le_i16(&mut self) -> Result<i16>;
@@ -58,6 +83,8 @@ pub trait ReaderExt {
     fn read_le_u32(&mut self) -> Result<u32>;
 
     fn read_le_i32(&mut self) -> Result<i32>;
+
+    fn read_le_u64(&mut self) -> Result<u64>;
 }
 
 // Extends any reader with these helper implementations
@@ -83,6 +110,12 @@ where
         Ok(buf)
     }
 
+    fn read_8_bytes(&mut self) -> Result<[u8; 8]> {
+        let mut buf = [0u8; 8];
+        self.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
     // Heleper for reading an u16 bit little endian from buffer
     fn read_le_u16(&mut self) -> Result<u16> {
         let buf = self.read_2_bytes()?;
@@ -100,9 +133,9 @@ where
         let buf = self.read_3_bytes()?;
         if buf[2] & 0x80 == 0 {
             // 0x80 is equal to 10000000 in binary, use it as mask to extract the sign bit
-            return Ok(i32::from_le_bytes([buf[0], buf[1], buf[2], 0x00])); // signed two complement num, padd with 00000000 to stay positive
+            Ok(i32::from_le_bytes([buf[0], buf[1], buf[2], 0x00])) // signed two complement num, padd with 00000000 to stay positive
         } else {
-            return Ok(i32::from_le_bytes([buf[0], buf[1], buf[2], 0xFF])); // signed two complement num, padd with 11111111 to keep the sign
+            Ok(i32::from_le_bytes([buf[0], buf[1], buf[2], 0xFF])) // signed two complement num, padd with 11111111 to keep the sign
         }
     }
 
@@ -117,23 +150,37 @@ where
         let buf = self.read_4_bytes()?;
         Ok(i32::from_le_bytes(buf))
     }
+
+    // Heleper for reading an 64 bit little endian from buffer
+    fn read_le_u64(&mut self) -> Result<u64> {
+        let buf = self.read_8_bytes()?;
+        Ok(u64::from_le_bytes(buf))
+    }
 }
 
-pub struct WavReader {
-    reader: BufReader<File>,
+// Decodes WAVE data from any reader, files are opened with WavReader::open
+pub struct WavReader<R = BufReader<File>> {
+    reader: R,
     config: WavConfig,
 }
 
 impl WavReader {
-    pub fn new(reader: BufReader<File>) -> Result<Self> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        WavReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> WavReader<R> {
+    pub fn new(reader: R) -> Result<Self> {
         let mut reader = reader;
-        let (wav_fmt, size) = WavReader::read_until_data(&mut reader)?;
+        let (wav_fmt, size) = Self::read_until_data(&mut reader)?;
 
         let bytes_per_sample = (wav_fmt.bits_per_sample / 8) as u32;
         let samples = size / bytes_per_sample;
 
-        if samples % wav_fmt.channels as u32 != 0 {
-            return Err(WavError::Corrupted(
+        if !samples.is_multiple_of(wav_fmt.channels as u32) {
+            return Err(Error::Corrupted(
                 "Number of samples per channel must be equal",
             ));
         }
@@ -148,11 +195,11 @@ impl WavReader {
     }
 
     // Returns the file size
-    fn read_riff_header(reader: &mut BufReader<File>) -> Result<u64> {
+    fn read_riff_header(reader: &mut R) -> Result<u64> {
         // Read the chunk id RIFF header
         let riff_id = reader.read_4_bytes()?;
         if &riff_id != b"RIFF" {
-            return Err(WavError::InvalidFormat("RIFF header not present"));
+            return Err(Error::InvalidFormat("RIFF header not present"));
         }
 
         // Read the chuks size
@@ -161,7 +208,7 @@ impl WavReader {
         // Read the WAVE format
         let format = reader.read_4_bytes()?;
         if &format != b"WAVE" {
-            return Err(WavError::InvalidFormat("WAVE format not present"));
+            return Err(Error::InvalidFormat("WAVE format not present"));
         }
 
         // File size is chuks_size + the 8 bytes we already read
@@ -170,7 +217,7 @@ impl WavReader {
     }
 
     // Reads "data" and "fmt " chunk headers
-    fn read_chunk_header(reader: &mut BufReader<File>) -> Result<ChunkHeader> {
+    fn read_chunk_header(reader: &mut R) -> Result<ChunkHeader> {
         let kind = reader.read_4_bytes()?;
         let size = reader.read_le_u32()?;
         match &kind {
@@ -182,28 +229,26 @@ impl WavReader {
                 kind: ChunkKind::Data,
                 size,
             }),
-            _ => Err(WavError::InvalidFormat(
+            _ => Err(Error::InvalidFormat(
                 "Chunk header should be 'fmt ' or 'data'",
             )),
         }
     }
 
     // Reads the WAVE file fmt spec
-    fn read_fmt_subchunk(reader: &mut BufReader<File>, chunk_size: u32) -> Result<WavFmt> {
+    fn read_fmt_subchunk(reader: &mut R, chunk_size: u32) -> Result<WavFmt> {
         if chunk_size < 16 {
-            return Err(WavError::Corrupted("Invalid chunk size"));
+            return Err(Error::Corrupted("Invalid chunk size"));
         }
 
         let pcm = reader.read_le_u16()?;
         if pcm != 1 {
-            return Err(WavError::UnsupportedFormat(
-                "Unsupported compression format",
-            ));
+            return Err(Error::UnsupportedFormat("Unsupported compression format"));
         }
 
         let channels = reader.read_le_u16()?;
         if channels == 0 {
-            return Err(WavError::Corrupted("Channels cannot be 0"));
+            return Err(Error::Corrupted("Channels cannot be 0"));
         }
 
         let sample_rate = reader.read_le_u32()?;
@@ -213,12 +258,12 @@ impl WavReader {
 
         let expected_byte_rate = sample_rate * channels as u32 * bits_per_sample as u32 / 8;
         if expected_byte_rate != byte_rate {
-            return Err(WavError::Corrupted("Invalid byte rate"));
+            return Err(Error::Corrupted("Invalid byte rate"));
         }
 
         let expected_block_align = channels * bits_per_sample / 8;
         if expected_block_align != block_align {
-            return Err(WavError::Corrupted("Invalid block align"));
+            return Err(Error::Corrupted("Invalid block align"));
         }
 
         Ok(WavFmt {
@@ -231,24 +276,22 @@ impl WavReader {
     }
 
     // Reads all chunks up until the actual audi data samples
-    fn read_until_data(reader: &mut BufReader<File>) -> Result<(WavFmt, u32)> {
-        let size = WavReader::read_riff_header(reader)?;
-
-        println!("WAVE file opened, size: {}", size);
+    fn read_until_data(reader: &mut R) -> Result<(WavFmt, u32)> {
+        Self::read_riff_header(reader)?;
 
         let mut wav_fmt = None;
         loop {
-            let chunk_header = WavReader::read_chunk_header(reader)?;
+            let chunk_header = Self::read_chunk_header(reader)?;
             match chunk_header.kind {
                 ChunkKind::Fmt => {
-                    wav_fmt = Some(WavReader::read_fmt_subchunk(reader, chunk_header.size)?);
+                    wav_fmt = Some(Self::read_fmt_subchunk(reader, chunk_header.size)?);
                 }
                 ChunkKind::Data => {
                     let data_size = chunk_header.size;
                     if let Some(wav) = wav_fmt {
                         return Ok((wav, data_size));
                     } else {
-                        return Err(WavError::InvalidFormat("WAVE \"fmt \" not present"));
+                        return Err(Error::InvalidFormat("WAVE \"fmt \" not present"));
                     }
                 } // Read until data, read it on demand later
             };
@@ -256,35 +299,64 @@ impl WavReader {
     }
 
     pub fn mono(&mut self) -> Result<Vec<f64>> {
-        let data_size: usize = match self.config.samples.try_into() {
+        self.mono_as()
+    }
+
+    // Downmix in f32 or f64, the frame is averaged in f64 before it gets rounded
+    //
+    // f32 keeps 24 bits of mantissa, the raw values of 16 and 24 bit files are exact and 32 bit ones lose the lowest bits
+    pub fn mono_as<T: Float>(&mut self) -> Result<Vec<T>> {
+        let data_size: usize = match self.config.samples_per_channel().try_into() {
             Ok(val) => val,
             Err(_) => {
-                return Err(WavError::UnsupportedFormat("Too many samples to process"));
+                return Err(Error::UnsupportedFormat("Too many samples to process"));
             }
         };
 
-        let channels = self.config.wav_fmt.channels;
-        let mut data = Vec::with_capacity(data_size / channels as usize);
-        for _ in 0..data.capacity() {
-            let mut channel_sum: f64 = 0.0;
-            for _ in 0..channels {
-                let sample = match self.config.wav_fmt.bits_per_sample {
+        let mut data = Vec::with_capacity(data_size);
+        self.for_each_frame(|frame| {
+            // Average the amplitude to avoid clipping
+            data.push(T::cast(frame.iter().sum::<f64>() / frame.len() as f64));
+        })?;
+
+        Ok(data)
+    }
+
+    // Streams the data one frame at a time, a frame holds one sample of every channel
+    //
+    // Nothing but the current frame is kept in memory, so this works on files of any length
+    pub fn for_each_frame(&mut self, mut f: impl FnMut(&[f64])) -> Result<()> {
+        self.try_for_each_frame(|frame| {
+            f(frame);
+            ControlFlow::Continue(())
+        })
+    }
+
+    // Same as for_each_frame but stops reading as soon as the callback breaks, the rest of the data is never decoded
+    pub fn try_for_each_frame(
+        &mut self,
+        mut f: impl FnMut(&[f64]) -> ControlFlow<()>,
+    ) -> Result<()> {
+        let bits_per_sample = self.config.wav_fmt.bits_per_sample;
+        if ![16, 24, 32].contains(&bits_per_sample) {
+            return Err(Error::UnsupportedFormat("Sample size not supported"));
+        }
+
+        let mut frame = vec![0.0; self.config.wav_fmt.channels as usize];
+        for _ in 0..self.config.samples_per_channel() {
+            for sample in frame.iter_mut() {
+                *sample = match bits_per_sample {
                     16 => self.reader.read_le_i16()? as f64, // TODO: cast all to f64 for now
                     24 => self.reader.read_le_i24()? as f64,
-                    32 => self.reader.read_le_i32()? as f64,
-                    _ => {
-                        return Err(WavError::UnsupportedFormat("Sample size not supported"));
-                    }
+                    _ => self.reader.read_le_i32()? as f64,
                 };
-
-                channel_sum += sample;
             }
-
-            // Average the amplitude to avoid clipping
-            data.push(channel_sum / channels as f64);
+            if f(&frame).is_break() {
+                break;
+            }
         }
 
-        Ok(data)
+        Ok(())
     }
 
     pub fn config(&self) -> &WavConfig {
@@ -298,7 +370,31 @@ impl WavReader {
 
 #[cfg(test)]
 mod tests {
-    use super::ReaderExt;
+    use super::{ReaderExt, WavReader};
+    use crate::error::Error;
+    use std::ops::ControlFlow;
+
+    // Minimal 16 bit PCM WAVE file
+    fn wav_bytes(channels: u16, sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+        let data_size = (samples.len() * 2) as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+        bytes.extend_from_slice(&(channels * 2).to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        bytes
+    }
 
     #[test]
     fn test_read_2_bytes() {
@@ -378,4 +474,63 @@ mod tests {
         let result = cursor.read_le_i32().unwrap();
         assert_eq!(result, -2147483648);
     }
+
+    #[test]
+    fn test_read_le_u64() {
+        let data = vec![0xEF, 0xCD, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x01]; // 0x0123456789ABCDEF little endian
+        let mut cursor = std::io::Cursor::new(data);
+        let result = cursor.read_le_u64().unwrap();
+        assert_eq!(result, 0x0123456789ABCDEF);
+    }
+
+    #[test]
+    fn test_wav_reader_from_memory() {
+        let bytes = wav_bytes(2, 8000, &[100, 300, -200, -400, 0, 10]);
+        let mut wav_reader = WavReader::new(std::io::Cursor::new(bytes)).unwrap();
+
+        let config = wav_reader.config();
+        assert_eq!(config.fmt().channels, 2);
+        assert_eq!(config.fmt().sample_rate, 8000);
+        assert_eq!(config.samples_per_channel(), 3);
+        assert_eq!(wav_reader.mono().unwrap(), vec![200.0, -300.0, 5.0]);
+    }
+
+    #[test]
+    fn test_for_each_frame() {
+        let bytes = wav_bytes(2, 8000, &[100, 300, -200, -400, 0, 10]);
+        let mut wav_reader = WavReader::new(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(wav_reader.config().fmt().full_scale(), 32768.0);
+
+        let mut frames = Vec::new();
+        wav_reader
+            .for_each_frame(|frame| frames.push(frame.to_vec()))
+            .unwrap();
+        assert_eq!(
+            frames,
+            vec![vec![100.0, 300.0], vec![-200.0, -400.0], vec![0.0, 10.0]]
+        );
+
+        let bytes = wav_bytes(1, 8000, &[1, 2, 3, 4, 5]);
+        let mut wav_reader = WavReader::new(std::io::Cursor::new(bytes)).unwrap();
+        let mut samples = Vec::new();
+        wav_reader
+            .try_for_each_frame(|frame| {
+                samples.push(frame[0]);
+                if samples.len() == 2 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .unwrap();
+        assert_eq!(samples, vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_wav_reader_rejects_other_formats() {
+        let mut bytes = wav_bytes(1, 8000, &[0, 0]);
+        bytes[8..12].copy_from_slice(b"AVI ");
+        let result = WavReader::new(std::io::Cursor::new(bytes));
+        assert!(matches!(result, Err(Error::InvalidFormat(_))));
+    }
 }
@@ -0,0 +1,301 @@
+use std::f64::consts::PI;
+
+use crate::error::{Error, Result};
+use crate::float::Float;
+use crate::key::{KeyEstimate, estimate_key};
+use crate::note::frequency_to_midi;
+use crate::spectrogram::{Spectrogram, StftConfig};
+use crate::spectrum::{Interpolation, refine_peak};
+use crate::window::Window;
+
+// Peaks below this fraction of the loudest bin of the frame do not count for the tuning
+const TUNING_PEAK_THRESHOLD: f64 = 0.1;
+
+// Chroma settings, the long default frames resolve semitones down to the bass
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChromaConfig {
+    pub stft: StftConfig,
+    // Bins outside the range are left out, the lowest bass notes smear over several semitones
+    pub min_frequency: f64,
+    pub max_frequency: f64,
+    // Offset of the recording from A440 in cents, estimated from the spectral peaks when not set
+    pub tuning: Option<f64>,
+}
+
+impl Default for ChromaConfig {
+    fn default() -> Self {
+        ChromaConfig {
+            stft: StftConfig {
+                window_size: 8192,
+                hop_size: 4096,
+                fft_size: 8192,
+                window: Window::Hann,
+            },
+            min_frequency: 65.0,
+            max_frequency: 2100.0,
+            tuning: None,
+        }
+    }
+}
+
+impl ChromaConfig {
+    pub fn chroma<T: Float>(&self, samples: &[T], sample_rate: u32) -> Result<Chroma> {
+        Chroma::new(&Spectrogram::new(samples, sample_rate, self.stft), self)
+    }
+}
+
+// Pitch class profiles, the energy of every frame folded into the 12 semitones of one octave
+//
+// Each bin adds its power to the equal tempered semitone nearest to it after the tuning offset, frames are scaled
+// so their strongest pitch class is 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chroma {
+    frames: Vec<[f64; 12]>,
+    tuning: f64,
+    frames_per_second: f64,
+}
+
+impl Chroma {
+    // Only the frequency range and the tuning of the config are used, the STFT is the one of the spectrogram
+    pub fn new<T: Float>(spectrogram: &Spectrogram<T>, config: &ChromaConfig) -> Result<Self> {
+        if !(config.min_frequency > 0.0 && config.min_frequency < config.max_frequency) {
+            return Err(Error::InvalidArgument(
+                "chroma needs 0 < min frequency < max frequency".to_string(),
+            ));
+        }
+
+        let tuning = match config.tuning {
+            Some(tuning) => tuning,
+            None => estimate_tuning(spectrogram, config.min_frequency, config.max_frequency),
+        };
+
+        // Pitch class of every bin in range, computed once
+        let pitch_classes: Vec<Option<usize>> = (0..spectrogram.num_bins())
+            .map(|bin| {
+                let frequency = spectrogram.bin_frequency(bin as f64);
+                if frequency < config.min_frequency || frequency > config.max_frequency {
+                    return None;
+                }
+                let midi = frequency_to_midi(frequency) - tuning / 100.0;
+                Some((midi.round() as i64).rem_euclid(12) as usize)
+            })
+            .collect();
+
+        let frames = spectrogram
+            .frames()
+            .iter()
+            .map(|frame| {
+                let mut chroma = [0.0; 12];
+                for (magnitude, pitch_class) in frame.iter().zip(&pitch_classes) {
+                    if let Some(pitch_class) = pitch_class {
+                        chroma[*pitch_class] += magnitude.as_f64() * magnitude.as_f64();
+                    }
+                }
+                let max = chroma.iter().copied().fold(0.0, f64::max);
+                if max > 0.0 {
+                    chroma.iter_mut().for_each(|c| *c /= max);
+                }
+                chroma
+            })
+            .collect();
+
+        Ok(Chroma {
+            frames,
+            tuning,
+            frames_per_second: spectrogram.frames_per_second(),
+        })
+    }
+
+    // Pitch classes from C to B per frame, all 0 in silent frames
+    pub fn frames(&self) -> &[[f64; 12]] {
+        &self.frames
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    // Offset from A440 in cents that the semitones were centred on
+    pub fn tuning(&self) -> f64 {
+        self.tuning
+    }
+
+    // Start of the frame in seconds
+    pub fn frame_time(&self, frame: usize) -> f64 {
+        frame as f64 / self.frames_per_second
+    }
+
+    // Average of the frames, every frame counts the same no matter how loud
+    pub fn profile(&self) -> [f64; 12] {
+        let mut profile = [0.0; 12];
+        for frame in &self.frames {
+            profile.iter_mut().zip(frame).for_each(|(p, c)| *p += c);
+        }
+        let count = self.frames.len().max(1) as f64;
+        profile.map(|p| p / count)
+    }
+
+    // Key of the whole profile, None for silence
+    pub fn key(&self) -> Option<KeyEstimate> {
+        estimate_key(&self.profile())
+    }
+}
+
+// Offset of the recording from A440 in cents, -50 to 50
+//
+// Refines the spectral peaks of every frame to a fractional frequency and averages how far they are off the nearest
+// semitone, weighted by their magnitude. The deviations are averaged as angles on a circle so that peaks at +49 and
+// -49 cents average to 50 instead of 0
+pub fn estimate_tuning<T: Float>(
+    spectrogram: &Spectrogram<T>,
+    min_frequency: f64,
+    max_frequency: f64,
+) -> f64 {
+    let low = spectrogram.frequency_bin(min_frequency).floor().max(1.0) as usize;
+    let high = (spectrogram.frequency_bin(max_frequency).ceil() as usize)
+        .min(spectrogram.num_bins().saturating_sub(1));
+
+    let (mut sin, mut cos) = (0.0, 0.0);
+    let mut magnitudes = Vec::new();
+    for frame in spectrogram.frames() {
+        magnitudes.clear();
+        magnitudes.extend(frame.iter().map(|m| m.as_f64()));
+        let threshold = magnitudes.iter().copied().fold(0.0, f64::max) * TUNING_PEAK_THRESHOLD;
+
+        for bin in low..high {
+            let magnitude = magnitudes[bin];
+            if magnitude <= threshold
+                || magnitude < magnitudes[bin - 1]
+                || magnitude <= magnitudes[bin + 1]
+            {
+                continue;
+            }
+
+            let peak = refine_peak(&magnitudes, bin, Interpolation::Gaussian);
+            let midi = frequency_to_midi(spectrogram.bin_frequency(peak.bin));
+            let angle = 2.0 * PI * (midi - midi.round());
+            sin += peak.magnitude * angle.sin();
+            cos += peak.magnitude * angle.cos();
+        }
+    }
+
+    if sin == 0.0 && cos == 0.0 {
+        return 0.0;
+    }
+    sin.atan2(cos) / (2.0 * PI) * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{Key, Mode};
+    use crate::note::midi_to_frequency;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    // Sum of sines at the MIDI notes, detuned by cents, one second per chord
+    fn chords(chords: &[&[i32]], cents: f64) -> Vec<f64> {
+        let mut samples = Vec::new();
+        for notes in chords {
+            samples.extend((0..SAMPLE_RATE).map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                notes
+                    .iter()
+                    .map(|&note| {
+                        let frequency = midi_to_frequency(note as f64 + cents / 100.0);
+                        (2.0 * PI * frequency * t).sin()
+                    })
+                    .sum::<f64>()
+                    / notes.len() as f64
+            }));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_chroma_of_triad() {
+        // C major triad, C4 E4 G4
+        let samples = chords(&[&[60, 64, 67]], 0.0);
+        let chroma = ChromaConfig::default()
+            .chroma(&samples, SAMPLE_RATE)
+            .unwrap();
+        assert!(chroma.num_frames() > 2);
+
+        let frame = chroma.frames()[1];
+        for (pitch_class, value) in frame.iter().enumerate() {
+            if [0, 4, 7].contains(&pitch_class) {
+                assert!(*value > 0.5, "{}: {}", pitch_class, value);
+            } else {
+                assert!(*value < 0.05, "{}: {}", pitch_class, value);
+            }
+        }
+        assert!(chroma.tuning().abs() < 3.0);
+        assert_eq!(chroma.frame_time(2), 2.0 * 4096.0 / SAMPLE_RATE as f64);
+    }
+
+    #[test]
+    fn test_tuning() {
+        for cents in [-35.0, -12.0, 20.0, 40.0] {
+            let samples = chords(&[&[57, 61, 64], &[62, 66, 69]], cents);
+            let chroma = ChromaConfig::default()
+                .chroma(&samples, SAMPLE_RATE)
+                .unwrap();
+            assert!(
+                (chroma.tuning() - cents).abs() < 3.0,
+                "{} != {}",
+                chroma.tuning(),
+                cents
+            );
+
+            // Every note lands in its own pitch class once the tuning is known
+            let profile = chroma.profile();
+            for pitch_class in [9, 1, 4, 2, 6] {
+                assert!(profile[pitch_class] > 0.3, "{:?}", profile);
+            }
+        }
+
+        // A given tuning is used as it is
+        let config = ChromaConfig {
+            tuning: Some(10.0),
+            ..ChromaConfig::default()
+        };
+        let samples = chords(&[&[69]], 0.0);
+        assert_eq!(config.chroma(&samples, SAMPLE_RATE).unwrap().tuning(), 10.0);
+    }
+
+    #[test]
+    fn test_key_of_progression() {
+        // I IV V I in G major
+        let g_major: &[&[i32]] = &[&[55, 59, 62], &[60, 64, 67], &[62, 66, 69], &[55, 59, 62]];
+        let chroma = ChromaConfig::default()
+            .chroma(&chords(g_major, 0.0), SAMPLE_RATE)
+            .unwrap();
+        let estimate = chroma.key().unwrap();
+        assert_eq!(estimate.key, Key::new(7, Mode::Major));
+        assert_eq!(estimate.key.camelot(), "9B");
+
+        // i iv V i in E minor, 20 cents flat
+        let e_minor: &[&[i32]] = &[&[52, 55, 59], &[57, 60, 64], &[59, 63, 66], &[52, 55, 59]];
+        let chroma = ChromaConfig::default()
+            .chroma(&chords(e_minor, -20.0), SAMPLE_RATE)
+            .unwrap();
+        assert_eq!(chroma.key().unwrap().key, Key::new(4, Mode::Minor));
+    }
+
+    #[test]
+    fn test_silence() {
+        let chroma = ChromaConfig::default()
+            .chroma(&vec![0.0; SAMPLE_RATE as usize], SAMPLE_RATE)
+            .unwrap();
+        assert!(chroma.frames().iter().all(|f| f.iter().all(|&c| c == 0.0)));
+        assert_eq!(chroma.tuning(), 0.0);
+        assert_eq!(chroma.key(), None);
+
+        let config = ChromaConfig {
+            min_frequency: 1000.0,
+            max_frequency: 100.0,
+            ..ChromaConfig::default()
+        };
+        assert!(config.chroma(&[0.0; 16], SAMPLE_RATE).is_err());
+    }
+}
@@ -0,0 +1,1282 @@
+use std::path::PathBuf;
+
+use earworm::chroma::ChromaConfig;
+use earworm::colormap::ColorMap;
+use earworm::cqt::CqtConfig;
+use earworm::error::{Error, Result};
+use earworm::mel::MelConfig;
+use earworm::mfcc::MfccConfig;
+use earworm::onset::OnsetConfig;
+use earworm::pitch::PitchConfig;
+use earworm::spectrogram::{FrequencyScale, StftConfig};
+use earworm::spectrum::padded_size;
+use earworm::tempo::TempoConfig;
+use earworm::terminal::Glyphs;
+use earworm::waveform::Lanes;
+use earworm::window::Window;
+
+pub const USAGE: &str = "Usage: earworm <command> [options]
+
+Commands:
+  info <file>                      Print the WAV header of the file
+  spectrum <file> --at <seconds>   Plot the spectrum of a single frame
+  spectrogram <file>               Render the spectrogram as an image, or a CSV of the magnitudes
+  waveform <file>                  Render the min/max and RMS envelope of the samples
+  mel <file>                       Write the log-mel spectrogram as a CSV of dB per band
+  mfcc <file>                      Write the MFCCs and their deltas as a CSV, takes the mel options too
+  key <file>                       Estimate the tuning and the key in standard and Camelot notation
+  tempo <file>                     Detect the onsets and estimate the tempo and the beat grid
+  pitch <file>                     Write the fundamental frequency and note of every frame as a CSV
+  fingerprint <file>               Print the fingerprint hashes of the file
+  index <dir> --db <path>          Add every .wav file in the directory to the database, tagged with its key
+  query <file> --db <path>         Find the file in the database
+  verify --db <path>               Check every posting of the database against its checksum
+
+Analysis options:
+  --window-size <samples>          Frame size, default 1024
+  --hop <samples>                  Samples between frames, default half of the window size
+  --window <name>                  hann, hamming, blackman, blackman-harris, flat-top, rectangular,
+                                   kaiser:<beta>, tukey:<alpha>, gaussian:<sigma> or trapezoid:<slope>
+  --padding <factor>               Zero pad frames to factor times the window size, default 1
+  --f32                            Analyse in single precision, half the memory on long files
+
+Spectrum options:
+  --scale <scale>                  Frequency axis, linear, log or mel, default linear
+  --range <dB>                     Levels shown below 0dBFS, default 120
+  --labels <count>                 Label the strongest peaks with their frequency and note, default 5
+
+Spectrogram options:
+  --scale <scale>                  Frequency axis, linear, log or mel, default linear
+  --colormap <name>                viridis, magma, inferno or grayscale, default viridis
+  --range <dB>                     Dynamic range below the loudest bin, default 80
+  --min-freq <Hz>                  Lowest frequency shown
+  --max-freq <Hz>                  Highest frequency shown, default Nyquist
+  --peaks                          Mark the fingerprint peaks
+  --pairs                          Mark the fingerprint peaks and the pairs hashed from them
+  --cqt                            Constant-Q transform instead of the STFT, on a log scale unless --scale
+                                   is given. --min-freq and --max-freq set the range of its bins,
+                                   --hop and --window its frames, default from 32.7Hz (C1) up
+  --bins-per-octave <count>        Bins of the constant-Q transform per octave, default 24
+
+Waveform options:
+  --lanes <lanes>                  downmix or channels for one lane per channel, default downmix
+  --silence <dBFS>                 Mark stretches where the peak level stays below the threshold
+  --min-silence <seconds>          Shortest silence marked, default 0.5
+  --match <clip>                   Mark where the clip matches the file
+
+Mel options:
+  --bands <count>                  Mel bands, default 128
+  --min-freq <Hz>                  Lower edge of the lowest band, default 0
+  --max-freq <Hz>                  Upper edge of the highest band, default Nyquist
+  --mel-scale <scale>              slaney or htk, default slaney
+  --no-norm                        Leave the triangles at a height of 1 instead of an area of 1
+
+MFCC options:
+  --coefficients <count>           Cepstral coefficients per frame, default 20
+  --lifter <L>                     Sine lifter of the coefficients, 22 in HTK, default 0 for none
+  --delta-window <frames>          Frames on each side of the deltas, default 2
+
+Key options:
+  --window-size <samples>          Chroma frame size, default 8192
+  --hop <samples>                  Samples between chroma frames, default half of the window size
+  --min-freq <Hz>                  Lowest frequency counted, default 65
+  --max-freq <Hz>                  Highest frequency counted, default 2100
+  --tuning <cents>                 Offset from A440, estimated when not given
+  --output <path>                  Also write the chroma of every frame as a CSV
+
+Tempo options:
+  --window-size <samples>          Onset frame size, default 1024
+  --hop <samples>                  Samples between onset frames, default a quarter of the window size
+  --onset-function <name>          flux or complex, default flux
+  --delta <value>                  How far an onset rises above the moving median, 0 to 1, default 0.07
+  --min-bpm <BPM>                  Slowest tempo considered, default 60
+  --max-bpm <BPM>                  Fastest tempo considered, default 200
+  --output <path>                  Also write the onsets and the beats as a CSV
+
+Pitch options:
+  --method <method>                yin or pyin, which also decides voiced or not from the frames around,
+                                   default yin
+  --window-size <samples>          Samples compared with the shifted copy, default 1024
+  --hop <samples>                  Samples between frames, default a quarter of the window size
+  --min-freq <Hz>                  Lowest pitch, default 40
+  --max-freq <Hz>                  Highest pitch, default 2000
+  --threshold <value>              How far the difference has to dip for YIN to call a frame voiced,
+                                   0 to 1, default 0.1
+
+Query options:
+  --min-matches <count>            Aligned hashes needed for a query or --match match, default 5
+  --plot <path>                    Plot the query time against the track time of the best match
+
+Image options:
+  --width <pixels>                 Width of the plot
+  --height <pixels>                Height of the plot
+  --dpi <dots>                     Resolution, scales text and lines, default 96
+
+Terminal options:
+  --terminal                       Draw spectrum or spectrogram as text instead of an image,
+                                   --width and --height count characters and lines
+  --glyphs <glyphs>                blocks or braille, default blocks
+  --no-color                       Leave out the ANSI colours, also when NO_COLOR is set
+
+Other options:
+  --output <path>                  Output file of spectrum, spectrogram, waveform, mel, mfcc, pitch and fingerprint,
+                                   plots ending in .svg are SVG, otherwise PNG,
+                                   a spectrogram ending in .csv is written as text
+  --naive                          Use the naive DFT instead of the FFT for spectrum";
+
+// Frequency resolution
+// For a 44100 sample rate file and 1024 samples per window, frequency_resolution = 44100 / 1024 ~ 43Hz
+// Which means each freq bin coresponds to a 43Hz range
+// For 1024 samples in a window there are 512 bins since n of samples give n/2 bins
+// Larger window size gives us a greater frequency resolution
+//
+// Time resoulution
+// For a 44100 sample rate file and 1024 samples per window, time_resolution = 1024 / 44100 ~ 23ms
+// Which means we get an update every 23ms
+// Smaller window size gives us greater time resolution
+//
+// Greater time resolution gives us more updates per second hence it is better for fast changing audio like speach or drums
+// Greater frequency resolution gives a more precise frequency response for better pitch accuracy and slow signals
+const DEFAULT_WINDOW_SIZE: usize = 1024;
+const DEFAULT_MIN_MATCHES: usize = 5;
+const DEFAULT_DYNAMIC_RANGE: f64 = 80.0;
+const DEFAULT_SPECTRUM_RANGE: f64 = 120.0;
+const DEFAULT_LABELS: usize = 5;
+const DEFAULT_MIN_SILENCE: f64 = 0.5;
+
+// Options without a value
+const FLAGS: [&str; 9] = [
+    "naive", "help", "peaks", "pairs", "terminal", "no-color", "f32", "no-norm", "cqt",
+];
+
+// How the spectrum plot looks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Graph {
+    pub scale: FrequencyScale,
+    pub dynamic_range: f64,
+    pub labels: usize,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Graph {
+            scale: FrequencyScale::Linear,
+            dynamic_range: DEFAULT_SPECTRUM_RANGE,
+            labels: DEFAULT_LABELS,
+        }
+    }
+}
+
+// How the spectrogram image looks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Heatmap {
+    pub scale: FrequencyScale,
+    pub color_map: ColorMap,
+    pub dynamic_range: f64,
+    pub min_frequency: Option<f64>,
+    pub max_frequency: Option<f64>,
+    // Constellation overlay
+    pub peaks: bool,
+    pub pairs: bool,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap {
+            scale: FrequencyScale::Linear,
+            color_map: ColorMap::Viridis,
+            dynamic_range: DEFAULT_DYNAMIC_RANGE,
+            min_frequency: None,
+            max_frequency: None,
+            peaks: false,
+            pairs: false,
+        }
+    }
+}
+
+// What gets marked on the waveform
+#[derive(Debug, Clone, PartialEq)]
+pub struct Markers {
+    // Threshold in dBFS
+    pub silence: Option<f64>,
+    // Seconds
+    pub min_silence: f64,
+    pub clip: Option<PathBuf>,
+    pub min_matches: usize,
+}
+
+impl Default for Markers {
+    fn default() -> Self {
+        Markers {
+            silence: None,
+            min_silence: DEFAULT_MIN_SILENCE,
+            clip: None,
+            min_matches: DEFAULT_MIN_MATCHES,
+        }
+    }
+}
+
+// Text output for the terminal instead of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Terminal {
+    pub glyphs: Glyphs,
+    pub no_color: bool,
+}
+
+// Size of a plot, None leaves it to the plot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Image {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub dpi: Option<u32>,
+}
+
+// How the frames are cut and windowed, shared by every command working on spectra
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Analysis {
+    pub window_size: usize,
+    pub hop_size: usize,
+    pub window: Window,
+    pub padding: usize,
+    // Samples and spectra in f32 for spectrogram and fingerprinting
+    pub single_precision: bool,
+}
+
+impl Default for Analysis {
+    fn default() -> Self {
+        Analysis {
+            window_size: DEFAULT_WINDOW_SIZE,
+            hop_size: DEFAULT_WINDOW_SIZE / 2,
+            window: Window::Hann,
+            padding: 1,
+            single_precision: false,
+        }
+    }
+}
+
+impl Analysis {
+    pub fn fft_size(&self) -> usize {
+        if self.padding <= 1 {
+            self.window_size
+        } else {
+            padded_size(self.window_size, self.padding)
+        }
+    }
+
+    pub fn stft(&self) -> StftConfig {
+        StftConfig {
+            window_size: self.window_size,
+            hop_size: self.hop_size,
+            fft_size: self.fft_size(),
+            window: self.window,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Info {
+        file: PathBuf,
+    },
+    Spectrum {
+        file: PathBuf,
+        // Start of the frame in seconds
+        at: f64,
+        analysis: Analysis,
+        graph: Graph,
+        output: PathBuf,
+        image: Image,
+        terminal: Option<Terminal>,
+        naive: bool,
+    },
+    Spectrogram {
+        file: PathBuf,
+        analysis: Analysis,
+        heatmap: Heatmap,
+        // Constant-Q bins instead of the STFT
+        cqt: Option<CqtConfig>,
+        output: PathBuf,
+        image: Image,
+        terminal: Option<Terminal>,
+    },
+    Waveform {
+        file: PathBuf,
+        lanes: Lanes,
+        markers: Markers,
+        // Fingerprinting the file and the clip of --match
+        analysis: Analysis,
+        output: PathBuf,
+        image: Image,
+    },
+    Mel {
+        file: PathBuf,
+        analysis: Analysis,
+        mel: MelConfig,
+        output: PathBuf,
+    },
+    Mfcc {
+        file: PathBuf,
+        analysis: Analysis,
+        mel: MelConfig,
+        mfcc: MfccConfig,
+        output: PathBuf,
+    },
+    Key {
+        file: PathBuf,
+        chroma: ChromaConfig,
+        single_precision: bool,
+        output: Option<PathBuf>,
+    },
+    Tempo {
+        file: PathBuf,
+        onsets: OnsetConfig,
+        tempo: TempoConfig,
+        single_precision: bool,
+        output: Option<PathBuf>,
+    },
+    Pitch {
+        file: PathBuf,
+        pitch: PitchConfig,
+        output: PathBuf,
+    },
+    Fingerprint {
+        file: PathBuf,
+        analysis: Analysis,
+        output: Option<PathBuf>,
+    },
+    Index {
+        dir: PathBuf,
+        db: PathBuf,
+        analysis: Analysis,
+    },
+    Query {
+        file: PathBuf,
+        db: PathBuf,
+        analysis: Analysis,
+        min_matches: usize,
+        plot: Option<PathBuf>,
+        image: Image,
+    },
+    Verify {
+        db: PathBuf,
+    },
+    Help,
+}
+
+// Parses the arguments after the program name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut args = Args::new(args)?;
+    if args.flag("help") {
+        return Ok(Command::Help);
+    }
+
+    let Some(name) = args.next_positional() else {
+        return Ok(Command::Help);
+    };
+
+    let command = match name.as_str() {
+        "help" => Command::Help,
+        "info" => Command::Info { file: args.file()? },
+        "spectrum" => {
+            let file = args.file()?;
+            let at: f64 = args
+                .parsed("at")?
+                .ok_or_else(|| missing("--at <seconds>"))?;
+            if !(at.is_finite() && at >= 0.0) {
+                return Err(Error::InvalidArgument(
+                    "--at has to be a time from 0 seconds on".to_string(),
+                ));
+            }
+
+            Command::Spectrum {
+                file,
+                at,
+                analysis: args.analysis()?,
+                graph: args.graph()?,
+                output: args.path("output")?.unwrap_or("spectrum.png".into()),
+                image: args.image()?,
+                terminal: args.terminal()?,
+                naive: args.flag("naive"),
+            }
+        }
+        "spectrogram" => {
+            let file = args.file()?;
+            let analysis = args.analysis()?;
+            let log_scale = !args.has("scale");
+            let mut heatmap = args.heatmap()?;
+            let cqt = args.cqt(&analysis, &heatmap)?;
+            // Constant-Q bins are evenly spaced on a log axis
+            if cqt.is_some() && log_scale {
+                heatmap.scale = FrequencyScale::Log;
+            }
+
+            Command::Spectrogram {
+                file,
+                analysis,
+                heatmap,
+                cqt,
+                output: args.path("output")?.unwrap_or("spectrogram.png".into()),
+                image: args.image()?,
+                terminal: args.terminal()?,
+            }
+        }
+        "waveform" => Command::Waveform {
+            file: args.file()?,
+            lanes: args.parsed("lanes")?.unwrap_or_default(),
+            markers: args.markers()?,
+            analysis: args.analysis()?,
+            output: args.path("output")?.unwrap_or("waveform.png".into()),
+            image: args.image()?,
+        },
+        "mel" => Command::Mel {
+            file: args.file()?,
+            analysis: args.analysis()?,
+            mel: args.mel()?,
+            output: args.path("output")?.unwrap_or("mel.csv".into()),
+        },
+        "mfcc" => Command::Mfcc {
+            file: args.file()?,
+            analysis: args.analysis()?,
+            mel: args.mel()?,
+            mfcc: args.mfcc()?,
+            output: args.path("output")?.unwrap_or("mfcc.csv".into()),
+        },
+        "key" => Command::Key {
+            file: args.file()?,
+            chroma: args.chroma()?,
+            single_precision: args.flag("f32"),
+            output: args.path("output")?,
+        },
+        "tempo" => Command::Tempo {
+            file: args.file()?,
+            onsets: args.onsets()?,
+            tempo: args.tempo()?,
+            single_precision: args.flag("f32"),
+            output: args.path("output")?,
+        },
+        "pitch" => Command::Pitch {
+            file: args.file()?,
+            pitch: args.pitch()?,
+            output: args.path("output")?.unwrap_or("pitch.csv".into()),
+        },
+        "fingerprint" => Command::Fingerprint {
+            file: args.file()?,
+            analysis: args.analysis()?,
+            output: args.path("output")?,
+        },
+        "index" => Command::Index {
+            dir: args
+                .next_positional()
+                .map(PathBuf::from)
+                .ok_or_else(|| missing("<dir>"))?,
+            db: args.path("db")?.ok_or_else(|| missing("--db <path>"))?,
+            analysis: args.analysis()?,
+        },
+        "query" => Command::Query {
+            file: args.file()?,
+            db: args.path("db")?.ok_or_else(|| missing("--db <path>"))?,
+            analysis: args.analysis()?,
+            min_matches: args.parsed("min-matches")?.unwrap_or(DEFAULT_MIN_MATCHES),
+            plot: args.path("plot")?,
+            image: args.image()?,
+        },
+        "verify" => Command::Verify {
+            db: args.path("db")?.ok_or_else(|| missing("--db <path>"))?,
+        },
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "unknown command '{}'",
+                name
+            )));
+        }
+    };
+
+    args.finish()?;
+    Ok(command)
+}
+
+fn missing(what: &str) -> Error {
+    Error::InvalidArgument(format!("missing {}", what))
+}
+
+// Arguments split into positionals and --name value options, consumed by the command being parsed
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    fn new(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let Some(option) = arg.strip_prefix("--") else {
+                positional.push(arg);
+                continue;
+            };
+
+            // Both --name value and --name=value are accepted
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None if FLAGS.contains(&option) => (option.to_string(), None),
+                None => {
+                    let value = args.next().ok_or_else(|| {
+                        Error::InvalidArgument(format!("--{} needs a value", option))
+                    })?;
+                    (option.to_string(), Some(value))
+                }
+            };
+            options.push((name, value));
+        }
+
+        // Positionals are popped from the back
+        positional.reverse();
+        Ok(Args {
+            positional,
+            options,
+        })
+    }
+
+    fn next_positional(&mut self) -> Option<String> {
+        self.positional.pop()
+    }
+
+    fn file(&mut self) -> Result<PathBuf> {
+        self.next_positional()
+            .map(PathBuf::from)
+            .ok_or_else(|| missing("<file>"))
+    }
+
+    fn take(&mut self, name: &str) -> Option<Option<String>> {
+        let index = self.options.iter().position(|(n, _)| n == name)?;
+        Some(self.options.remove(index).1)
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        self.take(name).is_some()
+    }
+
+    // Whether the option was given, without taking it
+    fn has(&self, name: &str) -> bool {
+        self.options.iter().any(|(n, _)| n == name)
+    }
+
+    fn value(&mut self, name: &str) -> Result<Option<String>> {
+        match self.take(name) {
+            Some(Some(value)) => Ok(Some(value)),
+            Some(None) => Err(Error::InvalidArgument(format!("--{} needs a value", name))),
+            None => Ok(None),
+        }
+    }
+
+    fn parsed<T: std::str::FromStr>(&mut self, name: &str) -> Result<Option<T>> {
+        self.value(name)?
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    Error::InvalidArgument(format!("bad value '{}' for --{}", value, name))
+                })
+            })
+            .transpose()
+    }
+
+    fn path(&mut self, name: &str) -> Result<Option<PathBuf>> {
+        Ok(self.value(name)?.map(PathBuf::from))
+    }
+
+    fn analysis(&mut self) -> Result<Analysis> {
+        let window_size: usize = self.parsed("window-size")?.unwrap_or(DEFAULT_WINDOW_SIZE);
+        let analysis = Analysis {
+            window_size,
+            hop_size: self.parsed("hop")?.unwrap_or(window_size / 2),
+            window: self.parsed("window")?.unwrap_or(Window::Hann),
+            padding: self.parsed("padding")?.unwrap_or(1),
+            single_precision: self.flag("f32"),
+        };
+
+        if analysis.window_size == 0 || analysis.hop_size == 0 {
+            return Err(Error::InvalidArgument(
+                "window size and hop have to be positive".to_string(),
+            ));
+        }
+
+        Ok(analysis)
+    }
+
+    fn graph(&mut self) -> Result<Graph> {
+        let graph = Graph {
+            scale: self.parsed("scale")?.unwrap_or_default(),
+            dynamic_range: self.parsed("range")?.unwrap_or(DEFAULT_SPECTRUM_RANGE),
+            labels: self.parsed("labels")?.unwrap_or(DEFAULT_LABELS),
+        };
+
+        if graph.dynamic_range <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "dynamic range has to be positive".to_string(),
+            ));
+        }
+
+        Ok(graph)
+    }
+
+    fn markers(&mut self) -> Result<Markers> {
+        let markers = Markers {
+            silence: self.parsed("silence")?,
+            min_silence: self.parsed("min-silence")?.unwrap_or(DEFAULT_MIN_SILENCE),
+            clip: self.path("match")?,
+            min_matches: self.parsed("min-matches")?.unwrap_or(DEFAULT_MIN_MATCHES),
+        };
+
+        if markers.min_silence < 0.0 {
+            return Err(Error::InvalidArgument(
+                "minimum silence cannot be negative".to_string(),
+            ));
+        }
+
+        Ok(markers)
+    }
+
+    fn heatmap(&mut self) -> Result<Heatmap> {
+        let heatmap = Heatmap {
+            scale: self.parsed("scale")?.unwrap_or_default(),
+            color_map: self.parsed("colormap")?.unwrap_or_default(),
+            dynamic_range: self.parsed("range")?.unwrap_or(DEFAULT_DYNAMIC_RANGE),
+            min_frequency: self.parsed("min-freq")?,
+            max_frequency: self.parsed("max-freq")?,
+            peaks: self.flag("peaks"),
+            pairs: self.flag("pairs"),
+        };
+
+        if heatmap.dynamic_range <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "dynamic range has to be positive".to_string(),
+            ));
+        }
+
+        Ok(heatmap)
+    }
+
+    // Frames and range come from the analysis and heatmap options, None without --cqt
+    fn cqt(&mut self, analysis: &Analysis, heatmap: &Heatmap) -> Result<Option<CqtConfig>> {
+        if !self.flag("cqt") {
+            return Ok(None);
+        }
+        if heatmap.peaks || heatmap.pairs {
+            return Err(Error::InvalidArgument(
+                "--peaks and --pairs are found on the STFT, they do not go with --cqt".to_string(),
+            ));
+        }
+
+        let defaults = CqtConfig::default();
+        let cqt = CqtConfig {
+            min_frequency: heatmap.min_frequency.unwrap_or(defaults.min_frequency),
+            max_frequency: heatmap.max_frequency,
+            bins_per_octave: self
+                .parsed("bins-per-octave")?
+                .unwrap_or(defaults.bins_per_octave),
+            hop_size: analysis.hop_size,
+            window: analysis.window,
+        };
+
+        if cqt.bins_per_octave == 0 {
+            return Err(Error::InvalidArgument(
+                "bins per octave have to be positive".to_string(),
+            ));
+        }
+
+        Ok(Some(cqt))
+    }
+
+    fn mel(&mut self) -> Result<MelConfig> {
+        let defaults = MelConfig::default();
+        let mel = MelConfig {
+            bands: self.parsed("bands")?.unwrap_or(defaults.bands),
+            min_frequency: self.parsed("min-freq")?.unwrap_or(defaults.min_frequency),
+            max_frequency: self.parsed("max-freq")?,
+            scale: self.parsed("mel-scale")?.unwrap_or_default(),
+            normalize: !self.flag("no-norm"),
+        };
+
+        if mel.bands == 0 {
+            return Err(Error::InvalidArgument(
+                "mel needs at least one band".to_string(),
+            ));
+        }
+
+        Ok(mel)
+    }
+
+    // Same window options as the analysis, with the longer chroma frames as the default
+    fn chroma(&mut self) -> Result<ChromaConfig> {
+        let defaults = ChromaConfig::default();
+        let window_size: usize = self
+            .parsed("window-size")?
+            .unwrap_or(defaults.stft.window_size);
+        let chroma = ChromaConfig {
+            stft: StftConfig {
+                window_size,
+                hop_size: self.parsed("hop")?.unwrap_or(window_size / 2),
+                fft_size: window_size,
+                window: self.parsed("window")?.unwrap_or(defaults.stft.window),
+            },
+            min_frequency: self.parsed("min-freq")?.unwrap_or(defaults.min_frequency),
+            max_frequency: self.parsed("max-freq")?.unwrap_or(defaults.max_frequency),
+            tuning: self.parsed("tuning")?,
+        };
+
+        if chroma.stft.window_size == 0 || chroma.stft.hop_size == 0 {
+            return Err(Error::InvalidArgument(
+                "window size and hop have to be positive".to_string(),
+            ));
+        }
+
+        Ok(chroma)
+    }
+
+    // Shorter frames than the analysis with more overlap, onsets need the time resolution
+    fn onsets(&mut self) -> Result<OnsetConfig> {
+        let defaults = OnsetConfig::default();
+        let window_size: usize = self
+            .parsed("window-size")?
+            .unwrap_or(defaults.stft.window_size);
+        let onsets = OnsetConfig {
+            stft: StftConfig {
+                window_size,
+                hop_size: self.parsed("hop")?.unwrap_or(window_size / 4),
+                fft_size: window_size,
+                window: self.parsed("window")?.unwrap_or(defaults.stft.window),
+            },
+            function: self.parsed("onset-function")?.unwrap_or_default(),
+            delta: self.parsed("delta")?.unwrap_or(defaults.delta),
+            ..defaults
+        };
+
+        if onsets.stft.window_size == 0 || onsets.stft.hop_size == 0 {
+            return Err(Error::InvalidArgument(
+                "window size and hop have to be positive".to_string(),
+            ));
+        }
+
+        Ok(onsets)
+    }
+
+    fn tempo(&mut self) -> Result<TempoConfig> {
+        let defaults = TempoConfig::default();
+        let tempo = TempoConfig {
+            min_bpm: self.parsed("min-bpm")?.unwrap_or(defaults.min_bpm),
+            max_bpm: self.parsed("max-bpm")?.unwrap_or(defaults.max_bpm),
+            ..defaults
+        };
+
+        if !(tempo.min_bpm > 0.0 && tempo.min_bpm < tempo.max_bpm) {
+            return Err(Error::InvalidArgument(
+                "tempo needs 0 < --min-bpm < --max-bpm".to_string(),
+            ));
+        }
+
+        Ok(tempo)
+    }
+
+    fn pitch(&mut self) -> Result<PitchConfig> {
+        let defaults = PitchConfig::default();
+        let window_size: usize = self.parsed("window-size")?.unwrap_or(defaults.window_size);
+        let pitch = PitchConfig {
+            method: self.parsed("method")?.unwrap_or_default(),
+            window_size,
+            hop_size: self.parsed("hop")?.unwrap_or(window_size / 4),
+            min_frequency: self.parsed("min-freq")?.unwrap_or(defaults.min_frequency),
+            max_frequency: self.parsed("max-freq")?.unwrap_or(defaults.max_frequency),
+            threshold: self.parsed("threshold")?.unwrap_or(defaults.threshold),
+        };
+
+        if pitch.window_size == 0 || pitch.hop_size == 0 {
+            return Err(Error::InvalidArgument(
+                "window size and hop have to be positive".to_string(),
+            ));
+        }
+
+        Ok(pitch)
+    }
+
+    fn mfcc(&mut self) -> Result<MfccConfig> {
+        let defaults = MfccConfig::default();
+        Ok(MfccConfig {
+            coefficients: self
+                .parsed("coefficients")?
+                .unwrap_or(defaults.coefficients),
+            lifter: self.parsed("lifter")?.unwrap_or(defaults.lifter),
+            delta_window: self
+                .parsed("delta-window")?
+                .unwrap_or(defaults.delta_window),
+        })
+    }
+
+    // The text options only count with --terminal, which also makes them valid
+    fn terminal(&mut self) -> Result<Option<Terminal>> {
+        let terminal = Terminal {
+            glyphs: self.parsed("glyphs")?.unwrap_or_default(),
+            no_color: self.flag("no-color"),
+        };
+        Ok(self.flag("terminal").then_some(terminal))
+    }
+
+    fn image(&mut self) -> Result<Image> {
+        let image = Image {
+            width: self.parsed("width")?,
+            height: self.parsed("height")?,
+            dpi: self.parsed("dpi")?,
+        };
+
+        if [image.width, image.height, image.dpi].contains(&Some(0)) {
+            return Err(Error::InvalidArgument(
+                "image size and DPI have to be positive".to_string(),
+            ));
+        }
+
+        Ok(image)
+    }
+
+    // Anything the command did not consume is a mistake
+    fn finish(self) -> Result<()> {
+        if let Some(arg) = self.positional.last() {
+            return Err(Error::InvalidArgument(format!(
+                "unexpected argument '{}'",
+                arg
+            )));
+        }
+        if let Some((name, _)) = self.options.first() {
+            return Err(Error::InvalidArgument(format!("unknown option --{}", name)));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use earworm::mel::MelScale;
+    use earworm::onset::OnsetFunction;
+    use earworm::pitch::PitchMethod;
+
+    fn parse_str(args: &str) -> Result<Command> {
+        parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_help() {
+        assert_eq!(parse_str("").unwrap(), Command::Help);
+        assert_eq!(parse_str("help").unwrap(), Command::Help);
+        assert_eq!(parse_str("query --help").unwrap(), Command::Help);
+    }
+
+    #[test]
+    fn test_info() {
+        assert_eq!(
+            parse_str("info song.wav").unwrap(),
+            Command::Info {
+                file: "song.wav".into()
+            }
+        );
+        assert!(parse_str("info").is_err());
+        assert!(parse_str("info a.wav b.wav").is_err());
+    }
+
+    #[test]
+    fn test_spectrum() {
+        let command = parse_str(
+            "spectrum song.wav --at 12.5 --window kaiser:6 --window-size 2048 --padding=4 --naive",
+        )
+        .unwrap();
+        let Command::Spectrum {
+            file,
+            at,
+            analysis,
+            graph,
+            output,
+            image,
+            terminal,
+            naive,
+        } = command
+        else {
+            panic!("expected spectrum, got {:?}", command);
+        };
+
+        assert_eq!(file, PathBuf::from("song.wav"));
+        assert_eq!(at, 12.5);
+        assert_eq!(analysis.window, Window::Kaiser { beta: 6.0 });
+        assert_eq!(analysis.window_size, 2048);
+        assert_eq!(analysis.hop_size, 1024);
+        assert_eq!(analysis.fft_size(), 8192);
+        assert_eq!(graph, Graph::default());
+        assert_eq!(output, PathBuf::from("spectrum.png"));
+        assert_eq!(image, Image::default());
+        assert_eq!(terminal, None);
+        assert!(naive);
+
+        assert!(parse_str("spectrum song.wav").is_err());
+        assert!(parse_str("spectrum song.wav --at soon").is_err());
+        for at in ["-5", "inf", "NaN"] {
+            assert!(parse_str(&format!("spectrum song.wav --at {}", at)).is_err());
+        }
+
+        let Command::Spectrum { graph, .. } =
+            parse_str("spectrum song.wav --at 1 --scale log --range 90 --labels 3").unwrap()
+        else {
+            panic!("expected spectrum");
+        };
+        assert_eq!(
+            graph,
+            Graph {
+                scale: FrequencyScale::Log,
+                dynamic_range: 90.0,
+                labels: 3,
+            }
+        );
+        assert!(parse_str("spectrum song.wav --at 1 --range 0").is_err());
+        assert!(parse_str("spectrum song.wav --at 1 --labels many").is_err());
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(
+            parse_str("key song.wav").unwrap(),
+            Command::Key {
+                file: "song.wav".into(),
+                chroma: ChromaConfig::default(),
+                single_precision: false,
+                output: None,
+            }
+        );
+
+        let command = parse_str(
+            "key song.wav --window-size 4096 --tuning -12 --max-freq 4000 --output c.csv",
+        )
+        .unwrap();
+        let Command::Key { chroma, output, .. } = command else {
+            panic!("expected key, got {:?}", command);
+        };
+        assert_eq!(chroma.stft.window_size, 4096);
+        assert_eq!(chroma.stft.hop_size, 2048);
+        assert_eq!(chroma.stft.fft_size, 4096);
+        assert_eq!(chroma.tuning, Some(-12.0));
+        assert_eq!(chroma.max_frequency, 4000.0);
+        assert_eq!(output, Some("c.csv".into()));
+        assert!(parse_str("key song.wav --hop 0").is_err());
+    }
+
+    #[test]
+    fn test_tempo() {
+        assert_eq!(
+            parse_str("tempo song.wav").unwrap(),
+            Command::Tempo {
+                file: "song.wav".into(),
+                onsets: OnsetConfig::default(),
+                tempo: TempoConfig::default(),
+                single_precision: false,
+                output: None,
+            }
+        );
+
+        let command = parse_str(
+            "tempo song.wav --window-size 2048 --onset-function complex --delta 0.1 --min-bpm 70 \
+             --max-bpm 180 --output beats.csv",
+        )
+        .unwrap();
+        let Command::Tempo {
+            onsets,
+            tempo,
+            output,
+            ..
+        } = command
+        else {
+            panic!("expected tempo, got {:?}", command);
+        };
+        assert_eq!(onsets.stft.window_size, 2048);
+        assert_eq!(onsets.stft.hop_size, 512);
+        assert_eq!(onsets.function, OnsetFunction::ComplexDomain);
+        assert_eq!(onsets.delta, 0.1);
+        assert_eq!((tempo.min_bpm, tempo.max_bpm), (70.0, 180.0));
+        assert_eq!(output, Some("beats.csv".into()));
+
+        assert!(parse_str("tempo song.wav --onset-function energy").is_err());
+        assert!(parse_str("tempo song.wav --min-bpm 150 --max-bpm 100").is_err());
+        assert!(parse_str("tempo song.wav --hop 0").is_err());
+    }
+
+    #[test]
+    fn test_pitch() {
+        assert_eq!(
+            parse_str("pitch song.wav").unwrap(),
+            Command::Pitch {
+                file: "song.wav".into(),
+                pitch: PitchConfig::default(),
+                output: "pitch.csv".into(),
+            }
+        );
+
+        let command = parse_str(
+            "pitch song.wav --method pyin --window-size 2048 --min-freq 30 --threshold 0.2",
+        )
+        .unwrap();
+        let Command::Pitch { pitch, .. } = command else {
+            panic!("expected pitch, got {:?}", command);
+        };
+        assert_eq!(pitch.method, PitchMethod::Pyin);
+        assert_eq!((pitch.window_size, pitch.hop_size), (2048, 512));
+        assert_eq!(pitch.min_frequency, 30.0);
+        assert_eq!(pitch.threshold, 0.2);
+
+        assert!(parse_str("pitch song.wav --method crepe").is_err());
+        assert!(parse_str("pitch song.wav --window-size 2").is_err());
+    }
+
+    #[test]
+    fn test_index_and_query() {
+        assert_eq!(
+            parse_str("index music --db songs.db --hop 256 --f32").unwrap(),
+            Command::Index {
+                dir: "music".into(),
+                db: "songs.db".into(),
+                analysis: Analysis {
+                    hop_size: 256,
+                    single_precision: true,
+                    ..Analysis::default()
+                },
+            }
+        );
+        assert_eq!(
+            parse_str("query clip.wav --db songs.db --min-matches 20 --plot hits.png").unwrap(),
+            Command::Query {
+                file: "clip.wav".into(),
+                db: "songs.db".into(),
+                analysis: Analysis::default(),
+                min_matches: 20,
+                plot: Some("hits.png".into()),
+                image: Image::default(),
+            }
+        );
+        assert!(parse_str("query clip.wav").is_err());
+        assert_eq!(
+            parse_str("verify --db songs.db").unwrap(),
+            Command::Verify {
+                db: "songs.db".into()
+            }
+        );
+        assert!(parse_str("verify").is_err());
+    }
+
+    #[test]
+    fn test_image() {
+        let command =
+            parse_str("spectrogram song.wav --output s.svg --width 1600 --dpi 192").unwrap();
+        let Command::Spectrogram { output, image, .. } = command else {
+            panic!("expected spectrogram, got {:?}", command);
+        };
+        assert_eq!(output, PathBuf::from("s.svg"));
+        assert_eq!(
+            image,
+            Image {
+                width: Some(1600),
+                height: None,
+                dpi: Some(192),
+            }
+        );
+    }
+
+    #[test]
+    fn test_mel() {
+        assert_eq!(
+            parse_str("mel song.wav").unwrap(),
+            Command::Mel {
+                file: "song.wav".into(),
+                analysis: Analysis::default(),
+                mel: MelConfig::default(),
+                output: "mel.csv".into(),
+            }
+        );
+
+        let command = parse_str(
+            "mel song.wav --bands 40 --min-freq 20 --max-freq 8000 --mel-scale htk --no-norm",
+        )
+        .unwrap();
+        let Command::Mel { mel, .. } = command else {
+            panic!("expected mel, got {:?}", command);
+        };
+        assert_eq!(
+            mel,
+            MelConfig {
+                bands: 40,
+                min_frequency: 20.0,
+                max_frequency: Some(8000.0),
+                scale: MelScale::Htk,
+                normalize: false,
+            }
+        );
+        assert!(parse_str("mel song.wav --bands 0").is_err());
+        assert!(parse_str("mel song.wav --mel-scale bark").is_err());
+    }
+
+    #[test]
+    fn test_mfcc() {
+        assert_eq!(
+            parse_str("mfcc song.wav --bands 40 --coefficients 13 --lifter 22 --delta-window 3")
+                .unwrap(),
+            Command::Mfcc {
+                file: "song.wav".into(),
+                analysis: Analysis::default(),
+                mel: MelConfig {
+                    bands: 40,
+                    ..MelConfig::default()
+                },
+                mfcc: MfccConfig {
+                    coefficients: 13,
+                    lifter: 22.0,
+                    delta_window: 3,
+                },
+                output: "mfcc.csv".into(),
+            }
+        );
+        assert!(parse_str("mfcc song.wav --coefficients many").is_err());
+    }
+
+    #[test]
+    fn test_waveform() {
+        assert_eq!(
+            parse_str("waveform song.wav").unwrap(),
+            Command::Waveform {
+                file: "song.wav".into(),
+                lanes: Lanes::Downmix,
+                markers: Markers::default(),
+                analysis: Analysis::default(),
+                output: "waveform.png".into(),
+                image: Image::default(),
+            }
+        );
+
+        let command = parse_str(
+            "waveform song.wav --lanes channels --silence -50 --min-silence 1 --match clip.wav",
+        )
+        .unwrap();
+        let Command::Waveform { lanes, markers, .. } = command else {
+            panic!("expected waveform, got {:?}", command);
+        };
+        assert_eq!(lanes, Lanes::Channels);
+        assert_eq!(
+            markers,
+            Markers {
+                silence: Some(-50.0),
+                min_silence: 1.0,
+                clip: Some("clip.wav".into()),
+                min_matches: 5,
+            }
+        );
+
+        assert!(parse_str("waveform song.wav --lanes stereo").is_err());
+        assert!(parse_str("waveform song.wav --min-silence -1").is_err());
+        assert!(parse_str("waveform").is_err());
+    }
+
+    #[test]
+    fn test_spectrogram() {
+        assert_eq!(
+            parse_str("spectrogram song.wav").unwrap(),
+            Command::Spectrogram {
+                file: "song.wav".into(),
+                analysis: Analysis::default(),
+                heatmap: Heatmap::default(),
+                cqt: None,
+                output: "spectrogram.png".into(),
+                image: Image::default(),
+                terminal: None,
+            }
+        );
+
+        let command = parse_str(
+            "spectrogram song.wav --scale mel --colormap magma --range 60 --max-freq 8000 --pairs",
+        )
+        .unwrap();
+        let Command::Spectrogram { heatmap, .. } = command else {
+            panic!("expected spectrogram, got {:?}", command);
+        };
+        assert_eq!(heatmap.scale, FrequencyScale::Mel);
+        assert_eq!(heatmap.color_map, ColorMap::Magma);
+        assert_eq!(heatmap.dynamic_range, 60.0);
+        assert_eq!(heatmap.min_frequency, None);
+        assert_eq!(heatmap.max_frequency, Some(8000.0));
+        assert!(!heatmap.peaks);
+        assert!(heatmap.pairs);
+
+        assert!(parse_str("spectrogram song.wav --scale bark").is_err());
+        assert!(parse_str("spectrogram song.wav --range -10").is_err());
+        assert!(parse_str("spectrogram song.wav --width 0").is_err());
+
+        let command =
+            parse_str("spectrogram song.wav --terminal --glyphs braille --no-color --width 120")
+                .unwrap();
+        let Command::Spectrogram {
+            terminal, image, ..
+        } = command
+        else {
+            panic!("expected spectrogram, got {:?}", command);
+        };
+        assert_eq!(
+            terminal,
+            Some(Terminal {
+                glyphs: Glyphs::Braille,
+                no_color: true,
+            })
+        );
+        assert_eq!(image.width, Some(120));
+        assert!(parse_str("spectrogram song.wav --terminal --glyphs ascii").is_err());
+    }
+
+    #[test]
+    fn test_cqt() {
+        let command =
+            parse_str("spectrogram song.wav --cqt --bins-per-octave 36 --min-freq 55 --hop 256")
+                .unwrap();
+        let Command::Spectrogram { heatmap, cqt, .. } = command else {
+            panic!("expected spectrogram, got {:?}", command);
+        };
+        assert_eq!(heatmap.scale, FrequencyScale::Log);
+        assert_eq!(
+            cqt,
+            Some(CqtConfig {
+                min_frequency: 55.0,
+                bins_per_octave: 36,
+                hop_size: 256,
+                ..CqtConfig::default()
+            })
+        );
+
+        // An explicit scale is kept
+        let command = parse_str("spectrogram song.wav --cqt --scale mel").unwrap();
+        let Command::Spectrogram { heatmap, cqt, .. } = command else {
+            panic!("expected spectrogram, got {:?}", command);
+        };
+        assert_eq!(heatmap.scale, FrequencyScale::Mel);
+        assert_eq!(cqt, Some(CqtConfig::default()));
+
+        assert!(parse_str("spectrogram song.wav --cqt --bins-per-octave 0").is_err());
+        assert!(parse_str("spectrogram song.wav --cqt --peaks").is_err());
+        // Only with --cqt
+        assert!(parse_str("spectrogram song.wav --bins-per-octave 12").is_err());
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(parse_str("dance").is_err());
+        assert!(parse_str("info song.wav --colour red").is_err());
+        assert!(parse_str("spectrogram song.wav --window-size").is_err());
+        assert!(parse_str("spectrogram song.wav --hop 0").is_err());
+        assert!(parse_str("fingerprint song.wav --window sinc").is_err());
+    }
+
+    #[test]
+    fn test_analysis_stft() {
+        let stft = Analysis::default().stft();
+        assert_eq!(stft, StftConfig::default());
+    }
+}
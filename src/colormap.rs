@@ -0,0 +1,154 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::Error;
+
+// Perceptually uniform maps sampled at nine evenly spaced points, intermediate values are interpolated linearly
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [72, 40, 120],
+    [62, 73, 137],
+    [49, 104, 142],
+    [38, 130, 142],
+    [31, 158, 137],
+    [53, 183, 121],
+    [110, 206, 88],
+    [253, 231, 37],
+];
+
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [28, 16, 68],
+    [79, 18, 123],
+    [129, 37, 129],
+    [181, 54, 122],
+    [229, 80, 100],
+    [251, 135, 97],
+    [254, 194, 135],
+    [252, 253, 191],
+];
+
+const INFERNO: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [31, 12, 72],
+    [85, 15, 109],
+    [136, 34, 106],
+    [186, 54, 85],
+    [227, 89, 51],
+    [249, 142, 9],
+    [249, 203, 53],
+    [252, 255, 164],
+];
+
+const GRAYSCALE: [[u8; 3]; 2] = [[0, 0, 0], [255, 255, 255]];
+
+// Maps a value from 0 (quiet) to 1 (loud) to an RGB colour
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMap {
+    #[default]
+    Viridis,
+    Magma,
+    Inferno,
+    Grayscale,
+}
+
+impl ColorMap {
+    // Values outside of 0..=1 are clamped
+    pub fn rgb(&self, value: f64) -> [u8; 3] {
+        let stops: &[[u8; 3]] = match self {
+            ColorMap::Viridis => &VIRIDIS,
+            ColorMap::Magma => &MAGMA,
+            ColorMap::Inferno => &INFERNO,
+            ColorMap::Grayscale => &GRAYSCALE,
+        };
+
+        let value = if value.is_nan() {
+            0.0
+        } else {
+            value.clamp(0.0, 1.0)
+        };
+        let position = value * (stops.len() - 1) as f64;
+        let low = (position.floor() as usize).min(stops.len() - 2);
+        let fraction = position - low as f64;
+
+        let mut rgb = [0; 3];
+        for (channel, out) in rgb.iter_mut().enumerate() {
+            let a = stops[low][channel] as f64;
+            let b = stops[low + 1][channel] as f64;
+            *out = (a + (b - a) * fraction).round() as u8;
+        }
+        rgb
+    }
+}
+
+impl FromStr for ColorMap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "viridis" => Ok(ColorMap::Viridis),
+            "magma" => Ok(ColorMap::Magma),
+            "inferno" => Ok(ColorMap::Inferno),
+            "grayscale" | "gray" | "grey" => Ok(ColorMap::Grayscale),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown colour map '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ColorMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorMap::Viridis => "viridis",
+            ColorMap::Magma => "magma",
+            ColorMap::Inferno => "inferno",
+            ColorMap::Grayscale => "grayscale",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_end_points() {
+        assert_eq!(ColorMap::Viridis.rgb(0.0), [68, 1, 84]);
+        assert_eq!(ColorMap::Viridis.rgb(1.0), [253, 231, 37]);
+        assert_eq!(ColorMap::Grayscale.rgb(0.5), [128, 128, 128]);
+        // Out of range and NaN are clamped rather than wrapping around
+        assert_eq!(ColorMap::Magma.rgb(-3.0), ColorMap::Magma.rgb(0.0));
+        assert_eq!(ColorMap::Magma.rgb(7.0), ColorMap::Magma.rgb(1.0));
+        assert_eq!(ColorMap::Inferno.rgb(f64::NAN), ColorMap::Inferno.rgb(0.0));
+    }
+
+    #[test]
+    fn test_brightness_increases() {
+        for map in [
+            ColorMap::Viridis,
+            ColorMap::Magma,
+            ColorMap::Inferno,
+            ColorMap::Grayscale,
+        ] {
+            let brightness = |v: f64| map.rgb(v).iter().map(|c| *c as u32).sum::<u32>();
+            assert!(brightness(0.0) < brightness(0.5), "{}", map);
+            assert!(brightness(0.5) < brightness(1.0), "{}", map);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        for map in [
+            ColorMap::Viridis,
+            ColorMap::Magma,
+            ColorMap::Inferno,
+            ColorMap::Grayscale,
+        ] {
+            assert_eq!(map.to_string().parse::<ColorMap>().unwrap(), map);
+        }
+        assert_eq!("grey".parse::<ColorMap>().unwrap(), ColorMap::Grayscale);
+        assert!("jet".parse::<ColorMap>().is_err());
+    }
+}
@@ -1,35 +1,173 @@
-use std::ops::{Add, AddAssign, Mul};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
-pub struct Complex {
-    re: f64,
-    im: f64,
+use crate::float::Float;
+
+// Complex number over f32 or f64, f64 unless said otherwise
+//
+// Laid out like num_complex::Complex, so whole rustfft buffers can be viewed as slices of it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[repr(C)]
+pub struct Complex<T = f64> {
+    pub re: T,
+    pub im: T,
 }
 
-impl Complex {
-    pub fn new(real: f64, im: f64) -> Self {
+pub type Complex32 = Complex<f32>;
+pub type Complex64 = Complex<f64>;
+
+impl<T: Float> Complex<T> {
+    pub const ZERO: Self = Complex {
+        re: T::ZERO,
+        im: T::ZERO,
+    };
+    pub const ONE: Self = Complex {
+        re: T::ONE,
+        im: T::ZERO,
+    };
+    pub const I: Self = Complex {
+        re: T::ZERO,
+        im: T::ONE,
+    };
+
+    pub fn new(real: T, im: T) -> Self {
         Complex { re: real, im }
     }
 
     // r is the magnitude of the Complex num and theta is the angle of the vector
-    pub fn from_polar(r: f64, theta: f64) -> Self {
+    pub fn from_polar(r: T, theta: T) -> Self {
+        let (sin, cos) = theta.sin_cos();
         Complex {
-            re: r * theta.cos(),
-            im: r * theta.sin(),
+            re: r * cos,
+            im: r * sin,
         }
     }
 
-    pub fn abs(&self) -> f64 {
-        (self.im.powi(2) + self.re.powi(2)).sqrt()
+    // hypot does not overflow for components above the square root of the largest float
+    pub fn abs(&self) -> T {
+        self.re.hypot(self.im)
+    }
+
+    // abs without the square root, cheaper when only comparing magnitudes or summing power
+    pub fn norm_sqr(&self) -> T {
+        self.re * self.re + self.im * self.im
+    }
+
+    // Angle from the positive real axis in -pi..=pi, the sign of a zero imaginary part picks the side of the negative
+    // real axis
+    pub fn arg(&self) -> T {
+        self.im.atan2(self.re)
+    }
+
+    pub fn to_polar(&self) -> (T, T) {
+        (self.abs(), self.arg())
+    }
+
+    pub fn conj(&self) -> Self {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn recip(&self) -> Self {
+        Self::ONE / *self
     }
 
     pub fn powi(&self, n: i32) -> Self {
         let r = self.abs().powi(n); // calculate the magnitude and raise to n
-        let theta = self.im.atan2(self.re) * n as f64; // calculate the angle and multiply it by n
+        let theta = self.im.atan2(self.re) * T::cast(n as f64); // calculate the angle and multiply it by n
         Complex::from_polar(r, theta)
     }
+
+    // e^(a + bi) = e^a * (cos b + i sin b)
+    pub fn exp(&self) -> Self {
+        // Keeps exp of a real number real, from_polar(inf, 0) would give inf * 0 = NaN for the imaginary part
+        if self.im == T::ZERO {
+            return Complex::new(self.re.exp(), self.im);
+        }
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    // Principal natural logarithm, ln |z| + i arg z, with the branch cut along the negative real axis
+    pub fn ln(&self) -> Self {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+
+    // Principal square root, the real part is never negative and the branch cut is along the negative real axis
+    pub fn sqrt(&self) -> Self {
+        if self.re == T::ZERO && self.im == T::ZERO {
+            return Complex::new(T::ZERO, self.im);
+        }
+        if self.im.is_infinite() {
+            return Complex::new(T::infinity(), self.im);
+        }
+
+        // Taking the root of the larger of the two parts first avoids cancellation
+        let two = T::cast(2.0);
+        let t = ((self.re.abs() + self.abs()) / two).sqrt();
+        if self.re >= T::ZERO {
+            Complex::new(t, self.im / (two * t))
+        } else {
+            Complex::new(self.im.abs() / (two * t), t.copysign(self.im))
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        self.re.is_nan() || self.im.is_nan()
+    }
+
+    // Infinite if either part is, even when the other one is NaN
+    pub fn is_infinite(&self) -> bool {
+        self.re.is_infinite() || self.im.is_infinite()
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.re.is_finite() && self.im.is_finite()
+    }
+}
+
+impl<T: Float> From<T> for Complex<T> {
+    fn from(re: T) -> Self {
+        Complex::new(re, T::ZERO)
+    }
+}
+
+impl From<Complex32> for Complex64 {
+    fn from(c: Complex32) -> Self {
+        Complex::new(c.re as f64, c.im as f64)
+    }
+}
+
+// The type rustfft works with, converting either way is free
+impl<T> From<num_complex::Complex<T>> for Complex<T> {
+    fn from(c: num_complex::Complex<T>) -> Self {
+        Complex { re: c.re, im: c.im }
+    }
+}
+
+impl<T> From<Complex<T>> for num_complex::Complex<T> {
+    fn from(c: Complex<T>) -> Self {
+        num_complex::Complex { re: c.re, im: c.im }
+    }
+}
+
+// A rustfft buffer as our own complex numbers without copying it
+pub fn from_num_complex_slice<T>(slice: &[num_complex::Complex<T>]) -> &[Complex<T>] {
+    // SAFETY: both types are #[repr(C)] structs of re followed by im, so their layouts are the same
+    unsafe { std::slice::from_raw_parts(slice.as_ptr().cast(), slice.len()) }
+}
+
+// 3+4i, 3-4i
+impl<T: Float> fmt::Display for Complex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.im.is_sign_negative() {
+            write!(f, "{}-{}i", self.re, -self.im)
+        } else {
+            write!(f, "{}+{}i", self.re, self.im)
+        }
+    }
 }
 
-impl Add for Complex {
+impl<T: Float> Add for Complex<T> {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
@@ -40,10 +178,36 @@ impl Add for Complex {
     }
 }
 
-impl AddAssign for Complex {
-    fn add_assign(&mut self, rhs: Self) -> () {
-        self.re += rhs.re;
-        self.im += rhs.im;
+impl<T: Float> AddAssign for Complex<T> {
+    fn add_assign(&mut self, rhs: Self) {
+        self.re = self.re + rhs.re;
+        self.im = self.im + rhs.im;
+    }
+}
+
+impl<T: Float> Sub for Complex<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Complex {
+            re: self.re - rhs.re,
+            im: self.im - rhs.im,
+        }
+    }
+}
+
+impl<T: Float> SubAssign for Complex<T> {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.re = self.re - rhs.re;
+        self.im = self.im - rhs.im;
+    }
+}
+
+impl<T: Float> Neg for Complex<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Complex::new(-self.re, -self.im)
     }
 }
 
@@ -51,7 +215,7 @@ impl AddAssign for Complex {
 // ac + adi + cbi + bdi^2
 // ac + adi + cbi - bd
 // (ac - bd) + (ad + cb)i
-impl Mul for Complex {
+impl<T: Float> Mul for Complex<T> {
     type Output = Self;
 
     fn mul(self, rhs: Self) -> Self {
@@ -62,9 +226,139 @@ impl Mul for Complex {
     }
 }
 
+impl<T: Float> MulAssign for Complex<T> {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+// (a + bi) / (c + di) = (a + bi)(c - di) / (c^2 + d^2)
+//
+// Smith's algorithm divides by the larger of c and d first, so c^2 + d^2 can neither overflow nor underflow
+impl<T: Float> Div for Complex<T> {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        let (a, b, c, d) = (self.re, self.im, rhs.re, rhs.im);
+        if c.abs() >= d.abs() {
+            if c == T::ZERO {
+                // Both parts are zero, divide like real numbers: infinities, or NaN for 0 / 0
+                return Complex::new(a / c, b / c);
+            }
+            let r = d / c;
+            let denominator = c + d * r;
+            Complex::new((a + b * r) / denominator, (b - a * r) / denominator)
+        } else {
+            let r = c / d;
+            let denominator = c * r + d;
+            Complex::new((a * r + b) / denominator, (b * r - a) / denominator)
+        }
+    }
+}
+
+impl<T: Float> DivAssign for Complex<T> {
+    fn div_assign(&mut self, rhs: Self) {
+        *self = *self / rhs;
+    }
+}
+
+// Real scalars, which skip the products with a zero imaginary part
+
+impl<T: Float> Add<T> for Complex<T> {
+    type Output = Self;
+
+    fn add(self, rhs: T) -> Self {
+        Complex::new(self.re + rhs, self.im)
+    }
+}
+
+impl<T: Float> Sub<T> for Complex<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: T) -> Self {
+        Complex::new(self.re - rhs, self.im)
+    }
+}
+
+impl<T: Float> Mul<T> for Complex<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: T) -> Self {
+        Complex::new(self.re * rhs, self.im * rhs)
+    }
+}
+
+impl<T: Float> Div<T> for Complex<T> {
+    type Output = Self;
+
+    fn div(self, rhs: T) -> Self {
+        Complex::new(self.re / rhs, self.im / rhs)
+    }
+}
+
+impl<T: Float> MulAssign<T> for Complex<T> {
+    fn mul_assign(&mut self, rhs: T) {
+        self.re = self.re * rhs;
+        self.im = self.im * rhs;
+    }
+}
+
+impl<T: Float> DivAssign<T> for Complex<T> {
+    fn div_assign(&mut self, rhs: T) {
+        self.re = self.re / rhs;
+        self.im = self.im / rhs;
+    }
+}
+
+// A generic T on the left of an operator is not allowed, so scalar first ops are spelled out per float type
+macro_rules! scalar_lhs_ops {
+    ($($t:ty),*) => {$(
+        impl Add<Complex<$t>> for $t {
+            type Output = Complex<$t>;
+
+            fn add(self, rhs: Complex<$t>) -> Complex<$t> {
+                rhs + self
+            }
+        }
+
+        impl Sub<Complex<$t>> for $t {
+            type Output = Complex<$t>;
+
+            fn sub(self, rhs: Complex<$t>) -> Complex<$t> {
+                Complex::new(self - rhs.re, -rhs.im)
+            }
+        }
+
+        impl Mul<Complex<$t>> for $t {
+            type Output = Complex<$t>;
+
+            fn mul(self, rhs: Complex<$t>) -> Complex<$t> {
+                rhs * self
+            }
+        }
+
+        impl Div<Complex<$t>> for $t {
+            type Output = Complex<$t>;
+
+            fn div(self, rhs: Complex<$t>) -> Complex<$t> {
+                Complex::from(self) / rhs
+            }
+        }
+    )*};
+}
+
+scalar_lhs_ops!(f32, f64);
+
+impl<T: Float> Sum for Complex<T> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, |sum, c| sum + c)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::f64::consts::{E, PI};
 
     #[test]
     fn test_new() {
@@ -93,14 +387,14 @@ mod tests {
 
     #[test]
     fn test_abs() {
-        let c = Complex::new(3.0, 4.0);
+        let c = Complex64::new(3.0, 4.0);
         let abs = c.abs();
         assert!((abs - 5.0).abs() < 1e-10);
     }
 
     #[test]
     fn test_mul() {
-        let a = Complex::new(1.0, 2.0);
+        let a = Complex64::new(1.0, 2.0);
         let b = Complex::new(3.0, 4.0);
         let result = a * b;
         // (1*3 - 2*4, 1*4 + 2*3) = (-5, 10)
@@ -119,10 +413,208 @@ mod tests {
 
     #[test]
     fn test_powi() {
-        let c = Complex::new(1.0, 1.0);
+        let c = Complex64::new(1.0, 1.0);
         let result = c.powi(2);
         // (1 + i)^2 = 1 + 2i + i^2 = (1 - 1) + 2i = 2i
         assert!((result.re).abs() < 1e-10);
         assert!((result.im - 2.0).abs() < 1e-10);
     }
+
+    fn assert_close(a: Complex, b: Complex) {
+        assert!(
+            (a.re - b.re).abs() < 1e-10 && (a.im - b.im).abs() < 1e-10,
+            "{} != {}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn test_sub_and_neg() {
+        let mut a = Complex::new(1.0, 2.0);
+        let b = Complex::new(3.0, 5.0);
+        assert_eq!(a - b, Complex::new(-2.0, -3.0));
+        assert_eq!(-b, Complex::new(-3.0, -5.0));
+        a -= b;
+        assert_eq!(a, Complex::new(-2.0, -3.0));
+    }
+
+    #[test]
+    fn test_mul_assign() {
+        let mut a = Complex::new(1.0, 2.0);
+        a *= Complex::new(3.0, 4.0);
+        assert_eq!(a, Complex::new(-5.0, 10.0));
+        assert_eq!(Complex64::I * Complex::I, -Complex::ONE);
+    }
+
+    #[test]
+    fn test_div() {
+        let a = Complex::new(-5.0, 10.0);
+        let b = Complex::new(3.0, 4.0);
+        assert_close(a / b, Complex::new(1.0, 2.0));
+        // The other branch of Smith's algorithm, |d| > |c|
+        assert_close(a / Complex::new(1.0, 2.0), b);
+
+        let mut c = a;
+        c /= b;
+        assert_close(c, Complex::new(1.0, 2.0));
+        assert_close(b * b.recip(), Complex::ONE);
+
+        // c^2 + d^2 would overflow
+        let huge = Complex::new(1e300, 1e300);
+        assert_close(huge / huge, Complex::ONE);
+        let tiny = Complex::new(1e-300, 1e-300);
+        assert_close(tiny / tiny, Complex::ONE);
+    }
+
+    #[test]
+    fn test_div_edge_cases() {
+        let one = Complex::ONE;
+        let zero = Complex::ZERO;
+        // Infinite like 1 / 0 for reals, any infinite part makes the whole number infinite
+        let quotient = one / zero;
+        assert!(quotient.is_infinite());
+        assert_eq!(quotient.re, f64::INFINITY);
+        assert!((zero / zero).is_nan());
+        assert_eq!(
+            one / Complex::new(f64::INFINITY, 0.0),
+            Complex::new(0.0, 0.0)
+        );
+        assert!((Complex::new(f64::NAN, 0.0) / one).is_nan());
+    }
+
+    #[test]
+    fn test_scalar_ops() {
+        let a = Complex::new(1.0, -2.0);
+        assert_eq!(a + 1.0, Complex::new(2.0, -2.0));
+        assert_eq!(a - 1.0, Complex::new(0.0, -2.0));
+        assert_eq!(a * 2.0, Complex::new(2.0, -4.0));
+        assert_eq!(a / 2.0, Complex::new(0.5, -1.0));
+        assert_eq!(1.0 + a, Complex::new(2.0, -2.0));
+        assert_eq!(1.0 - a, Complex::new(0.0, 2.0));
+        assert_eq!(2.0 * a, Complex::new(2.0, -4.0));
+        assert_close(5.0 / Complex::new(1.0, 2.0), Complex::new(1.0, -2.0));
+
+        let mut b = a;
+        b *= 3.0;
+        b /= 2.0;
+        assert_eq!(b, Complex::new(1.5, -3.0));
+        assert_eq!(Complex::from(2.5), Complex::new(2.5, 0.0));
+    }
+
+    #[test]
+    fn test_conj_arg_norm() {
+        let a = Complex::new(3.0, 4.0);
+        assert_eq!(a.conj(), Complex::new(3.0, -4.0));
+        assert_eq!(a * a.conj(), Complex::new(a.norm_sqr(), 0.0));
+        assert_eq!(a.norm_sqr(), 25.0);
+        assert!((Complex64::I.arg() - PI / 2.0).abs() < 1e-15);
+        assert_eq!(Complex64::ZERO.arg(), 0.0);
+
+        // Either side of the branch cut
+        assert_eq!(Complex::new(-1.0, 0.0).arg(), PI);
+        assert_eq!(Complex::new(-1.0, -0.0).arg(), -PI);
+
+        let (r, theta) = a.to_polar();
+        assert_close(Complex::from_polar(r, theta), a);
+
+        // Neither overflows nor loses the small part
+        assert_eq!(Complex::new(1e200, 1e200).abs(), 1e200 * 2f64.sqrt());
+        assert_eq!(Complex::new(f64::INFINITY, f64::NAN).abs(), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_exp_and_ln() {
+        // e^(i pi) = -1
+        assert_close(Complex::new(0.0, PI).exp(), Complex::new(-1.0, 0.0));
+        assert_close(Complex::new(1.0, 0.0).exp(), Complex::new(E, 0.0));
+        assert_close(Complex::new(-1.0, 0.0).ln(), Complex::new(0.0, PI));
+        assert_close(Complex::new(-1.0, -0.0).ln(), Complex::new(0.0, -PI));
+
+        let a = Complex::new(0.3, -2.1);
+        assert_close(a.ln().exp(), a);
+        assert_close(a.exp().ln(), a);
+
+        assert_eq!(Complex::ZERO.ln(), Complex::new(f64::NEG_INFINITY, 0.0));
+        assert_eq!(
+            Complex::new(f64::INFINITY, 0.0).exp(),
+            Complex::new(f64::INFINITY, 0.0)
+        );
+        assert_eq!(
+            Complex::new(f64::NEG_INFINITY, 1.0).exp(),
+            Complex::new(0.0, 0.0)
+        );
+        assert!(Complex::new(f64::NAN, 1.0).exp().is_nan());
+    }
+
+    #[test]
+    fn test_sqrt() {
+        assert_close(Complex::new(-4.0, 0.0).sqrt(), Complex::new(0.0, 2.0));
+        assert_close(Complex::new(-4.0, -0.0).sqrt(), Complex::new(0.0, -2.0));
+        assert_close(Complex::new(0.0, 2.0).sqrt(), Complex::new(1.0, 1.0));
+        assert_close(Complex::new(3.0, 4.0).sqrt(), Complex::new(2.0, 1.0));
+        assert_close(Complex::new(-3.0, -4.0).sqrt(), Complex::new(1.0, -2.0));
+
+        let a = Complex::new(-0.7, 1e-9);
+        assert_close(a.sqrt() * a.sqrt(), a);
+
+        assert_eq!(Complex64::ZERO.sqrt(), Complex::ZERO);
+        assert_eq!(
+            Complex::new(1.0, f64::NEG_INFINITY).sqrt(),
+            Complex::new(f64::INFINITY, f64::NEG_INFINITY)
+        );
+        assert_eq!(
+            Complex::new(f64::NEG_INFINITY, 1.0).sqrt(),
+            Complex::new(0.0, f64::INFINITY)
+        );
+        assert!(Complex::new(f64::NAN, 1.0).sqrt().is_nan());
+    }
+
+    #[test]
+    fn test_classification_and_sum() {
+        assert!(Complex::new(1.0, 2.0).is_finite());
+        assert!(Complex::new(f64::INFINITY, f64::NAN).is_infinite());
+        assert!(Complex::new(0.0, f64::NAN).is_nan());
+        assert!(!Complex::new(0.0, f64::NAN).is_finite());
+
+        let sum: Complex = (1..=4).map(|n| Complex::new(n as f64, 1.0)).sum();
+        assert_eq!(sum, Complex::new(10.0, 4.0));
+        assert_eq!(Complex64::default(), Complex::ZERO);
+        assert_eq!(Complex::new(3.0, -4.0).to_string(), "3-4i");
+        assert_eq!(Complex::new(3.0, 4.0).to_string(), "3+4i");
+    }
+
+    #[test]
+    fn test_f32() {
+        let a = Complex32::new(3.0, 4.0);
+        assert_eq!(a.abs(), 5.0f32);
+        assert_eq!(a * a.conj(), Complex32::new(25.0, 0.0));
+        assert_eq!(2.0f32 * a, Complex32::new(6.0, 8.0));
+        assert!((Complex32::new(-4.0, 0.0).sqrt().im - 2.0).abs() < 1e-6);
+        assert_eq!(Complex64::from(a), Complex::new(3.0, 4.0));
+        assert_eq!(Complex32::I * Complex32::I, -Complex32::ONE);
+    }
+
+    #[test]
+    fn test_num_complex_conversions() {
+        let ours = Complex::new(1.5, -2.0);
+        let theirs: num_complex::Complex64 = ours.into();
+        assert_eq!(theirs, num_complex::Complex64::new(1.5, -2.0));
+        assert_eq!(Complex::from(theirs), ours);
+
+        // Both sides agree on the arithmetic
+        let other = num_complex::Complex64::new(0.3, 0.7);
+        assert_close(Complex::from(theirs / other), ours / Complex::from(other));
+        assert_close(Complex::from(theirs.sqrt()), ours.sqrt());
+        assert_close(Complex::from(theirs.exp()), ours.exp());
+
+        let single: num_complex::Complex32 = Complex32::new(1.0, 2.0).into();
+        assert_eq!(single.im, 2.0);
+
+        let buffer = [theirs, other];
+        assert_eq!(
+            from_num_complex_slice(&buffer),
+            [ours, Complex::from(other)]
+        );
+    }
 }
@@ -0,0 +1,466 @@
+use std::f64::consts::PI;
+
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex as FftComplex;
+
+use crate::complex::{Complex, from_num_complex_slice};
+use crate::error::{Error, Result};
+use crate::float::Float;
+use crate::spectrogram::TimeFrequency;
+use crate::window::Window;
+
+// Kernel bins below this fraction of the kernel's peak are dropped, what Brown and Puckette suggest
+const SPARSITY: f64 = 0.0054;
+
+// Constant-Q settings, the default range starts at C1 and goes up as far as the sample rate allows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CqtConfig {
+    pub min_frequency: f64,
+    // Highest bins whose main lobe still fits below Nyquist when not set
+    pub max_frequency: Option<f64>,
+    pub bins_per_octave: usize,
+    pub hop_size: usize,
+    pub window: Window,
+}
+
+impl Default for CqtConfig {
+    fn default() -> Self {
+        CqtConfig {
+            min_frequency: 32.703,
+            max_frequency: None,
+            bins_per_octave: 24,
+            hop_size: 512,
+            window: Window::Hann,
+        }
+    }
+}
+
+impl CqtConfig {
+    pub fn spectrogram<T: Float>(&self, samples: &[T], sample_rate: u32) -> Result<CqtSpectrogram> {
+        Ok(ConstantQ::new(self, sample_rate)?.spectrogram(samples))
+    }
+}
+
+// Spectrum of one kernel, conjugated and scaled by 1 / fft_size, only the bins above the sparsity threshold
+#[derive(Debug, Clone, PartialEq)]
+struct Kernel<T> {
+    first_bin: usize,
+    weights: Vec<Complex<T>>,
+}
+
+// Constant-Q transform, geometrically spaced bins that are all the same number of periods long
+//
+// Bin k sits at min_frequency * 2^(k / bins_per_octave) and correlates the signal with a windowed complex sine of
+// Q periods, so low bins get long windows and fine frequency resolution and high bins short windows and fine time
+// resolution, like the octaves of a piano
+//
+// The correlation is done in the frequency domain after Brown and Puckette: the kernels are transformed once, and as
+// the spectrum of a kernel is nearly zero away from its own frequency only a few bins of it are kept. Every frame
+// then takes one FFT as long as the longest kernel and a short dot product per bin
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstantQ<T = f64> {
+    kernels: Vec<Kernel<T>>,
+    frequencies: Vec<f64>,
+    fft_size: usize,
+    sample_rate: u32,
+    config: CqtConfig,
+}
+
+impl<T: Float> ConstantQ<T> {
+    pub fn new(config: &CqtConfig, sample_rate: u32) -> Result<Self> {
+        let nyquist = sample_rate as f64 / 2.0;
+        let low = config.min_frequency;
+        if config.bins_per_octave == 0 || config.hop_size == 0 {
+            return Err(Error::InvalidArgument(
+                "bins per octave and hop have to be positive".to_string(),
+            ));
+        }
+        if !(low > 0.0 && low < nyquist) {
+            return Err(Error::InvalidArgument(format!(
+                "constant-Q min frequency has to be between 0 and {}Hz",
+                nyquist
+            )));
+        }
+
+        // Main lobe of the Hann window is 4 / N wide, 2 f / Q on each side of the bin
+        let bins_per_octave = config.bins_per_octave as f64;
+        let q = 1.0 / (2f64.powf(1.0 / bins_per_octave) - 1.0);
+        let high = config
+            .max_frequency
+            .unwrap_or(f64::INFINITY)
+            .min(nyquist / (1.0 + 2.0 / q));
+        if high < low {
+            return Err(Error::InvalidArgument(format!(
+                "empty constant-Q range {}Hz to {:.1}Hz",
+                low, high
+            )));
+        }
+        let count = (bins_per_octave * (high / low).log2()).floor() as usize + 1;
+        let frequencies: Vec<f64> = (0..count)
+            .map(|k| low * 2f64.powf(k as f64 / bins_per_octave))
+            .collect();
+
+        // Long enough for the kernel of the lowest bin
+        let longest = (q * sample_rate as f64 / low).ceil() as usize;
+        let fft_size = longest.next_power_of_two();
+        let mut planner: FftPlanner<f64> = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+
+        let mut buff = vec![FftComplex::new(0.0, 0.0); fft_size];
+        let kernels = frequencies
+            .iter()
+            .map(|&frequency| {
+                // Windowed complex sine centred in the frame, scaled so a sine of amplitude A comes out as A / 2
+                let length = ((q * sample_rate as f64 / frequency).ceil() as usize).min(fft_size);
+                let win = config.window.periodic(length);
+                let sum: f64 = win.iter().sum();
+                let start = (fft_size - length) / 2;
+                buff.fill(FftComplex::new(0.0, 0.0));
+                for (n, w) in win.iter().enumerate() {
+                    let phase = 2.0 * PI * frequency * n as f64 / sample_rate as f64;
+                    buff[start + n] = FftComplex::from_polar(w / sum, phase);
+                }
+                fft.process(&mut buff);
+
+                // sum x[n] conj(k[n]) = sum X[j] conj(K[j]) / N
+                let threshold = buff.iter().map(|x| x.norm()).fold(0.0, f64::max) * SPARSITY;
+                let significant = |x: &FftComplex<f64>| x.norm() >= threshold;
+                let first_bin = buff.iter().position(significant).unwrap_or(0);
+                let last_bin = buff.iter().rposition(significant).unwrap_or(0);
+                let weights = buff[first_bin..=last_bin]
+                    .iter()
+                    .map(|x| {
+                        let x = x.conj() / fft_size as f64;
+                        Complex::new(T::cast(x.re), T::cast(x.im))
+                    })
+                    .collect();
+                Kernel { first_bin, weights }
+            })
+            .collect();
+
+        Ok(ConstantQ {
+            kernels,
+            frequencies,
+            fft_size,
+            sample_rate,
+            config: *config,
+        })
+    }
+
+    // Magnitudes of frames centred every hop_size samples, the signal is taken as zero past both ends
+    pub fn spectrogram(&self, samples: &[T]) -> CqtSpectrogram {
+        let mut planner: FftPlanner<T> = FftPlanner::new();
+        let fft = planner.plan_fft_forward(self.fft_size);
+        let mut buff = vec![FftComplex::new(T::ZERO, T::ZERO); self.fft_size];
+        let half = self.fft_size / 2;
+
+        let hop = self.config.hop_size;
+        let frames = (0..samples.len().div_ceil(hop))
+            .map(|frame| {
+                let centre = frame * hop;
+                for (i, x) in buff.iter_mut().enumerate() {
+                    let sample = (centre + i)
+                        .checked_sub(half)
+                        .and_then(|n| samples.get(n))
+                        .copied()
+                        .unwrap_or(T::ZERO);
+                    *x = FftComplex::new(sample, T::ZERO);
+                }
+                fft.process(&mut buff);
+
+                let spectrum = from_num_complex_slice(&buff);
+                self.kernels
+                    .iter()
+                    .map(|kernel| {
+                        let bins =
+                            &spectrum[kernel.first_bin..kernel.first_bin + kernel.weights.len()];
+                        T::complex_mac(bins, &kernel.weights).abs().as_f64()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        CqtSpectrogram {
+            frames,
+            frequencies: self.frequencies.clone(),
+            sample_rate: self.sample_rate,
+            config: self.config,
+        }
+    }
+
+    // Centre frequency of every bin
+    pub fn frequencies(&self) -> &[f64] {
+        &self.frequencies
+    }
+
+    // Length of the FFT of every frame, the next power of two above the longest kernel
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    // Kernel bins kept over all the bins of the transform, the work per frame besides the FFT
+    pub fn num_weights(&self) -> usize {
+        self.kernels.iter().map(|k| k.weights.len()).sum()
+    }
+}
+
+// Constant-Q magnitudes of every frame, one row per frame from the lowest bin up
+#[derive(Debug, Clone, PartialEq)]
+pub struct CqtSpectrogram {
+    frames: Vec<Vec<f64>>,
+    frequencies: Vec<f64>,
+    sample_rate: u32,
+    config: CqtConfig,
+}
+
+impl CqtSpectrogram {
+    pub fn frames(&self) -> &[Vec<f64>] {
+        &self.frames
+    }
+
+    pub fn frame(&self, index: usize) -> &[f64] {
+        &self.frames[index]
+    }
+
+    pub fn frequencies(&self) -> &[f64] {
+        &self.frequencies
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn config(&self) -> &CqtConfig {
+        &self.config
+    }
+}
+
+impl TimeFrequency for CqtSpectrogram {
+    fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn num_bins(&self) -> usize {
+        self.frequencies.len()
+    }
+
+    fn magnitude(&self, frame: usize, bin: usize) -> f64 {
+        self.frames[frame][bin]
+    }
+
+    // Frames are centred on their time rather than starting at it
+    fn frame_time(&self, frame: usize) -> f64 {
+        (frame * self.config.hop_size) as f64 / self.sample_rate as f64
+    }
+
+    fn frames_per_second(&self) -> f64 {
+        self.sample_rate as f64 / self.config.hop_size as f64
+    }
+
+    fn bin_frequency(&self, bin: f64) -> f64 {
+        self.config.min_frequency * 2f64.powf(bin / self.config.bins_per_octave as f64)
+    }
+
+    // -inf for 0Hz, callers clamp the bin anyway
+    fn frequency_bin(&self, frequency: f64) -> f64 {
+        self.config.bins_per_octave as f64 * (frequency / self.config.min_frequency).log2()
+    }
+
+    fn frequency_range(&self) -> (f64, f64) {
+        let last = self.frequencies.len().saturating_sub(1);
+        (self.bin_frequency(0.0), self.bin_frequency(last as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::midi_to_frequency;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    fn tone(frequency: f64, amplitude: f64, seconds: f64) -> Vec<f64> {
+        (0..(SAMPLE_RATE as f64 * seconds) as usize)
+            .map(|n| amplitude * (2.0 * PI * frequency * n as f64 / SAMPLE_RATE as f64).sin())
+            .collect()
+    }
+
+    fn loudest(frame: &[f64]) -> usize {
+        (0..frame.len())
+            .max_by(|&a, &b| frame[a].total_cmp(&frame[b]))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bins() {
+        let config = CqtConfig {
+            bins_per_octave: 12,
+            max_frequency: Some(4186.0),
+            ..CqtConfig::default()
+        };
+        let cqt: ConstantQ = ConstantQ::new(&config, SAMPLE_RATE).unwrap();
+
+        // C1 to C8 in semitones
+        assert_eq!(cqt.frequencies().len(), 7 * 12 + 1);
+        for (k, frequency) in cqt.frequencies().iter().enumerate() {
+            let expected = midi_to_frequency(24.0 + k as f64);
+            assert!((frequency / expected - 1.0).abs() < 1e-4, "{}", k);
+        }
+
+        // The lowest kernel is Q = 16.8 periods of 32.7Hz long
+        assert_eq!(cqt.fft_size(), 16384);
+        // The kernels are sparse, all of them together hold fewer bins than two full ones would
+        assert!(cqt.num_weights() < 2 * cqt.fft_size());
+
+        // Top bins stay below Nyquist without a maximum
+        let config = CqtConfig {
+            min_frequency: 1000.0,
+            ..CqtConfig::default()
+        };
+        let cqt: ConstantQ = ConstantQ::new(&config, SAMPLE_RATE).unwrap();
+        let top = *cqt.frequencies().last().unwrap();
+        assert!(top < 11025.0 && top > 10000.0);
+    }
+
+    #[test]
+    fn test_tone() {
+        let config = CqtConfig {
+            bins_per_octave: 12,
+            min_frequency: 55.0,
+            max_frequency: Some(4000.0),
+            ..CqtConfig::default()
+        };
+        let cqt: ConstantQ = ConstantQ::new(&config, SAMPLE_RATE).unwrap();
+        for (frequency, bin) in [(110.0, 12), (440.0, 36), (1760.0, 60), (82.41, 7)] {
+            let spectrogram = cqt.spectrogram(&tone(frequency, 0.5, 1.0));
+            let middle = spectrogram.frame(spectrogram.num_frames() / 2);
+            assert_eq!(loudest(middle), bin, "{}Hz", frequency);
+            // Scaled so a sine of amplitude A is A / 2
+            assert!(
+                (middle[bin] - 0.25).abs() < 0.01,
+                "{}Hz: {}",
+                frequency,
+                middle[bin]
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches_direct_correlation() {
+        // The sparse kernels give the same as correlating with the full kernel in time
+        let config = CqtConfig {
+            bins_per_octave: 12,
+            min_frequency: 100.0,
+            max_frequency: Some(2000.0),
+            ..CqtConfig::default()
+        };
+        let cqt: ConstantQ = ConstantQ::new(&config, SAMPLE_RATE).unwrap();
+        let samples: Vec<f64> = (0..SAMPLE_RATE as usize)
+            .map(|n| ((n * 7919) % 1000) as f64 / 500.0 - 1.0)
+            .collect();
+        let spectrogram = cqt.spectrogram(&samples);
+
+        let q = 1.0 / (2f64.powf(1.0 / 12.0) - 1.0);
+        let frame = 20;
+        let centre = frame * config.hop_size;
+        let mut direct = Vec::new();
+        for &frequency in cqt.frequencies() {
+            let length = (q * SAMPLE_RATE as f64 / frequency).ceil() as usize;
+            let win = Window::Hann.periodic(length);
+            let sum: f64 = win.iter().sum();
+            let start = centre - cqt.fft_size() / 2 + (cqt.fft_size() - length) / 2;
+            let bin: Complex = win
+                .iter()
+                .enumerate()
+                .map(|(n, w)| {
+                    let phase = 2.0 * PI * frequency * n as f64 / SAMPLE_RATE as f64;
+                    Complex::from_polar(w / sum, -phase) * samples[start + n]
+                })
+                .sum();
+            direct.push(bin.abs());
+        }
+
+        // Up to the leakage of the sidelobes that were dropped
+        let max = direct.iter().copied().fold(0.0, f64::max);
+        for (k, (sparse, direct)) in spectrogram.frame(frame).iter().zip(&direct).enumerate() {
+            assert!(
+                (sparse - direct).abs() < 0.005 * max,
+                "bin {}: {} != {}",
+                k,
+                sparse,
+                direct
+            );
+        }
+    }
+
+    #[test]
+    fn test_frames() {
+        let config = CqtConfig {
+            min_frequency: 110.0,
+            max_frequency: Some(2000.0),
+            hop_size: 256,
+            ..CqtConfig::default()
+        };
+        let spectrogram = config
+            .spectrogram(&tone(440.0, 1.0, 1.0), SAMPLE_RATE)
+            .unwrap();
+        assert_eq!(
+            spectrogram.num_frames(),
+            (SAMPLE_RATE as usize).div_ceil(256)
+        );
+        assert_eq!(spectrogram.frame_time(4), 1024.0 / SAMPLE_RATE as f64);
+        assert!((spectrogram.bin_frequency(48.0) - 440.0).abs() < 1e-9);
+        assert!((spectrogram.frequency_bin(440.0) - 48.0).abs() < 1e-9);
+        let (low, high) = spectrogram.frequency_range();
+        assert_eq!(low, 110.0);
+        assert_eq!(high, *spectrogram.frequencies().last().unwrap());
+
+        // f32 samples give the same bins
+        let single: Vec<f32> = tone(440.0, 1.0, 1.0).iter().map(|&x| x as f32).collect();
+        let single = config.spectrogram(&single, SAMPLE_RATE).unwrap();
+        let middle = single.num_frames() / 2;
+        assert_eq!(loudest(single.frame(middle)), 48);
+        assert!((single.frame(middle)[48] - spectrogram.frame(middle)[48]).abs() < 1e-4);
+
+        assert!(
+            config
+                .spectrogram::<f64>(&[], SAMPLE_RATE)
+                .unwrap()
+                .frames()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        for config in [
+            CqtConfig {
+                bins_per_octave: 0,
+                ..CqtConfig::default()
+            },
+            CqtConfig {
+                hop_size: 0,
+                ..CqtConfig::default()
+            },
+            CqtConfig {
+                min_frequency: 0.0,
+                ..CqtConfig::default()
+            },
+            CqtConfig {
+                min_frequency: 20000.0,
+                ..CqtConfig::default()
+            },
+            CqtConfig {
+                min_frequency: 1000.0,
+                max_frequency: Some(500.0),
+                ..CqtConfig::default()
+            },
+        ] {
+            assert!(
+                ConstantQ::<f64>::new(&config, SAMPLE_RATE).is_err(),
+                "{:?}",
+                config
+            );
+        }
+    }
+}
@@ -0,0 +1,780 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use memmap2::Mmap;
+
+use crate::audio::{ReaderExt, WavConfig};
+use crate::error::{Error, Result};
+use crate::fingerprint::{FORMAT_VERSION, Fingerprints, HashLayout};
+use crate::index::{self, HashHit, HashLookup, Match, Posting, TrackId};
+use crate::key::Key;
+
+// On disk fingerprint database
+//
+// All numbers are little endian, the file is laid out as
+//   header   80 bytes, see Header::write
+//   tracks   one variable sized record per track, parsed into memory on open
+//   postings (hash u32, track u32, time u32) records sorted by hash, track and time
+//
+// Only the header and the track table are read on open, the postings are memory mapped
+// and binary searched so a query touches just the pages of the hashes it looks up
+const MAGIC: &[u8; 8] = b"EARWORM\0";
+// Version 2 added the key of the tracks, version 1 files still open with no keys
+pub const DATABASE_VERSION: u32 = 2;
+const NO_KEY: u8 = 0xff;
+const HEADER_SIZE: usize = 80;
+const POSTING_SIZE: usize = 12;
+
+// Metadata of an indexed track, taken from the WAV header
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackInfo {
+    pub name: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    // In seconds
+    pub duration: f64,
+    // Number of postings of the track, filled in when the track is added
+    pub hashes: u32,
+    // Estimated from the chroma while indexing, None when it could not be told
+    pub key: Option<Key>,
+}
+
+impl TrackInfo {
+    pub fn from_wav(name: &str, config: &WavConfig) -> Self {
+        TrackInfo {
+            name: name.to_string(),
+            sample_rate: config.fmt().sample_rate,
+            channels: config.fmt().channels,
+            bits_per_sample: config.fmt().bits_per_sample,
+            duration: config.duration_secs(),
+            hashes: 0,
+            key: None,
+        }
+    }
+
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        let name = self.name.as_bytes();
+        let name_len: u16 = name
+            .len()
+            .try_into()
+            .map_err(|_| Error::UnsupportedFormat("Track name too long"))?;
+
+        writer.write_all(&name_len.to_le_bytes())?;
+        writer.write_all(name)?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&self.channels.to_le_bytes())?;
+        writer.write_all(&self.bits_per_sample.to_le_bytes())?;
+        writer.write_all(&self.duration.to_le_bytes())?;
+        writer.write_all(&self.hashes.to_le_bytes())?;
+        writer.write_all(&[self.key.map_or(NO_KEY, |key| key.index())])?;
+        Ok(())
+    }
+
+    fn read(reader: &mut impl Read, version: u32) -> Result<Self> {
+        let name_len = reader.read_le_u16()?;
+        let mut name = vec![0u8; name_len as usize];
+        reader.read_exact(&mut name)?;
+        let name =
+            String::from_utf8(name).map_err(|_| Error::Corrupted("Track name is not UTF-8"))?;
+
+        let mut info = TrackInfo {
+            name,
+            sample_rate: reader.read_le_u32()?,
+            channels: reader.read_le_u16()?,
+            bits_per_sample: reader.read_le_u16()?,
+            duration: f64::from_bits(reader.read_le_u64()?),
+            hashes: reader.read_le_u32()?,
+            key: None,
+        };
+        if version >= 2 {
+            let mut key = [0u8];
+            reader.read_exact(&mut key)?;
+            if key[0] != NO_KEY {
+                info.key = Some(Key::from_index(key[0]).ok_or(Error::Corrupted("Invalid key"))?);
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+struct Header {
+    version: u32,
+    fingerprint_version: u32,
+    layout: HashLayout,
+    frames_per_second: f64,
+    track_count: u32,
+    postings_offset: u64,
+    posting_count: u64,
+    tracks_checksum: u64,
+    postings_checksum: u64,
+}
+
+impl Header {
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        writer.write_all(&self.fingerprint_version.to_le_bytes())?;
+        writer.write_all(&self.layout.frequency_bits().to_le_bytes())?;
+        writer.write_all(&self.layout.delta_bits().to_le_bytes())?;
+        writer.write_all(&self.frames_per_second.to_le_bytes())?;
+        writer.write_all(&self.track_count.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?; // Reserved
+        writer.write_all(&(HEADER_SIZE as u64).to_le_bytes())?; // Tracks offset
+        writer.write_all(&self.postings_offset.to_le_bytes())?;
+        writer.write_all(&self.posting_count.to_le_bytes())?;
+        writer.write_all(&self.tracks_checksum.to_le_bytes())?;
+        writer.write_all(&self.postings_checksum.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self> {
+        if &reader.read_8_bytes()? != MAGIC {
+            return Err(Error::InvalidFormat("Not an earworm database"));
+        }
+        let version = reader.read_le_u32()?;
+        if !(1..=DATABASE_VERSION).contains(&version) {
+            return Err(Error::UnsupportedFormat("Unsupported database version"));
+        }
+
+        let fingerprint_version = reader.read_le_u32()?;
+        let frequency_bits = reader.read_le_u32()?;
+        let delta_bits = reader.read_le_u32()?;
+        let layout = HashLayout::new(frequency_bits, delta_bits)
+            .ok_or(Error::Corrupted("Invalid hash layout"))?;
+        let frames_per_second = f64::from_bits(reader.read_le_u64()?);
+        let track_count = reader.read_le_u32()?;
+        let _reserved = reader.read_le_u32()?;
+        if reader.read_le_u64()? != HEADER_SIZE as u64 {
+            return Err(Error::Corrupted("Invalid track table offset"));
+        }
+
+        Ok(Header {
+            version,
+            fingerprint_version,
+            layout,
+            frames_per_second,
+            track_count,
+            postings_offset: reader.read_le_u64()?,
+            posting_count: reader.read_le_u64()?,
+            tracks_checksum: reader.read_le_u64()?,
+            postings_checksum: reader.read_le_u64()?,
+        })
+    }
+}
+
+pub struct Database {
+    mmap: Mmap,
+    header: Header,
+    tracks: Vec<TrackInfo>,
+}
+
+impl Database {
+    // Maps the file and checks its structure and the track table checksum,
+    // the postings are only checked by verify() since that reads the whole file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the database is only ever replaced by renaming a new file over it, never modified in place,
+        // so the mapped pages stay valid for as long as we hold the map
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_SIZE {
+            return Err(Error::Corrupted("File is smaller than the header"));
+        }
+        let header = Header::read(&mut &mmap[..HEADER_SIZE])?;
+
+        let postings_offset: usize = header
+            .postings_offset
+            .try_into()
+            .map_err(|_| Error::Corrupted("Invalid postings offset"))?;
+        if postings_offset < HEADER_SIZE || postings_offset > mmap.len() {
+            return Err(Error::Corrupted("Invalid postings offset"));
+        }
+        let postings_size = (mmap.len() - postings_offset) as u64;
+        if header.posting_count.checked_mul(POSTING_SIZE as u64) != Some(postings_size) {
+            return Err(Error::Corrupted("Postings do not match the file size"));
+        }
+
+        let table = &mmap[HEADER_SIZE..postings_offset];
+        if checksum(table) != header.tracks_checksum {
+            return Err(Error::Corrupted("Track table checksum mismatch"));
+        }
+        let mut reader = table;
+        let tracks = (0..header.track_count)
+            .map(|_| TrackInfo::read(&mut reader, header.version))
+            .collect::<Result<Vec<TrackInfo>>>()?;
+        if !reader.is_empty() {
+            return Err(Error::Corrupted("Unexpected data after the track table"));
+        }
+
+        let hashes: u64 = tracks.iter().map(|t| t.hashes as u64).sum();
+        if hashes != header.posting_count {
+            return Err(Error::Corrupted(
+                "Track hash counts do not match the postings",
+            ));
+        }
+
+        Ok(Database {
+            mmap,
+            header,
+            tracks,
+        })
+    }
+
+    // Reads all postings and checks their checksum, order and track ids
+    pub fn verify(&self) -> Result<()> {
+        if checksum(self.postings_bytes()) != self.header.postings_checksum {
+            return Err(Error::Corrupted("Postings checksum mismatch"));
+        }
+
+        let mut previous = None;
+        for i in 0..self.num_postings() {
+            let record = self.record(i);
+            if record.1 as usize >= self.tracks.len() {
+                return Err(Error::Corrupted("Posting refers to an unknown track"));
+            }
+            if previous.is_some_and(|p| p > record) {
+                return Err(Error::Corrupted("Postings are not sorted"));
+            }
+            previous = Some(record);
+        }
+
+        Ok(())
+    }
+
+    pub fn query(&self, fingerprints: &Fingerprints, min_matches: usize) -> Result<Vec<Match>> {
+        self.check_version()?;
+        let format = self.format();
+        index::check_format(format, fingerprints)?;
+        let Some((_, frames_per_second)) = format else {
+            return Ok(Vec::new());
+        };
+
+        Ok(index::rank_matches(
+            self,
+            fingerprints,
+            frames_per_second,
+            min_matches,
+        ))
+    }
+
+    // Every query hash found in the track, what a match is voted from
+    pub fn hits(&self, fingerprints: &Fingerprints, track: TrackId) -> Result<Vec<HashHit>> {
+        self.check_version()?;
+        index::check_format(self.format(), fingerprints)?;
+        Ok(index::hash_hits(self, fingerprints, track))
+    }
+
+    // Databases written by an older fingerprint version can still be opened, but not queried or extended
+    pub fn check_version(&self) -> Result<()> {
+        if self.header.fingerprint_version != FORMAT_VERSION {
+            return Err(Error::Incompatible(
+                "Database was built with another fingerprint version",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn tracks(&self) -> &[TrackInfo] {
+        &self.tracks
+    }
+
+    pub fn track(&self, track: TrackId) -> Option<&TrackInfo> {
+        self.tracks.get(track as usize)
+    }
+
+    pub fn num_postings(&self) -> usize {
+        self.header.posting_count as usize
+    }
+
+    // Layout and frame rate of the indexed fingerprints, None while the database is empty
+    pub fn format(&self) -> Option<(HashLayout, f64)> {
+        if self.tracks.is_empty() {
+            return None;
+        }
+
+        Some((self.header.layout, self.header.frames_per_second))
+    }
+
+    fn postings_bytes(&self) -> &[u8] {
+        &self.mmap[self.header.postings_offset as usize..]
+    }
+
+    // (hash, track, time) of the i-th posting
+    fn record(&self, i: usize) -> (u32, u32, u32) {
+        let start = i * POSTING_SIZE;
+        let bytes = &self.postings_bytes()[start..start + POSTING_SIZE];
+        let field = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        (field(0), field(4), field(8))
+    }
+
+    // Index of the first posting with a hash >= the given one
+    fn lower_bound(&self, hash: u32) -> usize {
+        let (mut low, mut high) = (0, self.num_postings());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.record(mid).0 < hash {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        low
+    }
+}
+
+impl HashLookup for Database {
+    fn lookup(&self, hash: u32, postings: &mut Vec<Posting>) {
+        let records = (self.lower_bound(hash)..self.num_postings()).map(|i| self.record(i));
+        postings.extend(
+            records
+                .take_while(|record| record.0 == hash)
+                .map(|(_, track, time)| Posting { track, time }),
+        );
+    }
+}
+
+// Collects new tracks and writes them together with the tracks of an existing database to a new file
+//
+// Existing postings are merged straight from the map so growing a large database does not load it into memory
+#[derive(Default)]
+pub struct DatabaseBuilder {
+    base: Option<Database>,
+    tracks: Vec<TrackInfo>,
+    // (hash, track, time) of the new tracks
+    postings: Vec<(u32, u32, u32)>,
+    format: Option<(HashLayout, f64)>,
+    // Names of the base and new tracks, index asks for every file it finds
+    names: HashSet<String>,
+}
+
+impl DatabaseBuilder {
+    pub fn new() -> Self {
+        DatabaseBuilder::default()
+    }
+
+    // The postings of the base are copied into the new file under a new checksum, so they are verified first
+    pub fn from_database(database: Database) -> Result<Self> {
+        database.check_version()?;
+        database.verify()?;
+        Ok(DatabaseBuilder {
+            format: database.format(),
+            names: database.tracks.iter().map(|t| t.name.clone()).collect(),
+            base: Some(database),
+            ..DatabaseBuilder::default()
+        })
+    }
+
+    pub fn add(&mut self, info: TrackInfo, fingerprints: &Fingerprints) -> Result<TrackId> {
+        index::check_format(self.format, fingerprints)?;
+        if self.format.is_none() {
+            self.format = Some((fingerprints.layout, fingerprints.frames_per_second));
+        }
+
+        let base_tracks = self.base.as_ref().map_or(0, |db| db.tracks.len());
+        let track = (base_tracks + self.tracks.len()) as TrackId;
+        let hashes = fingerprints
+            .hashes
+            .len()
+            .try_into()
+            .map_err(|_| Error::UnsupportedFormat("Too many hashes in a track"))?;
+
+        self.names.insert(info.name.clone());
+        self.tracks.push(TrackInfo { hashes, ..info });
+        self.postings
+            .extend(fingerprints.hashes.iter().map(|f| (f.hash, track, f.time)));
+
+        Ok(track)
+    }
+
+    pub fn num_tracks(&self) -> usize {
+        self.base.as_ref().map_or(0, |db| db.tracks.len()) + self.tracks.len()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    // Writes to a temporary file next to the target and renames it over the target once complete,
+    // so a crash never leaves a half written database behind and open maps of the old file stay valid
+    pub fn write(mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut temp = PathBuf::from(path);
+        temp.as_mut_os_string().push(".tmp");
+
+        // A failed write removes what it wrote, the next run would find a stale temporary file otherwise
+        let result = self
+            .write_temp(&temp)
+            .and_then(|()| fs::rename(&temp, path).map_err(Error::from));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    fn write_temp(&mut self, temp: &Path) -> Result<()> {
+        self.postings.sort_unstable();
+        let base_tracks: &[TrackInfo] = self.base.as_ref().map_or(&[], |db| &db.tracks);
+        let track_count: u32 = (base_tracks.len() + self.tracks.len())
+            .try_into()
+            .map_err(|_| Error::UnsupportedFormat("Too many tracks"))?;
+
+        let mut writer = BufWriter::new(File::create(temp)?);
+        writer.write_all(&[0u8; HEADER_SIZE])?; // Header is written last once the checksums are known
+
+        let mut table = Vec::new();
+        for track in base_tracks.iter().chain(&self.tracks) {
+            track.write(&mut table)?;
+        }
+        writer.write_all(&table)?;
+
+        let mut postings_checksum = Checksum::new();
+        let mut posting_count = 0u64;
+        let base_records = self
+            .base
+            .as_ref()
+            .map(|db| (0..db.num_postings()).map(|i| db.record(i)));
+        let mut base_records = base_records.into_iter().flatten().peekable();
+        let mut new_records = self.postings.iter().copied().peekable();
+        loop {
+            // Merge of the two sorted lists
+            let record = match (base_records.peek(), new_records.peek()) {
+                (Some(a), Some(b)) if a <= b => base_records.next(),
+                (Some(_), None) => base_records.next(),
+                (_, Some(_)) => new_records.next(),
+                (None, None) => None,
+            };
+            let Some((hash, track, time)) = record else {
+                break;
+            };
+
+            let mut bytes = [0u8; POSTING_SIZE];
+            bytes[0..4].copy_from_slice(&hash.to_le_bytes());
+            bytes[4..8].copy_from_slice(&track.to_le_bytes());
+            bytes[8..12].copy_from_slice(&time.to_le_bytes());
+            writer.write_all(&bytes)?;
+            postings_checksum.update(&bytes);
+            posting_count += 1;
+        }
+
+        let (layout, frames_per_second) = self.format.unwrap_or((HashLayout::default(), 0.0));
+        let header = Header {
+            version: DATABASE_VERSION,
+            fingerprint_version: FORMAT_VERSION,
+            layout,
+            frames_per_second,
+            track_count,
+            postings_offset: (HEADER_SIZE + table.len()) as u64,
+            posting_count,
+            tracks_checksum: checksum(&table),
+            postings_checksum: postings_checksum.0,
+        };
+        writer.seek(SeekFrom::Start(0))?;
+        header.write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+// 64 bit FNV-1a, not cryptographic but plenty to catch truncated or bit flipped files
+struct Checksum(u64);
+
+impl Checksum {
+    fn new() -> Self {
+        Checksum(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut checksum = Checksum::new();
+    checksum.update(bytes);
+    checksum.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::Fingerprint;
+    use crate::key::Mode;
+
+    // Unique file in the temp directory, removed when dropped
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("earworm-{}-{}.db", name, std::process::id()));
+            TempPath(path)
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn info(name: &str) -> TrackInfo {
+        TrackInfo {
+            name: name.to_string(),
+            sample_rate: 44100,
+            channels: 2,
+            bits_per_sample: 16,
+            duration: 180.5,
+            hashes: 0,
+            key: None,
+        }
+    }
+
+    fn fingerprints(seed: u32, frames: u32) -> Fingerprints {
+        let hashes = (0..frames)
+            .map(|time| Fingerprint {
+                // Spreads the hashes out while keeping them reproducible
+                hash: (time.wrapping_add(seed * 7919)).wrapping_mul(2_654_435_761),
+                time,
+            })
+            .collect();
+
+        Fingerprints {
+            version: FORMAT_VERSION,
+            layout: HashLayout::default(),
+            frames_per_second: 86.0,
+            hashes,
+        }
+    }
+
+    fn excerpt(fingerprints: &Fingerprints, start: u32, frames: u32) -> Fingerprints {
+        Fingerprints {
+            hashes: fingerprints
+                .hashes
+                .iter()
+                .filter(|f| f.time >= start && f.time < start + frames)
+                .map(|f| Fingerprint {
+                    hash: f.hash,
+                    time: f.time - start,
+                })
+                .collect(),
+            ..fingerprints.clone()
+        }
+    }
+
+    #[test]
+    fn test_write_and_open() {
+        let path = TempPath::new("roundtrip");
+        let mut builder = DatabaseBuilder::new();
+        builder.add(info("a"), &fingerprints(1, 500)).unwrap();
+        let b = TrackInfo {
+            key: Some(Key::new(9, Mode::Minor)),
+            ..info("b")
+        };
+        builder.add(b, &fingerprints(2, 300)).unwrap();
+        builder.write(&path.0).unwrap();
+
+        let database = Database::open(&path.0).unwrap();
+        database.verify().unwrap();
+        assert_eq!(database.tracks().len(), 2);
+        assert_eq!(database.num_postings(), 800);
+        assert_eq!(database.track(1).unwrap().name, "b");
+        assert_eq!(database.track(1).unwrap().hashes, 300);
+        assert_eq!(database.track(0).unwrap().duration, 180.5);
+        assert_eq!(database.track(0).unwrap().key, None);
+        assert_eq!(
+            database.track(1).unwrap().key,
+            Some(Key::new(9, Mode::Minor))
+        );
+        assert_eq!(database.format(), Some((HashLayout::default(), 86.0)));
+    }
+
+    #[test]
+    fn test_version_1() {
+        let path = TempPath::new("version1");
+        let mut builder = DatabaseBuilder::new();
+        builder.add(info("a"), &fingerprints(1, 50)).unwrap();
+        builder.write(&path.0).unwrap();
+
+        // The same file as version 1 wrote it, without the key byte at the end of the track table
+        let mut bytes = fs::read(&path.0).unwrap();
+        let field = |bytes: &[u8], offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+        };
+        let postings_offset = field(&bytes, 48) as usize;
+        bytes.remove(postings_offset - 1);
+        bytes[8..12].copy_from_slice(&1u32.to_le_bytes());
+        bytes[48..56].copy_from_slice(&(postings_offset as u64 - 1).to_le_bytes());
+        let table_checksum = checksum(&bytes[HEADER_SIZE..postings_offset - 1]);
+        bytes[64..72].copy_from_slice(&table_checksum.to_le_bytes());
+        fs::write(&path.0, &bytes).unwrap();
+
+        let database = Database::open(&path.0).unwrap();
+        database.verify().unwrap();
+        assert_eq!(database.track(0).unwrap().name, "a");
+        assert_eq!(database.track(0).unwrap().key, None);
+
+        // and gets upgraded when tracks are added
+        let mut builder = DatabaseBuilder::from_database(database).unwrap();
+        builder.add(info("b"), &fingerprints(2, 50)).unwrap();
+        builder.write(&path.0).unwrap();
+        let bytes = fs::read(&path.0).unwrap();
+        assert_eq!(&bytes[8..12], &DATABASE_VERSION.to_le_bytes());
+        assert_eq!(Database::open(&path.0).unwrap().tracks().len(), 2);
+    }
+
+    #[test]
+    fn test_query() {
+        let path = TempPath::new("query");
+        let tracks: Vec<Fingerprints> = (0..4).map(|seed| fingerprints(seed, 1000)).collect();
+        let mut builder = DatabaseBuilder::new();
+        for (i, track) in tracks.iter().enumerate() {
+            builder.add(info(&i.to_string()), track).unwrap();
+        }
+        builder.write(&path.0).unwrap();
+
+        let database = Database::open(&path.0).unwrap();
+        let matches = database.query(&excerpt(&tracks[2], 430, 200), 5).unwrap();
+        assert_eq!(matches[0].track, 2);
+        assert_eq!(matches[0].count, 200);
+        assert!((matches[0].offset - 430.0 / 86.0).abs() < 1e-12);
+
+        let hits = database.hits(&excerpt(&tracks[2], 430, 200), 2).unwrap();
+        assert_eq!(hits.len(), 200);
+        assert!(hits.iter().all(|hit| hit.offset() == 430));
+
+        let mut postings = Vec::new();
+        database.lookup(tracks[1].hashes[10].hash, &mut postings);
+        assert!(postings.contains(&Posting { track: 1, time: 10 }));
+    }
+
+    #[test]
+    fn test_incremental_build() {
+        let path = TempPath::new("incremental");
+        let mut builder = DatabaseBuilder::new();
+        builder.add(info("first"), &fingerprints(1, 400)).unwrap();
+        builder.write(&path.0).unwrap();
+
+        let mut builder = DatabaseBuilder::from_database(Database::open(&path.0).unwrap()).unwrap();
+        let second = fingerprints(2, 400);
+        assert_eq!(builder.add(info("second"), &second).unwrap(), 1);
+        assert_eq!(builder.num_tracks(), 2);
+        assert!(builder.contains("first") && builder.contains("second"));
+        assert!(!builder.contains("third"));
+        builder.write(&path.0).unwrap();
+
+        let database = Database::open(&path.0).unwrap();
+        database.verify().unwrap();
+        assert_eq!(database.tracks().len(), 2);
+        assert_eq!(database.num_postings(), 800);
+        let matches = database.query(&excerpt(&second, 100, 100), 5).unwrap();
+        assert_eq!(matches[0].track, 1);
+        let matches = database
+            .query(&excerpt(&fingerprints(1, 400), 0, 100), 5)
+            .unwrap();
+        assert_eq!(matches[0].track, 0);
+    }
+
+    #[test]
+    fn test_empty_database() {
+        let path = TempPath::new("empty");
+        DatabaseBuilder::new().write(&path.0).unwrap();
+
+        let database = Database::open(&path.0).unwrap();
+        database.verify().unwrap();
+        assert!(database.format().is_none());
+        assert!(database.query(&fingerprints(1, 10), 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failed_write() {
+        let path = TempPath::new("failed");
+        let mut temp = path.0.clone();
+        temp.as_mut_os_string().push(".tmp");
+
+        // The name length only overflows once the temporary file is open
+        let mut builder = DatabaseBuilder::new();
+        builder
+            .add(info(&"x".repeat(70_000)), &fingerprints(1, 10))
+            .unwrap();
+        assert!(matches!(
+            builder.write(&path.0),
+            Err(Error::UnsupportedFormat(_))
+        ));
+        assert!(!temp.exists());
+        assert!(!path.0.exists());
+    }
+
+    #[test]
+    fn test_incompatible_fingerprints() {
+        let mut builder = DatabaseBuilder::new();
+        builder.add(info("a"), &fingerprints(1, 10)).unwrap();
+        let mut other = fingerprints(2, 10);
+        other.frames_per_second = 43.0;
+        assert!(matches!(
+            builder.add(info("b"), &other),
+            Err(Error::Incompatible(_))
+        ));
+    }
+
+    #[test]
+    fn test_integrity_checks() {
+        let path = TempPath::new("corrupt");
+        let mut builder = DatabaseBuilder::new();
+        builder.add(info("a"), &fingerprints(1, 100)).unwrap();
+        builder.write(&path.0).unwrap();
+        let original = fs::read(&path.0).unwrap();
+
+        // Flipped bit in the postings is only caught by verify, and by adding to the database
+        let mut bytes = original.clone();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        fs::write(&path.0, &bytes).unwrap();
+        let database = Database::open(&path.0).unwrap();
+        assert!(matches!(database.verify(), Err(Error::Corrupted(_))));
+        assert!(matches!(
+            DatabaseBuilder::from_database(database),
+            Err(Error::Corrupted(_))
+        ));
+
+        // Flipped bit in the track name
+        let mut bytes = original.clone();
+        bytes[HEADER_SIZE + 2] ^= 0x01;
+        fs::write(&path.0, &bytes).unwrap();
+        assert!(matches!(Database::open(&path.0), Err(Error::Corrupted(_))));
+
+        // Truncated file
+        fs::write(&path.0, &original[..original.len() - 5]).unwrap();
+        assert!(matches!(Database::open(&path.0), Err(Error::Corrupted(_))));
+
+        // Wrong magic
+        let mut bytes = original.clone();
+        bytes[0] = b'X';
+        fs::write(&path.0, &bytes).unwrap();
+        assert!(matches!(
+            Database::open(&path.0),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        fs::write(&path.0, &original[..10]).unwrap();
+        assert!(Database::open(&path.0).is_err());
+    }
+}
@@ -1,36 +1,40 @@
 use std::{fmt, io};
 
 #[derive(Debug)]
-pub enum WavError {
+pub enum Error {
     Io(io::Error),
     InvalidFormat(&'static str),
     UnsupportedFormat(&'static str),
     Corrupted(&'static str),
+    Incompatible(&'static str),
+    InvalidArgument(String),
 }
 
 // This allows ? on I/O functions to work because when we use the ? operator it will use the from trait behind the scenes
-impl From<io::Error> for WavError {
+impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
-        WavError::Io(err)
+        Error::Io(err)
     }
 }
 
 // For displaying the error when printing
-impl std::fmt::Display for WavError {
+impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            WavError::Io(e) => write!(f, "I/O error: {}", e),
-            WavError::InvalidFormat(msg) => write!(f, "Invalid format: {}", msg),
-            WavError::UnsupportedFormat(msg) => write!(f, "Unsupported format: {}", msg),
-            WavError::Corrupted(msg) => write!(f, "Corrupted data: {}", msg),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::InvalidFormat(msg) => write!(f, "Invalid format: {}", msg),
+            Error::UnsupportedFormat(msg) => write!(f, "Unsupported format: {}", msg),
+            Error::Corrupted(msg) => write!(f, "Corrupted data: {}", msg),
+            Error::Incompatible(msg) => write!(f, "Incompatible fingerprints: {}", msg),
+            Error::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
         }
     }
 }
 
-// For sourcing the original IO error from WavError
-impl std::error::Error for WavError {
+// For sourcing the original IO error from Error
+impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        if let WavError::Io(e) = self {
+        if let Error::Io(e) = self {
             Some(e)
         } else {
             None
@@ -38,4 +42,4 @@ impl std::error::Error for WavError {
     }
 }
 
-pub type Result<T> = std::result::Result<T, WavError>;
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,336 @@
+use crate::float::Float;
+use crate::peaks::{Peak, PeakPicker};
+use crate::spectrogram::{Spectrogram, StftConfig};
+
+// Bumped whenever the hash layout or the analysis behind it changes, hashes of different versions never match
+pub const FORMAT_VERSION: u32 = 1;
+
+// Bit allocation of the packed (f1, f2, dt) hash, [f1 | f2 | dt] from the most significant bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashLayout {
+    frequency_bits: u32,
+    delta_bits: u32,
+}
+
+impl HashLayout {
+    // Returns None when the hash does not fit in 32 bits
+    pub fn new(frequency_bits: u32, delta_bits: u32) -> Option<Self> {
+        if frequency_bits == 0 || delta_bits == 0 || 2 * frequency_bits + delta_bits > 32 {
+            return None;
+        }
+
+        Some(HashLayout {
+            frequency_bits,
+            delta_bits,
+        })
+    }
+
+    pub fn frequency_bits(&self) -> u32 {
+        self.frequency_bits
+    }
+
+    pub fn delta_bits(&self) -> u32 {
+        self.delta_bits
+    }
+
+    // Frequencies are scaled down to frequency_bits when there are more bins than fit, deltas are saturated
+    pub fn pack(&self, f1: usize, f2: usize, dt: usize, num_bins: usize) -> u32 {
+        let f1 = self.quantise(f1, num_bins);
+        let f2 = self.quantise(f2, num_bins);
+        let dt = (dt as u32).min(mask(self.delta_bits));
+
+        (f1 << (self.frequency_bits + self.delta_bits)) | (f2 << self.delta_bits) | dt
+    }
+
+    // Quantised (f1, f2, dt) of a hash
+    pub fn unpack(&self, hash: u32) -> (u32, u32, u32) {
+        let dt = hash & mask(self.delta_bits);
+        let f2 = (hash >> self.delta_bits) & mask(self.frequency_bits);
+        let f1 = (hash >> (self.frequency_bits + self.delta_bits)) & mask(self.frequency_bits);
+        (f1, f2, dt)
+    }
+
+    fn quantise(&self, bin: usize, num_bins: usize) -> u32 {
+        let levels = 1u64 << self.frequency_bits;
+        if (num_bins as u64) <= levels {
+            return (bin as u32).min(mask(self.frequency_bits));
+        }
+
+        ((bin as u64 * levels / num_bins as u64) as u32).min(mask(self.frequency_bits))
+    }
+}
+
+impl Default for HashLayout {
+    // 10 bits are enough for the 512 bins of a 1024 FFT, 12 bits of frame delta covers any sensible target zone
+    fn default() -> Self {
+        HashLayout {
+            frequency_bits: 10,
+            delta_bits: 12,
+        }
+    }
+}
+
+// Area ahead of an anchor peak where its target peaks are searched for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetZone {
+    // Frames after the anchor
+    pub min_delta: usize,
+    pub max_delta: usize,
+    // Bins above or below the anchor
+    pub max_frequency_delta: usize,
+}
+
+impl Default for TargetZone {
+    fn default() -> Self {
+        TargetZone {
+            min_delta: 1,
+            max_delta: 64,
+            max_frequency_delta: 128,
+        }
+    }
+}
+
+// A hash together with the frame of its anchor peak
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fingerprint {
+    pub hash: u32,
+    pub time: u32,
+}
+
+// All fingerprints of one track
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprints {
+    pub version: u32,
+    pub layout: HashLayout,
+    // Converts anchor frames to seconds
+    pub frames_per_second: f64,
+    pub hashes: Vec<Fingerprint>,
+}
+
+// Constellation map fingerprinting
+//
+// Every spectral peak is used as an anchor and paired with up to fan_out peaks in the target zone ahead of it,
+// each pair is packed to a (f1, f2, dt) hash, which is far more specific than a single peak while still being
+// independent of where in the track the recording started
+#[derive(Debug, Clone, PartialEq)]
+pub struct FingerprintConfig {
+    pub stft: StftConfig,
+    pub peaks: PeakPicker,
+    pub fan_out: usize,
+    pub target_zone: TargetZone,
+    pub layout: HashLayout,
+}
+
+impl Default for FingerprintConfig {
+    fn default() -> Self {
+        FingerprintConfig {
+            stft: StftConfig::default(),
+            peaks: PeakPicker::default(),
+            fan_out: 5,
+            target_zone: TargetZone::default(),
+            layout: HashLayout::default(),
+        }
+    }
+}
+
+impl FingerprintConfig {
+    pub fn fingerprint<T: Float>(&self, samples: &[T], sample_rate: u32) -> Fingerprints {
+        let spectrogram = Spectrogram::new(samples, sample_rate, self.stft);
+        let peaks = self.peaks.spectrogram(&spectrogram);
+
+        Fingerprints {
+            version: FORMAT_VERSION,
+            layout: self.layout,
+            frames_per_second: spectrogram.frames_per_second(),
+            hashes: self.hash_peaks(&peaks, spectrogram.num_bins()),
+        }
+    }
+
+    // Hashes the anchor -> target pairs, peaks have to be sorted by frame
+    pub fn hash_peaks(&self, peaks: &[Peak], num_bins: usize) -> Vec<Fingerprint> {
+        self.pairs(peaks)
+            .into_iter()
+            .map(|(anchor, target)| Fingerprint {
+                hash: self.layout.pack(
+                    anchor.bin,
+                    target.bin,
+                    target.frame - anchor.frame,
+                    num_bins,
+                ),
+                time: anchor.frame as u32,
+            })
+            .collect()
+    }
+
+    // Anchor -> target pairs, the closest targets in time are taken first
+    pub fn pairs(&self, peaks: &[Peak]) -> Vec<(Peak, Peak)> {
+        let zone = self.target_zone;
+        let mut pairs = Vec::new();
+
+        for (i, anchor) in peaks.iter().enumerate() {
+            let targets = peaks[i + 1..]
+                .iter()
+                .take_while(|p| p.frame <= anchor.frame + zone.max_delta)
+                .filter(|p| p.frame >= anchor.frame + zone.min_delta)
+                .filter(|p| p.bin.abs_diff(anchor.bin) <= zone.max_frequency_delta)
+                .take(self.fan_out);
+            pairs.extend(targets.map(|target| (*anchor, *target)));
+        }
+
+        pairs
+    }
+}
+
+fn mask(bits: u32) -> u32 {
+    if bits >= 32 {
+        u32::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn peak(frame: usize, bin: usize) -> Peak {
+        Peak {
+            frame,
+            bin,
+            magnitude: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_layout_validation() {
+        assert!(HashLayout::new(10, 12).is_some());
+        assert!(HashLayout::new(11, 11).is_none());
+        assert!(HashLayout::new(0, 12).is_none());
+        assert!(HashLayout::new(8, 0).is_none());
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let layout = HashLayout::default();
+        let hash = layout.pack(300, 511, 40, 512);
+        assert_eq!(layout.unpack(hash), (300, 511, 40));
+
+        // Deltas saturate instead of wrapping into the frequency bits
+        let hash = layout.pack(1, 2, 10000, 512);
+        assert_eq!(layout.unpack(hash), (1, 2, 4095));
+    }
+
+    #[test]
+    fn test_pack_scales_frequencies() {
+        let layout = HashLayout::new(8, 16).unwrap();
+        // 1024 bins into 256 levels
+        let hash = layout.pack(1023, 4, 1, 1024);
+        assert_eq!(layout.unpack(hash), (255, 1, 1));
+    }
+
+    #[test]
+    fn test_pairs_target_zone() {
+        let config = FingerprintConfig {
+            fan_out: 2,
+            target_zone: TargetZone {
+                min_delta: 1,
+                max_delta: 3,
+                max_frequency_delta: 10,
+            },
+            ..FingerprintConfig::default()
+        };
+        let peaks = [
+            peak(0, 50),
+            peak(0, 55),  // same frame, outside of the zone
+            peak(1, 100), // too far in frequency
+            peak(2, 45),
+            peak(3, 60),
+            peak(4, 52), // too far in time for the first anchor
+        ];
+
+        let pairs = config.pairs(&peaks);
+        let first: Vec<(usize, usize)> = pairs
+            .iter()
+            .filter(|(a, _)| *a == peaks[0])
+            .map(|(_, t)| (t.frame, t.bin))
+            .collect();
+        assert_eq!(first, vec![(2, 45), (3, 60)]);
+
+        // Fan out limits the number of targets
+        assert!(pairs.iter().filter(|(a, _)| *a == peaks[1]).count() <= 2);
+        // The last peak has nothing ahead of it
+        assert!(pairs.iter().all(|(a, _)| *a != peaks[5]));
+    }
+
+    #[test]
+    fn test_hash_peaks() {
+        let config = FingerprintConfig::default();
+        let peaks = [peak(10, 20), peak(15, 30)];
+        let hashes = config.hash_peaks(&peaks, 512);
+        assert_eq!(
+            hashes,
+            vec![Fingerprint {
+                hash: config.layout.pack(20, 30, 5, 512),
+                time: 10,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_is_time_shift_invariant() {
+        let sample_rate = 8000;
+        let tone = |n: usize| {
+            let t = n as f64 / sample_rate as f64;
+            // A few tones switching every quarter of a second
+            let f = [440.0, 880.0, 660.0, 1320.0][(t * 4.0) as usize % 4];
+            (2.0 * PI * f * t).sin()
+        };
+        let samples: Vec<f64> = (0..sample_rate as usize * 2).map(tone).collect();
+
+        let config = FingerprintConfig::default();
+        let fingerprints = config.fingerprint(&samples, sample_rate);
+        assert_eq!(fingerprints.version, FORMAT_VERSION);
+        assert!(!fingerprints.hashes.is_empty());
+
+        // Skipping exactly one hop shifts every anchor by one frame but keeps the hashes
+        let hop = config.stft.hop_size;
+        let shifted = config.fingerprint(&samples[hop..], sample_rate);
+        let original: std::collections::HashSet<u32> =
+            fingerprints.hashes.iter().map(|f| f.hash).collect();
+        let common = shifted
+            .hashes
+            .iter()
+            .filter(|f| original.contains(&f.hash))
+            .count();
+        assert!(common * 2 > shifted.hashes.len());
+    }
+
+    #[test]
+    fn test_single_precision_fingerprints() {
+        let sample_rate = 8000;
+        let samples: Vec<f64> = (0..sample_rate as usize * 2)
+            .map(|n| {
+                let t = n as f64 / sample_rate as f64;
+                let f = [440.0, 880.0, 660.0, 1320.0][(t * 4.0) as usize % 4];
+                (2.0 * PI * f * t).sin() + 0.5 * (2.0 * PI * 2.5 * f * t).sin()
+            })
+            .collect();
+        let single: Vec<f32> = samples.iter().map(|&s| s as f32).collect();
+
+        let config = FingerprintConfig::default();
+        let double = config.fingerprint(&samples, sample_rate);
+        let single = config.fingerprint(&single, sample_rate);
+
+        // Rounding can only flip peaks that are ties to begin with, nearly every hash survives
+        let original: std::collections::HashSet<u32> =
+            double.hashes.iter().map(|f| f.hash).collect();
+        let common = single
+            .hashes
+            .iter()
+            .filter(|f| original.contains(&f.hash))
+            .count();
+        assert!(common * 10 >= double.hashes.len() * 9);
+        assert_eq!(single.frames_per_second, double.frames_per_second);
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt::{Debug, Display};
+use std::iter::Sum;
+
+use rustfft::FftNum;
+
+use crate::complex::Complex;
+use crate::simd;
+
+// Sample type of the generic analysis code, f32 or f64
+//
+// f64 is the default everywhere, f32 halves the memory of samples and spectra and speeds up the FFTs on long files
+pub trait Float:
+    num_traits::Float + FftNum + Default + Display + Debug + Sum + Send + Sync + 'static
+{
+    const ZERO: Self;
+    const ONE: Self;
+
+    // Rounds to the nearest f32 when Self is f32
+    fn cast(value: f64) -> Self;
+
+    fn as_f64(self) -> f64;
+
+    // Kernels of the hot loops, vectorised for f64 and plain loops for f32, see simd for what they do
+
+    fn multiply(values: &mut [Self], factors: &[Self]) {
+        simd::scalar::multiply(values, factors)
+    }
+
+    fn complex_mac(a: &[Complex<Self>], b: &[Complex<Self>]) -> Complex<Self> {
+        simd::scalar::complex_mac(a, b)
+    }
+
+    fn magnitude(spectrum: &[Complex<Self>], out: &mut [Self]) {
+        simd::scalar::magnitude(spectrum, out)
+    }
+
+    fn power(spectrum: &[Complex<Self>], out: &mut [Self]) {
+        simd::scalar::power(spectrum, out)
+    }
+
+    fn to_db(values: &mut [Self], reference: Self) {
+        simd::scalar::to_db(values, reference)
+    }
+}
+
+impl Float for f32 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+
+    fn cast(value: f64) -> Self {
+        value as f32
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Float for f64 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+
+    fn cast(value: f64) -> Self {
+        value
+    }
+
+    fn as_f64(self) -> f64 {
+        self
+    }
+
+    fn multiply(values: &mut [Self], factors: &[Self]) {
+        simd::multiply(values, factors)
+    }
+
+    fn complex_mac(a: &[Complex<Self>], b: &[Complex<Self>]) -> Complex<Self> {
+        simd::complex_mac(a, b)
+    }
+
+    fn magnitude(spectrum: &[Complex<Self>], out: &mut [Self]) {
+        simd::magnitude(spectrum, out)
+    }
+
+    fn power(spectrum: &[Complex<Self>], out: &mut [Self]) {
+        simd::power(spectrum, out)
+    }
+
+    fn to_db(values: &mut [Self], reference: Self) {
+        simd::to_db(values, reference)
+    }
+}
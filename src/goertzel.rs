@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use crate::complex::Complex;
+
+// Single frequency DFT, cheaper than a full FFT when only a handful of frequencies matter
+//
+// Runs the samples through a second order resonator tuned to the target frequency,
+// after block_size samples its state holds the DFT value at that frequency
+pub struct Goertzel {
+    // Target frequency in radians per sample
+    omega: f64,
+    coeff: f64,
+    block_size: usize,
+    count: usize,
+    s1: f64,
+    s2: f64,
+}
+
+impl Goertzel {
+    pub fn new(frequency: f64, sample_rate: u32, block_size: usize) -> Self {
+        let omega = 2.0 * PI * frequency / sample_rate as f64;
+        Goertzel {
+            omega,
+            coeff: 2.0 * omega.cos(),
+            block_size,
+            count: 0,
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    // Feeds a sample, once the block is full returns its DFT value and starts the next block
+    pub fn process(&mut self, sample: f64) -> Option<Complex> {
+        let s0 = sample + self.coeff * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s0;
+        self.count += 1;
+
+        if self.count < self.block_size {
+            return None;
+        }
+
+        let result = self.result();
+        self.reset();
+        Some(result)
+    }
+
+    // DFT value of the samples fed since the block started, phase is referenced to the first sample
+    pub fn result(&self) -> Complex {
+        // y = e^(i*omega) * s1 - s2 is the DFT value rotated by e^(i*omega*N), rotate it back
+        let y = Complex::from_polar(self.s1, self.omega) - self.s2;
+        y * Complex::from_polar(1.0, -self.omega * self.count as f64)
+    }
+
+    // Squared magnitude of the current block, does not need the complex result so it is cheaper
+    pub fn power(&self) -> f64 {
+        self.s1 * self.s1 + self.s2 * self.s2 - self.coeff * self.s1 * self.s2
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
+
+// Goertzel detectors for a set of frequencies sharing the same block, e.g. the 8 DTMF tones
+pub struct GoertzelBank {
+    detectors: Vec<Goertzel>,
+}
+
+impl GoertzelBank {
+    pub fn new(frequencies: &[f64], sample_rate: u32, block_size: usize) -> Self {
+        GoertzelBank {
+            detectors: frequencies
+                .iter()
+                .map(|f| Goertzel::new(*f, sample_rate, block_size))
+                .collect(),
+        }
+    }
+
+    // Returns one DFT value per frequency every time a block is completed
+    pub fn process(&mut self, sample: f64) -> Option<Vec<Complex>> {
+        let results: Vec<Option<Complex>> = self
+            .detectors
+            .iter_mut()
+            .map(|d| d.process(sample))
+            .collect();
+
+        results.into_iter().collect()
+    }
+
+    pub fn powers(&self) -> Vec<f64> {
+        self.detectors.iter().map(|d| d.power()).collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.detectors.iter_mut().for_each(|d| d.reset());
+    }
+}
+
+// Recursive DFT over the last `size` samples, updated with every new sample
+//
+// Each bin is updated with S = (S + x_new - x_old) * e^(2PIi*k/N) instead of recomputing the whole window,
+// bins are rounded to whole DFT bins because the recursion is only exact for those
+pub struct SlidingDft {
+    size: usize,
+    bins: Vec<usize>,
+    twiddles: Vec<Complex>,
+    values: Vec<Complex>,
+    // Last `size` samples, needed to remove the oldest one from the sum
+    history: VecDeque<f64>,
+}
+
+impl SlidingDft {
+    pub fn new(frequencies: &[f64], sample_rate: u32, size: usize) -> Self {
+        let bins: Vec<usize> = frequencies
+            .iter()
+            .map(|f| (f * size as f64 / sample_rate as f64).round() as usize)
+            .collect();
+        SlidingDft::with_bins(&bins, size)
+    }
+
+    pub fn with_bins(bins: &[usize], size: usize) -> Self {
+        let twiddles = bins
+            .iter()
+            .map(|k| Complex::from_polar(1.0, 2.0 * PI * *k as f64 / size as f64))
+            .collect();
+
+        SlidingDft {
+            size,
+            bins: bins.to_vec(),
+            twiddles,
+            values: vec![Complex::ZERO; bins.len()],
+            history: VecDeque::from(vec![0.0; size]),
+        }
+    }
+
+    // Slides the window by one sample and returns the updated bins
+    pub fn process(&mut self, sample: f64) -> &[Complex] {
+        let oldest = self.history.pop_front().unwrap_or(0.0);
+        self.history.push_back(sample);
+
+        let delta = sample - oldest;
+        for (value, twiddle) in self.values.iter_mut().zip(&self.twiddles) {
+            *value = (*value + delta) * *twiddle;
+        }
+
+        &self.values
+    }
+
+    pub fn values(&self) -> &[Complex] {
+        &self.values
+    }
+
+    pub fn bins(&self) -> &[usize] {
+        &self.bins
+    }
+
+    // Rounding errors slowly accumulate since the recursion never forgets, recomputing from the history clears them
+    pub fn resync(&mut self) {
+        for (value, k) in self.values.iter_mut().zip(&self.bins) {
+            let mut sum = Complex::ZERO;
+            for (n, sample) in self.history.iter().enumerate() {
+                let theta = -2.0 * PI * ((k * n) % self.size) as f64 / self.size as f64;
+                sum += Complex::from_polar(*sample, theta);
+            }
+            *value = sum;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.values.fill(Complex::ZERO);
+        self.history.iter_mut().for_each(|x| *x = 0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::fft;
+
+    const SAMPLE_RATE: u32 = 8000;
+
+    fn signal(size: usize) -> Vec<f64> {
+        (0..size)
+            .map(|n| {
+                let t = n as f64 / SAMPLE_RATE as f64;
+                (2.0 * PI * 697.0 * t).sin() + 0.5 * (2.0 * PI * 1209.0 * t + 0.3).cos() + 0.1
+            })
+            .collect()
+    }
+
+    fn assert_complex_close(a: Complex, re: f64, im: f64) {
+        assert!((a.re - re).abs() < 1e-6, "{:?} != {} + {}i", a, re, im);
+        assert!((a.im - im).abs() < 1e-6, "{:?} != {} + {}i", a, re, im);
+    }
+
+    #[test]
+    fn test_goertzel_matches_fft() {
+        let size = 256;
+        let samples = signal(size);
+        let spectrum = fft(&samples);
+
+        for bin in [0, 1, 22, 39, 100, 128] {
+            let frequency = bin as f64 * SAMPLE_RATE as f64 / size as f64;
+            let mut goertzel = Goertzel::new(frequency, SAMPLE_RATE, size);
+            let mut result = None;
+            for sample in &samples {
+                result = goertzel.process(*sample);
+            }
+
+            let result = result.unwrap();
+            assert_complex_close(result, spectrum[bin].re, spectrum[bin].im);
+        }
+    }
+
+    #[test]
+    fn test_goertzel_power() {
+        let size = 205;
+        let samples = signal(size);
+        let mut goertzel = Goertzel::new(697.0, SAMPLE_RATE, size + 1);
+        for sample in &samples {
+            assert!(goertzel.process(*sample).is_none());
+        }
+
+        let power = goertzel.power();
+        let magnitude = goertzel.result().abs();
+        assert!((power - magnitude * magnitude).abs() < 1e-6 * power);
+    }
+
+    #[test]
+    fn test_goertzel_bank_detects_dtmf() {
+        let dtmf = [697.0, 770.0, 852.0, 941.0, 1209.0, 1336.0, 1477.0, 1633.0];
+        let mut bank = GoertzelBank::new(&dtmf, SAMPLE_RATE, 205);
+
+        let mut blocks = Vec::new();
+        for sample in signal(410) {
+            if let Some(block) = bank.process(sample) {
+                blocks.push(block);
+            }
+        }
+        assert_eq!(blocks.len(), 2);
+
+        let magnitudes: Vec<f64> = blocks[1].iter().map(|x| x.abs()).collect();
+        let strongest = magnitudes[0].min(magnitudes[4]);
+        for (i, m) in magnitudes.iter().enumerate() {
+            if i != 0 && i != 4 {
+                assert!(*m < strongest / 4.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sliding_dft_matches_fft() {
+        let size = 128;
+        let samples = signal(1000);
+        let bins = [0, 3, 11, 19, 64];
+        let mut sdft = SlidingDft::with_bins(&bins, size);
+
+        for (n, sample) in samples.iter().enumerate() {
+            sdft.process(*sample);
+
+            if n + 1 >= size && (n + 1) % 97 == 0 {
+                let spectrum = fft(&samples[n + 1 - size..=n]);
+                for (value, k) in sdft.values().iter().zip(&bins) {
+                    assert_complex_close(*value, spectrum[*k].re, spectrum[*k].im);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sliding_dft_frequencies_round_to_bins() {
+        let sdft = SlidingDft::new(&[697.0, 1209.0], SAMPLE_RATE, 256);
+        assert_eq!(sdft.bins(), &[22, 39]);
+    }
+
+    #[test]
+    fn test_sliding_dft_resync() {
+        let size = 64;
+        let samples = signal(300);
+        let mut sdft = SlidingDft::with_bins(&[5, 9], size);
+        for sample in &samples {
+            sdft.process(*sample);
+        }
+
+        let before: Vec<Complex> = sdft.values().to_vec();
+        sdft.resync();
+        for (a, b) in before.iter().zip(sdft.values()) {
+            assert_complex_close(*a, b.re, b.im);
+        }
+
+        sdft.reset();
+        assert!(sdft.values().iter().all(|x| x.abs() == 0.0));
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::fingerprint::{FORMAT_VERSION, Fingerprints, HashLayout};
+
+// Query and reference anchors can land one frame apart when the recording does not start on a hop boundary,
+// so offsets this close to the best one are counted towards it
+pub(crate) const OFFSET_TOLERANCE: i64 = 1;
+
+pub type TrackId = u32;
+
+// Where a hash occurs in the catalogue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting {
+    pub track: TrackId,
+    pub time: u32,
+}
+
+// A track matching the query
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub track: TrackId,
+    // Hashes agreeing on the same time offset
+    pub count: usize,
+    // Where the query starts in the track, in seconds
+    pub offset: f64,
+    // Share of the query hashes that agree on the offset, from 0 to 1
+    pub confidence: f64,
+}
+
+// A query hash found in a track, with the anchor frame in the query and in the track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashHit {
+    pub query_time: u32,
+    pub reference_time: u32,
+}
+
+impl HashHit {
+    // Frames between the start of the track and the start of the query
+    pub fn offset(&self) -> i64 {
+        self.reference_time as i64 - self.query_time as i64
+    }
+}
+
+// Anything the postings of a hash can be looked up in, the index in memory or the database on disk
+pub trait HashLookup {
+    fn lookup(&self, hash: u32, postings: &mut Vec<Posting>);
+}
+
+// Inverted index from hash to every (track, anchor time) it appears at
+//
+// A query looks up each of its hashes and votes for the time difference between the reference and the query anchor,
+// only the right track gets a pile of votes on a single offset, random hash collisions spread out over all of them
+#[derive(Default)]
+pub struct FingerprintIndex {
+    postings: HashMap<u32, Vec<Posting>>,
+    tracks: Vec<String>,
+    // Layout and frame rate of the first track, every other track and query has to share them
+    format: Option<(HashLayout, f64)>,
+}
+
+impl FingerprintIndex {
+    pub fn new() -> Self {
+        FingerprintIndex::default()
+    }
+
+    pub fn add(&mut self, name: &str, fingerprints: &Fingerprints) -> Result<TrackId> {
+        check_format(self.format, fingerprints)?;
+        if self.format.is_none() {
+            self.format = Some((fingerprints.layout, fingerprints.frames_per_second));
+        }
+
+        let track = self.tracks.len() as TrackId;
+        self.tracks.push(name.to_string());
+        for fingerprint in &fingerprints.hashes {
+            self.postings
+                .entry(fingerprint.hash)
+                .or_default()
+                .push(Posting {
+                    track,
+                    time: fingerprint.time,
+                });
+        }
+
+        Ok(track)
+    }
+
+    // Tracks sharing at least min_matches aligned hashes with the query, best match first
+    pub fn query(&self, fingerprints: &Fingerprints, min_matches: usize) -> Result<Vec<Match>> {
+        check_format(self.format, fingerprints)?;
+        let Some((_, frames_per_second)) = self.format else {
+            return Ok(Vec::new());
+        };
+
+        Ok(rank_matches(
+            self,
+            fingerprints,
+            frames_per_second,
+            min_matches,
+        ))
+    }
+
+    // Every query hash found in the track, what a match is voted from
+    pub fn hits(&self, fingerprints: &Fingerprints, track: TrackId) -> Result<Vec<HashHit>> {
+        check_format(self.format, fingerprints)?;
+        Ok(hash_hits(self, fingerprints, track))
+    }
+
+    pub fn postings(&self, hash: u32) -> &[Posting] {
+        self.postings.get(&hash).map_or(&[], |p| p.as_slice())
+    }
+
+    pub fn track_name(&self, track: TrackId) -> Option<&str> {
+        self.tracks.get(track as usize).map(|name| name.as_str())
+    }
+
+    pub fn num_tracks(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn num_hashes(&self) -> usize {
+        self.postings.values().map(|p| p.len()).sum()
+    }
+}
+
+impl HashLookup for FingerprintIndex {
+    fn lookup(&self, hash: u32, postings: &mut Vec<Posting>) {
+        postings.extend_from_slice(self.postings(hash));
+    }
+}
+
+// Votes for the offsets of every track the query hashes appear in and ranks the tracks by their best offset
+pub(crate) fn rank_matches<L: HashLookup>(
+    lookup: &L,
+    fingerprints: &Fingerprints,
+    frames_per_second: f64,
+    min_matches: usize,
+) -> Vec<Match> {
+    // Offset histogram per track
+    let mut histograms: HashMap<TrackId, HashMap<i64, usize>> = HashMap::new();
+    let mut postings = Vec::new();
+    for fingerprint in &fingerprints.hashes {
+        postings.clear();
+        lookup.lookup(fingerprint.hash, &mut postings);
+        for posting in &postings {
+            let offset = posting.time as i64 - fingerprint.time as i64;
+            *histograms
+                .entry(posting.track)
+                .or_default()
+                .entry(offset)
+                .or_default() += 1;
+        }
+    }
+
+    let total = fingerprints.hashes.len().max(1);
+    let mut matches: Vec<Match> = histograms
+        .into_iter()
+        .filter_map(|(track, histogram)| {
+            let (offset, count) = best_offset(&histogram)?;
+            Some(Match {
+                track,
+                count,
+                offset: offset as f64 / frames_per_second,
+                confidence: (count as f64 / total as f64).min(1.0),
+            })
+        })
+        .filter(|m| m.count >= min_matches)
+        .collect();
+
+    matches.sort_by(|a, b| b.count.cmp(&a.count).then(a.track.cmp(&b.track)));
+    matches
+}
+
+// Query hashes found in one track, sorted by query time
+pub(crate) fn hash_hits<L: HashLookup>(
+    lookup: &L,
+    fingerprints: &Fingerprints,
+    track: TrackId,
+) -> Vec<HashHit> {
+    let mut hits = Vec::new();
+    let mut postings = Vec::new();
+    for fingerprint in &fingerprints.hashes {
+        postings.clear();
+        lookup.lookup(fingerprint.hash, &mut postings);
+        hits.extend(
+            postings
+                .iter()
+                .filter(|posting| posting.track == track)
+                .map(|posting| HashHit {
+                    query_time: fingerprint.time,
+                    reference_time: posting.time,
+                }),
+        );
+    }
+
+    hits.sort_by_key(|hit| (hit.query_time, hit.reference_time));
+    hits
+}
+
+// Fingerprints can only be compared when they were made with the same version, hash layout and hop
+pub(crate) fn check_format(
+    format: Option<(HashLayout, f64)>,
+    fingerprints: &Fingerprints,
+) -> Result<()> {
+    if fingerprints.version != FORMAT_VERSION {
+        return Err(Error::Incompatible("Fingerprint version does not match"));
+    }
+
+    match format {
+        Some((layout, _)) if layout != fingerprints.layout => {
+            Err(Error::Incompatible("Hash layout does not match the index"))
+        }
+        Some((_, fps)) if fps != fingerprints.frames_per_second => {
+            Err(Error::Incompatible("Frame rate does not match the index"))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Offset with the most votes including its neighbours within the tolerance
+fn best_offset(histogram: &HashMap<i64, usize>) -> Option<(i64, usize)> {
+    histogram
+        .keys()
+        .map(|offset| {
+            let count = (offset - OFFSET_TOLERANCE..=offset + OFFSET_TOLERANCE)
+                .filter_map(|o| histogram.get(&o))
+                .sum::<usize>();
+            (*offset, count)
+        })
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::Fingerprint;
+
+    // Small deterministic generator so the tests do not need a random crate
+    fn pseudo_random(seed: &mut u64) -> u32 {
+        *seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (*seed >> 33) as u32
+    }
+
+    fn track(seed: u64, frames: u32) -> Fingerprints {
+        let mut seed = seed;
+        let hashes = (0..frames)
+            .flat_map(|time| (0..3).map(move |_| time))
+            .map(|time| Fingerprint {
+                hash: pseudo_random(&mut seed),
+                time,
+            })
+            .collect();
+
+        Fingerprints {
+            version: FORMAT_VERSION,
+            layout: HashLayout::default(),
+            frames_per_second: 10.0,
+            hashes,
+        }
+    }
+
+    // Part of the track starting at `start` frames, as if it was recorded from there
+    fn excerpt(fingerprints: &Fingerprints, start: u32, frames: u32) -> Fingerprints {
+        let hashes = fingerprints
+            .hashes
+            .iter()
+            .filter(|f| f.time >= start && f.time < start + frames)
+            .map(|f| Fingerprint {
+                hash: f.hash,
+                time: f.time - start,
+            })
+            .collect();
+
+        Fingerprints {
+            hashes,
+            ..fingerprints.clone()
+        }
+    }
+
+    #[test]
+    fn test_add() {
+        let mut index = FingerprintIndex::new();
+        assert_eq!(index.add("a", &track(1, 100)).unwrap(), 0);
+        assert_eq!(index.add("b", &track(2, 100)).unwrap(), 1);
+        assert_eq!(index.num_tracks(), 2);
+        assert_eq!(index.num_hashes(), 600);
+        assert_eq!(index.track_name(1), Some("b"));
+        assert_eq!(index.track_name(2), None);
+    }
+
+    #[test]
+    fn test_query_finds_track_and_offset() {
+        let mut index = FingerprintIndex::new();
+        let tracks: Vec<Fingerprints> = (0..5).map(|seed| track(seed, 500)).collect();
+        for (i, fingerprints) in tracks.iter().enumerate() {
+            index.add(&format!("track {}", i), fingerprints).unwrap();
+        }
+
+        let query = excerpt(&tracks[3], 120, 50);
+        let matches = index.query(&query, 5).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].track, 3);
+        assert_eq!(matches[0].count, 150);
+        assert!((matches[0].offset - 12.0).abs() < 1e-12);
+        assert!((matches[0].confidence - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_query_tolerates_noise() {
+        let mut index = FingerprintIndex::new();
+        let reference = track(7, 300);
+        index.add("reference", &reference).unwrap();
+        index.add("other", &track(8, 300)).unwrap();
+
+        // Drop two out of three hashes and mix in the same amount of hashes from an unknown track
+        let mut query = excerpt(&reference, 40, 100);
+        query.hashes = query.hashes.into_iter().step_by(3).collect();
+        let noise = track(99, 100).hashes;
+        query.hashes.extend(noise.iter().take(query.hashes.len()));
+
+        let matches = index.query(&query, 5).unwrap();
+        assert_eq!(matches[0].track, 0);
+        assert!((matches[0].offset - 4.0).abs() < 1e-12);
+        assert!(matches[0].confidence > 0.4 && matches[0].confidence < 0.6);
+    }
+
+    #[test]
+    fn test_hits_line_up_on_the_offset() {
+        let mut index = FingerprintIndex::new();
+        let reference = track(3, 200);
+        index.add("reference", &reference).unwrap();
+        index.add("other", &track(4, 200)).unwrap();
+
+        let query = excerpt(&reference, 80, 40);
+        let hits = index.hits(&query, 0).unwrap();
+        assert_eq!(hits.len(), 120);
+        assert!(hits.iter().all(|hit| hit.offset() == 80));
+        assert!(hits.windows(2).all(|w| w[0].query_time <= w[1].query_time));
+
+        assert!(index.hits(&query, 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_best_offset_tolerance() {
+        let histogram = HashMap::from([(10, 4), (11, 3), (30, 5)]);
+        assert_eq!(best_offset(&histogram), Some((10, 7)));
+        assert_eq!(best_offset(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn test_incompatible_fingerprints() {
+        let mut index = FingerprintIndex::new();
+        index.add("a", &track(1, 10)).unwrap();
+
+        let mut other_rate = track(2, 10);
+        other_rate.frames_per_second = 20.0;
+        assert!(matches!(
+            index.add("b", &other_rate),
+            Err(Error::Incompatible(_))
+        ));
+
+        let mut other_layout = track(3, 10);
+        other_layout.layout = HashLayout::new(8, 16).unwrap();
+        assert!(index.query(&other_layout, 1).is_err());
+
+        let mut other_version = track(4, 10);
+        other_version.version = FORMAT_VERSION + 1;
+        assert!(index.query(&other_version, 1).is_err());
+    }
+
+    #[test]
+    fn test_query_empty_index() {
+        let index = FingerprintIndex::new();
+        assert!(index.query(&track(1, 10), 1).unwrap().is_empty());
+    }
+}
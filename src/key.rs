@@ -0,0 +1,227 @@
+use std::fmt;
+
+use crate::note::pitch_class_name;
+
+// Krumhansl-Kessler probe tone ratings, how well each pitch class fits a major or minor key on C
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Major => write!(f, "major"),
+            Mode::Minor => write!(f, "minor"),
+        }
+    }
+}
+
+// One of the 24 major and minor keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    // Pitch class of the tonic, 0 is C
+    pub tonic: usize,
+    pub mode: Mode,
+}
+
+impl Key {
+    pub fn new(tonic: usize, mode: Mode) -> Self {
+        Key {
+            tonic: tonic % 12,
+            mode,
+        }
+    }
+
+    // Short name DJ software uses, "F#" for F# major and "F#m" for F# minor
+    pub fn short_name(&self) -> String {
+        match self.mode {
+            Mode::Major => pitch_class_name(self.tonic).to_string(),
+            Mode::Minor => format!("{}m", pitch_class_name(self.tonic)),
+        }
+    }
+
+    // Position on the Camelot wheel, 1-12 around the circle of fifths with A for minor and B for major keys.
+    // Neighbours on the wheel and the relative key with the same number mix without a clash
+    pub fn camelot(&self) -> String {
+        // C major and A minor are 8, every fifth up is one step further
+        let (tonic, letter) = match self.mode {
+            Mode::Major => (self.tonic, 'B'),
+            Mode::Minor => ((self.tonic + 3) % 12, 'A'),
+        };
+        format!("{}{}", (7 * tonic + 7) % 12 + 1, letter)
+    }
+
+    // Major key with the same notes as a minor one and the other way round
+    pub fn relative(&self) -> Key {
+        match self.mode {
+            Mode::Major => Key::new(self.tonic + 9, Mode::Minor),
+            Mode::Minor => Key::new(self.tonic + 3, Mode::Major),
+        }
+    }
+
+    // 0-11 are the major keys from C, 12-23 the minor ones, how the database stores a key
+    pub fn index(&self) -> u8 {
+        let offset = match self.mode {
+            Mode::Major => 0,
+            Mode::Minor => 12,
+        };
+        (offset + self.tonic) as u8
+    }
+
+    pub fn from_index(index: u8) -> Option<Key> {
+        match index {
+            0..12 => Some(Key::new(index as usize, Mode::Major)),
+            12..24 => Some(Key::new(index as usize - 12, Mode::Minor)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", pitch_class_name(self.tonic), self.mode)
+    }
+}
+
+// Best matching key of a pitch class profile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEstimate {
+    pub key: Key,
+    // Pearson correlation of the profile with the key profile, -1 to 1
+    pub correlation: f64,
+    // How far ahead of the runner-up the key is, near 0 when two keys fit about equally well
+    pub confidence: f64,
+}
+
+// Krumhansl-Schmuckler key finding, correlates the profile with the 24 rotated key profiles
+//
+// None for a flat profile, silence or noise correlates with nothing
+pub fn estimate_key(profile: &[f64; 12]) -> Option<KeyEstimate> {
+    let mut scores: Vec<(Key, f64)> = key_correlations(profile)?.to_vec();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    Some(KeyEstimate {
+        key: scores[0].0,
+        correlation: scores[0].1,
+        confidence: scores[0].1 - scores[1].1,
+    })
+}
+
+// Correlation of the profile with every key, in the order of Key::index
+pub fn key_correlations(profile: &[f64; 12]) -> Option<[(Key, f64); 24]> {
+    let mean = profile.iter().sum::<f64>() / 12.0;
+    let deviations = profile.map(|p| p - mean);
+    let norm = deviations.iter().map(|d| d * d).sum::<f64>().sqrt();
+    if !norm.is_finite() || norm <= 1e-12 {
+        return None;
+    }
+
+    Some(std::array::from_fn(|index| {
+        let key = Key::from_index(index as u8).unwrap();
+        let template = match key.mode {
+            Mode::Major => &MAJOR_PROFILE,
+            Mode::Minor => &MINOR_PROFILE,
+        };
+        (key, correlation(&deviations, norm, template, key.tonic))
+    }))
+}
+
+// Pearson correlation of the centred profile with the template rotated to start at the tonic
+fn correlation(deviations: &[f64; 12], norm: f64, template: &[f64; 12], tonic: usize) -> f64 {
+    let mean = template.iter().sum::<f64>() / 12.0;
+    let (mut dot, mut template_norm) = (0.0, 0.0);
+    for (pitch_class, deviation) in deviations.iter().enumerate() {
+        let t = template[(pitch_class + 12 - tonic) % 12] - mean;
+        dot += deviation * t;
+        template_norm += t * t;
+    }
+    dot / (norm * template_norm.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        let c_major = Key::new(0, Mode::Major);
+        assert_eq!(c_major.to_string(), "C major");
+        assert_eq!(c_major.short_name(), "C");
+        assert_eq!(Key::new(6, Mode::Minor).to_string(), "F# minor");
+        assert_eq!(Key::new(6, Mode::Minor).short_name(), "F#m");
+        assert_eq!(c_major.relative(), Key::new(9, Mode::Minor));
+        assert_eq!(c_major.relative().relative(), c_major);
+    }
+
+    #[test]
+    fn test_camelot() {
+        let majors = [
+            "8B", "3B", "10B", "5B", "12B", "7B", "2B", "9B", "4B", "11B", "6B", "1B",
+        ];
+        let minors = [
+            "5A", "12A", "7A", "2A", "9A", "4A", "11A", "6A", "1A", "8A", "3A", "10A",
+        ];
+        for tonic in 0..12 {
+            assert_eq!(Key::new(tonic, Mode::Major).camelot(), majors[tonic]);
+            assert_eq!(Key::new(tonic, Mode::Minor).camelot(), minors[tonic]);
+            // Relative keys share the number
+            let key = Key::new(tonic, Mode::Major);
+            assert_eq!(
+                key.camelot().trim_end_matches('B'),
+                key.relative().camelot().trim_end_matches('A')
+            );
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        for index in 0..24 {
+            assert_eq!(Key::from_index(index).unwrap().index(), index);
+        }
+        assert_eq!(Key::from_index(14), Some(Key::new(2, Mode::Minor)));
+        assert_eq!(Key::from_index(24), None);
+    }
+
+    #[test]
+    fn test_estimate_key() {
+        // The key profiles themselves correlate perfectly with their own key
+        for tonic in 0..12 {
+            let major = std::array::from_fn(|i| MAJOR_PROFILE[(i + 12 - tonic) % 12]);
+            let estimate = estimate_key(&major).unwrap();
+            assert_eq!(estimate.key, Key::new(tonic, Mode::Major));
+            assert!((estimate.correlation - 1.0).abs() < 1e-12);
+            assert!(estimate.confidence > 0.0);
+
+            let minor = std::array::from_fn(|i| MINOR_PROFILE[(i + 12 - tonic) % 12]);
+            assert_eq!(
+                estimate_key(&minor).unwrap().key,
+                Key::new(tonic, Mode::Minor)
+            );
+        }
+
+        // Notes of the D major scale with the triad stressed
+        let mut profile = [0.0; 12];
+        for pitch_class in [2, 4, 6, 7, 9, 11, 1] {
+            profile[pitch_class] = 1.0;
+        }
+        for pitch_class in [2, 6, 9] {
+            profile[pitch_class] += 1.0;
+        }
+        assert_eq!(
+            estimate_key(&profile).unwrap().key,
+            Key::new(2, Mode::Major)
+        );
+
+        assert_eq!(estimate_key(&[0.0; 12]), None);
+        assert_eq!(estimate_key(&[0.5; 12]), None);
+    }
+}
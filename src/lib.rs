@@ -0,0 +1,55 @@
+//! Audio fingerprinting and spectral analysis of WAV files.
+//!
+//! The pipeline goes from decoding ([`WavReader`]) through the short time Fourier transform ([`Spectrogram`])
+//! and peak picking to constellation hashes ([`FingerprintConfig`]), which are matched against an in-memory
+//! [`FingerprintIndex`] or an on-disk [`Database`].
+//!
+//! Plotting lives behind the `plot` feature, which is enabled by default.
+
+pub mod audio;
+pub mod chroma;
+pub mod colormap;
+pub mod complex;
+pub mod cqt;
+pub mod database;
+pub mod error;
+pub mod fingerprint;
+pub mod float;
+pub mod goertzel;
+pub mod index;
+pub mod key;
+pub mod mel;
+pub mod mfcc;
+pub mod note;
+pub mod onset;
+pub mod peaks;
+pub mod pitch;
+#[cfg(feature = "plot")]
+pub mod plot;
+pub mod simd;
+pub mod spectrogram;
+pub mod spectrum;
+pub mod tempo;
+pub mod terminal;
+pub mod twiddle;
+pub mod waveform;
+pub mod window;
+
+pub use audio::WavReader;
+pub use chroma::{Chroma, ChromaConfig};
+pub use cqt::{ConstantQ, CqtConfig, CqtSpectrogram};
+pub use database::{Database, DatabaseBuilder, TrackInfo};
+pub use error::{Error, Result};
+pub use fingerprint::{FingerprintConfig, Fingerprints};
+pub use float::Float;
+pub use index::{FingerprintIndex, HashHit, Match};
+pub use key::{Key, KeyEstimate, Mode};
+pub use mel::{MelConfig, MelScale, MelSpectrogram};
+pub use mfcc::{Mfcc, MfccConfig, MfccFrame};
+pub use onset::{Onset, OnsetConfig, OnsetFunction, Onsets};
+pub use peaks::{Peak, PeakPicker};
+pub use pitch::{Pitch, PitchConfig, PitchFrame, PitchMethod, PitchTracker};
+pub use spectrogram::{FrequencyScale, Spectrogram, StftConfig, TimeFrequency};
+pub use tempo::{Tempo, TempoConfig};
+pub use waveform::{Lanes, Region, Waveform};
+pub use window::Window;
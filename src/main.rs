@@ -1,193 +1,1090 @@
-use plotters::prelude::*;
-use rustfft::num_complex::{Complex64, ComplexFloat};
-use std::f64::consts::{self, PI};
-use std::{fs::File, io::BufReader};
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use crate::audio::WavReader;
-use crate::complex::Complex;
-use crate::error::Result;
+use earworm::note::{Note, pitch_class_name};
+use earworm::spectrum::{CalibratedSpectrum, dft};
+use earworm::terminal::TextPlot;
+use earworm::{
+    Chroma, ChromaConfig, CqtConfig, Database, DatabaseBuilder, Error, FingerprintConfig,
+    FingerprintIndex, Fingerprints, Float, HashHit, Lanes, MelConfig, MelSpectrogram, Mfcc,
+    MfccConfig, OnsetConfig, Onsets, Peak, PitchConfig, PitchTracker, Region, Spectrogram,
+    TempoConfig, TimeFrequency, TrackInfo, WavReader, Waveform, simd, window,
+};
 
-mod audio;
-mod complex;
-mod error;
+use crate::cli::{Analysis, Command, Graph, Heatmap, Image, Markers, Terminal};
 
-const PATH: &str = "/Users/nikolai369/Downloads/dj co.kr & h4rdy - ill e sam sa (vip).wav";
-const WINDOW_SIZE: usize = 1024;
+mod cli;
 
-fn dft(samples: &[f64]) -> Vec<Complex> {
-    let n = samples.len();
-    let theta = -2.0 * PI / n as f64;
-    // r = 1 and theta = -2.0 * PI / n gives as the unit circle in the complex plain which is equal to e^-2PIi/N
-    // this will help us wrap our signal around the circle in different frequencies and find the ones that oscilate at the same rate in our input signal
-    let twiddle = Complex::from_polar(1.0, theta);
+// Number of matches printed by query
+const MAX_RESULTS: usize = 10;
+// Default image size of the waveform, its width is also the number of envelope columns
+const WAVEFORM_SIZE: (u32, u32) = (1200, 400);
 
-    let mut result: Vec<Complex> = Vec::with_capacity(n);
-    for f in 0..n {
-        let mut sum = Complex::new(0.0, 0.0);
-        for n in 0..n {
-            let sample = Complex::new(samples[n] as f64, 0.0);
-            let angle = twiddle.powi((n * f) as i32);
-            sum += sample * angle;
+type BoxResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            if let Some(Error::InvalidArgument(_)) = err.downcast_ref::<Error>() {
+                eprintln!("\n{}", cli::USAGE);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> BoxResult<()> {
+    match cli::parse(std::env::args().skip(1))? {
+        Command::Info { file } => info(&file),
+        Command::Spectrum {
+            file,
+            at,
+            analysis,
+            graph,
+            output,
+            image,
+            terminal,
+            naive,
+        } => spectrum(&file, at, &analysis, graph.labels, naive).and_then(|spectrum| {
+            draw_spectrum(&spectrum, &graph, &output, &image, terminal.as_ref())
+        }),
+        Command::Spectrogram {
+            file,
+            analysis,
+            heatmap,
+            cqt,
+            output,
+            image,
+            terminal,
+        } => spectrogram(
+            &file,
+            &analysis,
+            &heatmap,
+            cqt.as_ref(),
+            &output,
+            &image,
+            terminal.as_ref(),
+        ),
+        Command::Waveform {
+            file,
+            lanes,
+            markers,
+            analysis,
+            output,
+            image,
+        } => waveform(&file, lanes, &markers, &analysis, &output, &image),
+        Command::Mel {
+            file,
+            analysis,
+            mel,
+            output,
+        } => log_mel(&file, &analysis, &mel, &output),
+        Command::Mfcc {
+            file,
+            analysis,
+            mel,
+            mfcc,
+            output,
+        } => cepstrum(&file, &analysis, &mel, &mfcc, &output),
+        Command::Key {
+            file,
+            chroma,
+            single_precision,
+            output,
+        } => key(&file, &chroma, single_precision, output.as_deref()),
+        Command::Tempo {
+            file,
+            onsets,
+            tempo,
+            single_precision,
+            output,
+        } => beats(&file, &onsets, &tempo, single_precision, output.as_deref()),
+        Command::Pitch {
+            file,
+            pitch,
+            output,
+        } => track_pitch(&file, &pitch, &output),
+        Command::Fingerprint {
+            file,
+            analysis,
+            output,
+        } => fingerprint(&file, &analysis, output.as_deref()),
+        Command::Index { dir, db, analysis } => index(&dir, &db, &analysis),
+        Command::Query {
+            file,
+            db,
+            analysis,
+            min_matches,
+            plot,
+            image,
+        } => query(&file, &db, &analysis, min_matches, plot.as_deref(), &image),
+        Command::Verify { db } => verify(&db),
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+    }
+}
+
+fn info(file: &Path) -> BoxResult<()> {
+    let wav_reader = WavReader::open(file)?;
+    let config = wav_reader.config();
+    let fmt = config.fmt();
+
+    println!("File:            {}", file.display());
+    println!("Channels:        {}", fmt.channels);
+    println!("Sample rate:     {}Hz", fmt.sample_rate);
+    println!("Bits per sample: {}", fmt.bits_per_sample);
+    println!("Byte rate:       {}", fmt.byte_rate);
+    println!("Block align:     {}", fmt.block_align);
+    println!("Samples:         {}", config.samples_per_channel());
+    println!("Duration:        {:.2}s", config.duration_secs());
+    Ok(())
+}
+
+// Calibrated spectrum of the frame starting at the given second, its strongest peaks are printed
+fn spectrum(
+    file: &Path,
+    at: f64,
+    analysis: &Analysis,
+    peaks: usize,
+    naive: bool,
+) -> BoxResult<CalibratedSpectrum> {
+    let mut wav_reader = WavReader::open(file)?;
+    let fmt = wav_reader.config().fmt();
+    let (sample_rate, full_scale) = (fmt.sample_rate, fmt.full_scale());
+
+    let start = (at * sample_rate as f64) as usize;
+    let samples = wav_reader.config().samples_per_channel() as usize;
+    let end = start
+        .checked_add(analysis.window_size)
+        .filter(|&end| end <= samples);
+    let Some(end) = end else {
+        return Err(
+            Error::InvalidArgument(format!("--at {} is past the end of the file", at)).into(),
+        );
+    };
+
+    // Decoding stops at the end of the window and only the window itself is kept
+    let mut data = Vec::with_capacity(analysis.window_size);
+    let mut position = 0;
+    wav_reader.try_for_each_frame(|samples| {
+        if position >= start {
+            data.push(samples.iter().sum::<f64>() / samples.len() as f64);
+        }
+        position += 1;
+        if position < end {
+            ControlFlow::Continue(())
+        } else {
+            ControlFlow::Break(())
+        }
+    })?;
+    let frame = &data[..];
+
+    // The naive DFT is only here to compare it with rustfft, it is O(n^2) and does not zero pad
+    let spectrum = if naive {
+        let win = analysis.window.periodic(analysis.window_size);
+        let dft_result = dft(&window::apply(frame, &win));
+        let size = dft_result.len();
+        let mut magnitudes = vec![0.0; size / 2 + 1];
+        simd::magnitude(&dft_result, &mut magnitudes);
+        CalibratedSpectrum::from_magnitudes(&magnitudes, &win, size, sample_rate, full_scale)
+    } else {
+        CalibratedSpectrum::new(
+            frame,
+            analysis.window,
+            analysis.fft_size(),
+            sample_rate,
+            full_scale,
+        )
+    };
+
+    // Interpolating the peaks gives a much finer estimate than the 43Hz bin width
+    for peak in spectrum.peaks(peaks.max(1)) {
+        let note = Note::from_frequency(peak.frequency).map_or(String::new(), |n| n.to_string());
+        println!(
+            "{:>10.2}Hz {:>8.2}dBFS  {}",
+            peak.frequency, peak.level, note
+        );
+    }
+
+    Ok(spectrum)
+}
+
+fn draw_spectrum(
+    spectrum: &CalibratedSpectrum,
+    graph: &Graph,
+    output: &Path,
+    image: &Image,
+    terminal: Option<&Terminal>,
+) -> BoxResult<()> {
+    match terminal {
+        Some(terminal) => {
+            let text = TextPlot {
+                scale: graph.scale,
+                dynamic_range: graph.dynamic_range,
+                ..text_plot(image, terminal)
+            };
+            text.write_spectrum(&mut io::stdout().lock(), spectrum)?;
+            Ok(())
         }
+        None => plot_spectrum(spectrum, graph, output, image),
+    }
+}
+
+#[cfg(feature = "plot")]
+fn plot_spectrum(
+    spectrum: &CalibratedSpectrum,
+    graph: &Graph,
+    output: &Path,
+    image: &Image,
+) -> BoxResult<()> {
+    let plot = earworm::plot::SpectrumPlot {
+        scale: graph.scale,
+        dynamic_range: graph.dynamic_range,
+        labels: graph.labels,
+        ..earworm::plot::SpectrumPlot::new(spectrum)
+    };
+    image_options(image, output, (1000, 600)).save(&plot, output)?;
+    println!("Spectrum written to {}", output.display());
+    Ok(())
+}
+
+#[cfg(not(feature = "plot"))]
+fn plot_spectrum(
+    _spectrum: &CalibratedSpectrum,
+    _graph: &Graph,
+    _output: &Path,
+    _image: &Image,
+) -> BoxResult<()> {
+    Err(Error::InvalidArgument(
+        "built without the plot feature, draw the spectrum with --terminal instead".to_string(),
+    )
+    .into())
+}
+
+fn spectrogram(
+    file: &Path,
+    analysis: &Analysis,
+    heatmap: &Heatmap,
+    cqt: Option<&CqtConfig>,
+    output: &Path,
+    image: &Image,
+    terminal: Option<&Terminal>,
+) -> BoxResult<()> {
+    let mut wav_reader = WavReader::open(file)?;
+    let sample_rate = wav_reader.config().fmt().sample_rate;
 
-        result.push(sum);
+    // Transform the amplitude data in time into frequency spectrum sices
+    let (mut peaks, mut pairs) = (Vec::new(), Vec::new());
+    let spectrogram: Box<dyn TimeFrequency> = match cqt {
+        Some(config) if analysis.single_precision => {
+            Box::new(config.spectrogram(&wav_reader.mono_as::<f32>()?, sample_rate)?)
+        }
+        Some(config) => Box::new(config.spectrogram(&wav_reader.mono()?, sample_rate)?),
+        // The f32 spectrogram is drawn and written as it is, widening it would take back the memory it saves
+        None if analysis.single_precision => {
+            let data = wav_reader.mono_as::<f32>()?;
+            let spectrogram = Spectrogram::new(&data, sample_rate, analysis.stft());
+            (peaks, pairs) = constellation(&spectrogram, heatmap);
+            Box::new(spectrogram)
+        }
+        None => {
+            let data = wav_reader.mono()?;
+            let spectrogram = Spectrogram::new(&data, sample_rate, analysis.stft());
+            (peaks, pairs) = constellation(&spectrogram, heatmap);
+            Box::new(spectrogram)
+        }
+    };
+    let spectrogram = spectrogram.as_ref();
+
+    if let Some(terminal) = terminal {
+        let text = TextPlot {
+            scale: heatmap.scale,
+            color_map: heatmap.color_map,
+            dynamic_range: heatmap.dynamic_range,
+            ..text_plot(image, terminal)
+        };
+        text.write_spectrogram(&mut io::stdout().lock(), spectrogram)?;
+        return Ok(());
     }
 
-    result
-} // TODO: use only f64 values for now
+    let is_csv = output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    if is_csv {
+        write_spectrogram_csv(spectrogram, output)?;
+    } else {
+        plot_spectrogram(spectrogram, &peaks, &pairs, heatmap, output, image)?;
+    }
 
-fn fft(samples: &[f64]) -> Vec<Complex64> {
-    let mut planner: rustfft::FftPlanner<f64> = rustfft::FftPlanner::new();
-    let fft = planner.plan_fft_forward(WINDOW_SIZE);
-    let mut buff: Vec<Complex64> = samples
+    println!(
+        "{} frames of {} bins written to {}",
+        spectrogram.num_frames(),
+        spectrogram.num_bins(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn waveform(
+    file: &Path,
+    lanes: Lanes,
+    markers: &Markers,
+    analysis: &Analysis,
+    output: &Path,
+    image: &Image,
+) -> BoxResult<()> {
+    let mut regions = Vec::new();
+    if let Some(clip) = &markers.clip {
+        regions.extend(match_regions(file, clip, analysis, markers.min_matches)?);
+    }
+
+    // One column per pixel of the image, streamed so long files never sit in memory
+    let mut wav_reader = WavReader::open(file)?;
+    let columns = image.width.unwrap_or(WAVEFORM_SIZE.0) as usize;
+    let waveform = Waveform::read(&mut wav_reader, columns, lanes)?;
+
+    if let Some(threshold) = markers.silence {
+        regions.extend(waveform.silence(threshold, markers.min_silence));
+    }
+    for region in &regions {
+        println!(
+            "{:>8.2}s - {:>8.2}s  {}",
+            region.start, region.end, region.label
+        );
+    }
+
+    plot_waveform(&waveform, &regions, output, image)
+}
+
+// Where the clip shows up in the file, from an index holding only the file
+fn match_regions(
+    file: &Path,
+    clip: &Path,
+    analysis: &Analysis,
+    min_matches: usize,
+) -> BoxResult<Vec<Region>> {
+    let (_, fingerprints) = fingerprint_file(file, analysis)?;
+    let (clip_reader, clip_fingerprints) = fingerprint_file(clip, analysis)?;
+    let clip_duration = clip_reader.config().duration_secs();
+    let label = clip
+        .file_name()
+        .map_or("match".into(), |name| name.to_string_lossy());
+
+    let mut index = FingerprintIndex::new();
+    index.add(&file.to_string_lossy(), &fingerprints)?;
+    let matches = index.query(&clip_fingerprints, min_matches)?;
+    if matches.is_empty() {
+        println!("{} does not match", label);
+    }
+
+    Ok(matches
         .iter()
-        .copied()
-        .map(|x| Complex64 { re: x, im: 0.0 })
-        .collect();
-    fft.process(&mut buff);
+        .map(|m| Region {
+            start: m.offset,
+            end: m.offset + clip_duration,
+            label: format!("{} ({:.0}%)", label, m.confidence * 100.0),
+        })
+        .collect())
+}
 
-    buff
+#[cfg(feature = "plot")]
+fn plot_waveform(
+    waveform: &Waveform,
+    regions: &[Region],
+    output: &Path,
+    image: &Image,
+) -> BoxResult<()> {
+    let plot = earworm::plot::WaveformPlot { waveform, regions };
+    image_options(image, output, WAVEFORM_SIZE).save(&plot, output)?;
+    println!("Waveform written to {}", output.display());
+    Ok(())
 }
 
-// Naive linear slope window function
-fn window_function(window_size: usize) -> Vec<f64> {
-    let slope_size = (window_size * 5) / 100;
+#[cfg(not(feature = "plot"))]
+fn plot_waveform(
+    _waveform: &Waveform,
+    _regions: &[Region],
+    _output: &Path,
+    _image: &Image,
+) -> BoxResult<()> {
+    Err(Error::InvalidArgument(
+        "built without the plot feature, no waveform image can be written".to_string(),
+    )
+    .into())
+}
 
-    // Need to apply a gradient slope on begining and end of the window by multiplying the by te generated window function
+// Fills the terminal width unless --width says otherwise, colours go when asked or NO_COLOR is set
+fn text_plot(image: &Image, terminal: &Terminal) -> TextPlot {
+    let columns = env::var("COLUMNS").ok().and_then(|c| c.parse().ok());
+    TextPlot {
+        width: image.width.map(|w| w as usize).or(columns).unwrap_or(80),
+        height: image.height.map(|h| h as usize),
+        glyphs: terminal.glyphs,
+        color: !terminal.no_color && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+        ..TextPlot::default()
+    }
+}
 
-    let mut win_fn: Vec<f64> = Vec::with_capacity(window_size);
-    for i in 0..window_size {
-        if i < slope_size {
-            win_fn.push(i as f64 / slope_size as f64);
-            continue;
+// Spectrogram of samples scaled to -1..1, so features do not depend on the bit depth of the file
+fn normalized_spectrogram<T: Float>(file: &Path, analysis: &Analysis) -> BoxResult<Spectrogram<T>> {
+    let mut wav_reader = WavReader::open(file)?;
+    let fmt = wav_reader.config().fmt();
+    let (sample_rate, full_scale) = (fmt.sample_rate, fmt.full_scale());
+
+    let mut data = wav_reader.mono_as::<T>()?;
+    data.iter_mut()
+        .for_each(|x| *x = T::cast(x.as_f64() / full_scale));
+    Ok(Spectrogram::new(&data, sample_rate, analysis.stft()))
+}
+
+// Mel bands of the normalized spectrogram in the precision of the analysis
+fn file_mel(file: &Path, analysis: &Analysis, config: &MelConfig) -> BoxResult<MelSpectrogram> {
+    let mel = if analysis.single_precision {
+        MelSpectrogram::new(&normalized_spectrogram::<f32>(file, analysis)?, config)?
+    } else {
+        MelSpectrogram::new(&normalized_spectrogram::<f64>(file, analysis)?, config)?
+    };
+    Ok(mel)
+}
+
+fn log_mel(file: &Path, analysis: &Analysis, config: &MelConfig, output: &Path) -> BoxResult<()> {
+    let mel = file_mel(file, analysis, config)?;
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    write!(writer, "time")?;
+    for frequency in mel.filterbank().center_frequencies() {
+        write!(writer, ",{:.2}", frequency)?;
+    }
+    writeln!(writer)?;
+    for (i, frame) in mel.frames().iter().enumerate() {
+        write!(writer, "{:.4}", mel.frame_time(i))?;
+        for level in frame {
+            write!(writer, ",{:.3}", level)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    println!(
+        "{} frames of {} mel bands written to {}",
+        mel.num_frames(),
+        mel.filterbank().num_bands(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn cepstrum(
+    file: &Path,
+    analysis: &Analysis,
+    mel: &MelConfig,
+    config: &MfccConfig,
+    output: &Path,
+) -> BoxResult<()> {
+    let mel = file_mel(file, analysis, mel)?;
+    let mfcc = Mfcc::new(&mel, config)?;
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    write!(writer, "time")?;
+    for prefix in ["c", "d", "dd"] {
+        for k in 0..mfcc.num_coefficients() {
+            write!(writer, ",{}{}", prefix, k)?;
+        }
+    }
+    writeln!(writer)?;
+    for frame in mfcc.frames() {
+        write!(writer, "{:.4}", frame.time)?;
+        for value in frame
+            .coefficients
+            .iter()
+            .chain(&frame.delta)
+            .chain(&frame.delta2)
+        {
+            write!(writer, ",{:.4}", value)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    println!(
+        "{} frames of {} coefficients and their deltas written to {}",
+        mfcc.num_frames(),
+        mfcc.num_coefficients(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn key(
+    file: &Path,
+    config: &ChromaConfig,
+    single_precision: bool,
+    output: Option<&Path>,
+) -> BoxResult<()> {
+    let chroma = file_chroma(file, config, single_precision)?;
+
+    match chroma.key() {
+        Some(estimate) => println!(
+            "Key: {} ({}, {})  correlation: {:.2}  confidence: {:.2}",
+            estimate.key,
+            estimate.key.short_name(),
+            estimate.key.camelot(),
+            estimate.correlation,
+            estimate.confidence
+        ),
+        None => println!("Key: unknown"),
+    }
+    println!("Tuning: {:+.1} cents", chroma.tuning());
+
+    if let Some(output) = output {
+        let mut writer = BufWriter::new(File::create(output)?);
+        write!(writer, "time")?;
+        for pitch_class in 0..12 {
+            write!(writer, ",{}", pitch_class_name(pitch_class))?;
+        }
+        writeln!(writer)?;
+        for (i, frame) in chroma.frames().iter().enumerate() {
+            write!(writer, "{:.4}", chroma.frame_time(i))?;
+            for value in frame {
+                write!(writer, ",{:.4}", value)?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        println!(
+            "{} chroma frames written to {}",
+            chroma.num_frames(),
+            output.display()
+        );
+    }
+    Ok(())
+}
+
+fn file_chroma(file: &Path, config: &ChromaConfig, single_precision: bool) -> BoxResult<Chroma> {
+    let mut wav_reader = WavReader::open(file)?;
+    let sample_rate = wav_reader.config().fmt().sample_rate;
+
+    let chroma = if single_precision {
+        config.chroma(&wav_reader.mono_as::<f32>()?, sample_rate)?
+    } else {
+        config.chroma(&wav_reader.mono()?, sample_rate)?
+    };
+    Ok(chroma)
+}
+
+fn beats(
+    file: &Path,
+    onset_config: &OnsetConfig,
+    tempo_config: &TempoConfig,
+    single_precision: bool,
+    output: Option<&Path>,
+) -> BoxResult<()> {
+    let onsets = file_onsets(file, onset_config, single_precision)?;
+    let tempo = onsets.tempo(tempo_config)?;
+
+    match &tempo {
+        Some(tempo) => {
+            println!(
+                "Tempo: {:.2} BPM  confidence: {:.2}",
+                tempo.bpm, tempo.confidence
+            );
+            if let Some(first) = tempo.beats.first() {
+                println!(
+                    "Beat grid: {} beats from {:.3}s every {:.3}s",
+                    tempo.beats.len(),
+                    first,
+                    tempo.period()
+                );
+            }
+        }
+        None => println!("Tempo: unknown"),
+    }
+    println!(
+        "Onsets: {} ({})",
+        onsets.onsets().len(),
+        onset_config.function
+    );
+
+    if let Some(output) = output {
+        // Onsets with their strength and the beats of the grid, in time order
+        let mut events: Vec<(f64, &str, Option<f64>)> = onsets
+            .onsets()
+            .iter()
+            .map(|onset| (onset.time, "onset", Some(onset.strength)))
+            .collect();
+        if let Some(tempo) = &tempo {
+            events.extend(tempo.beats.iter().map(|&time| (time, "beat", None)));
+        }
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut writer = BufWriter::new(File::create(output)?);
+        writeln!(writer, "time,event,strength")?;
+        for (time, event, strength) in &events {
+            match strength {
+                Some(strength) => writeln!(writer, "{:.4},{},{:.4}", time, event, strength)?,
+                None => writeln!(writer, "{:.4},{},", time, event)?,
+            }
+        }
+        writer.flush()?;
+        println!("{} events written to {}", events.len(), output.display());
+    }
+    Ok(())
+}
+
+// Samples scaled to full scale, the flux compresses magnitudes relative to a full scale sine
+fn file_onsets(file: &Path, config: &OnsetConfig, single_precision: bool) -> BoxResult<Onsets> {
+    let mut wav_reader = WavReader::open(file)?;
+    let fmt = wav_reader.config().fmt();
+    let (sample_rate, full_scale) = (fmt.sample_rate, fmt.full_scale());
+
+    let onsets = if single_precision {
+        let mut data = wav_reader.mono_as::<f32>()?;
+        data.iter_mut().for_each(|x| *x /= full_scale as f32);
+        config.onsets(&data, sample_rate)?
+    } else {
+        let mut data = wav_reader.mono()?;
+        data.iter_mut().for_each(|x| *x /= full_scale);
+        config.onsets(&data, sample_rate)?
+    };
+    Ok(onsets)
+}
+
+fn track_pitch(file: &Path, config: &PitchConfig, output: &Path) -> BoxResult<()> {
+    let mut wav_reader = WavReader::open(file)?;
+    let sample_rate = wav_reader.config().fmt().sample_rate;
+
+    // Streamed frame by frame, the decoded file is never in memory
+    let mut tracker = PitchTracker::new(config, sample_rate)?;
+    wav_reader.for_each_frame(|frame| tracker.push(frame))?;
+    let pitch = tracker.finish();
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    writeln!(writer, "time,frequency,confidence,note,cents")?;
+    for frame in pitch.frames() {
+        match (frame.frequency, frame.note()) {
+            (Some(frequency), Some(note)) => writeln!(
+                writer,
+                "{:.4},{:.2},{:.3},{},{}",
+                frame.time,
+                frequency,
+                frame.confidence,
+                note.name(),
+                // Adding zero turns a rounded -0 into 0
+                note.cents.round() + 0.0
+            )?,
+            _ => writeln!(writer, "{:.4},,{:.3},,", frame.time, frame.confidence)?,
         }
+    }
+    writer.flush()?;
 
-        if window_size - i < slope_size {
-            let temp = window_size as f64 - i as f64;
-            win_fn.push(temp / slope_size as f64);
+    let mut voiced: Vec<f64> = pitch.voiced().filter_map(|frame| frame.frequency).collect();
+    voiced.sort_by(f64::total_cmp);
+    println!(
+        "{} of {} frames voiced ({})",
+        voiced.len(),
+        pitch.num_frames(),
+        config.method
+    );
+    if let Some(&median) = voiced.get(voiced.len() / 2)
+        && let Some(note) = Note::from_frequency(median)
+    {
+        println!("Median pitch: {:.1}Hz ({})", median, note);
+    }
+    println!("Pitch written to {}", output.display());
+    Ok(())
+}
+
+// One row per frame, the first column is the time and the rest are the bin magnitudes
+fn write_spectrogram_csv(spectrogram: &dyn TimeFrequency, output: &Path) -> BoxResult<()> {
+    let mut writer = BufWriter::new(File::create(output)?);
+    write!(writer, "time")?;
+    for bin in 0..spectrogram.num_bins() {
+        write!(writer, ",{:.2}", spectrogram.bin_frequency(bin as f64))?;
+    }
+    writeln!(writer)?;
+    for i in 0..spectrogram.num_frames() {
+        write!(writer, "{:.4}", spectrogram.frame_time(i))?;
+        for bin in 0..spectrogram.num_bins() {
+            write!(writer, ",{}", spectrogram.magnitude(i, bin))?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Same peaks and pairs the fingerprints of this analysis are hashed from, when the heatmap marks them
+fn constellation<T: Float>(
+    spectrogram: &Spectrogram<T>,
+    heatmap: &Heatmap,
+) -> (Vec<Peak>, Vec<(Peak, Peak)>) {
+    let config = FingerprintConfig {
+        stft: *spectrogram.config(),
+        ..FingerprintConfig::default()
+    };
+    let peaks = if heatmap.peaks || heatmap.pairs {
+        config.peaks.spectrogram(spectrogram)
+    } else {
+        Vec::new()
+    };
+    let pairs = if heatmap.pairs {
+        config.pairs(&peaks)
+    } else {
+        Vec::new()
+    };
+    (peaks, pairs)
+}
+
+#[cfg(feature = "plot")]
+fn plot_spectrogram(
+    spectrogram: &dyn TimeFrequency,
+    peaks: &[Peak],
+    pairs: &[(Peak, Peak)],
+    heatmap: &Heatmap,
+    output: &Path,
+    image: &Image,
+) -> BoxResult<()> {
+    let plot = earworm::plot::SpectrogramPlot {
+        scale: heatmap.scale,
+        color_map: heatmap.color_map,
+        dynamic_range: heatmap.dynamic_range,
+        min_frequency: heatmap.min_frequency,
+        max_frequency: heatmap.max_frequency,
+    };
+    let figure = plot.figure(spectrogram).constellation(peaks, pairs);
+    image_options(image, output, (1200, 600)).save(&figure, output)
+}
+
+#[cfg(not(feature = "plot"))]
+fn plot_spectrogram(
+    _spectrogram: &dyn TimeFrequency,
+    _peaks: &[Peak],
+    _pairs: &[(Peak, Peak)],
+    _heatmap: &Heatmap,
+    _output: &Path,
+    _image: &Image,
+) -> BoxResult<()> {
+    Err(Error::InvalidArgument(
+        "built without the plot feature, write the spectrogram to a .csv file instead".to_string(),
+    )
+    .into())
+}
+
+fn fingerprint(file: &Path, analysis: &Analysis, output: Option<&Path>) -> BoxResult<()> {
+    let (wav_reader, fingerprints) = fingerprint_file(file, analysis)?;
+    let duration = wav_reader.config().duration_secs();
+    println!(
+        "Generated {} hashes, {:.1} per second",
+        fingerprints.hashes.len(),
+        fingerprints.hashes.len() as f64 / duration.max(f64::EPSILON)
+    );
+
+    if let Some(output) = output {
+        let mut writer = BufWriter::new(File::create(output)?);
+        writeln!(writer, "hash,time")?;
+        for fingerprint in &fingerprints.hashes {
+            let time = fingerprint.time as f64 / fingerprints.frames_per_second;
+            writeln!(writer, "{:08x},{:.4}", fingerprint.hash, time)?;
+        }
+        writer.flush()?;
+        println!("Hashes written to {}", output.display());
+    }
+
+    Ok(())
+}
+
+fn index(dir: &Path, db: &Path, analysis: &Analysis) -> BoxResult<()> {
+    // Adding to an existing database keeps what is already indexed
+    let mut builder = if db.exists() {
+        DatabaseBuilder::from_database(Database::open(db)?)?
+    } else {
+        DatabaseBuilder::new()
+    };
+
+    let mut files = Vec::new();
+    find_wav_files(dir, &mut files)?;
+    files.sort();
+
+    let mut added = 0;
+    for file in files {
+        let name = file
+            .strip_prefix(dir)
+            .unwrap_or(&file)
+            .display()
+            .to_string();
+        if builder.contains(&name) {
             continue;
         }
 
-        win_fn.push(1.0);
+        let (wav_reader, fingerprints, chroma) = match fingerprint_file_with_chroma(&file, analysis)
+        {
+            Ok(result) => result,
+            Err(err) => {
+                eprintln!("Skipping {}: {}", name, err);
+                continue;
+            }
+        };
+        // A track without a key is still worth indexing
+        let key = match chroma {
+            Ok(chroma) => chroma.key().map(|estimate| estimate.key),
+            Err(err) => {
+                eprintln!("No key for {}: {}", name, err);
+                None
+            }
+        };
+        let info = TrackInfo {
+            key,
+            ..TrackInfo::from_wav(&name, wav_reader.config())
+        };
+        // Files at another sample rate have another frame rate than the rest of the index
+        let track = match builder.add(info, &fingerprints) {
+            Ok(track) => track,
+            Err(err @ Error::Incompatible(_)) => {
+                eprintln!("Skipping {}: {}", name, err);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        println!(
+            "[{}] {} ({} hashes, key {})",
+            track,
+            name,
+            fingerprints.hashes.len(),
+            key.map_or("unknown".to_string(), |key| format!(
+                "{} {}",
+                key.short_name(),
+                key.camelot()
+            ))
+        );
+        added += 1;
     }
 
-    win_fn
+    let tracks = builder.num_tracks();
+    builder.write(db)?;
+    println!(
+        "Added {} tracks, {} tracks in {}",
+        added,
+        tracks,
+        db.display()
+    );
+    Ok(())
 }
 
-fn apply_win_fn(window: &[f64], win_fn: &Vec<f64>) -> Vec<f64> {
-    let mut result: Vec<f64> = Vec::with_capacity(window.len());
+// Open only checks the header and the track table, this reads every posting too
+fn verify(db: &Path) -> BoxResult<()> {
+    let database = Database::open(db)?;
+    database.verify()?;
+    println!(
+        "{} is intact, {} tracks and {} postings",
+        db.display(),
+        database.tracks().len(),
+        database.num_postings()
+    );
+    Ok(())
+}
+
+fn query(
+    file: &Path,
+    db: &Path,
+    analysis: &Analysis,
+    min_matches: usize,
+    plot: Option<&Path>,
+    image: &Image,
+) -> BoxResult<()> {
+    let (_, fingerprints) = fingerprint_file(file, analysis)?;
+    let database = Database::open(db)?;
+    let matches = database.query(&fingerprints, min_matches)?;
+
+    if matches.is_empty() {
+        println!("No match found");
+        return Ok(());
+    }
 
-    for i in 0..window.len() {
-        result.push(window[i] as f64 * win_fn[i]);
+    for m in matches.iter().take(MAX_RESULTS) {
+        let track = database.track(m.track);
+        let name = track.map_or("?", |t| t.name.as_str());
+        let key = track.and_then(|t| t.key).map_or(String::new(), |key| {
+            format!("  key: {} ({})", key.short_name(), key.camelot())
+        });
+        println!(
+            "{}{}  matches: {}  offset: {:.2}s  confidence: {:.1}%",
+            name,
+            key,
+            m.count,
+            m.offset,
+            m.confidence * 100.0
+        );
     }
 
-    result
+    if let Some(output) = plot {
+        let best = &matches[0];
+        let hits = database.hits(&fingerprints, best.track)?;
+        plot_hits(
+            &hits,
+            fingerprints.frames_per_second,
+            best.offset,
+            output,
+            image,
+        )?;
+        println!("Hash matches written to {}", output.display());
+    }
+
+    Ok(())
 }
 
-fn plot_dft_magnitude(
-    output_path: &str,
-    dft_result: Vec<f64>,
-) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let root = BitMapBackend::new(output_path, (800, 600)).into_drawing_area();
-    root.fill(&WHITE)?;
+#[cfg(feature = "plot")]
+fn plot_hits(
+    hits: &[HashHit],
+    frames_per_second: f64,
+    offset: f64,
+    output: &Path,
+    image: &Image,
+) -> BoxResult<()> {
+    let plot = earworm::plot::MatchPlot {
+        hits,
+        frames_per_second,
+        offset: Some(offset),
+    };
+    image_options(image, output, (800, 600)).save(&plot, output)
+}
 
-    let max_mag = dft_result.iter().map(|c| c.clone()).fold(0.0, f64::max);
+// Size from the command line or the default of the plot, the format from the file extension
+#[cfg(feature = "plot")]
+fn image_options(image: &Image, output: &Path, default: (u32, u32)) -> earworm::plot::ImageOptions {
+    use earworm::plot::{ImageFormat, ImageOptions};
 
-    let mut chart = ChartBuilder::on(&root)
-        .caption("DFT Magnitude Spectrum", ("sans-serif", 30))
-        .margin(20)
-        .x_label_area_size(30)
-        .y_label_area_size(40)
-        .build_cartesian_2d(0..dft_result.len(), 0.0..max_mag)?;
+    let defaults = ImageOptions::default();
+    ImageOptions {
+        width: image.width.unwrap_or(default.0),
+        height: image.height.unwrap_or(default.1),
+        dpi: image.dpi.unwrap_or(defaults.dpi),
+        format: ImageFormat::from_path(output).unwrap_or_default(),
+    }
+}
+
+#[cfg(not(feature = "plot"))]
+fn plot_hits(
+    _hits: &[HashHit],
+    _frames_per_second: f64,
+    _offset: f64,
+    _output: &Path,
+    _image: &Image,
+) -> BoxResult<()> {
+    Err(Error::InvalidArgument(
+        "built without the plot feature, --plot is not available".to_string(),
+    )
+    .into())
+}
+
+fn fingerprint_file(file: &Path, analysis: &Analysis) -> BoxResult<(WavReader, Fingerprints)> {
+    let mut wav_reader = WavReader::open(file)?;
+    let sample_rate = wav_reader.config().fmt().sample_rate;
+
+    let config = fingerprint_config(analysis);
+    let fingerprints = if analysis.single_precision {
+        config.fingerprint(&wav_reader.mono_as::<f32>()?, sample_rate)
+    } else {
+        config.fingerprint(&wav_reader.mono()?, sample_rate)
+    };
+    Ok((wav_reader, fingerprints))
+}
+
+// The fingerprints and the chroma for the key from a single decode of the file, a failed chroma is left to the caller
+fn fingerprint_file_with_chroma(
+    file: &Path,
+    analysis: &Analysis,
+) -> BoxResult<(WavReader, Fingerprints, earworm::Result<Chroma>)> {
+    let mut wav_reader = WavReader::open(file)?;
+    let sample_rate = wav_reader.config().fmt().sample_rate;
 
-    chart.configure_mesh().draw()?;
+    let (config, chroma_config) = (fingerprint_config(analysis), ChromaConfig::default());
+    let (fingerprints, chroma) = if analysis.single_precision {
+        let samples = wav_reader.mono_as::<f32>()?;
+        (
+            config.fingerprint(&samples, sample_rate),
+            chroma_config.chroma(&samples, sample_rate),
+        )
+    } else {
+        let samples = wav_reader.mono()?;
+        (
+            config.fingerprint(&samples, sample_rate),
+            chroma_config.chroma(&samples, sample_rate),
+        )
+    };
+    Ok((wav_reader, fingerprints, chroma))
+}
+
+fn fingerprint_config(analysis: &Analysis) -> FingerprintConfig {
+    FingerprintConfig {
+        stft: analysis.stft(),
+        ..FingerprintConfig::default()
+    }
+}
 
-    chart.draw_series(LineSeries::new(
-        dft_result.iter().enumerate().map(|(i, c)| (i, c.clone())),
-        &BLUE,
-    ))?;
+fn find_wav_files(dir: &Path, files: &mut Vec<PathBuf>) -> BoxResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_wav_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+        {
+            files.push(path);
+        }
+    }
 
-    root.present()?;
     Ok(())
 }
 
-// Frequency resolution
-// For a 44100 sample rate file and 1024 samples per window, frequency_resolution = 44100 / 1024 ~ 43Hz
-// Which means each freq bin coresponds to a 43Hz range
-// For 1024 samples in a window there are 512 bins since n of samples give n/2 bins
-// Larger window size gives us a greater frequency resolution
-//
-// Time resoulution
-// For a 44100 sample rate file and 1024 samples per window, time_resolution = 1024 / 44100 ~ 23ms
-// Which means we get an update every 23ms
-// Smaller window size gives us greater time resolution
-//
-// Greater time resolution gives us more updates per second hence it is better for fast changing audio like speach or drums
-// Greater frequency resolution gives a more precise frequency response for better pitch accuracy and slow signals
-fn main() -> Result<()> {
-    let file = File::open(PATH)?;
-    let reader = BufReader::new(file);
-
-    // Reads all headers unil the actual audio data
-    let mut wav_reader = WavReader::new(reader)?;
-
-    // Read vector of i32 samples
-    let data = wav_reader.mono()?;
-
-    // How to minimize spectral leakage??
-    // Testing and comparing my naive dft vs rustfft
-    let mut windows = data.windows(WINDOW_SIZE);
-    let target_window = windows.nth(40000).unwrap();
-
-    // Hann window creates a cosine curve starting and ending at zero and peeking at one in the middle
-    let hann_window = (0..WINDOW_SIZE)
-        .map(|i| 0.5 - 0.5 * ((consts::PI * i as f64 * 2.0) / WINDOW_SIZE as f64).cos())
-        .collect();
-
-    let naive_window = window_function(WINDOW_SIZE);
-    let windowed = apply_win_fn(target_window, &hann_window);
-    let naive_windowed = apply_win_fn(target_window, &naive_window);
-
-    // Naive DFT
-    let dft_result = dft(&windowed);
-
-    let naive_window_result = dft(&naive_windowed);
-
-    // FFT
-    let fft_result = fft(&windowed);
-
-    match (
-        plot_dft_magnitude(
-            "./naive_window_fn_dft.png",
-            naive_window_result
-                .iter()
-                .take(naive_window_result.len() / 2)
-                .map(|x| x.abs())
-                .collect(),
-        ),
-        plot_dft_magnitude(
-            "./dft_plot.png",
-            dft_result
-                .iter()
-                .take(dft_result.len() / 2)
-                .map(|x| x.abs())
-                .collect(),
-        ),
-        plot_dft_magnitude(
-            "./fft_plot.png",
-            fft_result
-                .iter()
-                .take(fft_result.len() / 2)
-                .map(|x| x.abs())
-                .collect(),
-        ),
-    ) {
-        _ => return Ok(()),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_wav(path: &Path, sample_rate: u32) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        // A different tone every quarter second gives the fingerprint peaks to pair
+        for n in 0..2 * sample_rate {
+            let time = n as f64 / sample_rate as f64;
+            let frequency = 220.0 * (1.0 + (time * 4.0).floor() % 5.0);
+            let sample = 10000.0 * (2.0 * std::f64::consts::PI * frequency * time).sin();
+            writer.write_sample(sample as i16).unwrap();
+        }
+        writer.finalize().unwrap();
     }
 
-    // Transform the amplitude data in time into frequency spectrum sices
+    #[test]
+    fn test_index_mixed_sample_rates() {
+        let dir = env::temp_dir().join(format!("earworm-index-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        write_wav(&dir.join("a.wav"), 44100);
+        write_wav(&dir.join("b.wav"), 48000);
+        write_wav(&dir.join("c.wav"), 44100);
+        let db = dir.join("songs.db");
 
-    // Analyze peaks
+        // The 48kHz file does not fit the frame rate of the first one and is left out
+        index(&dir, &db, &Analysis::default()).unwrap();
+        let database = Database::open(&db).unwrap();
+        let names: Vec<&str> = database.tracks().iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["a.wav", "c.wav"]);
 
-    // Hash peaks
+        drop(database);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,435 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::{Error, Result};
+use crate::float::Float;
+use crate::spectrogram::{Spectrogram, hz_to_mel, mel_to_hz, power_to_db};
+
+// Below 1kHz the Slaney scale is linear at 3 mels per 200Hz
+const SLANEY_BREAK: f64 = 1000.0;
+const SLANEY_HZ_PER_MEL: f64 = 200.0 / 3.0;
+const SLANEY_BREAK_MEL: f64 = SLANEY_BREAK / SLANEY_HZ_PER_MEL;
+// and logarithmic above, 27 mels per factor of 6.4
+fn slaney_log_step() -> f64 {
+    6.4f64.ln() / 27.0
+}
+
+// The two mel scales in common use, they agree on the shape but not on the numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MelScale {
+    // 2595 log10(1 + f / 700), what HTK, Kaldi and torchaudio use by default
+    Htk,
+    // Linear below 1kHz and logarithmic above, from Slaney's Auditory Toolbox and the default of librosa
+    #[default]
+    Slaney,
+}
+
+impl MelScale {
+    pub fn to_mel(&self, frequency: f64) -> f64 {
+        match self {
+            MelScale::Htk => hz_to_mel(frequency),
+            MelScale::Slaney if frequency < SLANEY_BREAK => frequency / SLANEY_HZ_PER_MEL,
+            MelScale::Slaney => {
+                SLANEY_BREAK_MEL + (frequency / SLANEY_BREAK).ln() / slaney_log_step()
+            }
+        }
+    }
+
+    pub fn to_hz(&self, mel: f64) -> f64 {
+        match self {
+            MelScale::Htk => mel_to_hz(mel),
+            MelScale::Slaney if mel < SLANEY_BREAK_MEL => mel * SLANEY_HZ_PER_MEL,
+            MelScale::Slaney => SLANEY_BREAK * ((mel - SLANEY_BREAK_MEL) * slaney_log_step()).exp(),
+        }
+    }
+
+    // count frequencies evenly spaced in mels from low to high, both included
+    pub fn frequencies(&self, count: usize, low: f64, high: f64) -> Vec<f64> {
+        let (low, high) = (self.to_mel(low), self.to_mel(high));
+        let step = (high - low) / count.saturating_sub(1).max(1) as f64;
+        (0..count)
+            .map(|i| self.to_hz(low + i as f64 * step))
+            .collect()
+    }
+}
+
+impl FromStr for MelScale {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "htk" => Ok(MelScale::Htk),
+            "slaney" => Ok(MelScale::Slaney),
+            _ => Err(Error::InvalidArgument(format!("unknown mel scale '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for MelScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MelScale::Htk => write!(f, "htk"),
+            MelScale::Slaney => write!(f, "slaney"),
+        }
+    }
+}
+
+// Mel filterbank settings, the defaults are the ones of librosa.filters.mel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MelConfig {
+    pub bands: usize,
+    pub min_frequency: f64,
+    // Nyquist when not set
+    pub max_frequency: Option<f64>,
+    pub scale: MelScale,
+    // Scales every triangle to an area of 1, so wide high bands do not collect more energy than narrow low ones
+    pub normalize: bool,
+}
+
+impl Default for MelConfig {
+    fn default() -> Self {
+        MelConfig {
+            bands: 128,
+            min_frequency: 0.0,
+            max_frequency: None,
+            scale: MelScale::Slaney,
+            normalize: true,
+        }
+    }
+}
+
+// One triangle, only the bins it covers are stored
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    first_bin: usize,
+    weights: Vec<f64>,
+}
+
+// Overlapping triangular filters evenly spaced on the mel scale, applied to a power spectrum of fft_size / 2 + 1 bins
+//
+// Each triangle starts at the centre of the band below and ends at the centre of the band above
+#[derive(Debug, Clone, PartialEq)]
+pub struct MelFilterbank {
+    filters: Vec<Filter>,
+    centers: Vec<f64>,
+    fft_size: usize,
+    sample_rate: u32,
+}
+
+impl MelFilterbank {
+    pub fn new(config: &MelConfig, fft_size: usize, sample_rate: u32) -> Result<Self> {
+        let nyquist = sample_rate as f64 / 2.0;
+        let low = config.min_frequency;
+        let high = config.max_frequency.unwrap_or(nyquist);
+        if config.bands == 0 || fft_size == 0 {
+            return Err(Error::InvalidArgument(
+                "mel filterbank needs at least one band and bin".to_string(),
+            ));
+        }
+        if low < 0.0 || low >= high || high > nyquist {
+            return Err(Error::InvalidArgument(format!(
+                "bad mel frequency range {}Hz to {}Hz",
+                low, high
+            )));
+        }
+
+        // The edges of band i are points i and i + 2, its centre is point i + 1
+        let points = config.scale.frequencies(config.bands + 2, low, high);
+        let bin_width = sample_rate as f64 / fft_size as f64;
+        let num_bins = fft_size / 2 + 1;
+
+        let filters = points
+            .windows(3)
+            .map(|edges| {
+                let (left, centre, right) = (edges[0], edges[1], edges[2]);
+                let first_bin = ((left / bin_width).floor().max(0.0) as usize).min(num_bins);
+                let last_bin = ((right / bin_width).ceil() as usize).min(num_bins - 1);
+                let mut weights: Vec<f64> = (first_bin..=last_bin.max(first_bin))
+                    .map(|bin| {
+                        let frequency = bin as f64 * bin_width;
+                        let rising = (frequency - left) / (centre - left);
+                        let falling = (right - frequency) / (right - centre);
+                        rising.min(falling).max(0.0)
+                    })
+                    .collect();
+                if config.normalize {
+                    let area = 2.0 / (right - left);
+                    weights.iter_mut().for_each(|w| *w *= area);
+                }
+                Filter { first_bin, weights }
+            })
+            .collect();
+
+        Ok(MelFilterbank {
+            filters,
+            centers: points[1..=config.bands].to_vec(),
+            fft_size,
+            sample_rate,
+        })
+    }
+
+    pub fn num_bands(&self) -> usize {
+        self.filters.len()
+    }
+
+    // Peak of every triangle in Hz
+    pub fn center_frequencies(&self) -> &[f64] {
+        &self.centers
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Weight of every bin from 0 to fft_size / 2 in one band, the row of the band in the filterbank matrix
+    pub fn weights(&self, band: usize) -> Vec<f64> {
+        let filter = &self.filters[band];
+        let mut row = vec![0.0; self.fft_size / 2 + 1];
+        for (bin, weight) in filter.weights.iter().enumerate() {
+            if let Some(w) = row.get_mut(filter.first_bin + bin) {
+                *w = *weight;
+            }
+        }
+        row
+    }
+
+    // Energy of every band of a power spectrum, bins missing at the end count as zero
+    pub fn apply(&self, power: &[f64], energies: &mut [f64]) {
+        for (energy, filter) in energies.iter_mut().zip(&self.filters) {
+            let bins = power.iter().skip(filter.first_bin);
+            *energy = bins.zip(&filter.weights).map(|(p, w)| p * w).sum();
+        }
+    }
+
+    pub fn energies(&self, power: &[f64]) -> Vec<f64> {
+        let mut energies = vec![0.0; self.num_bands()];
+        self.apply(power, &mut energies);
+        energies
+    }
+}
+
+// Log mel energies in dB of every frame of a spectrogram
+//
+// The same as librosa.power_to_db(librosa.feature.melspectrogram(...)) with ref=1 and no top_db, up to the floor
+// of the dB conversion
+#[derive(Debug, Clone, PartialEq)]
+pub struct MelSpectrogram {
+    frames: Vec<Vec<f64>>,
+    filterbank: MelFilterbank,
+    frames_per_second: f64,
+}
+
+impl MelSpectrogram {
+    pub fn new<T: Float>(spectrogram: &Spectrogram<T>, config: &MelConfig) -> Result<Self> {
+        let filterbank = MelFilterbank::new(
+            config,
+            spectrogram.config().fft_size,
+            spectrogram.sample_rate(),
+        )?;
+
+        let mut power = Vec::new();
+        let frames = spectrogram
+            .frames()
+            .iter()
+            .map(|frame| {
+                power.clear();
+                power.extend(frame.iter().map(|m| m.as_f64() * m.as_f64()));
+                let mut energies = filterbank.energies(&power);
+                energies.iter_mut().for_each(|e| *e = power_to_db(*e, 1.0));
+                energies
+            })
+            .collect();
+
+        Ok(MelSpectrogram {
+            frames,
+            filterbank,
+            frames_per_second: spectrogram.frames_per_second(),
+        })
+    }
+
+    // One row of band levels in dB per frame
+    pub fn frames(&self) -> &[Vec<f64>] {
+        &self.frames
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn filterbank(&self) -> &MelFilterbank {
+        &self.filterbank
+    }
+
+    // Start of the frame in seconds
+    pub fn frame_time(&self, frame: usize) -> f64 {
+        frame as f64 / self.frames_per_second
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames_per_second
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::spectrogram::StftConfig;
+    use std::f64::consts::PI;
+
+    pub(crate) fn assert_all_close(actual: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() <= tolerance, "{} != {}", a, e);
+        }
+    }
+
+    #[test]
+    fn test_mel_scales() {
+        // librosa.hz_to_mel and mel_to_hz
+        let slaney = MelScale::Slaney;
+        assert!((slaney.to_mel(60.0) - 0.9).abs() < 1e-12);
+        let mels: Vec<f64> = [110.0, 220.0, 440.0].map(|f| slaney.to_mel(f)).to_vec();
+        assert_all_close(&mels, &[1.65, 3.3, 6.6], 1e-12);
+        let hz: Vec<f64> = [1.0, 2.0, 3.0, 4.0, 5.0].map(|m| slaney.to_hz(m)).to_vec();
+        assert_all_close(&hz, &[66.667, 133.333, 200.0, 266.667, 333.333], 1e-3);
+        assert!((slaney.to_mel(8000.0) - 45.245).abs() < 1e-3);
+
+        // htk=True
+        let htk = MelScale::Htk;
+        assert!((htk.to_mel(440.0) - 549.64).abs() < 1e-2);
+        assert!((htk.to_mel(8000.0) - 2840.023).abs() < 1e-3);
+
+        for scale in [slaney, htk] {
+            for frequency in [0.0, 500.0, 1000.0, 1500.0, 22050.0] {
+                assert!((scale.to_hz(scale.to_mel(frequency)) - frequency).abs() < 1e-9);
+            }
+        }
+
+        assert_eq!("HTK".parse::<MelScale>().unwrap(), MelScale::Htk);
+        assert!("bark".parse::<MelScale>().is_err());
+    }
+
+    #[test]
+    fn test_frequencies() {
+        // librosa.mel_frequencies(n_mels=40)
+        let expected = [
+            0.0, 85.317, 170.635, 255.952, 341.269, 426.586, 511.904, 597.221, 682.538, 767.855,
+            853.173, 938.49, 1024.856, 1119.114, 1222.042, 1334.436, 1457.167, 1591.187, 1737.532,
+            1897.337, 2071.84, 2262.393, 2470.47, 2697.686, 2945.799, 3216.731, 3512.582, 3835.643,
+            4188.417, 4573.636, 4994.285, 5453.621, 5955.205, 6502.92, 7101.009, 7754.107,
+            8467.272, 9246.028, 10096.408, 11025.0,
+        ];
+        let frequencies = MelScale::Slaney.frequencies(40, 0.0, 11025.0);
+        assert_all_close(&frequencies, &expected, 1e-3);
+    }
+
+    #[test]
+    fn test_filterbank() {
+        // librosa.filters.mel(sr=22050, n_fft=2048) starts with [0, 0.016, ...] in its first row
+        let filterbank = MelFilterbank::new(&MelConfig::default(), 2048, 22050).unwrap();
+        assert_eq!(filterbank.num_bands(), 128);
+        let first = filterbank.weights(0);
+        assert_eq!(first.len(), 1025);
+        assert_eq!(first[0], 0.0);
+        assert!((first[1] - 0.016).abs() < 5e-4);
+
+        // Every normalised triangle has an area of 1 Hz, as long as it spans enough bins to be sampled well
+        let bin_width = 22050.0 / 2048.0;
+        for band in [60, 100, 127] {
+            let area: f64 = filterbank.weights(band).iter().sum::<f64>() * bin_width;
+            assert!((area - 1.0).abs() < 0.01, "band {} area {}", band, area);
+        }
+
+        // Without normalisation a triangle peaks at 1 where its centre falls on a bin
+        let config = MelConfig {
+            bands: 10,
+            scale: MelScale::Htk,
+            normalize: false,
+            ..MelConfig::default()
+        };
+        let filterbank = MelFilterbank::new(&config, 512, 16000).unwrap();
+        for band in 0..10 {
+            let weights = filterbank.weights(band);
+            let peak = weights.iter().copied().fold(0.0, f64::max);
+            assert!(peak <= 1.0 && peak > 0.5);
+            let centre = filterbank.center_frequencies()[band] * 512.0 / 16000.0;
+            let strongest = weights.iter().position(|w| *w == peak).unwrap();
+            assert!((strongest as f64 - centre).abs() <= 0.5);
+        }
+
+        // Neighbouring unnormalised triangles sum to 1 between the first and last centre
+        let sum: Vec<f64> = (0..257)
+            .map(|bin| (0..10).map(|band| filterbank.weights(band)[bin]).sum())
+            .collect();
+        let (low, high) = (
+            filterbank.center_frequencies()[0],
+            filterbank.center_frequencies()[9],
+        );
+        for (bin, total) in sum.iter().enumerate() {
+            let frequency = bin as f64 * 16000.0 / 512.0;
+            if frequency >= low && frequency <= high {
+                assert!((total - 1.0).abs() < 1e-9, "bin {} sums to {}", bin, total);
+            }
+        }
+
+        assert!(MelFilterbank::new(&MelConfig { bands: 0, ..config }, 512, 16000).is_err());
+        let inverted = MelConfig {
+            min_frequency: 4000.0,
+            max_frequency: Some(2000.0),
+            ..config
+        };
+        assert!(MelFilterbank::new(&inverted, 512, 16000).is_err());
+        let past_nyquist = MelConfig {
+            max_frequency: Some(9000.0),
+            ..config
+        };
+        assert!(MelFilterbank::new(&past_nyquist, 512, 16000).is_err());
+    }
+
+    #[test]
+    fn test_log_mel_spectrogram() {
+        let sample_rate = 16000;
+        let samples: Vec<f64> = (0..16000)
+            .map(|n| 0.5 * (2.0 * PI * 1000.0 * n as f64 / sample_rate as f64).sin())
+            .collect();
+        let config = StftConfig {
+            window_size: 512,
+            hop_size: 256,
+            fft_size: 512,
+            ..StftConfig::default()
+        };
+        let spectrogram = Spectrogram::new(&samples, sample_rate, config);
+        let mel_config = MelConfig {
+            bands: 40,
+            ..MelConfig::default()
+        };
+        let mel = MelSpectrogram::new(&spectrogram, &mel_config).unwrap();
+        assert_eq!(mel.num_frames(), spectrogram.num_frames());
+        assert_eq!(mel.frame_time(2), spectrogram.frame_time(2));
+
+        // The tone lands in the band centred closest to it, far above every band it does not touch
+        let centers = mel.filterbank().center_frequencies();
+        let expected = (0..40)
+            .min_by(|a, b| {
+                (centers[*a] - 1000.0)
+                    .abs()
+                    .total_cmp(&(centers[*b] - 1000.0).abs())
+            })
+            .unwrap();
+        for frame in mel.frames() {
+            let loudest = (0..40)
+                .max_by(|a, b| frame[*a].total_cmp(&frame[*b]))
+                .unwrap();
+            assert!(loudest.abs_diff(expected) <= 1);
+            assert!(frame[loudest] - frame[5] > 60.0);
+        }
+
+        // Silence sits at the floor instead of -inf
+        let silence = Spectrogram::new(&[0.0; 2048], sample_rate, config);
+        let mel = MelSpectrogram::new(&silence, &mel_config).unwrap();
+        assert!(mel.frames()[0].iter().all(|db| db.is_finite()));
+    }
+}
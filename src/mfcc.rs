@@ -0,0 +1,321 @@
+use std::f64::consts::PI;
+
+use crate::error::{Error, Result};
+use crate::mel::MelSpectrogram;
+
+// MFCC settings, coefficients and lifter match librosa.feature.mfcc, the delta window the DELTAWINDOW of HTK
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MfccConfig {
+    pub coefficients: usize,
+    // Sine lifter 1 + L / 2 sin(PI (k + 1) / L) that boosts the higher coefficients, 0 turns it off. HTK uses 22
+    pub lifter: f64,
+    // Frames on each side of the regression of the deltas
+    pub delta_window: usize,
+}
+
+impl Default for MfccConfig {
+    fn default() -> Self {
+        MfccConfig {
+            coefficients: 20,
+            lifter: 0.0,
+            delta_window: 2,
+        }
+    }
+}
+
+// Orthonormal DCT-II of a fixed size, truncated to the first outputs
+//
+// X[k] = s(k) sum x[n] cos(PI k (2n + 1) / 2N) with s(0) = sqrt(1 / N) and s(k) = sqrt(2 / N), the same as
+// scipy.fft.dct(x, norm='ortho')
+#[derive(Debug, Clone, PartialEq)]
+struct Dct {
+    inputs: usize,
+    basis: Vec<f64>,
+}
+
+impl Dct {
+    fn new(inputs: usize, outputs: usize) -> Self {
+        let n = inputs as f64;
+        let mut basis = Vec::with_capacity(inputs * outputs);
+        for k in 0..outputs {
+            let scale = if k == 0 {
+                (1.0 / n).sqrt()
+            } else {
+                (2.0 / n).sqrt()
+            };
+            basis.extend(
+                (0..inputs).map(|i| scale * (PI * k as f64 * (2 * i + 1) as f64 / (2.0 * n)).cos()),
+            );
+        }
+        Dct { inputs, basis }
+    }
+
+    fn transform(&self, input: &[f64]) -> Vec<f64> {
+        self.basis
+            .chunks_exact(self.inputs)
+            .map(|row| row.iter().zip(input).map(|(b, x)| b * x).sum())
+            .collect()
+    }
+}
+
+// Cepstral coefficients of one frame with their first and second order deltas
+#[derive(Debug, Clone, PartialEq)]
+pub struct MfccFrame {
+    // Start of the frame in seconds
+    pub time: f64,
+    pub coefficients: Vec<f64>,
+    pub delta: Vec<f64>,
+    pub delta2: Vec<f64>,
+}
+
+// Mel-frequency cepstral coefficients, the DCT of the log mel energies of every frame
+//
+// The low coefficients describe the rough shape of the spectrum, the timbre, and hardly depend on the pitch or the
+// loudness apart from c0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mfcc {
+    frames: Vec<MfccFrame>,
+    config: MfccConfig,
+}
+
+impl Mfcc {
+    pub fn new(mel: &MelSpectrogram, config: &MfccConfig) -> Result<Self> {
+        let bands = mel.filterbank().num_bands();
+        if config.coefficients == 0 || config.coefficients > bands {
+            return Err(Error::InvalidArgument(format!(
+                "coefficients must be between 1 and the {} mel bands",
+                bands
+            )));
+        }
+        if config.lifter < 0.0 {
+            return Err(Error::InvalidArgument(
+                "lifter must not be negative".to_string(),
+            ));
+        }
+        if config.delta_window == 0 {
+            return Err(Error::InvalidArgument(
+                "delta window must be at least 1".to_string(),
+            ));
+        }
+
+        let dct = Dct::new(bands, config.coefficients);
+        let lifter = lifter_weights(config.coefficients, config.lifter);
+        let coefficients: Vec<Vec<f64>> = mel
+            .frames()
+            .iter()
+            .map(|frame| {
+                let mut c = dct.transform(frame);
+                c.iter_mut().zip(&lifter).for_each(|(c, w)| *c *= w);
+                c
+            })
+            .collect();
+
+        let delta = deltas(&coefficients, config.delta_window);
+        let delta2 = deltas(&delta, config.delta_window);
+        let frames = coefficients
+            .into_iter()
+            .zip(delta)
+            .zip(delta2)
+            .enumerate()
+            .map(|(i, ((coefficients, delta), delta2))| MfccFrame {
+                time: mel.frame_time(i),
+                coefficients,
+                delta,
+                delta2,
+            })
+            .collect();
+
+        Ok(Mfcc {
+            frames,
+            config: *config,
+        })
+    }
+
+    pub fn frames(&self) -> &[MfccFrame] {
+        &self.frames
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn num_coefficients(&self) -> usize {
+        self.config.coefficients
+    }
+
+    pub fn config(&self) -> &MfccConfig {
+        &self.config
+    }
+}
+
+fn lifter_weights(count: usize, lifter: f64) -> Vec<f64> {
+    (0..count)
+        .map(|k| {
+            if lifter > 0.0 {
+                1.0 + lifter / 2.0 * (PI * (k + 1) as f64 / lifter).sin()
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+// Regression deltas of HTK, d[t] = sum n (c[t + n] - c[t - n]) / 2 sum n^2 over n = 1..=window
+//
+// Frames past either end repeat the first or the last one, so the slope of a steady sound is 0 right up to the edges
+fn deltas(frames: &[Vec<f64>], window: usize) -> Vec<Vec<f64>> {
+    let last = frames.len().saturating_sub(1);
+    let norm = 2.0 * (1..=window).map(|n| (n * n) as f64).sum::<f64>();
+    (0..frames.len())
+        .map(|t| {
+            let mut delta = vec![0.0; frames[t].len()];
+            for n in 1..=window {
+                let (next, previous) = (&frames[(t + n).min(last)], &frames[t.saturating_sub(n)]);
+                for (d, (a, b)) in delta.iter_mut().zip(next.iter().zip(previous)) {
+                    *d += n as f64 * (a - b);
+                }
+            }
+            delta.iter_mut().for_each(|d| *d /= norm);
+            delta
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mel::MelConfig;
+    use crate::mel::tests::assert_all_close;
+    use crate::spectrogram::{Spectrogram, StftConfig};
+
+    #[test]
+    fn test_dct() {
+        // scipy.fft.dct([1, 2, 3, 4], norm='ortho')
+        let dct = Dct::new(4, 4);
+        let expected = [5.0, -2.230_442_497_387_663, 0.0, -0.158_512_667_781_108_15];
+        assert_all_close(&dct.transform(&[1.0, 2.0, 3.0, 4.0]), &expected, 1e-12);
+
+        // Orthonormal, a constant only lands in c0 and keeps its energy
+        let dct = Dct::new(40, 13);
+        let c = dct.transform(&[2.0; 40]);
+        assert!((c[0] - 2.0 * 40f64.sqrt()).abs() < 1e-12);
+        assert!(c[1..].iter().all(|c| c.abs() < 1e-12));
+
+        // and a basis cosine only in its own coefficient
+        let input: Vec<f64> = (0..40)
+            .map(|i| (PI * 3.0 * (2 * i + 1) as f64 / 80.0).cos())
+            .collect();
+        let c = dct.transform(&input);
+        for (k, c) in c.iter().enumerate() {
+            let expected = if k == 3 { 20f64.sqrt() } else { 0.0 };
+            assert!((c - expected).abs() < 1e-12, "c{} = {}", k, c);
+        }
+    }
+
+    #[test]
+    fn test_lifter() {
+        // librosa.feature.mfcc(..., lifter=22) scales by these
+        let expected = [
+            2.565_463_221_006_136,
+            4.099_058_125_255_727,
+            5.569_565_143_020_75,
+        ];
+        assert_all_close(&lifter_weights(3, 22.0), &expected, 1e-12);
+        assert_eq!(lifter_weights(3, 0.0), vec![1.0; 3]);
+    }
+
+    #[test]
+    fn test_deltas() {
+        // The slope of t^2 is 2t and its second derivative 2, away from the edges
+        let frames: Vec<Vec<f64>> = (0..20).map(|t| vec![(t * t) as f64, 5.0]).collect();
+        let delta = deltas(&frames, 2);
+        let delta2 = deltas(&delta, 2);
+        for t in 4..16 {
+            assert!((delta[t][0] - 2.0 * t as f64).abs() < 1e-12);
+            assert!((delta2[t][0] - 2.0).abs() < 1e-12);
+            assert_eq!(delta[t][1], 0.0);
+        }
+
+        // Edges repeat the end frames
+        assert!((delta[0][0] - (1.0 + 2.0 * 4.0) / 10.0).abs() < 1e-12);
+        assert!(deltas(&[], 2).is_empty());
+        assert_eq!(deltas(&[vec![1.0]], 2), vec![vec![0.0]]);
+    }
+
+    #[test]
+    fn test_mfcc() {
+        let sample_rate = 16000;
+        let samples: Vec<f64> = (0..sample_rate)
+            .map(|i| (2.0 * PI * 500.0 * i as f64 / sample_rate as f64).sin())
+            .collect();
+        let stft = StftConfig {
+            fft_size: 512,
+            hop_size: 160,
+            ..StftConfig::default()
+        };
+        let spectrogram = Spectrogram::new(&samples, sample_rate, stft);
+        let mel_config = MelConfig {
+            bands: 40,
+            ..MelConfig::default()
+        };
+        let mel = MelSpectrogram::new(&spectrogram, &mel_config).unwrap();
+        let config = MfccConfig {
+            coefficients: 13,
+            lifter: 22.0,
+            ..MfccConfig::default()
+        };
+        let mfcc = Mfcc::new(&mel, &config).unwrap();
+
+        assert_eq!(mfcc.num_frames(), mel.num_frames());
+        assert_eq!(mfcc.num_coefficients(), 13);
+        for (i, frame) in mfcc.frames().iter().enumerate() {
+            assert_eq!(frame.time, mel.frame_time(i));
+            assert_eq!(frame.coefficients.len(), 13);
+            assert_eq!(frame.delta.len(), 13);
+            assert_eq!(frame.delta2.len(), 13);
+        }
+
+        // A steady tone whose period divides the hop has the same coefficients in every frame and no deltas
+        let middle = &mfcc.frames()[mfcc.num_frames() / 2];
+        assert_all_close(&mfcc.frames()[10].coefficients, &middle.coefficients, 1e-6);
+        assert!(middle.delta.iter().all(|d| d.abs() < 1e-6));
+
+        // c0 is sqrt(bands) times the mean log energy, scaled by the lifter
+        let mean = mel.frames()[10].iter().sum::<f64>() / 40.0;
+        let c0 = 40f64.sqrt() * mean * lifter_weights(1, 22.0)[0];
+        assert!((mfcc.frames()[10].coefficients[0] - c0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let samples = vec![0.0; 4096];
+        let spectrogram = Spectrogram::new(&samples, 8000, StftConfig::default());
+        let mel_config = MelConfig {
+            bands: 20,
+            ..MelConfig::default()
+        };
+        let mel = MelSpectrogram::new(&spectrogram, &mel_config).unwrap();
+
+        for config in [
+            MfccConfig {
+                coefficients: 0,
+                ..MfccConfig::default()
+            },
+            MfccConfig {
+                coefficients: 21,
+                ..MfccConfig::default()
+            },
+            MfccConfig {
+                lifter: -1.0,
+                ..MfccConfig::default()
+            },
+            MfccConfig {
+                delta_window: 0,
+                ..MfccConfig::default()
+            },
+        ] {
+            assert!(Mfcc::new(&mel, &config).is_err(), "{:?}", config);
+        }
+        assert!(Mfcc::new(&mel, &MfccConfig::default()).is_ok());
+    }
+}
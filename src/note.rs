@@ -0,0 +1,136 @@
+use std::fmt;
+
+// Concert pitch, the A above middle C
+pub const A4_FREQUENCY: f64 = 440.0;
+const A4_MIDI: i32 = 69;
+
+const NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+// Fractional MIDI note number of a frequency, 69 is A4 and every semitone is 1
+pub fn frequency_to_midi(frequency: f64) -> f64 {
+    A4_MIDI as f64 + 12.0 * (frequency / A4_FREQUENCY).log2()
+}
+
+pub fn midi_to_frequency(midi: f64) -> f64 {
+    A4_FREQUENCY * 2f64.powf((midi - A4_MIDI as f64) / 12.0)
+}
+
+// Name of a pitch class, 0 is C
+pub fn pitch_class_name(pitch_class: usize) -> &'static str {
+    NAMES[pitch_class % 12]
+}
+
+// The equal tempered note closest to a frequency and how far off it the frequency is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Note {
+    pub midi: i32,
+    // -50 to 50, a hundredth of a semitone
+    pub cents: f64,
+}
+
+impl Note {
+    // None for frequencies that are not positive
+    pub fn from_frequency(frequency: f64) -> Option<Note> {
+        if !frequency.is_finite() || frequency <= 0.0 {
+            return None;
+        }
+
+        let midi = frequency_to_midi(frequency);
+        let nearest = midi.round();
+        Some(Note {
+            midi: nearest as i32,
+            cents: (midi - nearest) * 100.0,
+        })
+    }
+
+    // 0 is C, 9 is A
+    pub fn pitch_class(&self) -> usize {
+        self.midi.rem_euclid(12) as usize
+    }
+
+    // Scientific pitch notation, middle C (MIDI 60) is C4
+    pub fn octave(&self) -> i32 {
+        self.midi.div_euclid(12) - 1
+    }
+
+    // Name with the octave, e.g. "C#4"
+    pub fn name(&self) -> String {
+        format!("{}{}", pitch_class_name(self.pitch_class()), self.octave())
+    }
+
+    // Frequency of the note itself, without the cents
+    pub fn frequency(&self) -> f64 {
+        midi_to_frequency(self.midi as f64)
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Adding zero turns a -0 from rounding into 0, which prints as +0
+        write!(f, "{} {:+}c", self.name(), self.cents.round() + 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_midi_conversion() {
+        assert_eq!(frequency_to_midi(440.0), 69.0);
+        assert!((frequency_to_midi(261.6256) - 60.0).abs() < 1e-4);
+        assert!((midi_to_frequency(81.0) - 880.0).abs() < 1e-9);
+        assert!((midi_to_frequency(frequency_to_midi(1234.5)) - 1234.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_note_names() {
+        let a4 = Note::from_frequency(440.0).unwrap();
+        assert_eq!(a4.name(), "A4");
+        assert_eq!(a4.cents, 0.0);
+
+        let middle_c = Note::from_frequency(261.63).unwrap();
+        assert_eq!(middle_c.name(), "C4");
+        assert_eq!(middle_c.pitch_class(), 0);
+
+        assert_eq!(Note::from_frequency(27.5).unwrap().name(), "A0");
+        assert_eq!(Note::from_frequency(17.32).unwrap().name(), "C#0");
+        assert_eq!(
+            Note {
+                midi: 0,
+                cents: 0.0
+            }
+            .name(),
+            "C-1"
+        );
+    }
+
+    #[test]
+    fn test_cents() {
+        // A quarter tone above A4 rounds to A4 +50c or A#4 -50c, slightly less is clearly A4
+        let sharp = Note::from_frequency(midi_to_frequency(69.3)).unwrap();
+        assert_eq!(sharp.midi, 69);
+        assert!((sharp.cents - 30.0).abs() < 1e-9);
+        assert_eq!(sharp.to_string(), "A4 +30c");
+        let nearly_in_tune = Note {
+            midi: 69,
+            cents: -0.4,
+        };
+        assert_eq!(nearly_in_tune.to_string(), "A4 +0c");
+
+        let flat = Note::from_frequency(midi_to_frequency(68.8)).unwrap();
+        assert_eq!(flat.name(), "A4");
+        assert!((flat.cents + 20.0).abs() < 1e-9);
+        assert!((flat.frequency() - 440.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_invalid_frequencies() {
+        assert_eq!(Note::from_frequency(0.0), None);
+        assert_eq!(Note::from_frequency(-10.0), None);
+        assert_eq!(Note::from_frequency(f64::NAN), None);
+        assert_eq!(Note::from_frequency(f64::INFINITY), None);
+    }
+}
@@ -0,0 +1,468 @@
+use std::{fmt, str::FromStr};
+
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+
+use crate::error::{Error, Result};
+use crate::float::Float;
+use crate::spectrogram::StftConfig;
+use crate::tempo::{Tempo, TempoConfig, estimate_tempo};
+use crate::window::Window;
+
+// The flux compares log(1 + COMPRESSION |X|) of magnitudes scaled so a full scale sine peaks at 1, quiet attacks
+// count about as much as loud ones
+const COMPRESSION: f64 = 100.0;
+
+// How the change from one frame to the next is measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnsetFunction {
+    // Rise of the log magnitudes summed over the bins, made for percussive attacks
+    #[default]
+    SpectralFlux,
+    // Distance of every bin from the magnitude and phase it was heading for, also catches soft onsets that
+    // only change the pitch. Only bins that get louder count, as in Dixon's rectified variant
+    ComplexDomain,
+}
+
+impl FromStr for OnsetFunction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "flux" | "spectral-flux" => Ok(OnsetFunction::SpectralFlux),
+            "complex" | "complex-domain" => Ok(OnsetFunction::ComplexDomain),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown onset function '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for OnsetFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OnsetFunction::SpectralFlux => write!(f, "spectral flux"),
+            OnsetFunction::ComplexDomain => write!(f, "complex domain"),
+        }
+    }
+}
+
+// Onset detection settings, the peak picking ones are the defaults of librosa.onset.onset_detect
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OnsetConfig {
+    pub stft: StftConfig,
+    pub function: OnsetFunction,
+    // How far a peak has to rise above the moving median of the function, which is scaled to a maximum of 1
+    pub delta: f64,
+    // Seconds on each side a peak has to be the largest value of
+    pub max_window: f64,
+    // Seconds on each side of the moving median
+    pub median_window: f64,
+    // Shortest time between two onsets in seconds
+    pub min_interval: f64,
+}
+
+impl Default for OnsetConfig {
+    fn default() -> Self {
+        OnsetConfig {
+            stft: StftConfig {
+                window_size: 1024,
+                hop_size: 256,
+                fft_size: 1024,
+                window: Window::Hann,
+            },
+            function: OnsetFunction::SpectralFlux,
+            delta: 0.07,
+            max_window: 0.03,
+            median_window: 0.1,
+            min_interval: 0.03,
+        }
+    }
+}
+
+impl OnsetConfig {
+    pub fn onsets<T: Float>(&self, samples: &[T], sample_rate: u32) -> Result<Onsets> {
+        Onsets::new(samples, sample_rate, self)
+    }
+}
+
+// One detected onset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Onset {
+    // Seconds from the start, between frames when the peak of the function lies between them
+    pub time: f64,
+    // Value of the onset function at the peak, 0 to 1
+    pub strength: f64,
+}
+
+// Onset detection function of a recording and the onsets picked from it
+//
+// The function is scaled so its largest value is 1. Frame times are the centres of the windows, a sound that
+// starts in the middle of a window is where the magnitudes rise fastest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Onsets {
+    envelope: Vec<f64>,
+    onsets: Vec<Onset>,
+    sample_rate: u32,
+    config: OnsetConfig,
+}
+
+impl Onsets {
+    pub fn new<T: Float>(samples: &[T], sample_rate: u32, config: &OnsetConfig) -> Result<Self> {
+        let stft = config.stft;
+        if stft.window_size == 0 || stft.hop_size == 0 || stft.fft_size < stft.window_size {
+            return Err(Error::InvalidArgument(
+                "onsets need a positive window and hop and an FFT at least as long as the window"
+                    .to_string(),
+            ));
+        }
+        if [
+            config.delta,
+            config.max_window,
+            config.median_window,
+            config.min_interval,
+        ]
+        .iter()
+        .any(|v| v.is_nan() || *v < 0.0)
+        {
+            return Err(Error::InvalidArgument(
+                "onset peak picking settings must not be negative".to_string(),
+            ));
+        }
+
+        let mut envelope = onset_function(samples, config);
+        let max = envelope.iter().copied().fold(0.0, f64::max);
+        if max > 0.0 {
+            envelope.iter_mut().for_each(|v| *v /= max);
+        }
+
+        let frames_per_second = sample_rate as f64 / stft.hop_size as f64;
+        let frames = |seconds: f64| (seconds * frames_per_second).round() as usize;
+        let peaks = pick_peaks(
+            &envelope,
+            config.delta,
+            frames(config.max_window),
+            frames(config.median_window),
+            frames(config.min_interval),
+        );
+
+        let mut onsets = Onsets {
+            envelope,
+            onsets: Vec::new(),
+            sample_rate,
+            config: *config,
+        };
+        onsets.onsets = peaks
+            .into_iter()
+            .map(|frame| Onset {
+                time: onsets.time(refine(&onsets.envelope, frame)),
+                strength: onsets.envelope[frame],
+            })
+            .collect();
+        Ok(onsets)
+    }
+
+    // Onset function per frame, 0 to 1
+    pub fn envelope(&self) -> &[f64] {
+        &self.envelope
+    }
+
+    pub fn onsets(&self) -> &[Onset] {
+        &self.onsets
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.envelope.len()
+    }
+
+    pub fn config(&self) -> &OnsetConfig {
+        &self.config
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.sample_rate as f64 / self.config.stft.hop_size as f64
+    }
+
+    // Centre of the frame in seconds
+    pub fn frame_time(&self, frame: usize) -> f64 {
+        self.time(frame as f64)
+    }
+
+    // Same for a fractional frame
+    pub fn time(&self, frame: f64) -> f64 {
+        let stft = &self.config.stft;
+        (frame * stft.hop_size as f64 + stft.window_size as f64 / 2.0) / self.sample_rate as f64
+    }
+
+    // Tempo and beat grid of the onset function, None when it is too short for the slowest tempo or flat
+    pub fn tempo(&self, config: &TempoConfig) -> Result<Option<Tempo>> {
+        estimate_tempo(self, config)
+    }
+}
+
+// Onset function of every frame, the first frames that have nothing to compare with are 0
+fn onset_function<T: Float>(samples: &[T], config: &OnsetConfig) -> Vec<f64> {
+    let stft = config.stft;
+    let win: Vec<T> = stft
+        .window
+        .periodic(stft.window_size)
+        .into_iter()
+        .map(T::cast)
+        .collect();
+    // Scales the magnitudes so a full scale sine peaks at 1
+    let scale = 2.0 / win.iter().map(|w| w.as_f64()).sum::<f64>();
+    let bins = stft.fft_size / 2 + 1;
+
+    let mut planner: FftPlanner<T> = FftPlanner::new();
+    let fft = planner.plan_fft_forward(stft.fft_size);
+    let mut buff = vec![Complex::new(T::ZERO, T::ZERO); stft.fft_size];
+
+    // The current frame and the two before it
+    let mut frames = [(); 3].map(|_| vec![Complex::new(0.0, 0.0); bins]);
+    let mut envelope = Vec::new();
+    for (index, frame) in samples
+        .windows(stft.window_size)
+        .step_by(stft.hop_size)
+        .enumerate()
+    {
+        frames.rotate_right(1);
+        buff.iter_mut()
+            .for_each(|x| *x = Complex::new(T::ZERO, T::ZERO));
+        for ((x, s), w) in buff.iter_mut().zip(frame).zip(&win) {
+            *x = Complex::new(*s * *w, T::ZERO);
+        }
+        fft.process(&mut buff);
+        for (x, b) in frames[0].iter_mut().zip(&buff) {
+            *x = Complex::new(b.re.as_f64() * scale, b.im.as_f64() * scale);
+        }
+
+        let [current, previous, before] = &frames;
+        let value = match config.function {
+            OnsetFunction::SpectralFlux if index >= 1 => current
+                .iter()
+                .zip(previous)
+                .map(|(x, p)| {
+                    let rise = (COMPRESSION * x.norm()).ln_1p() - (COMPRESSION * p.norm()).ln_1p();
+                    rise.max(0.0)
+                })
+                .sum(),
+            OnsetFunction::ComplexDomain if index >= 2 => current
+                .iter()
+                .zip(previous.iter().zip(before))
+                .filter(|(x, (p, _))| x.norm() >= p.norm())
+                .map(|(x, (p, b))| {
+                    // Same magnitude as the last frame, the phase advancing as much as it did last time
+                    let target = Complex::from_polar(p.norm(), 2.0 * p.arg() - b.arg());
+                    (x - target).norm()
+                })
+                .sum(),
+            _ => 0.0,
+        };
+        envelope.push(value);
+    }
+    envelope
+}
+
+// Frames that are the largest within max_window frames on each side, at least delta above the median of
+// median_window frames on each side and min_interval frames after the previous onset
+//
+// The median follows the level of busy passages, so a hit has to stand out from what is around it
+fn pick_peaks(
+    envelope: &[f64],
+    delta: f64,
+    max_window: usize,
+    median_window: usize,
+    min_interval: usize,
+) -> Vec<usize> {
+    let mut peaks: Vec<usize> = Vec::new();
+    let mut window = Vec::with_capacity(2 * median_window + 1);
+    for (frame, &value) in envelope.iter().enumerate() {
+        if value <= 0.0 {
+            continue;
+        }
+        // The first frame of a plateau counts
+        let (low, high) = (
+            frame.saturating_sub(max_window),
+            (frame + max_window + 1).min(envelope.len()),
+        );
+        if envelope[low..frame].iter().any(|&v| v >= value)
+            || envelope[frame + 1..high].iter().any(|&v| v > value)
+        {
+            continue;
+        }
+
+        let (low, high) = (
+            frame.saturating_sub(median_window),
+            (frame + median_window + 1).min(envelope.len()),
+        );
+        window.clear();
+        window.extend_from_slice(&envelope[low..high]);
+        window.sort_by(f64::total_cmp);
+        if value < window[window.len() / 2] + delta {
+            continue;
+        }
+
+        if peaks
+            .last()
+            .is_some_and(|&last| frame - last < min_interval)
+        {
+            continue;
+        }
+        peaks.push(frame);
+    }
+    peaks
+}
+
+// Fractional index of a peak or a dip from a parabola through it and its neighbours
+pub(crate) fn refine(values: &[f64], index: usize) -> f64 {
+    if index == 0 || index + 1 >= values.len() {
+        return index as f64;
+    }
+    let (a, b, c) = (values[index - 1], values[index], values[index + 1]);
+    let curvature = a - 2.0 * b + c;
+    if curvature == 0.0 {
+        return index as f64;
+    }
+    index as f64 + (0.5 * (a - c) / curvature).clamp(-0.5, 0.5)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    pub(crate) const SAMPLE_RATE: u32 = 22050;
+
+    // Decaying 1kHz blips at the given times, like a metronome
+    pub(crate) fn clicks(times: &[f64], duration: f64) -> Vec<f64> {
+        let mut samples = vec![0.0; (duration * SAMPLE_RATE as f64) as usize];
+        for &time in times {
+            let start = (time * SAMPLE_RATE as f64).round() as usize;
+            for (i, sample) in samples[start..].iter_mut().take(2205).enumerate() {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                *sample += 0.8 * (-t / 0.015).exp() * (2.0 * PI * 1000.0 * t).sin();
+            }
+        }
+        samples
+    }
+
+    fn assert_onsets(onsets: &Onsets, times: &[f64]) {
+        assert_eq!(onsets.onsets().len(), times.len(), "{:?}", onsets.onsets());
+        for (onset, time) in onsets.onsets().iter().zip(times) {
+            assert!(
+                (onset.time - time).abs() < 0.015,
+                "{} != {}",
+                onset.time,
+                time
+            );
+            assert!(onset.strength > 0.0 && onset.strength <= 1.0);
+        }
+    }
+
+    #[test]
+    fn test_spectral_flux() {
+        let times = [0.25, 0.6, 1.1, 1.3, 1.45, 2.0];
+        let onsets = OnsetConfig::default()
+            .onsets(&clicks(&times, 2.5), SAMPLE_RATE)
+            .unwrap();
+        assert_onsets(&onsets, &times);
+        assert_eq!(onsets.envelope()[0], 0.0);
+        assert_eq!(onsets.envelope().iter().copied().fold(0.0, f64::max), 1.0);
+        assert_eq!(onsets.frame_time(0), 512.0 / SAMPLE_RATE as f64);
+    }
+
+    #[test]
+    fn test_complex_domain() {
+        let times = [0.3, 0.55, 1.2, 1.9];
+        let config = OnsetConfig {
+            function: OnsetFunction::ComplexDomain,
+            ..OnsetConfig::default()
+        };
+        let onsets = config.onsets(&clicks(&times, 2.5), SAMPLE_RATE).unwrap();
+        assert_onsets(&onsets, &times);
+
+        // A steady tone continues the way it was heading, the only onset is where it starts
+        let samples: Vec<f32> = (0..2 * SAMPLE_RATE)
+            .map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                if t < 0.5 {
+                    0.0
+                } else {
+                    (2.0 * PI * 440.0 * t).sin() as f32
+                }
+            })
+            .collect();
+        let onsets = config.onsets(&samples, SAMPLE_RATE).unwrap();
+        assert_onsets(&onsets, &[0.5]);
+    }
+
+    #[test]
+    fn test_pick_peaks() {
+        // The same bump counts on its own but not in a busy passage whose median is close to it
+        let mut envelope = vec![0.0; 40];
+        envelope[5] = 0.5;
+        envelope[20..32].fill(0.45);
+        envelope[26] = 0.5;
+        assert_eq!(pick_peaks(&envelope, 0.07, 1, 5, 0), vec![5]);
+        // it does with a lower delta, the passage itself never does
+        assert_eq!(pick_peaks(&envelope, 0.02, 1, 5, 0), vec![5, 26]);
+        assert!(pick_peaks(&envelope, 0.6, 1, 5, 0).is_empty());
+
+        // Two peaks closer than the interval, the first one wins
+        let envelope = [0.0, 1.0, 0.0, 0.0, 0.9, 0.0, 0.0, 0.0, 0.8, 0.0];
+        assert_eq!(pick_peaks(&envelope, 0.1, 1, 2, 0), vec![1, 4, 8]);
+        assert_eq!(pick_peaks(&envelope, 0.1, 1, 2, 4), vec![1, 8]);
+        // and only the largest one within the max window
+        assert_eq!(pick_peaks(&envelope, 0.1, 3, 2, 0), vec![1, 8]);
+
+        assert_eq!(refine(&[0.0, 1.0, 1.0, 0.0], 1), 1.5);
+        assert_eq!(refine(&[0.5, 1.0, 0.5], 1), 1.0);
+        assert_eq!(refine(&[1.0, 0.0, 0.0, 1.0], 1), 1.5);
+        assert_eq!(refine(&[1.0, 0.5], 0), 0.0);
+    }
+
+    #[test]
+    fn test_silence_and_invalid_config() {
+        let onsets = OnsetConfig::default()
+            .onsets(&[0.0; 8192], SAMPLE_RATE)
+            .unwrap();
+        assert!(onsets.envelope().iter().all(|&v| v == 0.0));
+        assert!(onsets.onsets().is_empty());
+        assert_eq!(onsets.num_frames(), 29);
+
+        // Shorter than a window
+        let onsets = OnsetConfig::default()
+            .onsets(&[0.5; 100], SAMPLE_RATE)
+            .unwrap();
+        assert_eq!(onsets.num_frames(), 0);
+
+        let config = OnsetConfig {
+            delta: -0.1,
+            ..OnsetConfig::default()
+        };
+        assert!(config.onsets(&[0.0; 8192], SAMPLE_RATE).is_err());
+        let config = OnsetConfig {
+            stft: StftConfig {
+                hop_size: 0,
+                ..OnsetConfig::default().stft
+            },
+            ..OnsetConfig::default()
+        };
+        assert!(config.onsets(&[0.0; 8192], SAMPLE_RATE).is_err());
+    }
+
+    #[test]
+    fn test_parse_function() {
+        assert_eq!(
+            "flux".parse::<OnsetFunction>().unwrap(),
+            OnsetFunction::SpectralFlux
+        );
+        assert_eq!(
+            "Complex-Domain".parse::<OnsetFunction>().unwrap(),
+            OnsetFunction::ComplexDomain
+        );
+        assert!("energy".parse::<OnsetFunction>().is_err());
+        assert_eq!(OnsetFunction::ComplexDomain.to_string(), "complex domain");
+    }
+}
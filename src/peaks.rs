@@ -0,0 +1,339 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::float::Float;
+use crate::spectrogram::Spectrogram;
+
+// A point of the constellation map, the spectral peaks survive noise and compression far better than anything else
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak {
+    pub frame: usize,
+    pub bin: usize,
+    pub magnitude: f64,
+}
+
+// Threshold relative to the magnitudes of the frame, so quiet passages still produce peaks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveThreshold {
+    // 50 is the median of the frame
+    pub percentile: f64,
+    // A peak has to be `factor` times louder than the percentile
+    pub factor: f64,
+}
+
+// Picks peaks that are the local maximum of a time x frequency neighbourhood
+//
+// The candidates are then filtered by the absolute and adaptive thresholds, the per band quotas
+// and finally by the maximum number of peaks per second
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeakPicker {
+    // Neighbourhood is (2 * radius + 1) frames by (2 * radius + 1) bins
+    pub time_radius: usize,
+    pub frequency_radius: usize,
+    pub min_magnitude: f64,
+    pub adaptive: Option<AdaptiveThreshold>,
+    // Band edges in bins, e.g. [0, 10, 20, 40, 80, 160, 512], the empty list is one band over the whole frame
+    pub bands: Vec<usize>,
+    // Strongest peaks kept per band in every frame
+    pub peaks_per_band: Option<usize>,
+    pub max_peaks_per_second: Option<usize>,
+}
+
+impl Default for PeakPicker {
+    fn default() -> Self {
+        PeakPicker {
+            time_radius: 5,
+            frequency_radius: 10,
+            min_magnitude: 0.0,
+            adaptive: Some(AdaptiveThreshold {
+                percentile: 50.0,
+                factor: 4.0,
+            }),
+            bands: Vec::new(),
+            peaks_per_band: None,
+            max_peaks_per_second: Some(30),
+        }
+    }
+}
+
+impl PeakPicker {
+    // Peaks of a single magnitude spectrum, all of them in frame 0
+    pub fn spectrum<T: Float>(&self, magnitudes: &[T]) -> Vec<Peak> {
+        let maxima = max_filter(magnitudes, self.frequency_radius);
+        let threshold = self.frame_threshold(magnitudes);
+
+        let candidates = (0..magnitudes.len())
+            .filter(|bin| magnitudes[*bin] > threshold && magnitudes[*bin] == maxima[*bin])
+            .map(|bin| Peak {
+                frame: 0,
+                bin,
+                magnitude: magnitudes[bin].as_f64(),
+            })
+            .collect();
+
+        self.apply_quotas(candidates)
+    }
+
+    // Peaks of the whole spectrogram sorted by frame and bin
+    pub fn spectrogram<T: Float>(&self, spectrogram: &Spectrogram<T>) -> Vec<Peak> {
+        let frames = spectrogram.frames();
+        if frames.is_empty() {
+            return Vec::new();
+        }
+
+        // The max filter is separable, filtering every frame along frequency and then every bin along time
+        // gives the maximum of the whole rectangle around each point
+        let mut maxima: Vec<Vec<T>> = frames
+            .iter()
+            .map(|frame| max_filter(frame, self.frequency_radius))
+            .collect();
+        let bins = frames.iter().map(|f| f.len()).min().unwrap_or(0);
+        for bin in 0..bins {
+            let column: Vec<T> = maxima.iter().map(|frame| frame[bin]).collect();
+            for (frame, max) in maxima.iter_mut().zip(max_filter(&column, self.time_radius)) {
+                frame[bin] = max;
+            }
+        }
+
+        let mut peaks = Vec::new();
+        for (index, (frame, frame_maxima)) in frames.iter().zip(&maxima).enumerate() {
+            let threshold = self.frame_threshold(frame);
+            let candidates = (0..bins)
+                .filter(|bin| frame[*bin] > threshold && frame[*bin] == frame_maxima[*bin])
+                .map(|bin| Peak {
+                    frame: index,
+                    bin,
+                    magnitude: frame[bin].as_f64(),
+                })
+                .collect();
+            peaks.extend(self.apply_quotas(candidates));
+        }
+
+        match self.max_peaks_per_second {
+            Some(limit) => limit_density(peaks, spectrogram.frames_per_second(), limit),
+            None => peaks,
+        }
+    }
+
+    // Compared against the magnitudes in their own precision, which keeps the test exact for f64
+    fn frame_threshold<T: Float>(&self, frame: &[T]) -> T {
+        let adaptive = self
+            .adaptive
+            .map_or(0.0, |a| percentile(frame, a.percentile) * a.factor);
+        T::cast(self.min_magnitude.max(adaptive))
+    }
+
+    // Keeps the strongest peaks_per_band peaks in every band of a frame
+    fn apply_quotas(&self, candidates: Vec<Peak>) -> Vec<Peak> {
+        let Some(quota) = self.peaks_per_band else {
+            return candidates;
+        };
+
+        let mut by_band: BTreeMap<usize, Vec<Peak>> = BTreeMap::new();
+        for peak in candidates {
+            // Bins past the last edge fall into the last band
+            let band = self
+                .bands
+                .iter()
+                .skip(1)
+                .position(|edge| peak.bin < *edge)
+                .unwrap_or(self.bands.len().saturating_sub(2));
+            by_band.entry(band).or_default().push(peak);
+        }
+
+        let mut kept: Vec<Peak> = by_band
+            .into_values()
+            .flat_map(|mut band| {
+                strongest_first(&mut band);
+                band.truncate(quota);
+                band
+            })
+            .collect();
+        kept.sort_by_key(|p| p.bin);
+        kept
+    }
+}
+
+// Sliding window maximum over radius values on each side, O(n) with a monotonic queue of indices
+pub fn max_filter<T: PartialOrd + Copy>(values: &[T], radius: usize) -> Vec<T> {
+    let mut result = Vec::with_capacity(values.len());
+    let mut queue: VecDeque<usize> = VecDeque::new();
+
+    for i in 0..values.len() + radius {
+        // Push the value entering the window on the right
+        if i < values.len() {
+            while queue.back().is_some_and(|j| values[*j] <= values[i]) {
+                queue.pop_back();
+            }
+            queue.push_back(i);
+        }
+
+        // Window centred at i - radius is complete
+        if i >= radius {
+            let centre = i - radius;
+            while queue.front().is_some_and(|j| *j + radius < centre) {
+                queue.pop_front();
+            }
+            result.push(values[queue[0]]);
+        }
+    }
+
+    result
+}
+
+// Percentile of the values with linear interpolation between the closest ranks
+pub fn percentile<T: Float>(values: &[T], percentile: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    let mut sorted: Vec<f64> = values.iter().map(|v| v.as_f64()).collect();
+    sorted.sort_by(f64::total_cmp);
+    let rank = (percentile.clamp(0.0, 100.0) / 100.0) * (sorted.len() - 1) as f64;
+    let low = rank.floor() as usize;
+    let high = rank.ceil() as usize;
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+}
+
+// Keeps the strongest `limit` peaks of every second
+fn limit_density(peaks: Vec<Peak>, frames_per_second: f64, limit: usize) -> Vec<Peak> {
+    let mut by_second: BTreeMap<usize, Vec<Peak>> = BTreeMap::new();
+    for peak in peaks {
+        let second = (peak.frame as f64 / frames_per_second) as usize;
+        by_second.entry(second).or_default().push(peak);
+    }
+
+    let mut kept: Vec<Peak> = by_second
+        .into_values()
+        .flat_map(|mut second| {
+            strongest_first(&mut second);
+            second.truncate(limit);
+            second
+        })
+        .collect();
+    kept.sort_by_key(|p| (p.frame, p.bin));
+    kept
+}
+
+fn strongest_first(peaks: &mut [Peak]) {
+    peaks.sort_by(|a, b| b.magnitude.total_cmp(&a.magnitude));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrogram::StftConfig;
+
+    fn picker() -> PeakPicker {
+        PeakPicker {
+            time_radius: 1,
+            frequency_radius: 1,
+            min_magnitude: 0.5,
+            adaptive: None,
+            bands: Vec::new(),
+            peaks_per_band: None,
+            max_peaks_per_second: None,
+        }
+    }
+
+    fn spectrogram(frames: Vec<Vec<f64>>) -> Spectrogram {
+        let config = StftConfig {
+            window_size: 16,
+            hop_size: 10,
+            fft_size: 16,
+            ..StftConfig::default()
+        };
+        Spectrogram::from_frames(frames, 100, config)
+    }
+
+    #[test]
+    fn test_max_filter() {
+        let values = [1.0, 3.0, 2.0, 0.0, 5.0, 4.0];
+        assert_eq!(max_filter(&values, 0), values.to_vec());
+        assert_eq!(max_filter(&values, 1), vec![3.0, 3.0, 3.0, 5.0, 5.0, 5.0]);
+        assert_eq!(max_filter(&values, 10), vec![5.0; 6]);
+        assert!(max_filter::<f64>(&[], 2).is_empty());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values = [4.0, 1.0, 3.0, 2.0, 5.0];
+        assert_eq!(percentile(&values, 50.0), 3.0);
+        assert_eq!(percentile(&values, 0.0), 1.0);
+        assert_eq!(percentile(&values, 100.0), 5.0);
+        assert_eq!(percentile(&values, 25.0), 2.0);
+        assert_eq!(percentile(&[1.0, 2.0], 50.0), 1.5);
+        assert_eq!(percentile::<f64>(&[], 50.0), 0.0);
+    }
+
+    #[test]
+    fn test_spectrum_peaks() {
+        let magnitudes = [0.0, 1.0, 0.2, 0.1, 0.4, 0.2, 3.0, 2.0, 0.0];
+        let peaks = picker().spectrum(&magnitudes);
+        let bins: Vec<usize> = peaks.iter().map(|p| p.bin).collect();
+        // Bin 4 is a local maximum but under the absolute threshold
+        assert_eq!(bins, vec![1, 6]);
+        assert!(peaks.iter().all(|p| p.frame == 0));
+    }
+
+    #[test]
+    fn test_adaptive_threshold() {
+        let magnitudes = [1.0, 2.0, 1.0, 1.0, 5.0, 1.0, 1.0, 1.5, 1.0];
+        let picker = PeakPicker {
+            min_magnitude: 0.0,
+            adaptive: Some(AdaptiveThreshold {
+                percentile: 50.0,
+                factor: 3.0,
+            }),
+            ..picker()
+        };
+        let bins: Vec<usize> = picker.spectrum(&magnitudes).iter().map(|p| p.bin).collect();
+        assert_eq!(bins, vec![4]);
+    }
+
+    #[test]
+    fn test_band_quotas() {
+        let magnitudes = [0.0, 2.0, 0.0, 3.0, 0.0, 1.0, 0.0, 4.0, 0.0, 5.0];
+        let picker = PeakPicker {
+            bands: vec![0, 5, 10],
+            peaks_per_band: Some(1),
+            ..picker()
+        };
+        let bins: Vec<usize> = picker.spectrum(&magnitudes).iter().map(|p| p.bin).collect();
+        assert_eq!(bins, vec![3, 9]);
+    }
+
+    #[test]
+    fn test_spectrogram_neighbourhood() {
+        let frames = vec![
+            vec![0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            vec![0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+        ];
+        let peaks = picker().spectrogram(&spectrogram(frames));
+        let points: Vec<(usize, usize)> = peaks.iter().map(|p| (p.frame, p.bin)).collect();
+        // (0, 1) is suppressed by the louder (1, 1) right after it
+        assert_eq!(points, vec![(1, 1), (1, 6), (4, 3)]);
+    }
+
+    #[test]
+    fn test_peaks_per_second() {
+        // 100Hz sample rate with a hop of 10 is 10 frames per second
+        let frames: Vec<Vec<f64>> = (0..20)
+            .map(|i| {
+                let mut frame = vec![0.0; 8];
+                frame[i % 8] = 1.0 + i as f64;
+                frame
+            })
+            .collect();
+        let picker = PeakPicker {
+            time_radius: 0,
+            max_peaks_per_second: Some(3),
+            ..picker()
+        };
+        let peaks = picker.spectrogram(&spectrogram(frames));
+        let frames: Vec<usize> = peaks.iter().map(|p| p.frame).collect();
+        assert_eq!(frames, vec![7, 8, 9, 17, 18, 19]);
+    }
+}
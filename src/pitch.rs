@@ -0,0 +1,760 @@
+use std::sync::Arc;
+use std::{fmt, str::FromStr};
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+use crate::error::{Error, Result};
+use crate::float::Float;
+use crate::note::{Note, frequency_to_midi};
+use crate::onset::refine;
+
+// pYIN draws its thresholds from a Beta(2, 18) distribution, mean 0.1, over this many steps from 0 to 1
+const PYIN_THRESHOLDS: usize = 100;
+const PYIN_BETA: (u32, u32) = (2, 18);
+// Weight of the lowest dip for thresholds no dip gets below
+const NO_DIP_PROBABILITY: f64 = 0.01;
+// Pitch states of the pYIN HMM per semitone
+const BINS_PER_SEMITONE: usize = 5;
+// Fastest glide the HMM follows, the same as librosa.pyin
+const MAX_OCTAVES_PER_SECOND: f64 = 35.92;
+// Chance of going from voiced to unvoiced or back between two frames
+const VOICING_SWITCH: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PitchMethod {
+    // The first dip of the normalised difference below the threshold, frame by frame
+    #[default]
+    Yin,
+    // Every dip weighted over a range of thresholds, then the most likely path of pitches and voicing
+    // through the frames. Fewer octave jumps and a voiced or unvoiced call for every frame
+    Pyin,
+}
+
+impl FromStr for PitchMethod {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "yin" => Ok(PitchMethod::Yin),
+            "pyin" => Ok(PitchMethod::Pyin),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown pitch method '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for PitchMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PitchMethod::Yin => write!(f, "YIN"),
+            PitchMethod::Pyin => write!(f, "pYIN"),
+        }
+    }
+}
+
+// Pitch tracking settings, the default range goes from below the low E of a bass guitar to the top of a soprano
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchConfig {
+    pub method: PitchMethod,
+    // Samples compared with the shifted copy, frames are this plus the longest period
+    pub window_size: usize,
+    pub hop_size: usize,
+    pub min_frequency: f64,
+    pub max_frequency: f64,
+    // Dip of the normalised difference that counts as periodic for YIN, 0 to 1. pYIN brings its own thresholds
+    pub threshold: f64,
+}
+
+impl Default for PitchConfig {
+    fn default() -> Self {
+        PitchConfig {
+            method: PitchMethod::Yin,
+            window_size: 1024,
+            hop_size: 256,
+            min_frequency: 40.0,
+            max_frequency: 2000.0,
+            threshold: 0.1,
+        }
+    }
+}
+
+impl PitchConfig {
+    pub fn track<T: Float>(&self, samples: &[T], sample_rate: u32) -> Result<Pitch> {
+        let mut tracker = PitchTracker::new(self, sample_rate)?;
+        for sample in samples {
+            tracker.push(&[sample.as_f64()]);
+        }
+        Ok(tracker.finish())
+    }
+}
+
+// Fundamental frequency of one frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchFrame {
+    // Centre of the samples the frame looks at, in seconds
+    pub time: f64,
+    // None for unvoiced frames, silence, noise or more than one note
+    pub frequency: Option<f64>,
+    // How periodic the frame is for YIN, one minus the depth of its dip. How likely it is voiced for pYIN
+    pub confidence: f64,
+}
+
+impl PitchFrame {
+    // Nearest note and how many cents off it, None for unvoiced frames
+    pub fn note(&self) -> Option<Note> {
+        self.frequency.and_then(Note::from_frequency)
+    }
+}
+
+// Pitch of every frame of a recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pitch {
+    frames: Vec<PitchFrame>,
+    config: PitchConfig,
+}
+
+impl Pitch {
+    pub fn frames(&self) -> &[PitchFrame] {
+        &self.frames
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn config(&self) -> &PitchConfig {
+        &self.config
+    }
+
+    // Frames with a pitch
+    pub fn voiced(&self) -> impl Iterator<Item = &PitchFrame> {
+        self.frames.iter().filter(|frame| frame.frequency.is_some())
+    }
+}
+
+// What the tracker keeps of a frame until finish
+#[derive(Debug, Clone, PartialEq)]
+enum Estimate {
+    Yin(Option<f64>, f64),
+    // Frequencies of the dips with their probability
+    Pyin(Vec<(f64, f64)>),
+}
+
+// Pitch tracker fed one frame of samples at a time, so long files never have to be in memory at once
+//
+// YIN after de Cheveigné and Kawahara (2002) and pYIN after Mauch and Dixon (2014). Only the estimates of the frames
+// are kept, pYIN needs all of them for its path through the recording
+pub struct PitchTracker {
+    config: PitchConfig,
+    sample_rate: u32,
+    min_lag: usize,
+    max_lag: usize,
+    difference: Difference,
+    // The samples of the next frame and how many to drop before it starts when the hop is longer than a frame
+    buffer: Vec<f64>,
+    skip: usize,
+    estimates: Vec<Estimate>,
+    // Probability of every pYIN threshold
+    thresholds: Vec<(f64, f64)>,
+}
+
+impl PitchTracker {
+    pub fn new(config: &PitchConfig, sample_rate: u32) -> Result<Self> {
+        if config.window_size == 0 || config.hop_size == 0 {
+            return Err(Error::InvalidArgument(
+                "pitch needs a positive window size and hop".to_string(),
+            ));
+        }
+        let nyquist = sample_rate as f64 / 2.0;
+        if !(config.min_frequency > 0.0
+            && config.min_frequency < config.max_frequency
+            && config.max_frequency <= nyquist)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "pitch needs 0 < min frequency < max frequency <= {} Hz",
+                nyquist
+            )));
+        }
+        if !(config.threshold > 0.0 && config.threshold <= 1.0) {
+            return Err(Error::InvalidArgument(
+                "the YIN threshold has to be between 0 and 1".to_string(),
+            ));
+        }
+
+        let min_lag = (sample_rate as f64 / config.max_frequency).floor() as usize;
+        let max_lag = (sample_rate as f64 / config.min_frequency).ceil() as usize;
+        let thresholds = (0..PYIN_THRESHOLDS)
+            .map(|i| {
+                let (low, high) = (
+                    i as f64 / PYIN_THRESHOLDS as f64,
+                    (i + 1) as f64 / PYIN_THRESHOLDS as f64,
+                );
+                let (a, b) = PYIN_BETA;
+                (high, beta_cdf(high, a, b) - beta_cdf(low, a, b))
+            })
+            .collect();
+
+        Ok(PitchTracker {
+            config: *config,
+            sample_rate,
+            min_lag,
+            max_lag,
+            difference: Difference::new(config.window_size, max_lag),
+            buffer: Vec::with_capacity(config.window_size + max_lag),
+            skip: 0,
+            estimates: Vec::new(),
+            thresholds,
+        })
+    }
+
+    // One sample per channel, the channels are averaged
+    pub fn push(&mut self, frame: &[f64]) {
+        if frame.is_empty() {
+            return;
+        }
+        if self.skip > 0 {
+            self.skip -= 1;
+            return;
+        }
+
+        self.buffer
+            .push(frame.iter().sum::<f64>() / frame.len() as f64);
+        if self.buffer.len() < self.config.window_size + self.max_lag {
+            return;
+        }
+
+        self.difference.update(&self.buffer);
+        let normalized = &self.difference.normalized;
+        let estimate = match self.config.method {
+            PitchMethod::Yin => {
+                let (frequency, confidence) = self.yin(normalized);
+                Estimate::Yin(frequency, confidence)
+            }
+            PitchMethod::Pyin => Estimate::Pyin(self.pyin(normalized)),
+        };
+        self.estimates.push(estimate);
+
+        let hop = self.config.hop_size;
+        self.skip = hop.saturating_sub(self.buffer.len());
+        self.buffer.drain(..hop.min(self.buffer.len()));
+    }
+
+    pub fn finish(self) -> Pitch {
+        let frames = match self.config.method {
+            PitchMethod::Yin => self
+                .estimates
+                .iter()
+                .enumerate()
+                .map(|(i, estimate)| {
+                    let Estimate::Yin(frequency, confidence) = *estimate else {
+                        unreachable!("YIN tracker with a pYIN estimate")
+                    };
+                    PitchFrame {
+                        time: self.frame_time(i),
+                        frequency,
+                        confidence,
+                    }
+                })
+                .collect(),
+            PitchMethod::Pyin => self.decode(),
+        };
+        Pitch {
+            frames,
+            config: self.config,
+        }
+    }
+
+    // Centre of the samples of the frame in seconds
+    pub fn frame_time(&self, frame: usize) -> f64 {
+        let length = self.config.window_size + self.max_lag;
+        ((frame * self.config.hop_size) as f64 + length as f64 / 2.0) / self.sample_rate as f64
+    }
+
+    // The first dip below the threshold followed down to its bottom, the shortest period that is periodic enough.
+    // Unvoiced when there is none
+    fn yin(&self, normalized: &[f64]) -> (Option<f64>, f64) {
+        let range = self.min_lag..=self.max_lag;
+        let Some(mut lag) = range
+            .clone()
+            .find(|&lag| normalized[lag] < self.config.threshold)
+        else {
+            let lowest = normalized[range].iter().copied().fold(f64::MAX, f64::min);
+            return (None, (1.0 - lowest).clamp(0.0, 1.0));
+        };
+        while lag < self.max_lag && normalized[lag + 1] < normalized[lag] {
+            lag += 1;
+        }
+
+        let frequency = self.sample_rate as f64 / refine(normalized, lag);
+        (Some(frequency), (1.0 - normalized[lag]).clamp(0.0, 1.0))
+    }
+
+    // Every dip gets the probability of the thresholds it is the first dip below. Silence has no dips and
+    // so no candidates at all
+    fn pyin(&self, normalized: &[f64]) -> Vec<(f64, f64)> {
+        let dips: Vec<usize> = (self.min_lag..=self.max_lag)
+            .filter(|&lag| match lag {
+                lag if lag == self.min_lag => normalized[lag] < normalized[lag + 1],
+                lag if lag == self.max_lag => normalized[lag] < normalized[lag - 1],
+                lag => {
+                    normalized[lag] < normalized[lag - 1] && normalized[lag] <= normalized[lag + 1]
+                }
+            })
+            .collect();
+        let Some(&lowest) = dips
+            .iter()
+            .min_by(|a, b| normalized[**a].total_cmp(&normalized[**b]))
+        else {
+            return Vec::new();
+        };
+
+        let mut probabilities = vec![0.0; dips.len()];
+        for &(threshold, probability) in &self.thresholds {
+            match dips.iter().position(|&lag| normalized[lag] < threshold) {
+                Some(dip) => probabilities[dip] += probability,
+                None => {
+                    let dip = dips.iter().position(|&lag| lag == lowest).unwrap();
+                    probabilities[dip] += probability * NO_DIP_PROBABILITY;
+                }
+            }
+        }
+
+        dips.iter()
+            .zip(probabilities)
+            .filter(|(_, probability)| *probability > 0.0)
+            .map(|(&lag, probability)| {
+                (
+                    self.sample_rate as f64 / refine(normalized, lag),
+                    probability,
+                )
+            })
+            .collect()
+    }
+
+    // Viterbi path through pitch bins that are voiced or not, pitches move at most a few bins per frame and the
+    // voicing rarely switches
+    fn decode(&self) -> Vec<PitchFrame> {
+        let low = frequency_to_midi(self.config.min_frequency);
+        let bins = ((frequency_to_midi(self.config.max_frequency) - low) * BINS_PER_SEMITONE as f64)
+            .ceil() as usize
+            + 1;
+        let bin = |frequency: f64| {
+            let bin = ((frequency_to_midi(frequency) - low) * BINS_PER_SEMITONE as f64).round();
+            (bin >= 0.0 && bin < bins as f64).then_some(bin as usize)
+        };
+        let bin_frequency = |bin: usize| {
+            self.config.min_frequency * 2f64.powf(bin as f64 / (12 * BINS_PER_SEMITONE) as f64)
+        };
+
+        // Triangular transitions over the bins a glide can reach in one frame, normalised per bin it leaves
+        let reach = (MAX_OCTAVES_PER_SECOND * 12.0 * self.config.hop_size as f64
+            / self.sample_rate as f64)
+            .round() as usize
+            * BINS_PER_SEMITONE
+            / 2;
+        let weight = |step: usize| (reach + 1 - step) as f64;
+        let leaving: Vec<f64> = (0..bins)
+            .map(|from| {
+                (from.saturating_sub(reach)..(from + reach + 1).min(bins))
+                    .map(|to| weight(from.abs_diff(to)))
+                    .sum::<f64>()
+                    .ln()
+            })
+            .collect();
+        let (stay, switch) = ((1.0 - VOICING_SWITCH).ln(), VOICING_SWITCH.ln());
+
+        // States 0..bins are voiced, bins..2 bins unvoiced
+        let mut scores = vec![-((2 * bins) as f64).ln(); 2 * bins];
+        let mut next = vec![0.0; 2 * bins];
+        let mut observations = vec![0.0; 2 * bins];
+        let mut back: Vec<Vec<u16>> = Vec::with_capacity(self.estimates.len());
+        let mut voicing = Vec::with_capacity(self.estimates.len());
+        for (frame, estimate) in self.estimates.iter().enumerate() {
+            let Estimate::Pyin(candidates) = estimate else {
+                unreachable!("pYIN tracker with a YIN estimate")
+            };
+            let voiced: f64 = candidates.iter().map(|(_, p)| p).sum();
+            voicing.push(voiced.min(1.0));
+            observations.fill(0.0);
+            for &(frequency, probability) in candidates {
+                if let Some(bin) = bin(frequency) {
+                    observations[bin] += probability;
+                }
+            }
+            observations[bins..].fill((1.0 - voiced).max(0.0) / bins as f64);
+            observations
+                .iter_mut()
+                .for_each(|p| *p = p.max(f64::MIN_POSITIVE).ln());
+
+            if frame == 0 {
+                scores
+                    .iter_mut()
+                    .zip(&observations)
+                    .for_each(|(s, o)| *s += o);
+                back.push(Vec::new());
+                continue;
+            }
+
+            let mut pointers = vec![0u16; 2 * bins];
+            for state in 0..2 * bins {
+                let to = state % bins;
+                let (from_voiced, from_unvoiced) = if state < bins {
+                    (stay, switch)
+                } else {
+                    (switch, stay)
+                };
+                let mut best = (f64::NEG_INFINITY, 0);
+                let reachable = to.saturating_sub(reach)..(to + reach + 1).min(bins);
+                for (from, leaving) in reachable.clone().zip(&leaving[reachable]) {
+                    let pitch = weight(from.abs_diff(to)).ln() - leaving;
+                    for (from_state, voicing) in [(from, from_voiced), (from + bins, from_unvoiced)]
+                    {
+                        let score = scores[from_state] + pitch + voicing;
+                        if score > best.0 {
+                            best = (score, from_state);
+                        }
+                    }
+                }
+                next[state] = best.0 + observations[state];
+                pointers[state] = best.1 as u16;
+            }
+            std::mem::swap(&mut scores, &mut next);
+            back.push(pointers);
+        }
+
+        // Back from the best last state
+        let mut path = vec![0; self.estimates.len()];
+        if let Some(last) = path.len().checked_sub(1) {
+            path[last] = (0..2 * bins)
+                .max_by(|a, b| scores[*a].total_cmp(&scores[*b]))
+                .unwrap();
+            for frame in (1..=last).rev() {
+                path[frame - 1] = back[frame][path[frame]] as usize;
+            }
+        }
+
+        path.iter()
+            .zip(&self.estimates)
+            .enumerate()
+            .map(|(frame, (&state, estimate))| {
+                let Estimate::Pyin(candidates) = estimate else {
+                    unreachable!("pYIN tracker with a YIN estimate")
+                };
+                // The exact frequency of the dip in the bin of the path, the centre of the bin otherwise
+                let frequency = (state < bins).then(|| {
+                    candidates
+                        .iter()
+                        .filter(|(frequency, _)| bin(*frequency) == Some(state))
+                        .max_by(|a, b| a.1.total_cmp(&b.1))
+                        .map_or(bin_frequency(state), |(frequency, _)| *frequency)
+                });
+                PitchFrame {
+                    time: self.frame_time(frame),
+                    frequency,
+                    confidence: voicing[frame],
+                }
+            })
+            .collect()
+    }
+}
+
+// Cumulative mean normalised difference of YIN with the cross term from an FFT
+//
+// d(lag) = sum (x[j] - x[j + lag])^2 over the window, which is the energy of both parts minus twice their
+// correlation. d'(lag) = d(lag) lag / sum d(1..=lag) is 1 where d is average and dips towards 0 at the periods
+struct Difference {
+    window_size: usize,
+    max_lag: usize,
+    forward: Arc<dyn Fft<f64>>,
+    inverse: Arc<dyn Fft<f64>>,
+    window: Vec<Complex<f64>>,
+    frame: Vec<Complex<f64>>,
+    energy: Vec<f64>,
+    normalized: Vec<f64>,
+}
+
+impl Difference {
+    fn new(window_size: usize, max_lag: usize) -> Self {
+        // The window shifted by the longest lag still ends inside the frame, so the circular correlation does not wrap
+        let size = (window_size + max_lag).next_power_of_two();
+        let mut planner = FftPlanner::new();
+        Difference {
+            window_size,
+            max_lag,
+            forward: planner.plan_fft_forward(size),
+            inverse: planner.plan_fft_inverse(size),
+            window: vec![Complex::new(0.0, 0.0); size],
+            frame: vec![Complex::new(0.0, 0.0); size],
+            energy: vec![0.0; window_size + max_lag + 1],
+            normalized: vec![0.0; max_lag + 1],
+        }
+    }
+
+    // d' for lags 0..=max_lag of a frame of window_size + max_lag samples, into normalized
+    fn update(&mut self, samples: &[f64]) {
+        let size = self.frame.len();
+        for (i, (w, f)) in self.window.iter_mut().zip(&mut self.frame).enumerate() {
+            let x = samples.get(i).copied().unwrap_or(0.0);
+            *f = Complex::new(x, 0.0);
+            *w = Complex::new(if i < self.window_size { x } else { 0.0 }, 0.0);
+        }
+        self.forward.process(&mut self.window);
+        self.forward.process(&mut self.frame);
+        for (f, w) in self.frame.iter_mut().zip(&self.window) {
+            *f *= w.conj();
+        }
+        self.inverse.process(&mut self.frame);
+
+        // Running sums of the squares give the energy of any stretch
+        for (i, x) in samples.iter().enumerate() {
+            self.energy[i + 1] = self.energy[i] + x * x;
+        }
+        let energy = |lag: usize| self.energy[lag + self.window_size] - self.energy[lag];
+
+        self.normalized[0] = 1.0;
+        let mut sum = 0.0;
+        for lag in 1..=self.max_lag {
+            let correlation = self.frame[lag].re / size as f64;
+            let difference = (energy(0) + energy(lag) - 2.0 * correlation).max(0.0);
+            sum += difference;
+            self.normalized[lag] = if sum > 0.0 {
+                difference * lag as f64 / sum
+            } else {
+                1.0
+            };
+        }
+    }
+}
+
+// Cumulative distribution of Beta(a, b) for whole a and b, the chance that at least a of a + b - 1 draws are below x
+fn beta_cdf(x: f64, a: u32, b: u32) -> f64 {
+    let n = a + b - 1;
+    let mut binomial = 1.0;
+    let mut cdf = 0.0;
+    for j in 0..=n {
+        if j >= a {
+            cdf += binomial * x.powi(j as i32) * (1.0 - x).powi((n - j) as i32);
+        }
+        binomial = binomial * (n - j) as f64 / (j + 1) as f64;
+    }
+    cdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    // Sum of the first harmonics of the frequency with falling amplitudes, like a plucked string
+    fn tone(frequency: f64, harmonics: usize, seconds: f64) -> Vec<f64> {
+        (0..(seconds * SAMPLE_RATE as f64) as usize)
+            .map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                (1..=harmonics)
+                    .map(|k| (2.0 * PI * k as f64 * frequency * t).sin() / k as f64)
+                    .sum::<f64>()
+                    * 0.5
+            })
+            .collect()
+    }
+
+    fn noise(count: usize) -> Vec<f64> {
+        let mut state = 7u32;
+        (0..count)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                state as f64 / u32::MAX as f64 - 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_difference() {
+        // Against the sums of the definition
+        let samples = noise(600);
+        let (window_size, max_lag) = (400, 200);
+        let mut difference = Difference::new(window_size, max_lag);
+        difference.update(&samples);
+        let normalized = &difference.normalized;
+
+        let mut sum = 0.0;
+        for lag in 1..=max_lag {
+            let d: f64 = (0..window_size)
+                .map(|j| (samples[j] - samples[j + lag]).powi(2))
+                .sum();
+            sum += d;
+            assert!((normalized[lag] - d * lag as f64 / sum).abs() < 1e-9);
+        }
+        assert_eq!(normalized[0], 1.0);
+
+        // Silence is equally unperiodic everywhere
+        difference.update(&[0.0; 600]);
+        assert!(difference.normalized.iter().all(|&d| d == 1.0));
+    }
+
+    #[test]
+    fn test_yin() {
+        for (frequency, name) in [
+            (55.0, "A1"),
+            (98.0, "G2"),
+            (261.63, "C4"),
+            (440.0, "A4"),
+            (1318.5, "E6"),
+        ] {
+            let pitch = PitchConfig::default()
+                .track(&tone(frequency, 6, 0.5), SAMPLE_RATE)
+                .unwrap();
+            assert!(pitch.num_frames() > 20);
+            for frame in pitch.frames() {
+                let estimate = frame.frequency.unwrap();
+                assert!(
+                    (estimate / frequency - 1.0).abs() < 0.002,
+                    "{} != {}",
+                    estimate,
+                    frequency
+                );
+                assert!(frame.confidence > 0.9);
+                assert_eq!(frame.note().unwrap().name(), name);
+            }
+        }
+
+        // A frame of 1024 + 552 samples centred at 788
+        let pitch = PitchConfig::default()
+            .track(&tone(440.0, 1, 0.5), SAMPLE_RATE)
+            .unwrap();
+        assert_eq!(pitch.frames()[0].time, 788.0 / SAMPLE_RATE as f64);
+        assert_eq!(pitch.frames()[1].time, (788.0 + 256.0) / SAMPLE_RATE as f64);
+        assert_eq!(pitch.voiced().count(), pitch.num_frames());
+    }
+
+    #[test]
+    fn test_unvoiced() {
+        for method in [PitchMethod::Yin, PitchMethod::Pyin] {
+            let config = PitchConfig {
+                method,
+                ..PitchConfig::default()
+            };
+            let pitch = config.track(&vec![0.0; 11025], SAMPLE_RATE).unwrap();
+            assert!(pitch.num_frames() > 0);
+            assert_eq!(pitch.voiced().count(), 0);
+
+            let pitch = config.track(&noise(22050), SAMPLE_RATE).unwrap();
+            assert!(
+                pitch.voiced().count() * 10 < pitch.num_frames(),
+                "{}: {} of {} voiced",
+                method,
+                pitch.voiced().count(),
+                pitch.num_frames()
+            );
+        }
+    }
+
+    #[test]
+    fn test_pyin() {
+        // Silence, a note, another a fifth up and silence again
+        let mut samples = vec![0.0; 5512];
+        samples.extend(tone(220.0, 4, 0.5));
+        samples.extend(tone(330.0, 4, 0.5));
+        samples.extend(vec![0.0; 5512]);
+        let config = PitchConfig {
+            method: PitchMethod::Pyin,
+            ..PitchConfig::default()
+        };
+        let pitch = config.track(&samples, SAMPLE_RATE).unwrap();
+
+        for frame in pitch.frames() {
+            // Frames that overlap two parts can go either way
+            let expected = match frame.time {
+                t if !(0.2..=1.3).contains(&t) => None,
+                t if (0.35..0.7).contains(&t) => Some(220.0),
+                t if (0.8..1.2).contains(&t) => Some(330.0),
+                _ => continue,
+            };
+            match (frame.frequency, expected) {
+                (Some(frequency), Some(expected)) => {
+                    assert!((frequency / expected - 1.0).abs() < 0.002, "{:?}", frame);
+                    assert!(frame.confidence > 0.9, "{:?}", frame);
+                }
+                (None, None) => assert!(frame.confidence < 0.1, "{:?}", frame),
+                _ => panic!("{:?} should be {:?}", frame, expected),
+            }
+        }
+        assert_eq!(
+            pitch.voiced().map(|f| f.note().unwrap().name()).max(),
+            Some("E4".to_string())
+        );
+    }
+
+    #[test]
+    fn test_streaming() {
+        // Stereo frames pushed one at a time and the downmix in one go give the same pitches
+        let left = tone(196.0, 3, 0.3);
+        let right = tone(196.0, 5, 0.3);
+        let config = PitchConfig {
+            hop_size: 2000,
+            ..PitchConfig::default()
+        };
+        let mut tracker = PitchTracker::new(&config, SAMPLE_RATE).unwrap();
+        for (l, r) in left.iter().zip(&right) {
+            tracker.push(&[*l, *r]);
+        }
+        let streamed = tracker.finish();
+
+        let mono: Vec<f64> = left
+            .iter()
+            .zip(&right)
+            .map(|(l, r)| (l + r) / 2.0)
+            .collect();
+        let whole = config.track(&mono, SAMPLE_RATE).unwrap();
+        assert_eq!(streamed, whole);
+        // A hop longer than the frame skips the samples in between
+        assert_eq!(streamed.num_frames(), 3);
+        assert_eq!(streamed.frames()[1].time, 2788.0 / SAMPLE_RATE as f64);
+    }
+
+    #[test]
+    fn test_beta_cdf() {
+        // scipy.stats.beta.cdf(0.1, 2, 18)
+        assert!((beta_cdf(0.1, 2, 18) - 0.579_735_021_168_402_7).abs() < 1e-12);
+        assert_eq!(beta_cdf(0.0, 2, 18), 0.0);
+        assert!((beta_cdf(1.0, 2, 18) - 1.0).abs() < 1e-12);
+        assert!((beta_cdf(0.3, 1, 1) - 0.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        for config in [
+            PitchConfig {
+                hop_size: 0,
+                ..PitchConfig::default()
+            },
+            PitchConfig {
+                min_frequency: 0.0,
+                ..PitchConfig::default()
+            },
+            PitchConfig {
+                max_frequency: 20000.0,
+                ..PitchConfig::default()
+            },
+            PitchConfig {
+                threshold: 1.5,
+                ..PitchConfig::default()
+            },
+        ] {
+            assert!(
+                PitchTracker::new(&config, SAMPLE_RATE).is_err(),
+                "{:?}",
+                config
+            );
+        }
+
+        assert_eq!("pYIN".parse::<PitchMethod>().unwrap(), PitchMethod::Pyin);
+        assert!("crepe".parse::<PitchMethod>().is_err());
+        assert_eq!(PitchMethod::Yin.to_string(), "YIN");
+    }
+}
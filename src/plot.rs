@@ -0,0 +1,308 @@
+use std::{fmt, fs, ops::Range, path::Path, str::FromStr};
+
+use plotters::coord::Shift;
+use plotters::coord::ranged1d::{KeyPointHint, NoDefaultFormatting, Ranged, ValueFormatter};
+use plotters::prelude::*;
+
+use crate::error::Error;
+use crate::spectrogram::FrequencyScale;
+pub use crate::spectrogram::{format_frequency, frequency_ticks};
+
+mod matches;
+mod spectrogram;
+mod spectrum;
+mod waveform;
+
+pub use matches::MatchPlot;
+pub use spectrogram::{SpectrogramFigure, SpectrogramPlot};
+pub use spectrum::SpectrumPlot;
+pub use waveform::WaveformPlot;
+
+// Sizes in the plots are given in pixels at this resolution and scaled up for higher DPI
+const BASE_DPI: u32 = 96;
+const INCH: f64 = 0.0254;
+
+pub type PlotResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// Anything that can be drawn on a plotters drawing area, so it can be rendered by every backend
+pub trait Figure {
+    fn draw<DB: DrawingBackend>(
+        &self,
+        root: &DrawingArea<DB, Shift>,
+        image: &ImageOptions,
+    ) -> PlotResult<()>
+    where
+        DB::ErrorType: 'static;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+impl ImageFormat {
+    // Format from the file extension, None when it is neither .png nor .svg
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "svg" => Ok(ImageFormat::Svg),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown image format '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Svg => "svg",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Size, resolution and format of a rendered figure
+//
+// width and height are in pixels, the DPI scales text, margins and lines so a high resolution render looks like
+// a larger version of the default one, and it is stored in the PNG so the image prints at its intended size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageOptions {
+    pub width: u32,
+    pub height: u32,
+    pub dpi: u32,
+    pub format: ImageFormat,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            width: 800,
+            height: 600,
+            dpi: BASE_DPI,
+            format: ImageFormat::Png,
+        }
+    }
+}
+
+impl ImageOptions {
+    // Encoded PNG or SVG file in memory
+    pub fn render(&self, figure: &impl Figure) -> PlotResult<Vec<u8>> {
+        if self.width == 0 || self.height == 0 || self.dpi == 0 {
+            return Err(Error::InvalidArgument(
+                "image size and DPI have to be positive".to_string(),
+            )
+            .into());
+        }
+
+        let size = (self.width, self.height);
+        match self.format {
+            ImageFormat::Png => {
+                let mut pixels = vec![0; self.width as usize * self.height as usize * 3];
+                {
+                    let root = BitMapBackend::with_buffer(&mut pixels, size).into_drawing_area();
+                    figure.draw(&root, self)?;
+                    root.present()?;
+                }
+                self.encode_png(&pixels)
+            }
+            ImageFormat::Svg => {
+                let mut svg = String::new();
+                {
+                    let root = SVGBackend::with_string(&mut svg, size).into_drawing_area();
+                    figure.draw(&root, self)?;
+                    root.present()?;
+                }
+                Ok(svg.into_bytes())
+            }
+        }
+    }
+
+    pub fn save(&self, figure: &impl Figure, path: &Path) -> PlotResult<()> {
+        fs::write(path, self.render(figure)?)?;
+        Ok(())
+    }
+
+    // Pixels of a size given at the base resolution
+    pub fn px(&self, size: u32) -> u32 {
+        if size == 0 {
+            return 0;
+        }
+        ((size * self.dpi) as f64 / BASE_DPI as f64)
+            .round()
+            .max(1.0) as u32
+    }
+
+    fn encode_png(&self, pixels: &[u8]) -> PlotResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        // pHYs chunk, PNG stores the resolution in pixels per metre
+        let per_metre = (self.dpi as f64 / INCH).round() as u32;
+        encoder.set_pixel_dims(Some(png::PixelDimensions {
+            xppu: per_metre,
+            yppu: per_metre,
+            unit: png::Unit::Meter,
+        }));
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(pixels)?;
+        writer.finish()?;
+        Ok(bytes)
+    }
+}
+
+// Frequency axis in positions of the scale, with the ticks at round frequencies and labelled in Hz
+pub(crate) struct FrequencyAxis {
+    scale: FrequencyScale,
+    range: Range<f64>,
+    ticks: Vec<f64>,
+}
+
+impl FrequencyAxis {
+    pub(crate) fn new(low: f64, high: f64, scale: FrequencyScale) -> Self {
+        FrequencyAxis {
+            scale,
+            range: scale.forward(low)..scale.forward(high),
+            ticks: frequency_ticks(low, high, scale)
+                .into_iter()
+                .map(|f| scale.forward(f))
+                .collect(),
+        }
+    }
+}
+
+impl Ranged for FrequencyAxis {
+    type ValueType = f64;
+    type FormatOption = NoDefaultFormatting;
+
+    fn map(&self, value: &f64, limit: (i32, i32)) -> i32 {
+        let fraction = (value - self.range.start) / (self.range.end - self.range.start);
+        limit.0 + (fraction * (limit.1 - limit.0) as f64).round() as i32
+    }
+
+    fn key_points<Hint: KeyPointHint>(&self, hint: Hint) -> Vec<f64> {
+        // Thin out the ticks evenly when the axis is too short for all of them
+        let max = hint.max_num_points().max(1);
+        let step = self.ticks.len().div_ceil(max).max(1);
+        self.ticks.iter().copied().step_by(step).collect()
+    }
+
+    fn range(&self) -> Range<f64> {
+        self.range.clone()
+    }
+}
+
+impl ValueFormatter<f64> for FrequencyAxis {
+    fn format_ext(&self, position: &f64) -> String {
+        format_frequency(self.scale.inverse(*position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Blank;
+
+    impl Figure for Blank {
+        fn draw<DB: DrawingBackend>(
+            &self,
+            root: &DrawingArea<DB, Shift>,
+            _image: &ImageOptions,
+        ) -> PlotResult<()>
+        where
+            DB::ErrorType: 'static,
+        {
+            root.fill(&WHITE)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_image_format() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("plot.SVG")),
+            Some(ImageFormat::Svg)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a/plot.png")),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("plot.csv")), None);
+        assert_eq!(ImageFormat::from_path(Path::new("plot")), None);
+        assert_eq!("svg".parse::<ImageFormat>().unwrap().to_string(), "svg");
+    }
+
+    #[test]
+    fn test_render_png_in_memory() {
+        let image = ImageOptions {
+            width: 30,
+            height: 20,
+            dpi: 300,
+            format: ImageFormat::Png,
+        };
+        let png = image.render(&Blank).unwrap();
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (30, 20));
+        // 300 DPI is 11811 pixels per metre
+        let dims = info.pixel_dims.unwrap();
+        assert_eq!((dims.xppu, dims.yppu), (11811, 11811));
+    }
+
+    #[test]
+    fn test_render_svg_in_memory() {
+        let image = ImageOptions {
+            width: 30,
+            height: 20,
+            format: ImageFormat::Svg,
+            ..ImageOptions::default()
+        };
+        let svg = String::from_utf8(image.render(&Blank).unwrap()).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("width=\"30\""));
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        let image = ImageOptions {
+            width: 0,
+            ..ImageOptions::default()
+        };
+        assert!(image.render(&Blank).is_err());
+    }
+
+    #[test]
+    fn test_px_scales_with_dpi() {
+        let image = ImageOptions::default();
+        assert_eq!(image.px(12), 12);
+        let print = ImageOptions {
+            dpi: 288,
+            ..ImageOptions::default()
+        };
+        assert_eq!(print.px(12), 36);
+        assert_eq!(print.px(0), 0);
+        // Lines never get thinner than a pixel
+        let draft = ImageOptions {
+            dpi: 30,
+            ..ImageOptions::default()
+        };
+        assert_eq!(draft.px(1), 1);
+    }
+}
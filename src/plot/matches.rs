@@ -0,0 +1,145 @@
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use super::{Figure, ImageOptions, PlotResult};
+use crate::index::{HashHit, OFFSET_TOLERANCE};
+
+// Query time against reference time of every hash the query shares with one track
+//
+// A true match piles up on a diagonal at the offset of the match, chance collisions are spread over the whole plot
+pub struct MatchPlot<'a> {
+    pub hits: &'a [HashHit],
+    pub frames_per_second: f64,
+    // Offset of the match in seconds, its hits are highlighted together with the diagonal
+    pub offset: Option<f64>,
+}
+
+impl Figure for MatchPlot<'_> {
+    fn draw<DB: DrawingBackend>(
+        &self,
+        root: &DrawingArea<DB, Shift>,
+        image: &ImageOptions,
+    ) -> PlotResult<()>
+    where
+        DB::ErrorType: 'static,
+    {
+        let seconds = |frame: u32| frame as f64 / self.frames_per_second;
+        let hits = self.hits;
+        let query_end = hits.iter().map(|h| h.query_time).max().map_or(1.0, seconds);
+        let reference_end = hits
+            .iter()
+            .map(|h| h.reference_time)
+            .max()
+            .map_or(1.0, seconds);
+        let offset_frames = self
+            .offset
+            .map(|o| (o * self.frames_per_second).round() as i64);
+        let (aligned, scattered): (Vec<&HashHit>, Vec<&HashHit>) = hits
+            .iter()
+            .partition(|hit| offset_frames.is_some_and(|o| is_aligned(hit, o)));
+
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(root)
+            .caption("Hash matches", ("sans-serif", image.px(24)))
+            .margin(image.px(10))
+            .x_label_area_size(image.px(40))
+            .y_label_area_size(image.px(60))
+            .build_cartesian_2d(
+                0.0..query_end.max(f64::EPSILON) * 1.05,
+                0.0..reference_end.max(f64::EPSILON) * 1.05,
+            )?;
+        chart
+            .configure_mesh()
+            .label_style(("sans-serif", image.px(12)))
+            .axis_desc_style(("sans-serif", image.px(14)))
+            .x_desc("Query time (s)")
+            .y_desc("Reference time (s)")
+            .draw()?;
+
+        let point = |hit: &&HashHit| (seconds(hit.query_time), seconds(hit.reference_time));
+        let radius = image.px(2);
+        chart
+            .draw_series(
+                scattered
+                    .iter()
+                    .map(|hit| Circle::new(point(hit), radius, BLACK.mix(0.3).filled())),
+            )?
+            .label(format!("other hashes ({})", scattered.len()))
+            .legend(move |(x, y)| Circle::new((x + 10, y), radius + 1, BLACK.mix(0.3).filled()));
+
+        if let Some(offset) = self.offset {
+            chart.draw_series(LineSeries::new(
+                [(0.0, offset), (query_end, query_end + offset)],
+                RED.mix(0.3).stroke_width(image.px(1)),
+            ))?;
+            chart
+                .draw_series(
+                    aligned
+                        .iter()
+                        .map(|hit| Circle::new(point(hit), radius, RED.filled())),
+                )?
+                .label(format!("at {:.2}s ({})", offset, aligned.len()))
+                .legend(move |(x, y)| Circle::new((x + 10, y), radius + 1, RED.filled()));
+        }
+
+        chart
+            .configure_series_labels()
+            .label_font(("sans-serif", image.px(12)))
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .position(SeriesLabelPosition::UpperLeft)
+            .draw()?;
+        Ok(())
+    }
+}
+
+// Whether the hit votes for the offset, with the same tolerance as the index
+fn is_aligned(hit: &HashHit, offset: i64) -> bool {
+    (hit.offset() - offset).abs() <= OFFSET_TOLERANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plot::ImageFormat;
+
+    #[test]
+    fn test_is_aligned() {
+        let hit = HashHit {
+            query_time: 10,
+            reference_time: 110,
+        };
+        assert!(is_aligned(&hit, 100));
+        assert!(is_aligned(&hit, 101));
+        assert!(!is_aligned(&hit, 102));
+    }
+
+    #[test]
+    fn test_render() {
+        let hits: Vec<HashHit> = (0..50)
+            .map(|t| HashHit {
+                query_time: t,
+                reference_time: t + 20,
+            })
+            .collect();
+        let image = ImageOptions {
+            format: ImageFormat::Svg,
+            ..ImageOptions::default()
+        };
+
+        let plot = MatchPlot {
+            hits: &hits,
+            frames_per_second: 10.0,
+            offset: Some(2.0),
+        };
+        let svg = String::from_utf8(image.render(&plot).unwrap()).unwrap();
+        assert!(svg.contains("at 2.00s (50)"));
+
+        let empty = MatchPlot {
+            hits: &[],
+            frames_per_second: 10.0,
+            offset: None,
+        };
+        assert!(!image.render(&empty).unwrap().is_empty());
+    }
+}
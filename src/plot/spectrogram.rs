@@ -0,0 +1,393 @@
+use std::ops::Range;
+
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use super::{Figure, FrequencyAxis, ImageOptions, PlotResult};
+use crate::colormap::ColorMap;
+use crate::error::Error;
+use crate::peaks::Peak;
+use crate::spectrogram::{FrequencyScale, TimeFrequency, to_db};
+
+// Width of the dB colour bar to the right of the heatmap
+const LEGEND_WIDTH: u32 = 90;
+// Steps of the colour bar
+const LEGEND_STEPS: usize = 128;
+
+// Spectrogram heatmap, time in seconds on x, frequency in Hz on y and the level in dB as colour
+//
+// Draws the STFT spectrogram as well as the constant-Q one, a log scale shows the even spacing of the latter
+//
+// Levels are relative to the loudest bin of the whole spectrogram, so two renders of the same settings
+// are only comparable when both recordings peak at a similar level
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectrogramPlot {
+    pub scale: FrequencyScale,
+    pub color_map: ColorMap,
+    // dB below the loudest bin that still get a colour, anything quieter is the bottom of the colour map
+    pub dynamic_range: f64,
+    // Shown frequencies, default all of the bins
+    pub min_frequency: Option<f64>,
+    pub max_frequency: Option<f64>,
+}
+
+impl Default for SpectrogramPlot {
+    fn default() -> Self {
+        SpectrogramPlot {
+            scale: FrequencyScale::Linear,
+            color_map: ColorMap::Viridis,
+            dynamic_range: 80.0,
+            min_frequency: None,
+            max_frequency: None,
+        }
+    }
+}
+
+impl SpectrogramPlot {
+    pub fn figure<'a>(&'a self, spectrogram: &'a dyn TimeFrequency) -> SpectrogramFigure<'a> {
+        SpectrogramFigure {
+            plot: self,
+            spectrogram,
+            peaks: &[],
+            pairs: &[],
+        }
+    }
+
+    // Colour of a magnitude from 0 at the bottom of the dynamic range to 1 at the reference
+    pub fn level(&self, magnitude: f64, reference: f64) -> f64 {
+        (1.0 + to_db(magnitude, reference) / self.dynamic_range).clamp(0.0, 1.0)
+    }
+
+    // Lowest and highest frequency shown, the log scale cannot start at 0Hz so it starts at the first bin instead
+    pub fn frequency_range(&self, spectrogram: &dyn TimeFrequency) -> PlotResult<(f64, f64)> {
+        let (bottom, top) = spectrogram.frequency_range();
+        let mut low = self.min_frequency.unwrap_or(bottom).max(bottom);
+        if self.scale == FrequencyScale::Log && low <= 0.0 {
+            low = spectrogram.bin_frequency(1.0);
+        }
+        let high = self.max_frequency.unwrap_or(top).min(top);
+
+        if low >= high {
+            return Err(Error::InvalidArgument(format!(
+                "empty frequency range {}Hz to {}Hz",
+                low, high
+            ))
+            .into());
+        }
+        Ok((low, high))
+    }
+
+    fn draw_legend<DB: DrawingBackend>(
+        &self,
+        area: &DrawingArea<DB, Shift>,
+        image: &ImageOptions,
+    ) -> PlotResult<()>
+    where
+        DB::ErrorType: 'static,
+    {
+        let mut bar = ChartBuilder::on(area)
+            .caption("dB", ("sans-serif", image.px(24)))
+            .margin(image.px(10))
+            .x_label_area_size(image.px(40))
+            .y_label_area_size(image.px(40))
+            .build_cartesian_2d(0.0..1.0, -self.dynamic_range..0.0)?;
+
+        let step = self.dynamic_range / LEGEND_STEPS as f64;
+        bar.draw_series((0..LEGEND_STEPS).map(|i| {
+            let db = -self.dynamic_range + i as f64 * step;
+            let [r, g, b] = self.color_map.rgb(i as f64 / (LEGEND_STEPS - 1) as f64);
+            Rectangle::new([(0.0, db), (1.0, db + step)], RGBColor(r, g, b).filled())
+        }))?;
+
+        bar.configure_mesh()
+            .disable_mesh()
+            .disable_x_axis()
+            .y_labels(9)
+            .y_label_style(("sans-serif", image.px(12)))
+            .y_label_formatter(&|db| format!("{:.0}", db))
+            .draw()?;
+        Ok(())
+    }
+}
+
+// A spectrogram drawn with the settings of a SpectrogramPlot, optionally with its constellation on top
+pub struct SpectrogramFigure<'a> {
+    plot: &'a SpectrogramPlot,
+    spectrogram: &'a dyn TimeFrequency,
+    peaks: &'a [Peak],
+    pairs: &'a [(Peak, Peak)],
+}
+
+impl<'a> SpectrogramFigure<'a> {
+    // Picked peaks as circles and the anchor -> target pairs hashed from them as lines
+    pub fn constellation(self, peaks: &'a [Peak], pairs: &'a [(Peak, Peak)]) -> Self {
+        SpectrogramFigure {
+            peaks,
+            pairs,
+            ..self
+        }
+    }
+}
+
+impl Figure for SpectrogramFigure<'_> {
+    fn draw<DB: DrawingBackend>(
+        &self,
+        root: &DrawingArea<DB, Shift>,
+        image: &ImageOptions,
+    ) -> PlotResult<()>
+    where
+        DB::ErrorType: 'static,
+    {
+        let plot = self.plot;
+        let spectrogram = self.spectrogram;
+        if plot.dynamic_range <= 0.0 {
+            return Err(
+                Error::InvalidArgument("dynamic range has to be positive".to_string()).into(),
+            );
+        }
+
+        let (low, high) = plot.frequency_range(spectrogram)?;
+        let scale = plot.scale;
+        let (bottom, top) = (scale.forward(low), scale.forward(high));
+        let axis = FrequencyAxis::new(low, high, scale);
+
+        root.fill(&WHITE)?;
+        let (width, _) = root.dim_in_pixel();
+        let (main, legend) = root.split_horizontally(width.saturating_sub(image.px(LEGEND_WIDTH)));
+
+        let mut chart = ChartBuilder::on(&main)
+            .caption("Spectrogram", ("sans-serif", image.px(24)))
+            .margin(image.px(10))
+            .x_label_area_size(image.px(40))
+            .y_label_area_size(image.px(60))
+            .build_cartesian_2d(0.0..spectrogram.duration().max(f64::EPSILON), axis)?;
+
+        // Every pixel row and column looks up its own bin and frame, so any frequency scale works the same way,
+        // neighbouring pixels of the same cell are merged into one rectangle to keep vector output small
+        let area = chart.plotting_area().strip_coord_spec();
+        let (columns, rows) = area.dim_in_pixel();
+        let frames = spectrogram.num_frames();
+        let bins = spectrogram.num_bins();
+        if frames > 0 && bins > 0 {
+            let reference = spectrogram.max_magnitude();
+            let row_bins = runs(rows, |y| {
+                let position = top - (y as f64 + 0.5) / rows as f64 * (top - bottom);
+                let bin = spectrogram.frequency_bin(scale.inverse(position)).round();
+                (bin.max(0.0) as usize).min(bins - 1)
+            });
+            let column_frames = runs(columns, |x| {
+                let frame = ((x as f64 + 0.5) / columns as f64 * frames as f64) as usize;
+                frame.min(frames - 1)
+            });
+
+            for (x, frame) in &column_frames {
+                for (y, bin) in &row_bins {
+                    let level = plot.level(spectrogram.magnitude(*frame, *bin), reference);
+                    let [r, g, b] = plot.color_map.rgb(level);
+                    area.draw(&Rectangle::new(
+                        [
+                            (x.start as i32, y.start as i32),
+                            (x.end as i32, y.end as i32),
+                        ],
+                        RGBColor(r, g, b).filled(),
+                    ))?;
+                }
+            }
+        }
+
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .label_style(("sans-serif", image.px(12)))
+            .axis_desc_style(("sans-serif", image.px(14)))
+            .x_desc("Time (s)")
+            .y_desc("Frequency (Hz)")
+            .draw()?;
+
+        // Centre of the peak's cell, peaks outside of the shown frequencies are left out
+        let frames_per_second = spectrogram.frames_per_second();
+        let point = |peak: &Peak| {
+            let frequency = spectrogram.bin_frequency(peak.bin as f64);
+            (frequency >= low && frequency <= high).then(|| {
+                (
+                    (peak.frame as f64 + 0.5) / frames_per_second,
+                    scale.forward(frequency),
+                )
+            })
+        };
+        chart.draw_series(self.pairs.iter().filter_map(|(anchor, target)| {
+            Some(PathElement::new(
+                vec![point(anchor)?, point(target)?],
+                WHITE.mix(0.6).stroke_width(image.px(1)),
+            ))
+        }))?;
+        chart.draw_series(
+            self.peaks
+                .iter()
+                .filter_map(point)
+                .map(|p| Circle::new(p, image.px(3), RED.stroke_width(image.px(2)))),
+        )?;
+
+        plot.draw_legend(&legend, image)?;
+        Ok(())
+    }
+}
+
+// Pixels 0..count grouped into runs that map to the same index
+fn runs(count: u32, index: impl Fn(u32) -> usize) -> Vec<(Range<u32>, usize)> {
+    let mut runs: Vec<(Range<u32>, usize)> = Vec::new();
+    for pixel in 0..count {
+        let i = index(pixel);
+        match runs.last_mut() {
+            Some((range, last)) if *last == i => range.end = pixel + 1,
+            _ => runs.push((pixel..pixel + 1, i)),
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cqt::CqtConfig;
+    use crate::fingerprint::FingerprintConfig;
+    use crate::plot::ImageFormat;
+    use crate::spectrogram::{Spectrogram, StftConfig};
+    use std::f64::consts::PI;
+
+    fn tone(frequency: f64, sample_rate: u32, seconds: f64) -> Vec<f64> {
+        (0..(sample_rate as f64 * seconds) as usize)
+            .map(|n| (2.0 * PI * frequency * n as f64 / sample_rate as f64).sin())
+            .collect()
+    }
+
+    fn image() -> ImageOptions {
+        ImageOptions {
+            width: 400,
+            height: 300,
+            ..ImageOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_level() {
+        let plot = SpectrogramPlot {
+            dynamic_range: 60.0,
+            ..SpectrogramPlot::default()
+        };
+        assert_eq!(plot.level(1.0, 1.0), 1.0);
+        assert!((plot.level(0.001, 1.0) - 0.0).abs() < 1e-12);
+        assert!((plot.level(10f64.powf(-1.5), 1.0) - 0.5).abs() < 1e-12);
+        assert_eq!(plot.level(0.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_frequency_range() {
+        let spectrogram: Spectrogram =
+            Spectrogram::from_frames(Vec::new(), 8000, StftConfig::default());
+        let linear = SpectrogramPlot::default();
+        assert_eq!(linear.frequency_range(&spectrogram).unwrap(), (0.0, 4000.0));
+
+        let log = SpectrogramPlot {
+            scale: FrequencyScale::Log,
+            max_frequency: Some(10000.0),
+            ..SpectrogramPlot::default()
+        };
+        assert_eq!(log.frequency_range(&spectrogram).unwrap(), (7.8125, 4000.0));
+
+        let empty = SpectrogramPlot {
+            min_frequency: Some(3000.0),
+            max_frequency: Some(2000.0),
+            ..SpectrogramPlot::default()
+        };
+        assert!(empty.frequency_range(&spectrogram).is_err());
+    }
+
+    #[test]
+    fn test_runs() {
+        assert_eq!(
+            runs(5, |x| x as usize / 2),
+            vec![(0..2, 0), (2..4, 1), (4..5, 2)]
+        );
+        assert_eq!(runs(3, |_| 7), vec![(0..3, 7)]);
+        assert!(runs(0, |x| x as usize).is_empty());
+    }
+
+    #[test]
+    fn test_render_scales() {
+        let sample_rate = 8000;
+        let spectrogram = Spectrogram::new(
+            &tone(1000.0, sample_rate, 1.0),
+            sample_rate,
+            StftConfig::default(),
+        );
+
+        for scale in [
+            FrequencyScale::Linear,
+            FrequencyScale::Log,
+            FrequencyScale::Mel,
+        ] {
+            let plot = SpectrogramPlot {
+                scale,
+                ..SpectrogramPlot::default()
+            };
+            let png = image().render(&plot.figure(&spectrogram)).unwrap();
+            assert!(png.starts_with(b"\x89PNG"));
+        }
+
+        // One rectangle per visible cell, not per pixel
+        let svg = ImageOptions {
+            format: ImageFormat::Svg,
+            ..image()
+        }
+        .render(&SpectrogramPlot::default().figure(&spectrogram))
+        .unwrap();
+        let cells = spectrogram.num_frames() * spectrogram.num_bins();
+        assert!(String::from_utf8(svg).unwrap().matches("<rect").count() < cells + 200);
+    }
+
+    #[test]
+    fn test_render_cqt() {
+        let sample_rate = 8000;
+        let config = CqtConfig {
+            min_frequency: 110.0,
+            bins_per_octave: 12,
+            ..CqtConfig::default()
+        };
+        let spectrogram = config
+            .spectrogram(&tone(440.0, sample_rate, 0.5), sample_rate)
+            .unwrap();
+
+        // Starts at the lowest bin rather than 0Hz
+        let plot = SpectrogramPlot::default();
+        let (low, high) = plot.frequency_range(&spectrogram).unwrap();
+        assert_eq!(low, 110.0);
+        assert!(high < 4000.0);
+
+        let log = SpectrogramPlot {
+            scale: FrequencyScale::Log,
+            min_frequency: Some(50.0),
+            ..SpectrogramPlot::default()
+        };
+        assert_eq!(log.frequency_range(&spectrogram).unwrap().0, 110.0);
+        let png = image().render(&log.figure(&spectrogram)).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn test_render_constellation() {
+        let sample_rate = 8000;
+        let samples = tone(440.0, sample_rate, 1.0);
+        let config = FingerprintConfig::default();
+        let spectrogram = Spectrogram::new(&samples, sample_rate, config.stft);
+        let peaks = config.peaks.spectrogram(&spectrogram);
+        let pairs = config.pairs(&peaks);
+
+        let plot = SpectrogramPlot {
+            scale: FrequencyScale::Log,
+            ..SpectrogramPlot::default()
+        };
+        let figure = plot.figure(&spectrogram).constellation(&peaks, &pairs);
+        assert!(!image().render(&figure).unwrap().is_empty());
+    }
+}
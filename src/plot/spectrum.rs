@@ -0,0 +1,179 @@
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use super::{Figure, FrequencyAxis, ImageOptions, PlotResult};
+use crate::note::Note;
+use crate::spectrogram::FrequencyScale;
+use crate::spectrum::{CalibratedSpectrum, SpectralPeak};
+
+// Headroom above the loudest level so the labels of the top peaks fit
+const HEADROOM: f64 = 10.0;
+
+// Level of a single frame in dBFS against its frequency in Hz, with the strongest peaks labelled
+pub struct SpectrumPlot<'a> {
+    pub spectrum: &'a CalibratedSpectrum,
+    pub scale: FrequencyScale,
+    // dB shown below 0dBFS
+    pub dynamic_range: f64,
+    // How many of the strongest peaks get their frequency and note written next to them
+    pub labels: usize,
+}
+
+impl<'a> SpectrumPlot<'a> {
+    pub fn new(spectrum: &'a CalibratedSpectrum) -> Self {
+        SpectrumPlot {
+            spectrum,
+            scale: FrequencyScale::Linear,
+            dynamic_range: 120.0,
+            labels: 5,
+        }
+    }
+
+    // DC has no place on a log axis, it starts at the first bin instead
+    fn first_bin(&self) -> usize {
+        match self.scale {
+            FrequencyScale::Log => 1,
+            _ => 0,
+        }
+    }
+}
+
+impl Figure for SpectrumPlot<'_> {
+    fn draw<DB: DrawingBackend>(
+        &self,
+        root: &DrawingArea<DB, Shift>,
+        image: &ImageOptions,
+    ) -> PlotResult<()>
+    where
+        DB::ErrorType: 'static,
+    {
+        let spectrum = self.spectrum;
+        let scale = self.scale;
+        let first_bin = self.first_bin();
+        let last_bin = spectrum.num_bins().saturating_sub(1).max(first_bin + 1);
+        let axis = FrequencyAxis::new(
+            spectrum.frequency(first_bin as f64),
+            spectrum.frequency(last_bin as f64),
+            scale,
+        );
+
+        let loudest = spectrum.levels().iter().copied().fold(0.0, f64::max);
+        let bottom = -self.dynamic_range;
+        let top = loudest + HEADROOM;
+
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(root)
+            .caption("Spectrum", ("sans-serif", image.px(24)))
+            .margin(image.px(10))
+            .x_label_area_size(image.px(40))
+            .y_label_area_size(image.px(60))
+            .build_cartesian_2d(axis, bottom..top)?;
+        chart
+            .configure_mesh()
+            .label_style(("sans-serif", image.px(12)))
+            .axis_desc_style(("sans-serif", image.px(14)))
+            .x_desc("Frequency (Hz)")
+            .y_desc("Level (dBFS)")
+            .draw()?;
+
+        chart.draw_series(LineSeries::new(
+            spectrum
+                .levels()
+                .iter()
+                .enumerate()
+                .skip(first_bin)
+                .map(|(bin, level)| {
+                    (
+                        scale.forward(spectrum.frequency(bin as f64)),
+                        level.max(bottom),
+                    )
+                }),
+            BLUE.stroke_width(image.px(1)),
+        ))?;
+
+        let font = ("sans-serif", image.px(12)).into_font();
+        let radius = image.px(3) as i32;
+        chart.draw_series(
+            spectrum
+                .peaks(self.labels)
+                .into_iter()
+                .filter(|peak| {
+                    peak.level > bottom && peak.frequency >= spectrum.frequency(first_bin as f64)
+                })
+                .map(|peak| {
+                    EmptyElement::at((scale.forward(peak.frequency), peak.level.max(bottom)))
+                        + Circle::new((0, 0), radius, RED.filled())
+                        + Text::new(
+                            peak_label(&peak),
+                            (radius + image.px(2) as i32, -radius - image.px(12) as i32),
+                            font.clone(),
+                        )
+                }),
+        )?;
+
+        Ok(())
+    }
+}
+
+// "440.2 Hz A4 +1c", DC has no note
+fn peak_label(peak: &SpectralPeak) -> String {
+    let frequency = format!("{:.1} Hz", peak.frequency);
+    match Note::from_frequency(peak.frequency) {
+        Some(note) => format!("{} {}", frequency, note),
+        None => frequency,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plot::ImageFormat;
+    use crate::window::Window;
+    use std::f64::consts::PI;
+
+    fn tone(frequency: f64, amplitude: f64) -> Vec<f64> {
+        (0..4096)
+            .map(|n| amplitude * (2.0 * PI * frequency * n as f64 / 44100.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_peak_label() {
+        let a4 = SpectralPeak {
+            frequency: 440.2,
+            level: -3.0,
+        };
+        assert_eq!(peak_label(&a4), "440.2 Hz A4 +1c");
+
+        let high = SpectralPeak {
+            frequency: 1499.6,
+            level: -3.0,
+        };
+        assert_eq!(peak_label(&high), "1499.6 Hz F#6 +23c");
+
+        let dc = SpectralPeak {
+            frequency: 0.0,
+            level: -3.0,
+        };
+        assert_eq!(peak_label(&dc), "0.0 Hz");
+    }
+
+    #[test]
+    fn test_render() {
+        let spectrum = CalibratedSpectrum::new(&tone(440.0, 0.5), Window::Hann, 8192, 44100, 1.0);
+        let image = ImageOptions {
+            format: ImageFormat::Svg,
+            ..ImageOptions::default()
+        };
+
+        for scale in [FrequencyScale::Linear, FrequencyScale::Log] {
+            let plot = SpectrumPlot {
+                scale,
+                ..SpectrumPlot::new(&spectrum)
+            };
+            let svg = String::from_utf8(image.render(&plot).unwrap()).unwrap();
+            assert!(svg.contains("Hz A4"), "{}", scale);
+            assert!(svg.contains("dBFS"));
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use super::{Figure, ImageOptions, PlotResult};
+use crate::waveform::{Region, Waveform};
+
+const PEAK_COLOR: RGBColor = RGBColor(120, 160, 220);
+const RMS_COLOR: RGBColor = RGBColor(30, 70, 150);
+
+// Waveform overview, the min/max envelope of every column with its RMS inside, one lane per channel or the downmix
+//
+// Regions are shaded over every lane, each distinct label gets its own colour
+pub struct WaveformPlot<'a> {
+    pub waveform: &'a Waveform,
+    pub regions: &'a [Region],
+}
+
+impl Figure for WaveformPlot<'_> {
+    fn draw<DB: DrawingBackend>(
+        &self,
+        root: &DrawingArea<DB, Shift>,
+        image: &ImageOptions,
+    ) -> PlotResult<()>
+    where
+        DB::ErrorType: 'static,
+    {
+        let waveform = self.waveform;
+        let num_lanes = waveform.lanes().len();
+        let duration = waveform.duration().max(f64::EPSILON);
+        let column_width = duration / waveform.num_columns().max(1) as f64;
+        // Envelopes are drawn relative to full scale
+        let scale = 1.0 / waveform.full_scale();
+
+        // First appearance of a label picks its colour
+        let mut labels: Vec<&str> = Vec::new();
+        for region in self.regions {
+            if !labels.contains(&region.label.as_str()) {
+                labels.push(&region.label);
+            }
+        }
+        let color = |label: &str| {
+            let index = labels.iter().position(|l| *l == label).unwrap_or(0);
+            Palette99::pick(index + 1)
+        };
+
+        root.fill(&WHITE)?;
+        let root = root.titled("Waveform", ("sans-serif", image.px(24)))?;
+        let lanes = root.split_evenly((num_lanes.max(1), 1));
+
+        for (i, (lane, area)) in waveform.lanes().iter().zip(&lanes).enumerate() {
+            let is_last = i + 1 == num_lanes;
+            let mut chart = ChartBuilder::on(area)
+                .margin(image.px(10))
+                .x_label_area_size(if is_last { image.px(40) } else { 0 })
+                .y_label_area_size(image.px(60))
+                .build_cartesian_2d(0.0..duration, -1.0..1.0)?;
+
+            let name = if num_lanes == 1 {
+                "Amplitude".to_string()
+            } else {
+                format!("Channel {}", i + 1)
+            };
+            let mut mesh = chart.configure_mesh();
+            mesh.disable_mesh()
+                .y_labels(5)
+                .label_style(("sans-serif", image.px(12)))
+                .axis_desc_style(("sans-serif", image.px(14)))
+                .y_desc(name);
+            if is_last {
+                mesh.x_desc("Time (s)");
+            }
+            mesh.draw()?;
+
+            chart.draw_series(self.regions.iter().map(|region| {
+                Rectangle::new(
+                    [(region.start, -1.0), (region.end, 1.0)],
+                    color(&region.label).mix(0.25).filled(),
+                )
+            }))?;
+
+            let column = |c: usize| (c as f64 * column_width, (c + 1) as f64 * column_width);
+            chart.draw_series(lane.iter().enumerate().map(|(c, envelope)| {
+                let (start, end) = column(c);
+                Rectangle::new(
+                    [(start, envelope.min * scale), (end, envelope.max * scale)],
+                    PEAK_COLOR.filled(),
+                )
+            }))?;
+            // The RMS band sits inside the envelope, a square wave fills it completely
+            chart.draw_series(lane.iter().enumerate().map(|(c, envelope)| {
+                let (start, end) = column(c);
+                let rms = (envelope.rms * scale)
+                    .min(envelope.max * scale)
+                    .min(-envelope.min * scale);
+                Rectangle::new([(start, -rms), (end, rms)], RMS_COLOR.filled())
+            }))?;
+            chart.draw_series(LineSeries::new(
+                [(0.0, 0.0), (duration, 0.0)],
+                BLACK.mix(0.3).stroke_width(image.px(1)),
+            ))?;
+
+            // Labels only go on the top lane
+            if i == 0 {
+                let font = ("sans-serif", image.px(12)).into_font();
+                chart.draw_series(self.regions.iter().map(|region| {
+                    Text::new(region.label.clone(), (region.start, 0.95), font.clone())
+                }))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plot::ImageFormat;
+    use crate::waveform::{EnvelopeBuilder, Lanes};
+
+    #[test]
+    fn test_render() {
+        let mut builder = EnvelopeBuilder::new(100, 1000, 2, Lanes::Channels);
+        for n in 0..1000 {
+            let sample = (n as f64 * 0.1).sin();
+            builder.push(&[sample, 0.5 * sample]);
+        }
+        let waveform = builder.finish(1000, 1.0);
+        let regions = [
+            Region {
+                start: 0.2,
+                end: 0.4,
+                label: "match".to_string(),
+            },
+            Region {
+                start: 0.8,
+                end: 0.9,
+                label: "silence".to_string(),
+            },
+        ];
+
+        let image = ImageOptions {
+            format: ImageFormat::Svg,
+            ..ImageOptions::default()
+        };
+        let plot = WaveformPlot {
+            waveform: &waveform,
+            regions: &regions,
+        };
+        let svg = String::from_utf8(image.render(&plot).unwrap()).unwrap();
+        assert!(svg.contains("Channel 2"));
+        assert!(svg.contains("match"));
+        assert!(svg.contains("silence"));
+
+        let empty = Waveform::from_samples(&[], 1000, 1.0, 0);
+        let plot = WaveformPlot {
+            waveform: &empty,
+            regions: &[],
+        };
+        assert!(!image.render(&plot).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,390 @@
+use crate::complex::Complex;
+
+// Vectorised kernels of the hot loops: windowing, complex multiply-accumulate, magnitude, power and dB conversion
+//
+// std::simd is still nightly only, so on x86_64 the AVX2 + FMA versions are picked at runtime and everywhere else,
+// or on CPUs without AVX2, the scalar loops run. Those are simple enough for the compiler to vectorise some of them
+// for the baseline target on its own.
+//
+// Every kernel works on the slices it is given and never allocates. Results can differ from the scalar loops in the
+// last bits, FMA rounds once and the sums run in a different order.
+
+// Whether the kernels below run the AVX2 versions
+pub fn is_accelerated() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+// values[i] *= factors[i], the longer slice is cut to the shorter one
+pub fn multiply(values: &mut [f64], factors: &[f64]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_accelerated() {
+        // SAFETY: AVX2 and FMA were detected above
+        return unsafe { avx2::multiply(values, factors) };
+    }
+    scalar::multiply(values, factors)
+}
+
+// Sum of a[i] * b[i]
+pub fn complex_mac(a: &[Complex], b: &[Complex]) -> Complex {
+    #[cfg(target_arch = "x86_64")]
+    if is_accelerated() {
+        // SAFETY: AVX2 and FMA were detected above
+        return unsafe { avx2::complex_mac(a, b) };
+    }
+    scalar::complex_mac(a, b)
+}
+
+// out[i] = |spectrum[i]|
+pub fn magnitude(spectrum: &[Complex], out: &mut [f64]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_accelerated() {
+        // SAFETY: AVX2 and FMA were detected above
+        return unsafe { avx2::magnitude(spectrum, out) };
+    }
+    scalar::magnitude(spectrum, out)
+}
+
+// out[i] = |spectrum[i]|^2
+pub fn power(spectrum: &[Complex], out: &mut [f64]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_accelerated() {
+        // SAFETY: AVX2 and FMA were detected above
+        return unsafe { avx2::power(spectrum, out) };
+    }
+    scalar::power(spectrum, out)
+}
+
+// Magnitudes to dB relative to reference in place, the same as spectrogram::to_db on every value
+pub fn to_db(values: &mut [f64], reference: f64) {
+    #[cfg(target_arch = "x86_64")]
+    if is_accelerated() {
+        // SAFETY: AVX2 and FMA were detected above
+        return unsafe { avx2::to_db(values, reference) };
+    }
+    scalar::to_db(values, reference)
+}
+
+// Plain loops for any float type, the fallback of the kernels above and what f32 always runs
+pub mod scalar {
+    use crate::complex::Complex;
+    use crate::float::Float;
+
+    pub fn multiply<T: Float>(values: &mut [T], factors: &[T]) {
+        for (value, factor) in values.iter_mut().zip(factors) {
+            *value = *value * *factor;
+        }
+    }
+
+    pub fn complex_mac<T: Float>(a: &[Complex<T>], b: &[Complex<T>]) -> Complex<T> {
+        a.iter().zip(b).map(|(a, b)| *a * *b).sum()
+    }
+
+    pub fn magnitude<T: Float>(spectrum: &[Complex<T>], out: &mut [T]) {
+        for (out, x) in out.iter_mut().zip(spectrum) {
+            *out = x.abs();
+        }
+    }
+
+    pub fn power<T: Float>(spectrum: &[Complex<T>], out: &mut [T]) {
+        for (out, x) in out.iter_mut().zip(spectrum) {
+            *out = x.norm_sqr();
+        }
+    }
+
+    pub fn to_db<T: Float>(values: &mut [T], reference: T) {
+        let reference = reference.as_f64();
+        for value in values {
+            *value = T::cast(crate::spectrogram::to_db(value.as_f64(), reference));
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+    use std::f64::consts::{LOG10_2, LOG10_E, SQRT_2};
+
+    use super::scalar;
+    use crate::complex::Complex;
+    use crate::spectrogram::MIN_MAGNITUDE;
+
+    // Four f64 lanes, two complex numbers per register
+    const LANES: usize = 4;
+
+    // Complex is #[repr(C)], so a slice of n of them is 2n floats alternating re and im
+    fn floats(slice: &[Complex]) -> *const f64 {
+        slice.as_ptr().cast()
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn multiply(values: &mut [f64], factors: &[f64]) {
+        let n = values.len().min(factors.len());
+        let body = n - n % LANES;
+        for i in (0..body).step_by(LANES) {
+            // SAFETY: i + LANES <= n, which is in bounds of both slices
+            unsafe {
+                let v = _mm256_loadu_pd(values.as_ptr().add(i));
+                let f = _mm256_loadu_pd(factors.as_ptr().add(i));
+                _mm256_storeu_pd(values.as_mut_ptr().add(i), _mm256_mul_pd(v, f));
+            }
+        }
+        scalar::multiply(&mut values[body..n], &factors[body..n]);
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn complex_mac(a: &[Complex], b: &[Complex]) -> Complex {
+        let n = a.len().min(b.len());
+        let body = n - n % 2;
+        // [ar * br, ai * bi, ...] and [ar * bi, ai * br, ...], re is the difference of the first pair and im the sum
+        // of the second
+        let mut same = _mm256_setzero_pd();
+        let mut crossed = _mm256_setzero_pd();
+        for i in (0..body).step_by(2) {
+            // SAFETY: complex i + 1 < n is in bounds of both slices
+            let (x, y) = unsafe {
+                (
+                    _mm256_loadu_pd(floats(a).add(2 * i)),
+                    _mm256_loadu_pd(floats(b).add(2 * i)),
+                )
+            };
+            same = _mm256_fmadd_pd(x, y, same);
+            crossed = _mm256_fmadd_pd(x, _mm256_permute_pd::<0b0101>(y), crossed);
+        }
+
+        let mut same_lanes = [0.0; LANES];
+        let mut crossed_lanes = [0.0; LANES];
+        // SAFETY: both arrays hold four f64
+        unsafe {
+            _mm256_storeu_pd(same_lanes.as_mut_ptr(), same);
+            _mm256_storeu_pd(crossed_lanes.as_mut_ptr(), crossed);
+        }
+        let sum = Complex::new(
+            (same_lanes[0] - same_lanes[1]) + (same_lanes[2] - same_lanes[3]),
+            (crossed_lanes[0] + crossed_lanes[1]) + (crossed_lanes[2] + crossed_lanes[3]),
+        );
+        sum + scalar::complex_mac(&a[body..n], &b[body..n])
+    }
+
+    // |x|^2 of four complex numbers in order
+    #[target_feature(enable = "avx2,fma")]
+    fn norm_sqr(spectrum: &[Complex], i: usize) -> __m256d {
+        // SAFETY: the callers only pass i with i + 3 < spectrum.len()
+        let (low, high) = unsafe {
+            (
+                _mm256_loadu_pd(floats(spectrum).add(2 * i)),
+                _mm256_loadu_pd(floats(spectrum).add(2 * i + 4)),
+            )
+        };
+        // hadd gives [|x0|^2, |x2|^2, |x1|^2, |x3|^2], swapping the middle lanes puts them back in order
+        let squares = _mm256_hadd_pd(_mm256_mul_pd(low, low), _mm256_mul_pd(high, high));
+        _mm256_permute4x64_pd::<0b11_01_10_00>(squares)
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn magnitude(spectrum: &[Complex], out: &mut [f64]) {
+        let n = spectrum.len().min(out.len());
+        let body = n - n % LANES;
+        for i in (0..body).step_by(LANES) {
+            let magnitudes = _mm256_sqrt_pd(norm_sqr(spectrum, i));
+            // SAFETY: i + LANES <= n <= out.len()
+            unsafe { _mm256_storeu_pd(out.as_mut_ptr().add(i), magnitudes) };
+        }
+        scalar::magnitude(&spectrum[body..n], &mut out[body..n]);
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn power(spectrum: &[Complex], out: &mut [f64]) {
+        let n = spectrum.len().min(out.len());
+        let body = n - n % LANES;
+        for i in (0..body).step_by(LANES) {
+            let power = norm_sqr(spectrum, i);
+            // SAFETY: i + LANES <= n <= out.len()
+            unsafe { _mm256_storeu_pd(out.as_mut_ptr().add(i), power) };
+        }
+        scalar::power(&spectrum[body..n], &mut out[body..n]);
+    }
+
+    // log10 of positive, normal and finite values, within a few ulp of f64::log10
+    //
+    // x = 2^e * m with m folded into [sqrt(1/2), sqrt(2)), then ln m = 2 atanh(s) with s = (m - 1) / (m + 1),
+    // |s| <= 0.172 so the odd series of atanh is below an ulp after s^23
+    #[target_feature(enable = "avx2,fma")]
+    fn log10(x: __m256d) -> __m256d {
+        let bits = _mm256_castpd_si256(x);
+        let mantissa_mask = _mm256_set1_epi64x(0x000f_ffff_ffff_ffff);
+        let one_bits = _mm256_set1_epi64x(0x3ff0_0000_0000_0000);
+        let mut m = _mm256_castsi256_pd(_mm256_or_si256(
+            _mm256_and_si256(bits, mantissa_mask),
+            one_bits,
+        ));
+        let above = _mm256_cmp_pd::<_CMP_GT_OQ>(m, _mm256_set1_pd(SQRT_2));
+        m = _mm256_blendv_pd(m, _mm256_mul_pd(m, _mm256_set1_pd(0.5)), above);
+
+        // The biased exponent is at most 11 bits, ORed into the mantissa of 2^52 it converts exactly
+        let two_52 = _mm256_set1_pd(4_503_599_627_370_496.0);
+        let biased = _mm256_srli_epi64::<52>(bits);
+        let mut e = _mm256_sub_pd(
+            _mm256_castsi256_pd(_mm256_or_si256(biased, _mm256_castpd_si256(two_52))),
+            two_52,
+        );
+        e = _mm256_sub_pd(e, _mm256_set1_pd(1023.0));
+        e = _mm256_add_pd(e, _mm256_and_pd(above, _mm256_set1_pd(1.0)));
+
+        let one = _mm256_set1_pd(1.0);
+        let s = _mm256_div_pd(_mm256_sub_pd(m, one), _mm256_add_pd(m, one));
+        let s2 = _mm256_mul_pd(s, s);
+        let mut series = _mm256_set1_pd(1.0 / 23.0);
+        for k in (0..11).rev() {
+            series = _mm256_fmadd_pd(series, s2, _mm256_set1_pd(1.0 / (2 * k + 1) as f64));
+        }
+        let ln_m = _mm256_mul_pd(_mm256_add_pd(s, s), series);
+
+        _mm256_fmadd_pd(
+            e,
+            _mm256_set1_pd(LOG10_2),
+            _mm256_mul_pd(ln_m, _mm256_set1_pd(LOG10_E)),
+        )
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub fn to_db(values: &mut [f64], reference: f64) {
+        let n = values.len();
+        let body = n - n % LANES;
+        // log10(a / b) as log10(a) - log10(b), the floor keeps both sides normal
+        let floor = _mm256_set1_pd(MIN_MAGNITUDE);
+        let offset = _mm256_set1_pd(reference.max(MIN_MAGNITUDE).log10());
+        let infinity = _mm256_set1_pd(f64::INFINITY);
+        let twenty = _mm256_set1_pd(20.0);
+        for i in (0..body).step_by(LANES) {
+            // SAFETY: i + LANES <= n
+            let v = unsafe { _mm256_loadu_pd(values.as_ptr().add(i)) };
+            // max_pd returns the second operand for NaN, which is what f64::max does as well
+            let v = _mm256_max_pd(v, floor);
+            let db = _mm256_mul_pd(twenty, _mm256_sub_pd(log10(v), offset));
+            let db = _mm256_blendv_pd(db, infinity, _mm256_cmp_pd::<_CMP_EQ_OQ>(v, infinity));
+            // SAFETY: as above
+            unsafe { _mm256_storeu_pd(values.as_mut_ptr().add(i), db) };
+        }
+        scalar::to_db(&mut values[body..], reference);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(size: usize) -> Vec<f64> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+            })
+            .collect()
+    }
+
+    fn spectrum(size: usize) -> Vec<Complex> {
+        noise(2 * size)
+            .chunks(2)
+            .map(|pair| Complex::new(1000.0 * pair[0], 1000.0 * pair[1]))
+            .collect()
+    }
+
+    // Odd sizes leave a tail for the scalar loop
+    const SIZES: [usize; 6] = [0, 1, 3, 4, 17, 1023];
+
+    #[test]
+    fn test_multiply() {
+        for size in SIZES {
+            let factors = noise(size + 2);
+            let mut values = noise(size);
+            let mut expected = values.clone();
+            multiply(&mut values, &factors);
+            scalar::multiply(&mut expected, &factors);
+            assert_eq!(values, expected);
+        }
+    }
+
+    #[test]
+    fn test_complex_mac() {
+        for size in SIZES {
+            let (a, b) = (spectrum(size), spectrum(size + 1)[1..].to_vec());
+            let expected = scalar::complex_mac(&a, &b);
+            let sum = complex_mac(&a, &b);
+            assert!(
+                (sum - expected).abs() <= 1e-9 * (1.0 + expected.abs()),
+                "{}",
+                size
+            );
+        }
+        let dot = complex_mac(&[Complex::I; 5], &[Complex::new(2.0, 1.0); 5]);
+        assert_eq!(dot, Complex::new(-5.0, 10.0));
+    }
+
+    #[test]
+    fn test_magnitude_and_power() {
+        for size in SIZES {
+            let spectrum = spectrum(size);
+            let (mut magnitudes, mut expected) = (vec![0.0; size], vec![0.0; size]);
+            magnitude(&spectrum, &mut magnitudes);
+            scalar::magnitude(&spectrum, &mut expected);
+            for (a, b) in magnitudes.iter().zip(&expected) {
+                assert!((a - b).abs() <= 2.0 * f64::EPSILON * b);
+            }
+
+            power(&spectrum, &mut magnitudes);
+            scalar::power(&spectrum, &mut expected);
+            for (a, b) in magnitudes.iter().zip(&expected) {
+                assert!((a - b).abs() <= 2.0 * f64::EPSILON * b);
+            }
+        }
+
+        // A short output only gets its own length written
+        let mut short = [0.0; 2];
+        magnitude(&[Complex::new(3.0, 4.0); 6], &mut short);
+        assert_eq!(short, [5.0, 5.0]);
+    }
+
+    #[test]
+    fn test_to_db() {
+        for size in SIZES {
+            let mut values: Vec<f64> = noise(size).iter().map(|v| v.abs() * 1e6).collect();
+            let mut expected = values.clone();
+            to_db(&mut values, 32768.0);
+            scalar::to_db(&mut expected, 32768.0);
+            for (a, b) in values.iter().zip(&expected) {
+                assert!((a - b).abs() < 1e-12, "{} {}", a, b);
+            }
+        }
+
+        // Every exponent from the floor up, including the fold around sqrt(2)
+        let mut values: Vec<f64> = (-40..40)
+            .flat_map(|e| [1.0, 1.41, 1.42, 1.999].map(|m| m * 2f64.powi(e)))
+            .collect();
+        let mut expected = values.clone();
+        to_db(&mut values, 1.0);
+        scalar::to_db(&mut expected, 1.0);
+        for (a, b) in values.iter().zip(&expected) {
+            assert!((a - b).abs() < 1e-12, "{} {}", a, b);
+        }
+
+        // Zero and NaN hit the floor, infinity stays infinite
+        let mut special = [0.0, f64::NAN, f64::INFINITY, -1.0, 1.0, 0.1, 10.0, 1e300];
+        to_db(&mut special, 1.0);
+        assert_eq!(special[0], -240.0);
+        assert_eq!(special[1], -240.0);
+        assert_eq!(special[2], f64::INFINITY);
+        assert_eq!(special[3], -240.0);
+        assert!((special[5] + 20.0).abs() < 1e-12);
+        assert!((special[7] - 6000.0).abs() < 1e-9);
+    }
+}
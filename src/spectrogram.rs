@@ -0,0 +1,488 @@
+use std::{fmt, str::FromStr};
+
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+
+use crate::complex::from_num_complex_slice;
+use crate::error::Error;
+use crate::float::Float;
+use crate::window::Window;
+
+// Floor of the dB conversion so silent bins do not turn into -inf
+pub(crate) const MIN_MAGNITUDE: f64 = 1e-12;
+
+// Short time Fourier transform settings
+//
+// window_size trades time resolution for frequency resolution, hop_size sets how often we get a new frame
+// and fft_size >= window_size zero pads each frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StftConfig {
+    pub window_size: usize,
+    pub hop_size: usize,
+    pub fft_size: usize,
+    pub window: Window,
+}
+
+impl Default for StftConfig {
+    fn default() -> Self {
+        StftConfig {
+            window_size: 1024,
+            hop_size: 512,
+            fft_size: 1024,
+            window: Window::Hann,
+        }
+    }
+}
+
+// Magnitude spectra of overlapping windowed frames, one row per frame and fft_size / 2 bins per row
+//
+// Computed in f64 unless built from f32 samples, which halves the memory of long files
+pub struct Spectrogram<T = f64> {
+    frames: Vec<Vec<T>>,
+    sample_rate: u32,
+    config: StftConfig,
+}
+
+impl<T: Float> Spectrogram<T> {
+    pub fn new(samples: &[T], sample_rate: u32, config: StftConfig) -> Self {
+        let fft_size = config.fft_size.max(config.window_size);
+        let config = StftConfig { fft_size, ..config };
+        let win: Vec<T> = config
+            .window
+            .periodic(config.window_size)
+            .into_iter()
+            .map(T::cast)
+            .collect();
+
+        // Plan once and reuse it for every frame
+        let mut planner: FftPlanner<T> = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let mut buff = vec![Complex::new(T::ZERO, T::ZERO); fft_size];
+        let mut windowed = vec![T::ZERO; config.window_size];
+
+        let mut frames = Vec::new();
+        if config.window_size > 0 && config.hop_size > 0 {
+            for frame in samples.windows(config.window_size).step_by(config.hop_size) {
+                windowed.copy_from_slice(frame);
+                T::multiply(&mut windowed, &win);
+                // Everything past the window is the zero padding
+                for (i, x) in buff.iter_mut().enumerate() {
+                    *x = Complex::new(windowed.get(i).copied().unwrap_or(T::ZERO), T::ZERO);
+                }
+                fft.process(&mut buff);
+
+                let mut magnitudes = vec![T::ZERO; fft_size / 2];
+                T::magnitude(from_num_complex_slice(&buff), &mut magnitudes);
+                frames.push(magnitudes);
+            }
+        }
+
+        Spectrogram {
+            frames,
+            sample_rate,
+            config,
+        }
+    }
+
+    // Wraps already computed magnitude frames, every frame should have fft_size / 2 bins
+    pub fn from_frames(frames: Vec<Vec<T>>, sample_rate: u32, config: StftConfig) -> Self {
+        Spectrogram {
+            frames,
+            sample_rate,
+            config,
+        }
+    }
+
+    pub fn frames(&self) -> &[Vec<T>] {
+        &self.frames
+    }
+
+    pub fn frame(&self, index: usize) -> &[T] {
+        &self.frames[index]
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn num_bins(&self) -> usize {
+        self.config.fft_size / 2
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn config(&self) -> &StftConfig {
+        &self.config
+    }
+
+    // Start of the frame in seconds
+    pub fn frame_time(&self, frame: usize) -> f64 {
+        (frame * self.config.hop_size) as f64 / self.sample_rate as f64
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.sample_rate as f64 / self.config.hop_size as f64
+    }
+
+    pub fn bin_frequency(&self, bin: f64) -> f64 {
+        crate::spectrum::bin_frequency(bin, self.config.fft_size, self.sample_rate)
+    }
+
+    // Fractional bin of a frequency, the inverse of bin_frequency
+    pub fn frequency_bin(&self, frequency: f64) -> f64 {
+        crate::spectrum::frequency_bin(frequency, self.config.fft_size, self.sample_rate)
+    }
+}
+
+// Magnitudes over time and frequency, what the spectrogram plot, the text plot and the CSV export draw from
+//
+// The STFT spectrogram has linear bins and the constant-Q one geometric bins, the renderers only go through
+// bin_frequency and frequency_bin so they do not care which
+pub trait TimeFrequency {
+    fn num_frames(&self) -> usize;
+
+    fn num_bins(&self) -> usize;
+
+    // Magnitude of one bin of one frame, widened to f64 so single precision spectrograms are drawn as they are
+    fn magnitude(&self, frame: usize, bin: usize) -> f64;
+
+    // Time of the frame in seconds
+    fn frame_time(&self, frame: usize) -> f64;
+
+    fn frames_per_second(&self) -> f64;
+
+    // Frequency of a fractional bin and the fractional bin of a frequency
+    fn bin_frequency(&self, bin: f64) -> f64;
+
+    fn frequency_bin(&self, frequency: f64) -> f64;
+
+    // Lowest and highest frequency the bins cover
+    fn frequency_range(&self) -> (f64, f64);
+
+    fn duration(&self) -> f64 {
+        self.num_frames() as f64 / self.frames_per_second()
+    }
+
+    fn max_magnitude(&self) -> f64 {
+        (0..self.num_frames())
+            .flat_map(|frame| (0..self.num_bins()).map(move |bin| self.magnitude(frame, bin)))
+            .fold(0.0, f64::max)
+    }
+}
+
+impl<T: Float> TimeFrequency for Spectrogram<T> {
+    fn num_frames(&self) -> usize {
+        Spectrogram::num_frames(self)
+    }
+
+    fn num_bins(&self) -> usize {
+        Spectrogram::num_bins(self)
+    }
+
+    fn magnitude(&self, frame: usize, bin: usize) -> f64 {
+        self.frames[frame][bin].as_f64()
+    }
+
+    fn frame_time(&self, frame: usize) -> f64 {
+        Spectrogram::frame_time(self, frame)
+    }
+
+    fn frames_per_second(&self) -> f64 {
+        Spectrogram::frames_per_second(self)
+    }
+
+    fn bin_frequency(&self, bin: f64) -> f64 {
+        Spectrogram::bin_frequency(self, bin)
+    }
+
+    fn frequency_bin(&self, frequency: f64) -> f64 {
+        Spectrogram::frequency_bin(self, frequency)
+    }
+
+    // DC up to Nyquist
+    fn frequency_range(&self) -> (f64, f64) {
+        (0.0, self.sample_rate as f64 / 2.0)
+    }
+}
+
+// Magnitude in dB relative to reference, 0dB is the reference itself
+pub fn to_db(magnitude: f64, reference: f64) -> f64 {
+    20.0 * (magnitude.max(MIN_MAGNITUDE) / reference.max(MIN_MAGNITUDE)).log10()
+}
+
+// Power in dB relative to reference, squared magnitudes get the same levels and floor as to_db
+pub fn power_to_db(power: f64, reference: f64) -> f64 {
+    let floor = MIN_MAGNITUDE * MIN_MAGNITUDE;
+    10.0 * (power.max(floor) / reference.max(floor)).log10()
+}
+
+// Mel scale of O'Shaughnessy as used by HTK, roughly linear below 1kHz and logarithmic above
+pub fn hz_to_mel(frequency: f64) -> f64 {
+    2595.0 * (1.0 + frequency / 700.0).log10()
+}
+
+pub fn mel_to_hz(mel: f64) -> f64 {
+    700.0 * (10f64.powf(mel / 2595.0) - 1.0)
+}
+
+// How frequencies are spread along an axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrequencyScale {
+    #[default]
+    Linear,
+    // Every octave gets the same height, frequencies have to be positive
+    Log,
+    Mel,
+}
+
+impl FrequencyScale {
+    // Position of the frequency along the axis
+    pub fn forward(&self, frequency: f64) -> f64 {
+        match self {
+            FrequencyScale::Linear => frequency,
+            FrequencyScale::Log => frequency.log10(),
+            FrequencyScale::Mel => hz_to_mel(frequency),
+        }
+    }
+
+    // Frequency at a position along the axis
+    pub fn inverse(&self, position: f64) -> f64 {
+        match self {
+            FrequencyScale::Linear => position,
+            FrequencyScale::Log => 10f64.powf(position),
+            FrequencyScale::Mel => mel_to_hz(position),
+        }
+    }
+}
+
+impl FromStr for FrequencyScale {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(FrequencyScale::Linear),
+            "log" => Ok(FrequencyScale::Log),
+            "mel" => Ok(FrequencyScale::Mel),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown frequency scale '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for FrequencyScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FrequencyScale::Linear => "linear",
+            FrequencyScale::Log => "log",
+            FrequencyScale::Mel => "mel",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Round frequencies for the axis labels, evenly spaced on a linear axis and 1-2-5 per decade otherwise
+pub fn frequency_ticks(low: f64, high: f64, scale: FrequencyScale) -> Vec<f64> {
+    let mut ticks = Vec::new();
+    match scale {
+        FrequencyScale::Linear => {
+            let step = nice_step((high - low) / 8.0);
+            let mut tick = (low / step).ceil() * step;
+            while tick <= high {
+                ticks.push(tick);
+                tick += step;
+            }
+        }
+        FrequencyScale::Log | FrequencyScale::Mel => {
+            // Low frequencies are squeezed together on the mel scale, so its ticks start at 100Hz
+            let first = match scale {
+                FrequencyScale::Mel => {
+                    if low <= 0.0 {
+                        ticks.push(0.0);
+                    }
+                    100f64.max(low)
+                }
+                _ => low,
+            };
+            let mut decade = 10f64.powf(first.max(1.0).log10().floor());
+            while decade <= high {
+                for multiple in [1.0, 2.0, 5.0] {
+                    let tick = multiple * decade;
+                    if tick >= first && tick <= high {
+                        ticks.push(tick);
+                    }
+                }
+                decade *= 10.0;
+            }
+        }
+    }
+    ticks
+}
+
+// 1, 2 or 5 times a power of ten at or above the raw step
+fn nice_step(raw: f64) -> f64 {
+    if raw <= 0.0 || !raw.is_finite() {
+        return 1.0;
+    }
+
+    let power = 10f64.powf(raw.log10().floor());
+    let multiple = match raw / power {
+        r if r <= 1.0 => 1.0,
+        r if r <= 2.0 => 2.0,
+        r if r <= 5.0 => 5.0,
+        _ => 10.0,
+    };
+    multiple * power
+}
+
+// 440 -> "440", 2000 -> "2k", 1500 -> "1.5k"
+pub fn format_frequency(frequency: f64) -> String {
+    if frequency < 1000.0 {
+        return format!("{:.0}", frequency);
+    }
+
+    let kilo = frequency / 1000.0;
+    if (kilo - kilo.round()).abs() < 0.05 {
+        format!("{:.0}k", kilo)
+    } else {
+        format!("{:.1}k", kilo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_frame_count() {
+        let samples = vec![0.0; 4096];
+        let config = StftConfig::default();
+        let spectrogram = Spectrogram::new(&samples, 44100, config);
+        // (4096 - 1024) / 512 + 1
+        assert_eq!(spectrogram.num_frames(), 7);
+        assert_eq!(spectrogram.num_bins(), 512);
+        assert!(spectrogram.frames().iter().all(|f| f.len() == 512));
+
+        let short = Spectrogram::new(&samples[..100], 44100, config);
+        assert_eq!(short.num_frames(), 0);
+    }
+
+    #[test]
+    fn test_time_and_frequency_axes() {
+        let config = StftConfig {
+            fft_size: 2048,
+            ..StftConfig::default()
+        };
+        let spectrogram: Spectrogram = Spectrogram::from_frames(Vec::new(), 44100, config);
+        assert!((spectrogram.frame_time(10) - 5120.0 / 44100.0).abs() < 1e-12);
+        assert!((spectrogram.frames_per_second() - 86.1328125).abs() < 1e-9);
+        assert!((spectrogram.bin_frequency(1.0) - 21.533203125).abs() < 1e-9);
+        assert!((spectrogram.frequency_bin(21.533203125) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_to_db() {
+        assert!((to_db(1.0, 1.0)).abs() < 1e-12);
+        assert!((to_db(0.1, 1.0) + 20.0).abs() < 1e-12);
+        assert!((to_db(0.5, 0.25) - 6.0206).abs() < 1e-4);
+        // Silence is floored instead of -inf
+        assert!(to_db(0.0, 1.0).is_finite());
+        assert!((power_to_db(0.25, 1.0) - to_db(0.5, 1.0)).abs() < 1e-12);
+        assert_eq!(power_to_db(0.0, 1.0), to_db(0.0, 1.0));
+    }
+
+    #[test]
+    fn test_frequency_scales() {
+        assert!((hz_to_mel(1000.0) - 1000.0).abs() < 0.1);
+        assert!((mel_to_hz(hz_to_mel(4321.0)) - 4321.0).abs() < 1e-9);
+
+        for scale in [
+            FrequencyScale::Linear,
+            FrequencyScale::Log,
+            FrequencyScale::Mel,
+        ] {
+            assert!((scale.inverse(scale.forward(440.0)) - 440.0).abs() < 1e-9);
+            assert_eq!(scale.to_string().parse::<FrequencyScale>().unwrap(), scale);
+        }
+        // Octaves are evenly spaced on the log scale
+        let log = FrequencyScale::Log;
+        let octave = log.forward(200.0) - log.forward(100.0);
+        assert!((log.forward(8000.0) - log.forward(4000.0) - octave).abs() < 1e-12);
+        assert!("bark".parse::<FrequencyScale>().is_err());
+    }
+
+    #[test]
+    fn test_tone_lands_in_its_bin() {
+        let sample_rate = 8000;
+        let samples: Vec<f64> = (0..8000)
+            .map(|n| (2.0 * PI * 1000.0 * n as f64 / sample_rate as f64).sin())
+            .collect();
+        let config = StftConfig {
+            window_size: 256,
+            hop_size: 128,
+            fft_size: 512,
+            window: Window::Hann,
+        };
+        let spectrogram = Spectrogram::new(&samples, sample_rate, config);
+
+        for frame in spectrogram.frames() {
+            let strongest = (0..frame.len())
+                .max_by(|a, b| frame[*a].total_cmp(&frame[*b]))
+                .unwrap();
+            assert_eq!(spectrogram.bin_frequency(strongest as f64), 1000.0);
+        }
+    }
+
+    #[test]
+    fn test_single_precision() {
+        let sample_rate = 8000;
+        let samples: Vec<f64> = (0..4000)
+            .map(|n| 1000.0 * (2.0 * PI * 440.0 * n as f64 / sample_rate as f64).sin())
+            .collect();
+        let single: Vec<f32> = samples.iter().map(|&s| s as f32).collect();
+
+        let double = Spectrogram::new(&samples, sample_rate, StftConfig::default());
+        let single = Spectrogram::new(&single, sample_rate, StftConfig::default());
+        assert_eq!(single.num_frames(), double.num_frames());
+
+        // Around 1e-7 relative to the loudest bin, far below anything a plot or the peak picking can see
+        let max = double.max_magnitude();
+        assert!((single.max_magnitude() - max).abs() < max * 1e-5);
+        for frame in 0..double.num_frames() {
+            for bin in 0..double.num_bins() {
+                let (a, b) = (single.magnitude(frame, bin), double.magnitude(frame, bin));
+                assert!((a - b).abs() < max * 1e-5, "{} {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_frequency_ticks() {
+        assert_eq!(
+            frequency_ticks(0.0, 4000.0, FrequencyScale::Linear),
+            vec![
+                0.0, 500.0, 1000.0, 1500.0, 2000.0, 2500.0, 3000.0, 3500.0, 4000.0
+            ]
+        );
+        assert_eq!(
+            frequency_ticks(30.0, 1200.0, FrequencyScale::Log),
+            vec![50.0, 100.0, 200.0, 500.0, 1000.0]
+        );
+        assert_eq!(
+            frequency_ticks(0.0, 600.0, FrequencyScale::Mel),
+            vec![0.0, 100.0, 200.0, 500.0]
+        );
+    }
+
+    #[test]
+    fn test_format_frequency() {
+        assert_eq!(format_frequency(0.0), "0");
+        assert_eq!(format_frequency(440.0), "440");
+        assert_eq!(format_frequency(2000.0), "2k");
+        assert_eq!(format_frequency(1500.0), "1.5k");
+    }
+}
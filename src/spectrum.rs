@@ -0,0 +1,744 @@
+use std::f64::consts::PI;
+
+use rustfft::FftPlanner;
+use rustfft::num_complex::{self, Complex64};
+
+use crate::complex::{Complex, from_num_complex_slice};
+use crate::float::Float;
+use crate::peaks::max_filter;
+use crate::simd;
+use crate::spectrogram::to_db;
+use crate::twiddle::twiddles;
+use crate::window::{self, Window};
+
+// Interpolation used to find where a spectral peak really is between the bins around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    // Parabola through the linear magnitudes of the peak bin and its neighbours, cheap but biased
+    Parabolic,
+    // Parabola through the log magnitudes, exact for a Gaussian main lobe and close for Hann/Blackman
+    Gaussian,
+}
+
+// Position of a peak in fractional bins and its interpolated magnitude
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RefinedPeak {
+    pub bin: f64,
+    pub magnitude: f64,
+}
+
+// Naive O(n^2) transform, only here as a reference for the FFT
+pub fn dft<T: Float>(samples: &[T]) -> Vec<Complex<T>> {
+    let table = twiddles(samples.len());
+    let samples: Vec<Complex<T>> = samples.iter().map(|&x| Complex::from(x)).collect();
+    let mut row = vec![Complex::ZERO; samples.len()];
+    (0..samples.len())
+        .map(|bin| dft_bin(&samples, &table, bin, &mut row))
+        .collect()
+}
+
+// One bin of the DFT, the twiddle factor of sample n is table[n * bin % N]
+//
+// The factors of the bin are gathered into row first so the multiply-accumulate runs over two contiguous slices.
+// Stepping through the table keeps the index below N, n * bin itself overflows for large transforms
+fn dft_bin<T: Float>(
+    samples: &[Complex<T>],
+    table: &[Complex<T>],
+    bin: usize,
+    row: &mut [Complex<T>],
+) -> Complex<T> {
+    let n = table.len();
+    let step = bin % n;
+    let mut index = 0;
+    for factor in row.iter_mut() {
+        *factor = table[index];
+        index += step;
+        if index >= n {
+            index -= n;
+        }
+    }
+    T::complex_mac(samples, row)
+}
+
+pub fn fft<T: Float>(samples: &[T]) -> Vec<num_complex::Complex<T>> {
+    fft_padded(samples, samples.len())
+}
+
+// Appends zeros to the frame up to fft_size before transforming it
+//
+// Padding does not add information, the main lobe of a tone stays as wide as the window allows,
+// but the spectrum gets sampled more densely so the peaks land closer to the bins
+pub fn fft_padded<T: Float>(samples: &[T], fft_size: usize) -> Vec<num_complex::Complex<T>> {
+    let fft_size = fft_size.max(samples.len());
+    let mut planner: FftPlanner<T> = FftPlanner::new();
+    let fft = planner.plan_fft_forward(fft_size);
+    let mut buff: Vec<num_complex::Complex<T>> =
+        samples.iter().map(|&x| Complex::from(x).into()).collect();
+    buff.resize(fft_size, Complex::ZERO.into());
+    fft.process(&mut buff);
+
+    buff
+}
+
+// Smallest power of two FFT size holding the frame, multiplied by the padding factor
+pub fn padded_size(frame_size: usize, factor: usize) -> usize {
+    frame_size.next_power_of_two() * factor.max(1)
+}
+
+// Each bin is sample_rate / fft_size wide, the padded size has to be used once the frame is zero padded
+pub fn bin_frequency(bin: f64, fft_size: usize, sample_rate: u32) -> f64 {
+    bin * sample_rate as f64 / fft_size as f64
+}
+
+pub fn frequency_bin(frequency: f64, fft_size: usize, sample_rate: u32) -> f64 {
+    frequency * fft_size as f64 / sample_rate as f64
+}
+
+// Fits a parabola through the peak bin and its two neighbours and returns the position and height of its vertex
+pub fn refine_peak(magnitudes: &[f64], bin: usize, method: Interpolation) -> RefinedPeak {
+    if bin == 0 || bin + 1 >= magnitudes.len() {
+        return RefinedPeak {
+            bin: bin as f64,
+            magnitude: magnitudes.get(bin).copied().unwrap_or(0.0),
+        };
+    }
+
+    let (left, centre, right) = (magnitudes[bin - 1], magnitudes[bin], magnitudes[bin + 1]);
+    match method {
+        Interpolation::Parabolic => {
+            let (offset, height) = parabola_vertex(left, centre, right);
+            RefinedPeak {
+                bin: bin as f64 + offset,
+                magnitude: height,
+            }
+        }
+        Interpolation::Gaussian => {
+            // Log of a zero magnitude would be -inf, keep the bin as it is
+            if left <= 0.0 || centre <= 0.0 || right <= 0.0 {
+                return RefinedPeak {
+                    bin: bin as f64,
+                    magnitude: centre,
+                };
+            }
+            let (offset, height) = parabola_vertex(left.ln(), centre.ln(), right.ln());
+            RefinedPeak {
+                bin: bin as f64 + offset,
+                magnitude: height.exp(),
+            }
+        }
+    }
+}
+
+// Phase vocoder frequency estimate of a bin from two spectra taken hop samples apart
+//
+// A tone exactly at the bin centre advances its phase by 2PI * bin * hop / N between the frames,
+// whatever is left over after removing that expected advance tells us how far the tone is from the centre
+pub fn instantaneous_frequency(
+    previous: &[Complex64],
+    current: &[Complex64],
+    bin: usize,
+    hop: usize,
+    sample_rate: u32,
+) -> f64 {
+    let fft_size = current.len();
+    let expected = 2.0 * PI * bin as f64 * hop as f64 / fft_size as f64;
+    let advance = current[bin].arg() - previous[bin].arg();
+    let deviation = wrap_phase(advance - expected);
+
+    let bin_offset = deviation * fft_size as f64 / (2.0 * PI * hop as f64);
+    bin_frequency(bin as f64 + bin_offset, fft_size, sample_rate)
+}
+
+// Wraps the phase to the -PI..PI range
+pub fn wrap_phase(phase: f64) -> f64 {
+    phase - 2.0 * PI * ((phase + PI) / (2.0 * PI)).floor()
+}
+
+// Single sided amplitude spectrum of one frame in dB relative to full scale
+//
+// The bins are corrected for the coherent gain of the window, so a full scale sine reads 0dBFS at its peak
+// whatever the window, frame size or zero padding
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibratedSpectrum {
+    // Bins 0 to fft_size / 2, Nyquist included
+    levels: Vec<f64>,
+    window_size: usize,
+    fft_size: usize,
+    sample_rate: u32,
+}
+
+// A peak of a calibrated spectrum at its interpolated frequency and level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectralPeak {
+    pub frequency: f64,
+    // dBFS
+    pub level: f64,
+}
+
+impl CalibratedSpectrum {
+    // full_scale is the amplitude of 0dBFS, 32768 for 16 bit samples or 1 for normalised ones
+    pub fn new(
+        frame: &[f64],
+        window: Window,
+        fft_size: usize,
+        sample_rate: u32,
+        full_scale: f64,
+    ) -> Self {
+        let win = window.periodic(frame.len());
+        let spectrum = fft_padded(&window::apply(frame, &win), fft_size);
+        let mut magnitudes = vec![0.0; spectrum.len() / 2 + 1];
+        simd::magnitude(from_num_complex_slice(&spectrum), &mut magnitudes);
+        Self::from_magnitudes(&magnitudes, &win, spectrum.len(), sample_rate, full_scale)
+    }
+
+    // Magnitudes of the bins 0 to fft_size / 2 of a frame multiplied by win
+    pub fn from_magnitudes(
+        magnitudes: &[f64],
+        win: &[f64],
+        fft_size: usize,
+        sample_rate: u32,
+        full_scale: f64,
+    ) -> Self {
+        // A windowed sine of amplitude A peaks at A / 2 * sum(win), the sum is the coherent gain times the size
+        let window_sum: f64 = win.iter().sum();
+        let levels = magnitudes
+            .iter()
+            .enumerate()
+            .map(|(bin, magnitude)| {
+                // Every bin except DC and Nyquist only got half of the sine, the other half is a negative frequency
+                let sides = if bin == 0 || 2 * bin == fft_size {
+                    1.0
+                } else {
+                    2.0
+                };
+                to_db(sides * magnitude / window_sum, full_scale)
+            })
+            .collect();
+
+        CalibratedSpectrum {
+            levels,
+            window_size: win.len(),
+            fft_size,
+            sample_rate,
+        }
+    }
+
+    pub fn levels(&self) -> &[f64] {
+        &self.levels
+    }
+
+    pub fn num_bins(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn frequency(&self, bin: f64) -> f64 {
+        bin_frequency(bin, self.fft_size, self.sample_rate)
+    }
+
+    // The strongest local maxima, loudest first
+    //
+    // A peak has to be the maximum of about a main lobe around it so the sidelobes of a loud tone are not reported,
+    // the parabola through the dB levels is the Gaussian interpolation of the magnitudes
+    pub fn peaks(&self, count: usize) -> Vec<SpectralPeak> {
+        let radius = (2 * self.fft_size / self.window_size.max(1)).max(1);
+        let maxima = max_filter(&self.levels, radius);
+
+        let mut peaks: Vec<SpectralPeak> = (0..self.levels.len())
+            .filter(|bin| self.levels[*bin] == maxima[*bin])
+            // A flat stretch, e.g. digital silence, only counts once
+            .filter(|bin| *bin == 0 || self.levels[*bin] > self.levels[*bin - 1])
+            .map(|bin| {
+                let refined = refine_peak(&self.levels, bin, Interpolation::Parabolic);
+                SpectralPeak {
+                    frequency: self.frequency(refined.bin),
+                    level: refined.magnitude,
+                }
+            })
+            .collect();
+
+        peaks.sort_by(|a, b| b.level.total_cmp(&a.level));
+        peaks.truncate(count);
+        peaks
+    }
+}
+
+// Vertex of the parabola going through (-1, left), (0, centre) and (1, right)
+fn parabola_vertex(left: f64, centre: f64, right: f64) -> (f64, f64) {
+    let denominator = left - 2.0 * centre + right;
+    if denominator == 0.0 {
+        return (0.0, centre);
+    }
+
+    let offset = 0.5 * (left - right) / denominator;
+    let height = centre - 0.25 * (left - right) * offset;
+    (offset, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twiddle::twiddle;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    fn sine(frequency: f64, size: usize, offset: usize) -> Vec<f64> {
+        (offset..offset + size)
+            .map(|n| (2.0 * PI * frequency * n as f64 / SAMPLE_RATE as f64).sin())
+            .collect()
+    }
+
+    fn magnitudes(spectrum: &[Complex64]) -> Vec<f64> {
+        spectrum
+            .iter()
+            .take(spectrum.len() / 2)
+            .map(|x| x.norm())
+            .collect()
+    }
+
+    fn max_bin(magnitudes: &[f64]) -> usize {
+        (0..magnitudes.len())
+            .max_by(|a, b| magnitudes[*a].total_cmp(&magnitudes[*b]))
+            .unwrap()
+    }
+
+    fn windowed_sine(frequency: f64, size: usize) -> Vec<f64> {
+        window::apply(&sine(frequency, size, 0), &Window::Hann.periodic(size))
+    }
+
+    #[test]
+    fn test_fft_matches_dft() {
+        let samples = windowed_sine(1000.0, 64);
+        let dft_result = dft(&samples);
+        let fft_result = fft(&samples);
+        for (a, b) in dft_result.iter().zip(fft_result) {
+            assert!((a.abs() - b.norm()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_single_precision_dft() {
+        let samples: Vec<f32> = windowed_sine(1000.0, 64)
+            .iter()
+            .map(|&s| s as f32)
+            .collect();
+        let dft_result = dft(&samples);
+        let fft_result = fft(&samples);
+        for (a, b) in dft_result.iter().zip(fft_result) {
+            assert!((*a - Complex::from(b)).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_fft_padded() {
+        let samples = windowed_sine(1000.0, 1024);
+        let padded = fft_padded(&samples, 4096);
+        let plain = fft(&samples);
+        assert_eq!(padded.len(), 4096);
+        // Every 4th bin of the padded spectrum is a bin of the unpadded one
+        for (k, x) in plain.iter().enumerate() {
+            assert!((padded[k * 4] - x).norm() < 1e-9);
+        }
+        // Padding to a smaller size than the frame is ignored
+        assert_eq!(fft_padded(&samples, 16).len(), 1024);
+    }
+
+    #[test]
+    fn test_padded_size() {
+        assert_eq!(padded_size(1024, 1), 1024);
+        assert_eq!(padded_size(1000, 4), 4096);
+        assert_eq!(padded_size(1024, 0), 1024);
+    }
+
+    #[test]
+    fn test_bin_frequency() {
+        assert!((bin_frequency(1.0, 1024, SAMPLE_RATE) - 43.066).abs() < 1e-3);
+        assert!(
+            (frequency_bin(bin_frequency(10.5, 4096, SAMPLE_RATE), 4096, SAMPLE_RATE) - 10.5).abs()
+                < 1e-12
+        );
+    }
+
+    #[test]
+    fn test_zero_padding_gets_closer_to_the_tone() {
+        let frequency = 1001.7;
+        let samples = windowed_sine(frequency, 1024);
+
+        let plain = magnitudes(&fft(&samples));
+        let plain_error =
+            (bin_frequency(max_bin(&plain) as f64, 1024, SAMPLE_RATE) - frequency).abs();
+
+        let padded = magnitudes(&fft_padded(&samples, 8192));
+        let padded_error =
+            (bin_frequency(max_bin(&padded) as f64, 8192, SAMPLE_RATE) - frequency).abs();
+
+        assert!(padded_error < plain_error);
+        assert!(padded_error < bin_frequency(0.5, 8192, SAMPLE_RATE));
+    }
+
+    #[test]
+    fn test_refine_peak() {
+        let frequency = 1001.7;
+        let samples = windowed_sine(frequency, 1024);
+        let mags = magnitudes(&fft(&samples));
+        let bin = max_bin(&mags);
+
+        let parabolic = refine_peak(&mags, bin, Interpolation::Parabolic);
+        let parabolic_hz = bin_frequency(parabolic.bin, 1024, SAMPLE_RATE);
+        assert!((parabolic_hz - frequency).abs() < 3.0);
+
+        let gaussian = refine_peak(&mags, bin, Interpolation::Gaussian);
+        let gaussian_hz = bin_frequency(gaussian.bin, 1024, SAMPLE_RATE);
+        assert!((gaussian_hz - frequency).abs() < 1.0);
+
+        // Hann window has a coherent gain of 0.5, so a unit sine peaks at about N / 4
+        assert!((gaussian.magnitude - 256.0).abs() < 5.0);
+        assert!(gaussian.magnitude >= mags[bin]);
+    }
+
+    #[test]
+    fn test_refine_peak_edges() {
+        let mags = [3.0, 1.0, 2.0];
+        assert_eq!(refine_peak(&mags, 0, Interpolation::Gaussian).bin, 0.0);
+        assert_eq!(
+            refine_peak(&mags, 2, Interpolation::Parabolic).magnitude,
+            2.0
+        );
+
+        let flat = [1.0, 1.0, 1.0];
+        assert_eq!(refine_peak(&flat, 1, Interpolation::Parabolic).bin, 1.0);
+
+        let zero = [0.0, 1.0, 0.5];
+        assert_eq!(refine_peak(&zero, 1, Interpolation::Gaussian).bin, 1.0);
+    }
+
+    #[test]
+    fn test_instantaneous_frequency() {
+        let frequency = 1001.7;
+        let size = 1024;
+        let hop = 64;
+        let win = Window::Hann.periodic(size);
+
+        let previous = fft(&window::apply(&sine(frequency, size, 0), &win));
+        let current = fft(&window::apply(&sine(frequency, size, hop), &win));
+        let bin = max_bin(&magnitudes(&current));
+
+        let estimate = instantaneous_frequency(&previous, &current, bin, hop, SAMPLE_RATE);
+        assert!((estimate - frequency).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_wrap_phase() {
+        assert!(
+            (wrap_phase(3.0 * PI) - PI).abs() < 1e-12 || (wrap_phase(3.0 * PI) + PI).abs() < 1e-12
+        );
+        assert!((wrap_phase(0.5) - 0.5).abs() < 1e-12);
+        assert!((wrap_phase(-2.0 * PI + 0.5) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_calibrated_full_scale_sine() {
+        // Off bin tone, full scale for 16 bit samples
+        let frame: Vec<f64> = sine(1234.5, 2048, 0).iter().map(|x| x * 32768.0).collect();
+
+        for window in [Window::Hann, Window::BlackmanHarris, Window::FlatTop] {
+            for padding in [1, 4] {
+                let spectrum =
+                    CalibratedSpectrum::new(&frame, window, 2048 * padding, SAMPLE_RATE, 32768.0);
+                assert_eq!(spectrum.num_bins(), 1024 * padding + 1);
+
+                let peak = spectrum.peaks(1)[0];
+                assert!(
+                    peak.level.abs() < 0.3,
+                    "{} x{}: {}",
+                    window,
+                    padding,
+                    peak.level
+                );
+                // The flat top window trades frequency resolution for level accuracy
+                if window != Window::FlatTop {
+                    assert!(
+                        (peak.frequency - 1234.5).abs() < 1.0,
+                        "{} x{}: {}",
+                        window,
+                        padding,
+                        peak.frequency
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_calibrated_levels() {
+        // -6dBFS and -40dBFS tones, the flat top window keeps the level within 0.2dB wherever the tone falls
+        let frame: Vec<f64> = sine(1000.0, 4096, 0)
+            .iter()
+            .zip(sine(5000.0, 4096, 0))
+            .map(|(a, b)| 0.5 * a + 0.01 * b)
+            .collect();
+        let spectrum = CalibratedSpectrum::new(&frame, Window::FlatTop, 4096, SAMPLE_RATE, 1.0);
+
+        let peaks = spectrum.peaks(2);
+        assert_eq!(peaks.len(), 2);
+        // Within a quarter of a bin, the flat top window is the least precise in frequency
+        let bin_width = spectrum.frequency(1.0);
+        assert!((peaks[0].frequency - 1000.0).abs() < 0.25 * bin_width);
+        assert!((peaks[0].level + 6.02).abs() < 0.2, "{}", peaks[0].level);
+        assert!((peaks[1].frequency - 5000.0).abs() < 0.25 * bin_width);
+        assert!((peaks[1].level + 40.0).abs() < 0.2, "{}", peaks[1].level);
+    }
+
+    #[test]
+    fn test_calibrated_dc_and_silence() {
+        let spectrum = CalibratedSpectrum::new(&[0.25; 256], Window::Hann, 256, 8000, 1.0);
+        assert!((spectrum.levels()[0] - to_db(0.25, 1.0)).abs() < 1e-9);
+        assert_eq!(spectrum.peaks(3)[0].frequency, 0.0);
+
+        let silence = CalibratedSpectrum::new(&[0.0; 256], Window::Hann, 256, 8000, 1.0);
+        assert_eq!(silence.peaks(5).len(), 1);
+    }
+
+    // Double-double numbers, hi + lo with |lo| <= ulp(hi) / 2, around 106 bits of mantissa for the reference DFT
+    #[derive(Debug, Clone, Copy)]
+    struct Dd(f64, f64);
+
+    impl Dd {
+        fn two_sum(a: f64, b: f64) -> Dd {
+            let s = a + b;
+            let v = s - a;
+            Dd(s, (a - (s - v)) + (b - v))
+        }
+
+        fn add(self, rhs: Dd) -> Dd {
+            let Dd(s, e) = Dd::two_sum(self.0, rhs.0);
+            Dd::two_sum(s, e + self.1 + rhs.1)
+        }
+
+        fn mul(self, rhs: Dd) -> Dd {
+            let p = self.0 * rhs.0;
+            let e = self.0.mul_add(rhs.0, -p) + self.0 * rhs.1 + self.1 * rhs.0;
+            Dd::two_sum(p, e)
+        }
+
+        fn div(self, rhs: f64) -> Dd {
+            let q = self.0 / rhs;
+            let r = self.add(Dd(q, 0.0).mul(Dd(-rhs, 0.0)));
+            Dd::two_sum(q, r.0 / rhs)
+        }
+
+        fn neg(self) -> Dd {
+            Dd(-self.0, -self.1)
+        }
+    }
+
+    // pi to double-double precision
+    const DD_PI: Dd = Dd(PI, 1.224_646_799_147_353_2e-16);
+
+    // e^(-2PI i k / n) as (re, im), reduced to the nearest quarter turn and summed up from the Taylor series
+    fn reference_twiddle(k: usize, n: usize) -> (Dd, Dd) {
+        let (k, n) = (k as i64 % n as i64, n as i64);
+        let quadrant = (8 * k + n) / (2 * n);
+        // Angle left over after the quadrant, at most PI / 4
+        let r = DD_PI
+            .mul(Dd((4 * k - quadrant * n) as f64, 0.0))
+            .div(2.0 * n as f64);
+
+        let (mut sin, mut cos) = (r, Dd(1.0, 0.0));
+        let (mut sin_term, mut cos_term) = (r, Dd(1.0, 0.0));
+        let r2 = r.mul(r).neg();
+        for i in 1..20 {
+            let i = i as f64;
+            cos_term = cos_term.mul(r2).div((2.0 * i - 1.0) * (2.0 * i));
+            sin_term = sin_term.mul(r2).div((2.0 * i) * (2.0 * i + 1.0));
+            cos = cos.add(cos_term);
+            sin = sin.add(sin_term);
+        }
+
+        let (cos, sin) = match quadrant % 4 {
+            0 => (cos, sin),
+            1 => (sin.neg(), cos),
+            2 => (cos.neg(), sin.neg()),
+            _ => (sin, cos.neg()),
+        };
+        (cos, sin.neg())
+    }
+
+    fn reference_bin(samples: &[f64], table: &[(Dd, Dd)], bin: usize) -> (Dd, Dd) {
+        let n = table.len();
+        let (mut re, mut im) = (Dd(0.0, 0.0), Dd(0.0, 0.0));
+        for (j, sample) in samples.iter().enumerate() {
+            let (w_re, w_im) = table[(j as u64 * bin as u64 % n as u64) as usize];
+            re = re.add(w_re.mul(Dd(*sample, 0.0)));
+            im = im.add(w_im.mul(Dd(*sample, 0.0)));
+        }
+        (re, im)
+    }
+
+    fn noise(size: usize, seed: u64) -> Vec<f64> {
+        let mut state = seed;
+        (0..size)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+            })
+            .collect()
+    }
+
+    // Bins checked against the reference, every bin of small transforms and a spread including the edges otherwise
+    fn checked_bins(n: usize) -> Vec<usize> {
+        if n <= 1024 {
+            return (0..n).collect();
+        }
+        let mut bins: Vec<usize> = (0..61).map(|i| i * (n / 61) + i).collect();
+        bins.extend([1, n / 2, n / 4 + 1, n - 1]);
+        bins
+    }
+
+    // Max error relative to the largest bin and RMS error relative to the RMS of the bins
+    fn transform_error(
+        samples: &[f64],
+        bins: &[usize],
+        mut transform: impl FnMut(usize) -> Complex,
+    ) -> (f64, f64) {
+        let n = samples.len();
+        let table: Vec<(Dd, Dd)> = (0..n).map(|k| reference_twiddle(k, n)).collect();
+
+        let (mut max_error, mut max_bin) = (0.0f64, 0.0f64);
+        let (mut sum_error, mut sum_bin) = (0.0, 0.0);
+        for &bin in bins {
+            let (re, im) = reference_bin(samples, &table, bin);
+            let value = transform(bin);
+            // Differences of the double-doubles keep the low parts, the reference is exact to well below f64
+            let error = Complex::new(
+                Dd(value.re, 0.0).add(re.neg()).0,
+                Dd(value.im, 0.0).add(im.neg()).0,
+            )
+            .abs();
+            let magnitude = Complex::new(re.0, im.0).abs();
+            max_error = max_error.max(error);
+            max_bin = max_bin.max(magnitude);
+            sum_error += error * error;
+            sum_bin += magnitude * magnitude;
+        }
+        (max_error / max_bin, (sum_error / sum_bin).sqrt())
+    }
+
+    // Single bins of dft, for sizes where computing every bin takes too long
+    fn single_bins<T: Float>(samples: &[T]) -> impl FnMut(usize) -> Complex<T> {
+        let table = twiddles(samples.len());
+        let samples: Vec<Complex<T>> = samples.iter().map(|&x| Complex::from(x)).collect();
+        let mut row = vec![Complex::ZERO; samples.len()];
+        move |bin| dft_bin(&samples, &table, bin, &mut row)
+    }
+
+    #[test]
+    fn test_reference_twiddles() {
+        for n in [1, 4, 7, 360, 4096] {
+            for k in 0..n {
+                let (re, im) = reference_twiddle(k, n);
+                let expected: Complex = twiddle(k, n);
+                assert!(
+                    (re.0 - expected.re).abs() <= f64::EPSILON,
+                    "n = {}, k = {}",
+                    n,
+                    k
+                );
+                assert!(
+                    (im.0 - expected.im).abs() <= f64::EPSILON,
+                    "n = {}, k = {}",
+                    n,
+                    k
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_dft_accuracy() {
+        // The error of the naive sum grows roughly with sqrt(N) and the twiddles add half an ulp each at most
+        for (n, max_limit, rms_limit) in [
+            (1, 1e-16, 1e-16),
+            (2, 1e-16, 1e-16),
+            (3, 1e-16, 1e-16),
+            (100, 1e-15, 1e-15),
+            (1024, 5e-15, 5e-15),
+            (4096, 1e-14, 1e-14),
+            (44100, 3e-14, 3e-14),
+            (65536, 5e-14, 5e-14),
+        ] {
+            let samples = noise(n, n as u64);
+            let bins = checked_bins(n);
+            let (max_error, rms_error) = if n <= 1024 {
+                let spectrum = dft(&samples);
+                transform_error(&samples, &bins, |bin| spectrum[bin])
+            } else {
+                transform_error(&samples, &bins, single_bins(&samples))
+            };
+            assert!(
+                max_error < max_limit,
+                "n = {}, max error {:e}",
+                n,
+                max_error
+            );
+            assert!(
+                rms_error < rms_limit,
+                "n = {}, RMS error {:e}",
+                n,
+                rms_error
+            );
+        }
+    }
+
+    #[test]
+    fn test_single_precision_dft_accuracy() {
+        for n in [64, 1000, 16384] {
+            let samples = noise(n, 7);
+            let single: Vec<f32> = samples.iter().map(|&s| s as f32).collect();
+            // The reference transforms the rounded samples, so only the arithmetic is measured
+            let rounded: Vec<f64> = single.iter().map(|&s| s as f64).collect();
+            let bins = checked_bins(n);
+            let mut transform = single_bins(&single);
+            let (max_error, rms_error) =
+                transform_error(&rounded, &bins, |bin| transform(bin).into());
+            assert!(max_error < 1e-5, "n = {}, max error {:e}", n, max_error);
+            assert!(rms_error < 1e-5, "n = {}, RMS error {:e}", n, rms_error);
+        }
+    }
+
+    #[test]
+    fn test_powi_twiddles_drift() {
+        // What dft used to do, raising the first twiddle to the power n * bin through polar form
+        let n = 4096;
+        let samples = noise(n, 3);
+        let bins = checked_bins(n);
+        let first = Complex::from_polar(1.0, -2.0 * PI / n as f64);
+        let (powi_error, _) = transform_error(&samples, &bins, |bin| {
+            samples
+                .iter()
+                .enumerate()
+                .map(|(j, &sample)| first.powi((j * bin) as i32) * sample)
+                .sum()
+        });
+        let (table_error, _) = transform_error(&samples, &bins, single_bins(&samples));
+        assert!(
+            table_error * 100.0 < powi_error,
+            "{:e} {:e}",
+            table_error,
+            powi_error
+        );
+    }
+}
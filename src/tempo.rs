@@ -0,0 +1,277 @@
+use crate::error::{Error, Result};
+use crate::onset::{Onsets, refine};
+
+// Multiples of the beat period the comb adds up, a tempo twice as fast as the real one misses every other tooth
+const HARMONICS: usize = 4;
+
+// Tempo settings, the prior is the one of librosa.feature.tempo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoConfig {
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+    // Centre of the log-normal weighting that settles between half and double time, a tempo an octave away
+    // weighs 0.61 of one right on it
+    pub prior_bpm: f64,
+}
+
+impl Default for TempoConfig {
+    fn default() -> Self {
+        TempoConfig {
+            min_bpm: 60.0,
+            max_bpm: 200.0,
+            prior_bpm: 120.0,
+        }
+    }
+}
+
+// Tempo of a recording with a grid of beats at that tempo
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tempo {
+    pub bpm: f64,
+    // Autocorrelation of the onset function at the beat period relative to no shift, near 1 for a metronome and
+    // near 0 for no steady beat
+    pub confidence: f64,
+    // Seconds of every beat from the first to the end
+    pub beats: Vec<f64>,
+}
+
+impl Tempo {
+    // Seconds between beats
+    pub fn period(&self) -> f64 {
+        60.0 / self.bpm
+    }
+}
+
+// Beat period from the autocorrelation of the onset function, weighted by a comb over the multiples of the period and
+// by the prior, then the phase that lines the grid up with the strongest onsets
+pub(crate) fn estimate_tempo(onsets: &Onsets, config: &TempoConfig) -> Result<Option<Tempo>> {
+    if !(config.min_bpm > 0.0 && config.min_bpm < config.max_bpm && config.prior_bpm > 0.0) {
+        return Err(Error::InvalidArgument(
+            "tempo needs 0 < min BPM < max BPM and a positive prior".to_string(),
+        ));
+    }
+
+    let envelope = onsets.envelope();
+    let frames_per_second = onsets.frames_per_second();
+    let min_lag = ((60.0 * frames_per_second / config.max_bpm).floor() as usize).max(1);
+    let max_lag = (60.0 * frames_per_second / config.min_bpm).ceil() as usize;
+    // At least two periods of the slowest tempo
+    if envelope.len() <= 2 * max_lag {
+        return Ok(None);
+    }
+
+    let correlation = autocorrelation(envelope, HARMONICS * max_lag);
+    if correlation[0] <= 0.0 {
+        return Ok(None);
+    }
+
+    let scores: Vec<f64> = (0..=max_lag)
+        .map(|lag| {
+            if lag < min_lag {
+                return 0.0;
+            }
+            // The error of the whole frame lag grows with every multiple, so the teeth widen with it
+            let teeth: Vec<f64> = (1..=HARMONICS)
+                .take_while(|m| m * lag < correlation.len())
+                .map(|m| {
+                    let (low, high) = (
+                        m * lag - m / 2,
+                        (m * lag + m / 2).min(correlation.len() - 1),
+                    );
+                    correlation[low..=high]
+                        .iter()
+                        .copied()
+                        .fold(f64::MIN, f64::max)
+                })
+                .collect();
+            let comb = teeth.iter().sum::<f64>() / teeth.len() as f64;
+            let octaves = (60.0 * frames_per_second / lag as f64 / config.prior_bpm).log2();
+            comb.max(0.0) * (-0.5 * octaves * octaves).exp()
+        })
+        .collect();
+    let (lag, &score) = scores
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .unwrap();
+    if score <= 0.0 {
+        return Ok(None);
+    }
+    // The peak at the highest multiple of the period that fits pins the period down a few times more precisely
+    let period = (1..=HARMONICS)
+        .rev()
+        .find_map(|m| {
+            let centre = m * lag;
+            if centre + 1 >= correlation.len() {
+                return None;
+            }
+            let peak = (centre - m..=centre + m)
+                .filter(|&l| l + 1 < correlation.len())
+                .max_by(|a, b| correlation[*a].total_cmp(&correlation[*b]))?;
+            Some(refine(&correlation, peak) / m as f64)
+        })
+        .unwrap_or(lag as f64);
+
+    // The grid starts with the music, a beat may come a little early but not a whole period before the first onset
+    let start = onsets.onsets().first().map_or(0.0, |onset| onset.time);
+    let beats = beat_grid(envelope, period)
+        .into_iter()
+        .map(|frame| onsets.time(frame))
+        .filter(|&time| time >= start - period / frames_per_second / 4.0)
+        .collect();
+
+    Ok(Some(Tempo {
+        bpm: 60.0 * frames_per_second / period,
+        confidence: (correlation[lag] / correlation[0]).clamp(0.0, 1.0),
+        beats,
+    }))
+}
+
+// Autocorrelation of the function without its mean for shifts up to max_lag, every shift divided by the number of
+// products so long shifts are not penalised
+fn autocorrelation(values: &[f64], max_lag: usize) -> Vec<f64> {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let centred: Vec<f64> = values.iter().map(|v| v - mean).collect();
+    (0..=max_lag.min(values.len() - 1))
+        .map(|lag| {
+            let products = centred.len() - lag;
+            centred[..products]
+                .iter()
+                .zip(&centred[lag..])
+                .map(|(a, b)| a * b)
+                .sum::<f64>()
+                / products as f64
+        })
+        .collect()
+}
+
+// Fractional frames of the beats, the offset within the first period that collects the most onset strength
+fn beat_grid(envelope: &[f64], period: f64) -> Vec<f64> {
+    let beats = |offset: f64| {
+        (0..)
+            .map(move |beat| offset + beat as f64 * period)
+            .take_while(|&frame| frame.round() < envelope.len() as f64)
+    };
+    let strengths: Vec<f64> = (0..period.ceil() as usize)
+        .map(|offset| {
+            beats(offset as f64)
+                .map(|frame| envelope[frame.round() as usize])
+                .sum()
+        })
+        .collect();
+    let offset = strengths
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map_or(0, |(offset, _)| offset);
+
+    beats(refine(&strengths, offset)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::onset::OnsetConfig;
+    use crate::onset::tests::{SAMPLE_RATE, clicks};
+
+    fn click_track(bpm: f64, start: f64, duration: f64) -> Vec<f64> {
+        let times: Vec<f64> = (0..)
+            .map(|beat| start + beat as f64 * 60.0 / bpm)
+            .take_while(|&time| time < duration - 0.1)
+            .collect();
+        clicks(&times, duration)
+    }
+
+    #[test]
+    fn test_click_tracks() {
+        for bpm in [90.0, 120.0, 128.0, 140.0, 100.5] {
+            let start = 1.1;
+            let onsets = OnsetConfig::default()
+                .onsets(&click_track(bpm, start, 10.0), SAMPLE_RATE)
+                .unwrap();
+            let tempo = onsets.tempo(&TempoConfig::default()).unwrap().unwrap();
+            assert!((tempo.bpm - bpm).abs() < 0.2, "{} != {}", tempo.bpm, bpm);
+            assert!(tempo.confidence > 0.5, "{}", tempo.confidence);
+
+            // The grid lands on the clicks, from the first one on
+            assert!(tempo.beats.len() >= (9.6 - start) as usize * bpm as usize / 60);
+            for (beat, time) in tempo.beats.iter().enumerate() {
+                let click = start + beat as f64 * 60.0 / bpm;
+                assert!(
+                    (time - click).abs() < 0.02,
+                    "beat {}: {} != {}",
+                    beat,
+                    time,
+                    click
+                );
+            }
+            assert!((tempo.period() - 60.0 / bpm).abs() < 0.002);
+        }
+    }
+
+    #[test]
+    fn test_prior() {
+        // Clicks at 160 BPM are also a steady 80 BPM, the prior picks the one closer to it
+        let onsets = OnsetConfig::default()
+            .onsets(&click_track(160.0, 0.2, 10.0), SAMPLE_RATE)
+            .unwrap();
+        let tempo = onsets.tempo(&TempoConfig::default()).unwrap().unwrap();
+        assert!((tempo.bpm - 160.0).abs() < 0.5, "{}", tempo.bpm);
+        let config = TempoConfig {
+            prior_bpm: 70.0,
+            ..TempoConfig::default()
+        };
+        let tempo = onsets.tempo(&config).unwrap().unwrap();
+        assert!((tempo.bpm - 80.0).abs() < 0.5, "{}", tempo.bpm);
+
+        // A range that leaves out the real tempo finds a multiple of the period
+        let config = TempoConfig {
+            min_bpm: 40.0,
+            max_bpm: 100.0,
+            ..TempoConfig::default()
+        };
+        let tempo = onsets.tempo(&config).unwrap().unwrap();
+        assert!((tempo.bpm - 80.0).abs() < 0.5, "{}", tempo.bpm);
+    }
+
+    #[test]
+    fn test_no_beat() {
+        // Noise has no period
+        let mut state = 1u32;
+        let noise: Vec<f64> = (0..10 * SAMPLE_RATE)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                state as f64 / u32::MAX as f64 - 0.5
+            })
+            .collect();
+        let onsets = OnsetConfig::default().onsets(&noise, SAMPLE_RATE).unwrap();
+        if let Some(tempo) = onsets.tempo(&TempoConfig::default()).unwrap() {
+            assert!(tempo.confidence < 0.2, "{}", tempo.confidence);
+        }
+
+        // Silence and recordings shorter than two beats at the slowest tempo
+        let onsets = OnsetConfig::default()
+            .onsets(&vec![0.0; 5 * SAMPLE_RATE as usize], SAMPLE_RATE)
+            .unwrap();
+        assert_eq!(onsets.tempo(&TempoConfig::default()).unwrap(), None);
+        let onsets = OnsetConfig::default()
+            .onsets(&click_track(120.0, 0.1, 1.5), SAMPLE_RATE)
+            .unwrap();
+        assert_eq!(onsets.tempo(&TempoConfig::default()).unwrap(), None);
+
+        let config = TempoConfig {
+            min_bpm: 200.0,
+            max_bpm: 100.0,
+            ..TempoConfig::default()
+        };
+        assert!(onsets.tempo(&config).is_err());
+    }
+
+    #[test]
+    fn test_autocorrelation() {
+        let values = [1.0, 0.0, 1.0, 0.0, 1.0, 0.0];
+        let correlation = autocorrelation(&values, 3);
+        assert_eq!(correlation, vec![0.25, -0.25, 0.25, -0.25]);
+        assert_eq!(autocorrelation(&values, 10).len(), 6);
+    }
+}
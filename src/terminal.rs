@@ -0,0 +1,525 @@
+use std::io::{self, Write};
+use std::ops::Range;
+use std::{fmt, str::FromStr};
+
+use crate::colormap::ColorMap;
+use crate::error::{Error, Result};
+use crate::spectrogram::{FrequencyScale, TimeFrequency, format_frequency, frequency_ticks, to_db};
+use crate::spectrum::CalibratedSpectrum;
+
+// Lower eighth blocks from empty to full
+const BARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// Spectrogram levels without colour
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+// Bit of each dot of a braille cell by column and row, the cell is 2 dots wide and 4 high
+const BRAILLE_DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+// Ordered dither thresholds of the dots, so the density of a braille spectrogram follows the level
+const DITHER: [[f64; 4]; 2] = [
+    [0.5 / 8.0, 6.5 / 8.0, 2.5 / 8.0, 4.5 / 8.0],
+    [4.5 / 8.0, 2.5 / 8.0, 7.5 / 8.0, 0.5 / 8.0],
+];
+// Characters left of the plot for the level or time labels
+const LABEL_WIDTH: usize = 8;
+const DEFAULT_SPECTRUM_HEIGHT: usize = 16;
+const RESET: &str = "\x1b[0m";
+
+// Characters the text plots are drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Glyphs {
+    // Eighth blocks for bars, half blocks (shades without colour) for spectrograms
+    #[default]
+    Blocks,
+    // 2x4 dots per character, twice the horizontal resolution
+    Braille,
+}
+
+impl FromStr for Glyphs {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "blocks" => Ok(Glyphs::Blocks),
+            "braille" => Ok(Glyphs::Braille),
+            _ => Err(Error::InvalidArgument(format!("unknown glyphs '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for Glyphs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Glyphs::Blocks => write!(f, "blocks"),
+            Glyphs::Braille => write!(f, "braille"),
+        }
+    }
+}
+
+// Spectra and spectrograms drawn as text for terminals, from the same data as the image plots
+//
+// The spectrogram is written one line at a time with time going down, so it scrolls by like a waterfall
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextPlot {
+    // Characters per line, labels included
+    pub width: usize,
+    // Lines of the spectrum bars, or of the whole spectrogram with frames merged to fit,
+    // None draws the default spectrum height and every frame of a spectrogram
+    pub height: Option<usize>,
+    pub scale: FrequencyScale,
+    pub color_map: ColorMap,
+    // dB shown, below 0dBFS for a spectrum and below the loudest bin for a spectrogram
+    pub dynamic_range: f64,
+    pub glyphs: Glyphs,
+    // 24 bit ANSI colours
+    pub color: bool,
+}
+
+impl Default for TextPlot {
+    fn default() -> Self {
+        TextPlot {
+            width: 80,
+            height: None,
+            scale: FrequencyScale::Linear,
+            color_map: ColorMap::Viridis,
+            dynamic_range: 80.0,
+            glyphs: Glyphs::Blocks,
+            color: true,
+        }
+    }
+}
+
+impl TextPlot {
+    // Columns of the plot itself
+    fn columns(&self) -> usize {
+        self.width.saturating_sub(LABEL_WIDTH).max(1)
+    }
+
+    // Plot columns per character
+    fn dots_across(&self) -> usize {
+        match self.glyphs {
+            Glyphs::Blocks => 1,
+            Glyphs::Braille => 2,
+        }
+    }
+
+    // dB relative to the top of the range as 0 to 1
+    fn fraction(&self, level: f64) -> f64 {
+        ((level + self.dynamic_range) / self.dynamic_range).clamp(0.0, 1.0)
+    }
+
+    // Frequencies at the edges, DC has no place on a log axis so it starts at the first bin
+    fn frequency_range(&self, (low, high): (f64, f64), first_bin: f64) -> (f64, f64) {
+        match self.scale {
+            FrequencyScale::Log if low <= 0.0 => (first_bin, high.max(first_bin * 2.0)),
+            _ => (low, high),
+        }
+    }
+
+    // Bins under each of count evenly spaced columns of the scale, every column gets at least one
+    fn column_bins(
+        &self,
+        count: usize,
+        (low, high): (f64, f64),
+        frequency_bin: impl Fn(f64) -> f64,
+        num_bins: usize,
+    ) -> Vec<Range<usize>> {
+        let (bottom, top) = (self.scale.forward(low), self.scale.forward(high));
+        let bin = |column: usize| {
+            let position = bottom + column as f64 / count as f64 * (top - bottom);
+            (frequency_bin(self.scale.inverse(position)).round().max(0.0) as usize)
+                .min(num_bins.saturating_sub(1))
+        };
+        (0..count)
+            .map(|column| {
+                let start = bin(column);
+                start..bin(column + 1).max(start + 1)
+            })
+            .collect()
+    }
+
+    // Round frequencies placed under the columns they belong to, left out where they would overlap
+    fn frequency_labels(&self, (low, high): (f64, f64)) -> String {
+        let columns = self.columns();
+        let (bottom, top) = (self.scale.forward(low), self.scale.forward(high));
+        let mut line = vec![' '; columns];
+        let mut free = 0;
+        for tick in frequency_ticks(low, high, self.scale) {
+            let column = ((self.scale.forward(tick) - bottom) / (top - bottom) * columns as f64)
+                .round() as usize;
+            let label: Vec<char> = format_frequency(tick).chars().collect();
+            if column >= free && column + label.len() <= columns {
+                line[column..column + label.len()].copy_from_slice(&label);
+                free = column + label.len() + 1;
+            }
+        }
+        format!("{:>w$}{}", "Hz ", String::from_iter(line), w = LABEL_WIDTH)
+    }
+
+    fn rgb(&self, fraction: f64) -> [u8; 3] {
+        self.color_map.rgb(fraction)
+    }
+
+    // Vertical bars of the level in dBFS against frequency, the bottom line holds the frequency labels
+    pub fn write_spectrum<W: Write>(
+        &self,
+        out: &mut W,
+        spectrum: &CalibratedSpectrum,
+    ) -> io::Result<()> {
+        let rows = self.height.unwrap_or(DEFAULT_SPECTRUM_HEIGHT).max(1);
+        let bin_width = spectrum.frequency(1.0);
+        let range = self.frequency_range(
+            (
+                0.0,
+                spectrum.frequency(spectrum.num_bins().saturating_sub(1) as f64),
+            ),
+            bin_width,
+        );
+        let across = self.dots_across();
+        let levels = spectrum.levels();
+        let heights: Vec<f64> = self
+            .column_bins(
+                self.columns() * across,
+                range,
+                |frequency| frequency / bin_width,
+                levels.len(),
+            )
+            .into_iter()
+            .map(|bins| {
+                let level = levels[bins]
+                    .iter()
+                    .copied()
+                    .fold(f64::NEG_INFINITY, f64::max);
+                self.fraction(level)
+            })
+            .collect();
+
+        // Eighths of a character for blocks, quarters for braille dots
+        let steps = match self.glyphs {
+            Glyphs::Blocks => 8,
+            Glyphs::Braille => 4,
+        };
+        for row in 0..rows {
+            // Level labels every 4 lines, from 0dBFS at the top
+            if row % 4 == 0 {
+                let level = -(row as f64) / rows as f64 * self.dynamic_range + 0.0;
+                write!(out, "{:>5.0}dB┤", level)?;
+            } else {
+                write!(out, "{:>w$}│", "", w = LABEL_WIDTH - 1)?;
+            }
+
+            // Steps filled below this line
+            let floor = (rows - 1 - row) * steps;
+            let filled = |height: f64| {
+                ((height * (rows * steps) as f64).round() as usize)
+                    .saturating_sub(floor)
+                    .min(steps)
+            };
+            let mut color = None;
+            for cell in heights.chunks(across) {
+                let glyph = match self.glyphs {
+                    Glyphs::Blocks => BARS[filled(cell[0])],
+                    Glyphs::Braille => {
+                        let mut bits = 0;
+                        for (x, height) in cell.iter().enumerate() {
+                            // Dots fill up from the bottom row of the cell
+                            for dot in 0..filled(*height) {
+                                bits |= BRAILLE_DOTS[x][3 - dot];
+                            }
+                        }
+                        char::from_u32(0x2800 + bits).unwrap_or(' ')
+                    }
+                };
+                if self.color {
+                    let top = cell.iter().copied().fold(0.0, f64::max);
+                    let rgb = self.rgb(top);
+                    if color != Some(rgb) {
+                        write!(out, "{}", fg(rgb))?;
+                        color = Some(rgb);
+                    }
+                }
+                write!(out, "{}", glyph)?;
+            }
+            if self.color {
+                write!(out, "{}", RESET)?;
+            }
+            writeln!(out)?;
+        }
+
+        writeln!(
+            out,
+            "{:>w$}└{}",
+            "",
+            "─".repeat(self.columns()),
+            w = LABEL_WIDTH - 1
+        )?;
+        writeln!(out, "{}", self.frequency_labels(range))
+    }
+
+    // Time going down and frequency across, written a line at a time as the frames are merged
+    pub fn write_spectrogram<W: Write>(
+        &self,
+        out: &mut W,
+        spectrogram: &dyn TimeFrequency,
+    ) -> io::Result<()> {
+        let range = self.frequency_range(
+            (
+                spectrogram.frequency_range().0,
+                spectrogram.bin_frequency(spectrogram.num_bins().saturating_sub(1) as f64),
+            ),
+            spectrogram.bin_frequency(1.0),
+        );
+        let across = self.dots_across();
+        let bins = self.column_bins(
+            self.columns() * across,
+            range,
+            |frequency| spectrogram.frequency_bin(frequency),
+            spectrogram.num_bins(),
+        );
+
+        // Frames drawn by one line, half blocks hold two with colour
+        let down = match (self.glyphs, self.color) {
+            (Glyphs::Braille, _) => 4,
+            (Glyphs::Blocks, true) => 2,
+            (Glyphs::Blocks, false) => 1,
+        };
+        let frames = spectrogram.num_frames();
+        let merge = self
+            .height
+            .map_or(1, |lines| frames.div_ceil(lines.max(1) * down).max(1));
+        let steps = frames.div_ceil(merge);
+
+        // Loudest level of every column over the merged frames, 0 to 1 within the dynamic range
+        let reference = spectrogram.max_magnitude();
+        let step = |index: usize| -> Option<Vec<f64>> {
+            let frames = index * merge..((index + 1) * merge).min(frames);
+            if frames.is_empty() {
+                return None;
+            }
+            Some(
+                bins.iter()
+                    .map(|bins| {
+                        let magnitude = frames
+                            .clone()
+                            .flat_map(|frame| {
+                                bins.clone()
+                                    .map(move |bin| spectrogram.magnitude(frame, bin))
+                            })
+                            .fold(0.0, f64::max);
+                        self.fraction(to_db(magnitude, reference))
+                    })
+                    .collect(),
+            )
+        };
+
+        writeln!(out, "{}", self.frequency_labels(range))?;
+        writeln!(
+            out,
+            "{:>w$}┌{}",
+            "s ",
+            "─".repeat(self.columns()),
+            w = LABEL_WIDTH - 1
+        )?;
+
+        let seconds_per_step = merge as f64 / spectrogram.frames_per_second();
+        for (line, first) in (0..steps).step_by(down).enumerate() {
+            if line % 4 == 0 {
+                write!(out, "{:>6.2}s│", first as f64 * seconds_per_step)?;
+            } else {
+                write!(out, "{:>w$}│", "", w = LABEL_WIDTH - 1)?;
+            }
+
+            let rows: Vec<Vec<f64>> = (first..first + down).map_while(step).collect();
+            let mut colors = None;
+            for column in 0..self.columns() {
+                let cells = column * across..(column + 1) * across;
+                let (glyph, fg_rgb, bg_rgb) = match (self.glyphs, self.color) {
+                    (Glyphs::Blocks, true) => {
+                        // Upper half block, the foreground is the first frame and the background the second
+                        let top = self.rgb(rows[0][column]);
+                        let bottom = rows.get(1).map(|row| self.rgb(row[column]));
+                        ('▀', Some(top), bottom)
+                    }
+                    (Glyphs::Blocks, false) => {
+                        let shade = (rows[0][column] * (SHADES.len() - 1) as f64).round();
+                        (SHADES[shade as usize], None, None)
+                    }
+                    (Glyphs::Braille, _) => {
+                        let mut bits = 0;
+                        let mut loudest: f64 = 0.0;
+                        for (y, row) in rows.iter().enumerate() {
+                            for (x, fraction) in row[cells.clone()].iter().enumerate() {
+                                loudest = loudest.max(*fraction);
+                                if *fraction > DITHER[x][y] {
+                                    bits |= BRAILLE_DOTS[x][y];
+                                }
+                            }
+                        }
+                        let glyph = char::from_u32(0x2800 + bits).unwrap_or(' ');
+                        (glyph, self.color.then(|| self.rgb(loudest)), None)
+                    }
+                };
+
+                if self.color && colors != Some((fg_rgb, bg_rgb)) {
+                    write!(out, "{}", RESET)?;
+                    if let Some(rgb) = fg_rgb {
+                        write!(out, "{}", fg(rgb))?;
+                    }
+                    if let Some(rgb) = bg_rgb {
+                        write!(out, "{}", bg(rgb))?;
+                    }
+                    colors = Some((fg_rgb, bg_rgb));
+                }
+                write!(out, "{}", glyph)?;
+            }
+            if self.color {
+                write!(out, "{}", RESET)?;
+            }
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn fg([r, g, b]: [u8; 3]) -> String {
+    format!("\x1b[38;2;{};{};{}m", r, g, b)
+}
+
+fn bg([r, g, b]: [u8; 3]) -> String {
+    format!("\x1b[48;2;{};{};{}m", r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cqt::CqtConfig;
+    use crate::spectrogram::{Spectrogram, StftConfig};
+    use crate::window::Window;
+    use std::f64::consts::PI;
+
+    fn tone(frequency: f64, size: usize) -> Vec<f64> {
+        (0..size)
+            .map(|n| 0.5 * (2.0 * PI * frequency * n as f64 / 8000.0).sin())
+            .collect()
+    }
+
+    fn render(f: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_glyphs() {
+        assert_eq!("braille".parse::<Glyphs>().unwrap(), Glyphs::Braille);
+        assert_eq!("Blocks".parse::<Glyphs>().unwrap(), Glyphs::Blocks);
+        assert!("ascii".parse::<Glyphs>().is_err());
+        assert_eq!(Glyphs::Braille.to_string(), "braille");
+    }
+
+    #[test]
+    fn test_spectrum_bars() {
+        // 1kHz at -6dBFS with 1 column per 50Hz, so the peak is in column 20
+        let spectrum = CalibratedSpectrum::new(&tone(1000.0, 1024), Window::Hann, 1024, 8000, 1.0);
+        let plot = TextPlot {
+            width: LABEL_WIDTH + 80,
+            height: Some(10),
+            dynamic_range: 60.0,
+            color: false,
+            ..TextPlot::default()
+        };
+        let text = render(|out| plot.write_spectrum(out, &spectrum));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 12);
+        assert!(lines[0].starts_with("    0dB┤"));
+        assert!(!text.contains('\x1b'));
+
+        // -6dB out of 60 leaves the top line empty and fills the one below
+        let column = |line: &str| line.chars().nth(LABEL_WIDTH + 20).unwrap();
+        assert_eq!(column(lines[0]), ' ');
+        assert_eq!(column(lines[1]), '█');
+        assert_eq!(column(lines[9]), '█');
+        assert!(lines[11].contains("1k"));
+
+        // Two dots across per character, 1kHz falls between the halves of character 20
+        let braille = TextPlot {
+            glyphs: Glyphs::Braille,
+            ..plot
+        };
+        let text = render(|out| braille.write_spectrum(out, &spectrum));
+        let line = text.lines().nth(5).unwrap();
+        assert_eq!(column(line), '⡇');
+        assert_eq!(line.chars().nth(LABEL_WIDTH + 19).unwrap(), '⢸');
+
+        let colored = TextPlot {
+            color: true,
+            ..plot
+        };
+        let text = render(|out| colored.write_spectrum(out, &spectrum));
+        assert!(text.contains("\x1b[38;2;"));
+    }
+
+    #[test]
+    fn test_spectrogram_lines() {
+        let config = StftConfig {
+            window_size: 256,
+            hop_size: 256,
+            ..StftConfig::default()
+        };
+        let spectrogram = Spectrogram::new(&tone(1000.0, 256 * 40), 8000, config);
+        assert_eq!(spectrogram.num_frames(), 40);
+        let plot = TextPlot {
+            width: LABEL_WIDTH + 64,
+            ..TextPlot::default()
+        };
+
+        // Two header lines, then two frames per line with half blocks
+        let text = render(|out| plot.write_spectrogram(out, &spectrogram));
+        assert_eq!(text.lines().count(), 2 + 20);
+        assert!(text.contains('▀'));
+        assert!(text.contains("\x1b[48;2;"));
+
+        let shades = TextPlot {
+            color: false,
+            ..plot
+        };
+        let text = render(|out| shades.write_spectrogram(out, &spectrogram));
+        assert_eq!(text.lines().count(), 2 + 40);
+        // The tone is the loudest bin, in column 16 of 64 across 4kHz
+        let line = text.lines().nth(4).unwrap();
+        assert_eq!(line.chars().nth(LABEL_WIDTH + 16).unwrap(), '█');
+
+        // Frames are merged to fit the height
+        let braille = TextPlot {
+            glyphs: Glyphs::Braille,
+            height: Some(5),
+            ..plot
+        };
+        let text = render(|out| braille.write_spectrogram(out, &spectrogram));
+        assert_eq!(text.lines().count(), 2 + 5);
+    }
+
+    #[test]
+    fn test_cqt_spectrogram() {
+        let config = CqtConfig {
+            min_frequency: 110.0,
+            bins_per_octave: 12,
+            hop_size: 512,
+            ..CqtConfig::default()
+        };
+        let spectrogram = config.spectrogram(&tone(440.0, 512 * 16), 8000).unwrap();
+        let plot = TextPlot {
+            width: LABEL_WIDTH + 64,
+            scale: FrequencyScale::Log,
+            color: false,
+            ..TextPlot::default()
+        };
+        let text = render(|out| plot.write_spectrogram(out, &spectrogram));
+        assert_eq!(text.lines().count(), 2 + 16);
+
+        // Two octaves up from the lowest bin on an axis evenly spaced in octaves
+        let (low, high) = spectrogram.frequency_range();
+        let column = (64.0 * 2.0 / (high / low).log2()).round() as usize;
+        let line = text.lines().nth(10).unwrap();
+        assert_eq!(line.chars().nth(LABEL_WIDTH + column).unwrap(), '█');
+    }
+}
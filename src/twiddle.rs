@@ -0,0 +1,122 @@
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+use crate::complex::Complex;
+use crate::float::Float;
+
+// Twiddle factor e^(-2PI i k / n) of a forward transform, the inverse one is its conjugate
+//
+// r = 1 and theta = -2PI k / n is a point on the unit circle in the complex plane, the DFT wraps the signal around
+// the circle at every frequency and sums it up to find the ones oscillating at the same rate as the signal
+//
+// The index is folded into the first octant with the symmetries of sin and cos before calling sin_cos, so the angle
+// never gets large and the factors of symmetric angles are exact mirror images of each other. 0, 1/8, 1/4, 3/8
+// turns and so on come out exact: 1, (1 - i) / sqrt 2, -i, ...
+pub fn twiddle<T: Float>(k: usize, n: usize) -> Complex<T> {
+    // Index in eighths of 1 / n turns, the reflections below stay in integers that way even for odd n
+    let n = n as u128;
+    let mut a = 8 * (k as u128 % n);
+
+    // Second half of the circle mirrors the first one across the real axis
+    let conjugate = a > 4 * n;
+    if conjugate {
+        a = 8 * n - a;
+    }
+    // Second quadrant mirrors the first across the imaginary axis
+    let negate_re = a > 2 * n;
+    if negate_re {
+        a = 4 * n - a;
+    }
+    // Second octant mirrors the first across the diagonal
+    let swap = a > n;
+    if swap {
+        a = 2 * n - a;
+    }
+
+    let (sin, cos) = if a == n {
+        (FRAC_1_SQRT_2, FRAC_1_SQRT_2)
+    } else {
+        (PI * a as f64 / (4 * n) as f64).sin_cos()
+    };
+    let (mut re, mut im) = if swap { (sin, cos) } else { (cos, sin) };
+    if negate_re {
+        re = -re;
+    }
+    if !conjugate {
+        im = -im;
+    }
+
+    Complex::new(T::cast(re), T::cast(im))
+}
+
+// Every twiddle factor of an n point forward transform, the factor of k * j is table[k * j % n]
+pub fn twiddles<T: Float>(n: usize) -> Vec<Complex<T>> {
+    (0..n).map(|k| twiddle(k, n)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::complex::{Complex32, Complex64};
+
+    #[test]
+    fn test_exact_angles() {
+        let table: Vec<Complex64> = twiddles(8);
+        let h = FRAC_1_SQRT_2;
+        let expected = [
+            (1.0, 0.0),
+            (h, -h),
+            (0.0, -1.0),
+            (-h, -h),
+            (-1.0, 0.0),
+            (-h, h),
+            (0.0, 1.0),
+            (h, h),
+        ];
+        for (twiddle, (re, im)) in table.iter().zip(expected) {
+            assert_eq!((twiddle.re, twiddle.im), (re, im));
+        }
+
+        // k wraps around the circle
+        assert_eq!(twiddle::<f64>(10, 8), table[2]);
+        assert_eq!(twiddle::<f64>(usize::MAX, 3), twiddle(usize::MAX % 3, 3));
+    }
+
+    #[test]
+    fn test_symmetry() {
+        for n in [6, 7, 12, 1000, 1024, 4097] {
+            let table: Vec<Complex64> = twiddles(n);
+            for k in 1..n {
+                // Mirror images across the real axis are exact conjugates
+                assert_eq!(table[n - k], table[k].conj(), "n = {}, k = {}", n, k);
+                if 2 * k < n && n % 2 == 0 {
+                    // and across the imaginary axis exact negated conjugates
+                    assert_eq!(table[n / 2 - k], -table[k].conj(), "n = {}, k = {}", n, k);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_accuracy() {
+        for n in [3, 5, 360, 1024, 65536] {
+            for k in 0..n {
+                let angle = -2.0 * PI * k as f64 / n as f64;
+                let direct = Complex64::from_polar(1.0, angle);
+                let folded: Complex64 = twiddle(k, n);
+                // The direct angle is off by a few ulp itself near a full turn
+                assert!((folded - direct).abs() < 1e-14, "n = {}, k = {}", n, k);
+                assert!((folded.abs() - 1.0).abs() < 1e-15);
+            }
+        }
+
+        // cos and sin of 32 degrees are 0.8480480961564260 and 0.5299192642332049
+        let folded: Complex64 = twiddle(328, 360);
+        assert!((folded.re - 0.848_048_096_156_426).abs() <= f64::EPSILON);
+        assert!((folded.im - 0.529_919_264_233_204_9).abs() <= f64::EPSILON);
+
+        // The f32 factors are the f64 ones rounded once
+        let single: Complex32 = twiddle(3, 7);
+        let double: Complex64 = twiddle(3, 7);
+        assert_eq!(single, Complex::new(double.re as f32, double.im as f32));
+    }
+}
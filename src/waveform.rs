@@ -0,0 +1,334 @@
+use std::{fmt, io::Read, str::FromStr};
+
+use crate::audio::WavReader;
+use crate::error::{Error, Result};
+use crate::spectrogram::to_db;
+
+// Whether a multichannel file is drawn as a single downmixed lane or as one lane per channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lanes {
+    #[default]
+    Downmix,
+    Channels,
+}
+
+impl FromStr for Lanes {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "downmix" | "mono" => Ok(Lanes::Downmix),
+            "channels" => Ok(Lanes::Channels),
+            _ => Err(Error::InvalidArgument(format!("unknown lanes '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for Lanes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lanes::Downmix => write!(f, "downmix"),
+            Lanes::Channels => write!(f, "channels"),
+        }
+    }
+}
+
+// Smallest and largest sample and the RMS of the samples falling into one column of the picture
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Envelope {
+    pub min: f64,
+    pub max: f64,
+    pub rms: f64,
+}
+
+impl Envelope {
+    // Largest absolute sample
+    pub fn peak(&self) -> f64 {
+        self.min.abs().max(self.max.abs())
+    }
+}
+
+// Running envelope of one column, an empty column stays all zeros
+#[derive(Debug, Clone, Copy)]
+struct Accumulator {
+    min: f64,
+    max: f64,
+    sum_squares: f64,
+    count: u64,
+}
+
+impl Accumulator {
+    const EMPTY: Accumulator = Accumulator {
+        min: f64::INFINITY,
+        max: f64::NEG_INFINITY,
+        sum_squares: 0.0,
+        count: 0,
+    };
+
+    fn push(&mut self, sample: f64) {
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.sum_squares += sample * sample;
+        self.count += 1;
+    }
+
+    fn envelope(&self) -> Envelope {
+        if self.count == 0 {
+            return Envelope::default();
+        }
+        Envelope {
+            min: self.min,
+            max: self.max,
+            rms: (self.sum_squares / self.count as f64).sqrt(),
+        }
+    }
+}
+
+// Builds the envelopes of every lane in a single pass over the frames
+//
+// Only one accumulator per column and lane is kept, so the memory does not grow with the length of the file
+pub struct EnvelopeBuilder {
+    columns: usize,
+    total_frames: u64,
+    lanes: Lanes,
+    // Per lane, per column
+    accumulators: Vec<Vec<Accumulator>>,
+    position: u64,
+}
+
+impl EnvelopeBuilder {
+    // total_frames is needed up front to know which column a frame falls into
+    pub fn new(columns: usize, total_frames: u64, channels: usize, lanes: Lanes) -> Self {
+        let num_lanes = match lanes {
+            Lanes::Downmix => 1,
+            Lanes::Channels => channels.max(1),
+        };
+        EnvelopeBuilder {
+            columns,
+            total_frames,
+            lanes,
+            accumulators: vec![vec![Accumulator::EMPTY; columns]; num_lanes],
+            position: 0,
+        }
+    }
+
+    // One sample per channel, frames past total_frames end up in the last column
+    pub fn push(&mut self, frame: &[f64]) {
+        if self.columns == 0 || frame.is_empty() {
+            return;
+        }
+
+        let column = (self.position as u128 * self.columns as u128
+            / self.total_frames.max(1) as u128)
+            .min(self.columns as u128 - 1) as usize;
+        match self.lanes {
+            Lanes::Downmix => {
+                let mix = frame.iter().sum::<f64>() / frame.len() as f64;
+                self.accumulators[0][column].push(mix);
+            }
+            Lanes::Channels => {
+                for (lane, sample) in self.accumulators.iter_mut().zip(frame) {
+                    lane[column].push(*sample);
+                }
+            }
+        }
+        self.position += 1;
+    }
+
+    pub fn finish(self, sample_rate: u32, full_scale: f64) -> Waveform {
+        Waveform {
+            lanes: self
+                .accumulators
+                .iter()
+                .map(|lane| lane.iter().map(Accumulator::envelope).collect())
+                .collect(),
+            duration: self.total_frames as f64 / sample_rate as f64,
+            sample_rate,
+            full_scale,
+        }
+    }
+}
+
+// A stretch of time to highlight on the waveform, like a match or silence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    // Seconds
+    pub start: f64,
+    pub end: f64,
+    pub label: String,
+}
+
+// Overview of a signal in the time domain, one envelope per column and lane
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waveform {
+    lanes: Vec<Vec<Envelope>>,
+    sample_rate: u32,
+    // Seconds
+    duration: f64,
+    // Amplitude of 0dBFS
+    full_scale: f64,
+}
+
+impl Waveform {
+    // Streams the rest of the file into columns envelopes
+    pub fn read<R: Read>(
+        wav_reader: &mut WavReader<R>,
+        columns: usize,
+        lanes: Lanes,
+    ) -> Result<Waveform> {
+        let config = wav_reader.config();
+        let fmt = config.fmt();
+        let (sample_rate, full_scale) = (fmt.sample_rate, fmt.full_scale());
+        let mut builder = EnvelopeBuilder::new(
+            columns,
+            config.samples_per_channel() as u64,
+            fmt.channels as usize,
+            lanes,
+        );
+        wav_reader.for_each_frame(|frame| builder.push(frame))?;
+        Ok(builder.finish(sample_rate, full_scale))
+    }
+
+    // Single lane of mono samples
+    pub fn from_samples(
+        samples: &[f64],
+        sample_rate: u32,
+        full_scale: f64,
+        columns: usize,
+    ) -> Waveform {
+        let mut builder = EnvelopeBuilder::new(columns, samples.len() as u64, 1, Lanes::Downmix);
+        for sample in samples {
+            builder.push(&[*sample]);
+        }
+        builder.finish(sample_rate, full_scale)
+    }
+
+    pub fn lanes(&self) -> &[Vec<Envelope>] {
+        &self.lanes
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.lanes.first().map_or(0, Vec::len)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    pub fn full_scale(&self) -> f64 {
+        self.full_scale
+    }
+
+    // Start of a column in seconds
+    pub fn column_time(&self, column: usize) -> f64 {
+        column as f64 * self.duration / self.num_columns().max(1) as f64
+    }
+
+    // Stretches of at least min_duration seconds where every lane stays below threshold dBFS
+    //
+    // Silence is found per column, so the edges are only as precise as the width of a column
+    pub fn silence(&self, threshold: f64, min_duration: f64) -> Vec<Region> {
+        let is_silent = |column: usize| {
+            self.lanes
+                .iter()
+                .all(|lane| to_db(lane[column].peak(), self.full_scale) < threshold)
+        };
+
+        let mut regions = Vec::new();
+        let mut start = None;
+        for column in 0..=self.num_columns() {
+            let silent = column < self.num_columns() && is_silent(column);
+            match (start, silent) {
+                (None, true) => start = Some(column),
+                (Some(first), false) => {
+                    let region = Region {
+                        start: self.column_time(first),
+                        end: self.column_time(column),
+                        label: "silence".to_string(),
+                    };
+                    if region.end - region.start >= min_duration {
+                        regions.push(region);
+                    }
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        regions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_columns() {
+        // Two columns of two samples each
+        let waveform = Waveform::from_samples(&[1.0, -3.0, 4.0, 0.0], 4, 8.0, 2);
+        assert_eq!(waveform.num_columns(), 2);
+        assert_eq!(waveform.duration(), 1.0);
+        assert_eq!(waveform.column_time(1), 0.5);
+
+        let lane = &waveform.lanes()[0];
+        assert_eq!(lane[0].min, -3.0);
+        assert_eq!(lane[0].max, 1.0);
+        assert_eq!(lane[0].rms, 5f64.sqrt());
+        assert_eq!(lane[0].peak(), 3.0);
+        assert_eq!(lane[1].rms, 8f64.sqrt());
+
+        // More columns than samples leaves some of them empty
+        let sparse = Waveform::from_samples(&[2.0, -2.0], 2, 8.0, 4);
+        assert_eq!(sparse.lanes()[0][1], Envelope::default());
+        assert_eq!(sparse.lanes()[0][2].min, -2.0);
+    }
+
+    #[test]
+    fn test_lanes() {
+        let frames = [[1.0, -1.0], [3.0, 1.0]];
+        let mut downmix = EnvelopeBuilder::new(1, 2, 2, Lanes::Downmix);
+        let mut channels = EnvelopeBuilder::new(1, 2, 2, Lanes::Channels);
+        for frame in &frames {
+            downmix.push(frame);
+            channels.push(frame);
+        }
+
+        let downmix = downmix.finish(2, 1.0);
+        assert_eq!(downmix.lanes().len(), 1);
+        assert_eq!(downmix.lanes()[0][0].max, 2.0);
+
+        let channels = channels.finish(2, 1.0);
+        assert_eq!(channels.lanes().len(), 2);
+        assert_eq!(channels.lanes()[0][0].max, 3.0);
+        assert_eq!(channels.lanes()[1][0].min, -1.0);
+
+        assert_eq!("channels".parse::<Lanes>().unwrap(), Lanes::Channels);
+        assert_eq!("mono".parse::<Lanes>().unwrap(), Lanes::Downmix);
+        assert!("stereo".parse::<Lanes>().is_err());
+    }
+
+    #[test]
+    fn test_silence() {
+        // 1s of tone, 2s of near silence, 0.5s of tone, 0.25s of silence at 1000 columns per second
+        let mut samples = vec![0.5; 1000];
+        samples.extend(vec![1e-5; 2000]);
+        samples.extend(vec![0.5; 500]);
+        samples.extend(vec![0.0; 250]);
+        let waveform = Waveform::from_samples(&samples, 1000, 1.0, samples.len());
+
+        let silence = waveform.silence(-60.0, 0.1);
+        assert_eq!(silence.len(), 2);
+        assert!((silence[0].start - 1.0).abs() < 1e-9);
+        assert!((silence[0].end - 3.0).abs() < 1e-9);
+        assert!((silence[1].end - 3.75).abs() < 1e-9);
+        assert_eq!(silence[0].label, "silence");
+
+        assert_eq!(waveform.silence(-60.0, 0.5).len(), 1);
+        // -100dBFS is not silent enough, only the digital silence at the end is
+        assert_eq!(waveform.silence(-120.0, 0.1).len(), 1);
+    }
+}
@@ -0,0 +1,451 @@
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+use rustfft::{FftPlanner, num_complex::Complex64};
+
+use crate::error::Error;
+use crate::simd;
+
+// Oversampling factor used when measuring the window spectrum for sidelobe levels
+const SPECTRUM_OVERSAMPLING: usize = 16;
+
+// A named, parameterised window function
+//
+// Multiplying a frame by a window tapers its edges towards zero so the frame looks periodic to the DFT,
+// which reduces spectral leakage at the cost of a wider main lobe
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Rectangular,
+    // Cosine bump starting and ending at zero, good general purpose choice
+    Hann,
+    // Hann raised slightly so the first sidelobe is cancelled, but the edges do not reach zero
+    Hamming,
+    Blackman,
+    // 4 term Blackman-Harris, ~92dB sidelobe suppression
+    BlackmanHarris,
+    // Very flat main lobe, almost no scalloping loss so it is the one to use for amplitude measurements
+    FlatTop,
+    // beta trades main lobe width for sidelobe level, 0 is rectangular and ~8.6 is close to Blackman
+    Kaiser { beta: f64 },
+    // Flat in the middle with cosine tapers over alpha of the window, 0 is rectangular and 1 is Hann
+    Tukey { alpha: f64 },
+    // sigma is the standard deviation relative to half of the window length, usually <= 0.5
+    Gaussian { sigma: f64 },
+    // Naive linear slope over `slope` of the window on each side
+    Trapezoid { slope: f64 },
+}
+
+// Symmetric windows are used for filter design, periodic ones for spectral analysis since they
+// line up with the DFT period (the would be sample N is equal to sample 0)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symmetry {
+    Symmetric,
+    Periodic,
+}
+
+// Figures of merit of a window, used to correct magnitudes and compare windows on leakage
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowProperties {
+    // Mean of the window, a windowed sine peak is scaled by this factor
+    pub coherent_gain: f64,
+    // Equivalent noise bandwidth in bins, how much broadband noise a single bin collects
+    pub enbw: f64,
+    // Worst case drop in dB of a tone falling exactly between two bins
+    pub scalloping_loss: f64,
+    // Level in dB of the highest sidelobe relative to the main lobe peak
+    pub peak_sidelobe: f64,
+}
+
+impl Window {
+    pub fn generate(&self, size: usize, symmetry: Symmetry) -> Vec<f64> {
+        match (size, symmetry) {
+            (0, _) => Vec::new(),
+            (1, _) => vec![1.0],
+            (_, Symmetry::Symmetric) => self.symmetric(size),
+            // A periodic window of size N is the symmetric window of size N + 1 without its last sample
+            (_, Symmetry::Periodic) => {
+                let mut win = self.symmetric(size + 1);
+                win.pop();
+                win
+            }
+        }
+    }
+
+    pub fn periodic(&self, size: usize) -> Vec<f64> {
+        self.generate(size, Symmetry::Periodic)
+    }
+
+    pub fn properties(&self, size: usize) -> WindowProperties {
+        WindowProperties::of(&self.periodic(size))
+    }
+
+    fn symmetric(&self, size: usize) -> Vec<f64> {
+        if size <= 1 {
+            return vec![1.0; size];
+        }
+
+        // Last index of the window, x = n / m goes from 0 to 1 over the window
+        let m = (size - 1) as f64;
+        (0..size).map(|n| self.coefficient(n as f64, m)).collect()
+    }
+
+    fn coefficient(&self, n: f64, m: f64) -> f64 {
+        let x = n / m;
+        match *self {
+            Window::Rectangular => 1.0,
+            Window::Hann => cosine_sum(&[0.5, 0.5], x),
+            Window::Hamming => cosine_sum(&[0.54, 0.46], x),
+            Window::Blackman => cosine_sum(&[0.42, 0.5, 0.08], x),
+            Window::BlackmanHarris => cosine_sum(&[0.35875, 0.48829, 0.14128, 0.01168], x),
+            Window::FlatTop => cosine_sum(
+                &[
+                    0.215_578_95,
+                    0.416_631_58,
+                    0.277_263_158,
+                    0.083_578_947,
+                    0.006_947_368,
+                ],
+                x,
+            ),
+            Window::Kaiser { beta } => {
+                // Position relative to the centre, from -1 to 1
+                let r = 2.0 * x - 1.0;
+                bessel_i0(beta * (1.0 - r * r).max(0.0).sqrt()) / bessel_i0(beta)
+            }
+            Window::Tukey { alpha } => {
+                if alpha <= 0.0 {
+                    return 1.0;
+                }
+                let alpha = alpha.min(1.0);
+                // Distance from the closest edge, the taper is mirrored on both sides
+                let edge = x.min(1.0 - x);
+                if edge < alpha / 2.0 {
+                    0.5 * (1.0 - (2.0 * PI * edge / alpha).cos())
+                } else {
+                    1.0
+                }
+            }
+            Window::Gaussian { sigma } => {
+                let r = 2.0 * x - 1.0;
+                (-0.5 * (r / sigma).powi(2)).exp()
+            }
+            Window::Trapezoid { slope } => {
+                if slope <= 0.0 {
+                    return 1.0;
+                }
+                let edge = x.min(1.0 - x);
+                (edge / slope).min(1.0)
+            }
+        }
+    }
+}
+
+// Parses names like "hann", "blackman-harris" or "kaiser:8.6", the number after the colon is the window parameter
+impl FromStr for Window {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => {
+                let param = param.parse::<f64>().map_err(|_| {
+                    Error::InvalidArgument(format!("bad window parameter '{}'", param))
+                })?;
+                (name, Some(param))
+            }
+            None => (s, None),
+        };
+
+        let window = match (name.to_ascii_lowercase().as_str(), param) {
+            ("rectangular", None) => Window::Rectangular,
+            ("hann", None) => Window::Hann,
+            ("hamming", None) => Window::Hamming,
+            ("blackman", None) => Window::Blackman,
+            ("blackman-harris", None) => Window::BlackmanHarris,
+            ("flat-top", None) => Window::FlatTop,
+            ("kaiser", param) => Window::Kaiser {
+                beta: param.unwrap_or(8.6),
+            },
+            ("tukey", param) => Window::Tukey {
+                alpha: param.unwrap_or(0.5),
+            },
+            ("gaussian", param) => Window::Gaussian {
+                sigma: param.unwrap_or(0.4),
+            },
+            ("trapezoid", param) => Window::Trapezoid {
+                slope: param.unwrap_or(0.05),
+            },
+            _ => return Err(Error::InvalidArgument(format!("unknown window '{}'", s))),
+        };
+
+        Ok(window)
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Window::Rectangular => write!(f, "rectangular"),
+            Window::Hann => write!(f, "hann"),
+            Window::Hamming => write!(f, "hamming"),
+            Window::Blackman => write!(f, "blackman"),
+            Window::BlackmanHarris => write!(f, "blackman-harris"),
+            Window::FlatTop => write!(f, "flat-top"),
+            Window::Kaiser { beta } => write!(f, "kaiser:{}", beta),
+            Window::Tukey { alpha } => write!(f, "tukey:{}", alpha),
+            Window::Gaussian { sigma } => write!(f, "gaussian:{}", sigma),
+            Window::Trapezoid { slope } => write!(f, "trapezoid:{}", slope),
+        }
+    }
+}
+
+impl WindowProperties {
+    pub fn of(win: &[f64]) -> Self {
+        if win.is_empty() {
+            return WindowProperties {
+                coherent_gain: 0.0,
+                enbw: 0.0,
+                scalloping_loss: 0.0,
+                peak_sidelobe: 0.0,
+            };
+        }
+
+        let n = win.len() as f64;
+        let sum: f64 = win.iter().sum();
+        let sum_sq: f64 = win.iter().map(|w| w * w).sum();
+
+        // The response to a tone half a bin away from the centre, e^(-i*PI*k/N) shifts it by half a bin
+        let half_bin = win
+            .iter()
+            .enumerate()
+            .map(|(k, w)| Complex64::from_polar(*w, -PI * k as f64 / n))
+            .sum::<Complex64>()
+            .norm();
+
+        WindowProperties {
+            coherent_gain: sum / n,
+            enbw: n * sum_sq / (sum * sum),
+            scalloping_loss: -20.0 * (half_bin / sum).log10(),
+            peak_sidelobe: peak_sidelobe(win),
+        }
+    }
+
+    // Converts the magnitude of a windowed bin back to the amplitude of the sine that produced it
+    pub fn amplitude_correction(&self) -> f64 {
+        1.0 / self.coherent_gain
+    }
+}
+
+// Multiplies the frame by the window sample by sample
+pub fn apply(frame: &[f64], win: &[f64]) -> Vec<f64> {
+    let mut windowed = frame[..frame.len().min(win.len())].to_vec();
+    apply_in_place(&mut windowed, win);
+    windowed
+}
+
+// Same as apply without the allocation, for frames that are thrown away afterwards anyway
+pub fn apply_in_place(frame: &mut [f64], win: &[f64]) {
+    simd::multiply(frame, win);
+}
+
+// Generalised cosine window, a0 - a1*cos(2PIx) + a2*cos(4PIx) - ...
+fn cosine_sum(coefficients: &[f64], x: f64) -> f64 {
+    coefficients
+        .iter()
+        .enumerate()
+        .map(|(k, a)| {
+            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+            sign * a * (2.0 * PI * k as f64 * x).cos()
+        })
+        .sum()
+}
+
+// Zeroth order modified Bessel function of the first kind, power series converges quickly for the betas we use
+fn bessel_i0(x: f64) -> f64 {
+    let half = x / 2.0;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for k in 1..100 {
+        term *= (half / k as f64).powi(2);
+        sum += term;
+        if term < sum * 1e-16 {
+            break;
+        }
+    }
+
+    sum
+}
+
+// Walks down the main lobe of the oversampled window spectrum to its first minimum,
+// the highest point after it is the peak sidelobe
+fn peak_sidelobe(win: &[f64]) -> f64 {
+    let size = win.len() * SPECTRUM_OVERSAMPLING;
+    let mut buff: Vec<Complex64> = win.iter().map(|w| Complex64::new(*w, 0.0)).collect();
+    buff.resize(size, Complex64::new(0.0, 0.0));
+    FftPlanner::new().plan_fft_forward(size).process(&mut buff);
+
+    let spectrum: Vec<f64> = buff.iter().take(size / 2).map(|x| x.norm()).collect();
+    let main_lobe = spectrum[0];
+    if main_lobe == 0.0 {
+        return 0.0;
+    }
+
+    let first_null = spectrum
+        .windows(2)
+        .position(|pair| pair[1] > pair[0])
+        .unwrap_or(spectrum.len());
+    let sidelobe = spectrum[first_null..].iter().copied().fold(0.0, f64::max);
+    if sidelobe == 0.0 {
+        return f64::NEG_INFINITY;
+    }
+
+    20.0 * (sidelobe / main_lobe).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() < tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_empty_and_single() {
+        assert!(Window::Hann.periodic(0).is_empty());
+        assert_eq!(Window::Hann.generate(1, Symmetry::Symmetric), vec![1.0]);
+    }
+
+    #[test]
+    fn test_hann_symmetric() {
+        let win = Window::Hann.generate(5, Symmetry::Symmetric);
+        let expected = [0.0, 0.5, 1.0, 0.5, 0.0];
+        for (w, e) in win.iter().zip(expected) {
+            assert_close(*w, e, 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_hann_periodic() {
+        // Same values as the symmetric window of size N + 1 without the last sample
+        let win = Window::Hann.periodic(4);
+        let expected = [0.0, 0.5, 1.0, 0.5];
+        assert_eq!(win.len(), 4);
+        for (w, e) in win.iter().zip(expected) {
+            assert_close(*w, e, 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_symmetric_windows_are_symmetric() {
+        let windows = [
+            Window::Hamming,
+            Window::Blackman,
+            Window::BlackmanHarris,
+            Window::FlatTop,
+            Window::Kaiser { beta: 8.0 },
+            Window::Tukey { alpha: 0.3 },
+            Window::Gaussian { sigma: 0.4 },
+            Window::Trapezoid { slope: 0.1 },
+        ];
+        for window in windows {
+            let win = window.generate(33, Symmetry::Symmetric);
+            for i in 0..win.len() {
+                assert_close(win[i], win[win.len() - 1 - i], 1e-12);
+            }
+            // Odd sized symmetric windows peak at one in the middle, except flat-top which overshoots slightly
+            if window != Window::FlatTop {
+                assert_close(win[16], 1.0, 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_kaiser_zero_beta_is_rectangular() {
+        let win = Window::Kaiser { beta: 0.0 }.periodic(16);
+        assert!(win.iter().all(|w| (w - 1.0).abs() < 1e-12));
+    }
+
+    #[test]
+    fn test_tukey_limits() {
+        let rect = Window::Tukey { alpha: 0.0 }.periodic(16);
+        assert!(rect.iter().all(|w| *w == 1.0));
+
+        let tukey = Window::Tukey { alpha: 1.0 }.periodic(16);
+        let hann = Window::Hann.periodic(16);
+        for (t, h) in tukey.iter().zip(hann) {
+            assert_close(*t, h, 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_bessel_i0() {
+        assert_close(bessel_i0(0.0), 1.0, 1e-15);
+        assert_close(bessel_i0(1.0), 1.266_065_877_752_008_4, 1e-12);
+        assert_close(bessel_i0(5.0), 27.239_871_823_604_442, 1e-9);
+    }
+
+    #[test]
+    fn test_rectangular_properties() {
+        let props = Window::Rectangular.properties(1024);
+        assert_close(props.coherent_gain, 1.0, 1e-12);
+        assert_close(props.enbw, 1.0, 1e-12);
+        assert_close(props.scalloping_loss, 3.92, 0.01);
+        assert_close(props.peak_sidelobe, -13.26, 0.1);
+    }
+
+    #[test]
+    fn test_hann_properties() {
+        let props = Window::Hann.properties(1024);
+        assert_close(props.coherent_gain, 0.5, 1e-12);
+        assert_close(props.enbw, 1.5, 1e-9);
+        assert_close(props.scalloping_loss, 1.42, 0.01);
+        assert_close(props.peak_sidelobe, -31.47, 0.1);
+        assert_close(props.amplitude_correction(), 2.0, 1e-12);
+    }
+
+    #[test]
+    fn test_known_window_properties() {
+        let hamming = Window::Hamming.properties(1024);
+        assert_close(hamming.enbw, 1.36, 0.01);
+        assert_close(hamming.peak_sidelobe, -42.7, 0.5);
+
+        let blackman_harris = Window::BlackmanHarris.properties(1024);
+        assert_close(blackman_harris.enbw, 2.0, 0.01);
+        assert!(blackman_harris.peak_sidelobe < -91.0);
+
+        let flat_top = Window::FlatTop.properties(1024);
+        assert!(flat_top.scalloping_loss < 0.02);
+        assert_close(flat_top.enbw, 3.77, 0.01);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("hann".parse::<Window>().unwrap(), Window::Hann);
+        assert_eq!(
+            "Blackman-Harris".parse::<Window>().unwrap(),
+            Window::BlackmanHarris
+        );
+        assert_eq!(
+            "kaiser:5".parse::<Window>().unwrap(),
+            Window::Kaiser { beta: 5.0 }
+        );
+        assert_eq!(
+            "tukey".parse::<Window>().unwrap(),
+            Window::Tukey { alpha: 0.5 }
+        );
+        assert!("hann:2".parse::<Window>().is_err());
+        assert!("kaiser:x".parse::<Window>().is_err());
+        assert!("triangle".parse::<Window>().is_err());
+
+        let window = Window::Gaussian { sigma: 0.3 };
+        assert_eq!(window.to_string().parse::<Window>().unwrap(), window);
+    }
+
+    #[test]
+    fn test_apply() {
+        let frame = [2.0, 2.0, 2.0, 2.0];
+        let win = Window::Hann.periodic(4);
+        for (x, e) in apply(&frame, &win).iter().zip([0.0, 1.0, 2.0, 1.0]) {
+            assert_close(*x, e, 1e-12);
+        }
+    }
+}